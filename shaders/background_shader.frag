@@ -0,0 +1,14 @@
+#version 450
+
+layout(location = 0) in float fragHeight;
+
+layout(location = 0) out vec4 outColor;
+
+layout(push_constant) uniform Push {
+    vec4 topColor; // ignore w
+    vec4 bottomColor; // ignore w
+} push;
+
+void main() {
+    outColor = vec4(mix(push.topColor.rgb, push.bottomColor.rgb, fragHeight), 1.0);
+}
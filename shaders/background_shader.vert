@@ -0,0 +1,21 @@
+#version 450
+
+// A single triangle covering the whole screen, generated from the vertex index so no vertex
+// buffer is needed. The parts outside the viewport are clipped
+const vec2 POSITIONS[3] = vec2[](
+    vec2(-1.0, -1.0),
+    vec2(3.0, -1.0),
+    vec2(-1.0, 3.0)
+);
+
+layout(location = 0) out float fragHeight;
+
+void main() {
+    vec2 position = POSITIONS[gl_VertexIndex];
+
+    // 0 at the top of the screen, 1 at the bottom (Vulkan's y axis points down)
+    fragHeight = position.y * 0.5 + 0.5;
+
+    // At the far plane, though depth testing is off anyway
+    gl_Position = vec4(position, 1.0, 1.0);
+}
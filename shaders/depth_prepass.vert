@@ -0,0 +1,21 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+
+// Must match the position calculation in simple_shader.vert exactly, otherwise the
+// EQUAL depth test in the colour pass will reject fragments
+invariant gl_Position;
+
+layout(set = 0, binding = 0) uniform GlobalUbo {
+    mat4 projectionViewMatrix;
+} ubo;
+
+layout(push_constant) uniform Push {
+    mat4 modelMatrix;
+    mat4 normalMatrix; // unused, kept so the block matches the simple shader's vertex range
+} push;
+
+void main() {
+    vec4 positionWorld = push.modelMatrix * vec4(position, 1.0);
+    gl_Position = ubo.projectionViewMatrix * positionWorld;
+}
@@ -0,0 +1,14 @@
+#version 450
+
+layout(location = 0) in vec2 fragUv;
+layout(location = 1) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+layout(set = 0, binding = 0) uniform sampler2D fontTexture;
+
+void main() {
+    // The font texture is white with coverage in the alpha channel, and the colour is already
+    // premultiplied, so the coverage scales all four channels
+    outColor = fragColor * texture(fontTexture, fragUv).a;
+}
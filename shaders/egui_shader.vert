@@ -0,0 +1,29 @@
+#version 450
+
+// egui's vertex layout, positions are in points and colours are premultiplied sRGB
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 uv;
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec2 fragUv;
+layout(location = 1) out vec4 fragColor;
+
+layout(push_constant) uniform Push {
+    vec2 screenSize; // in points
+} push;
+
+vec3 srgbToLinear(vec3 srgb) {
+    bvec3 cutoff = lessThan(srgb, vec3(0.04045));
+    vec3 lower = srgb / 12.92;
+    vec3 higher = pow((srgb + 0.055) / 1.055, vec3(2.4));
+    return mix(higher, lower, cutoff);
+}
+
+void main() {
+    // Vulkan's y axis already points down, so egui's top left origin maps straight across
+    gl_Position = vec4(position / push.screenSize * 2.0 - 1.0, 0.0, 1.0);
+
+    fragUv = uv;
+    // The swapchain image is sRGB, so the hardware converts back when writing
+    fragColor = vec4(srgbToLinear(color.rgb), color.a);
+}
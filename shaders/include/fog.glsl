@@ -0,0 +1,25 @@
+// Distance fog for the lit fragment shaders. Include it before the GlobalUbo, which holds the
+// FogParams. This is in a subdirectory so build.rs doesn't compile it on its own
+
+struct FogParams {
+    vec4 color; // ignore w
+    float density; // 0 disables the fog
+    float start; // view space depth
+    float end;
+};
+
+// The view space depth of this fragment, undoing the perspective projection's depth mapping
+float linearDepth(float near, float far) {
+    return near * far / (far - gl_FragCoord.z * (far - near));
+}
+
+// Blends the lit color towards the fog color, more the further the fragment is past the start
+vec3 applyFog(vec3 color, FogParams fog, float near, float far) {
+    if (fog.density <= 0.0) {
+        return color;
+    }
+
+    float distance = (linearDepth(near, far) - fog.start) / (fog.end - fog.start);
+    float fogAmount = clamp(distance, 0.0, 1.0) * fog.density;
+    return mix(color, fog.color.xyz, fogAmount);
+}
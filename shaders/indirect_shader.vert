@@ -0,0 +1,35 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 color;
+layout(location = 2) in vec3 normal;
+layout(location = 3) in vec2 uv;
+
+// Per instance, one instance per object. These replace simple_shader.vert's push constants
+layout(location = 4) in mat4 modelMatrix; // locations 4 to 7
+layout(location = 8) in mat4 normalMatrix; // locations 8 to 11, only the upper 3x3 is used
+layout(location = 12) in vec4 objectColor; // ignore w
+
+layout(location = 0) out vec3 fragColor;
+layout(location = 1) out vec3 fragPosWorld;
+layout(location = 2) out vec3 fragNormalWorld;
+
+// Keeps the depth identical to depth_prepass.vert when the depth pre-pass is enabled
+invariant gl_Position;
+
+// The fragment shader's USE_VERTEX_COLOR is always on for this pipeline, as the object's
+// color comes through fragColor. This decides whether the vertex colors are part of it
+layout(constant_id = 1) const bool APPLY_VERTEX_COLOR = true;
+
+layout(set = 0, binding = 0) uniform GlobalUbo {
+    mat4 projectionViewMatrix;
+} ubo;
+
+void main() {
+    vec4 positionWorld = modelMatrix * vec4(position, 1.0);
+    gl_Position = ubo.projectionViewMatrix * positionWorld;
+
+    fragNormalWorld = normalize(mat3(normalMatrix) * normal);
+    fragPosWorld = positionWorld.xyz;
+    fragColor = (APPLY_VERTEX_COLOR ? color : vec3(1.0)) * objectColor.xyz;
+}
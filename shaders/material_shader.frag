@@ -0,0 +1,93 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "include/fog.glsl"
+
+layout (location = 0) in vec3 fragColor;
+layout (location = 1) in vec3 fragPosWorld;
+layout (location = 2) in vec3 fragNormalWorld;
+
+layout (location = 0) out vec4 outColor;
+
+// Set from the pipeline, when false the mesh's vertex colors are ignored
+layout (constant_id = 0) const bool USE_VERTEX_COLOR = true;
+
+#define MAX_LIGHTS 10 // Must match MAX_LIGHTS in lve_frameinfo.rs
+
+struct PointLight {
+    vec4 position; // ignore w
+    vec4 color; // w is intensity
+};
+
+layout(set = 0, binding = 0) uniform GlobalUbo {
+    mat4 projectionViewMatrix;
+    mat4 inverseViewMatrix; // column 3 is the camera position
+    vec4 ambientLightColor; // w is intensity
+    vec4 directionalLightDirection; // ignore w
+    vec4 directionalLightColor; // w is intensity
+    PointLight pointLights[MAX_LIGHTS];
+    int numLights;
+    float nearPlane;
+    float farPlane;
+    FogParams fog;
+    mat4 previousProjectionViewMatrix; // unjittered
+    vec4 jitter; // xy is this frame's projection jitter in NDC, zw last frame's
+} ubo;
+
+// The object's material, bound with a dynamic offset for each object
+layout(set = 1, binding = 0) uniform MaterialUbo {
+    vec4 albedo; // ignore w
+    float roughness;
+    float metallic;
+} material;
+
+// Dielectrics all reflect about 4% of the light head on
+const vec3 DIELECTRIC_SPECULAR = vec3(0.04);
+
+void main() {
+    vec3 diffuseLight = ubo.ambientLightColor.xyz * ubo.ambientLightColor.w;
+    vec3 specularLight = vec3(0.0);
+    vec3 surfaceNormal = normalize(fragNormalWorld);
+
+    vec3 cameraPosWorld = ubo.inverseViewMatrix[3].xyz;
+    vec3 viewDirection = normalize(cameraPosWorld - fragPosWorld);
+
+    // Blinn-Phong standing in for a proper BRDF, rougher surfaces get a wider, dimmer highlight
+    float shininess = mix(256.0, 2.0, clamp(material.roughness, 0.0, 1.0));
+
+    if (ubo.directionalLightColor.w > 0.0) {
+        vec3 directionalLightColor = ubo.directionalLightColor.xyz * ubo.directionalLightColor.w;
+        vec3 directionToLight = -normalize(ubo.directionalLightDirection.xyz);
+        float cosAngIncidence = max(dot(surfaceNormal, directionToLight), 0);
+        vec3 halfAngle = normalize(directionToLight + viewDirection);
+        float blinn = pow(max(dot(surfaceNormal, halfAngle), 0), shininess);
+
+        diffuseLight += directionalLightColor * cosAngIncidence;
+        specularLight += directionalLightColor * blinn * float(cosAngIncidence > 0.0);
+    }
+
+    for (int i = 0; i < ubo.numLights; i++) {
+        PointLight light = ubo.pointLights[i];
+        vec3 directionToLight = light.position.xyz - fragPosWorld;
+        float attenuation = 1.0 / dot(directionToLight, directionToLight); // 1/r^2
+        directionToLight = normalize(directionToLight);
+        float cosAngIncidence = max(dot(surfaceNormal, directionToLight), 0);
+        vec3 intensity = light.color.xyz * light.color.w * attenuation;
+        vec3 halfAngle = normalize(directionToLight + viewDirection);
+        float blinn = pow(max(dot(surfaceNormal, halfAngle), 0), shininess);
+
+        diffuseLight += intensity * cosAngIncidence;
+        specularLight += intensity * blinn * float(cosAngIncidence > 0.0);
+    }
+
+    vec3 vertexColor = USE_VERTEX_COLOR ? fragColor : vec3(1.0);
+    vec3 albedo = material.albedo.xyz * vertexColor;
+
+    // Metals have no diffuse, and tint their reflections with their albedo
+    vec3 diffuseColor = albedo * (1.0 - material.metallic);
+    vec3 specularColor = mix(DIELECTRIC_SPECULAR, albedo, material.metallic);
+
+    vec3 color = diffuseLight * diffuseColor + specularLight * specularColor;
+
+    outColor = vec4(applyFog(color, ubo.fog, ubo.nearPlane, ubo.farPlane), 1.0);
+}
\ No newline at end of file
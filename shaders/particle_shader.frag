@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) in vec2 fragOffset;
+layout(location = 1) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    // A soft round dot rather than a square
+    float falloff = 1.0 - dot(fragOffset, fragOffset);
+
+    if (falloff <= 0.0) {
+        discard;
+    }
+
+    // Blended additively, so alpha only scales how much is added
+    outColor = vec4(fragColor.rgb * fragColor.a * falloff, 1.0);
+}
@@ -0,0 +1,36 @@
+#version 450
+
+// One instance per particle, the billboard corners come from the vertex index
+layout(location = 0) in vec4 positionSize; // xyz is the world position, w the size
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec2 fragOffset;
+layout(location = 1) out vec4 fragColor;
+
+layout(set = 0, binding = 0) uniform GlobalUbo {
+    mat4 projectionViewMatrix;
+    mat4 inverseViewMatrix; // columns 0 and 1 are the camera's right and up
+} ubo;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, -1.0),
+    vec2(1.0, -1.0),
+    vec2(1.0, 1.0),
+    vec2(-1.0, -1.0),
+    vec2(1.0, 1.0),
+    vec2(-1.0, 1.0)
+);
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex];
+
+    // Facing the camera, so the quad is never seen edge on
+    vec3 right = ubo.inverseViewMatrix[0].xyz;
+    vec3 up = ubo.inverseViewMatrix[1].xyz;
+    vec3 position = positionSize.xyz + (right * corner.x + up * corner.y) * positionSize.w;
+
+    gl_Position = ubo.projectionViewMatrix * vec4(position, 1.0);
+
+    fragOffset = corner;
+    fragColor = color;
+}
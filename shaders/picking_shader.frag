@@ -0,0 +1,12 @@
+#version 450
+
+// R32_UINT attachment, 0 means no object
+layout(location = 0) out uint outObjectId;
+
+layout(push_constant) uniform Push {
+    layout(offset = 64) uint objectId; // the game object's id + 1
+} push;
+
+void main() {
+    outObjectId = push.objectId;
+}
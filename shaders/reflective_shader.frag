@@ -0,0 +1,76 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "include/fog.glsl"
+
+layout (location = 0) in vec3 fragColor;
+layout (location = 1) in vec3 fragPosWorld;
+layout (location = 2) in vec3 fragNormalWorld;
+
+layout (location = 0) out vec4 outColor;
+
+// Set from the pipeline, when false the mesh's vertex colors are ignored
+layout (constant_id = 0) const bool USE_VERTEX_COLOR = true;
+
+#define MAX_LIGHTS 10 // Must match MAX_LIGHTS in lve_frameinfo.rs
+
+struct PointLight {
+    vec4 position; // ignore w
+    vec4 color; // w is intensity
+};
+
+layout(set = 0, binding = 0) uniform GlobalUbo {
+    mat4 projectionViewMatrix;
+    mat4 inverseViewMatrix; // column 3 is the camera position
+    vec4 ambientLightColor; // w is intensity
+    vec4 directionalLightDirection; // ignore w
+    vec4 directionalLightColor; // w is intensity
+    PointLight pointLights[MAX_LIGHTS];
+    int numLights;
+    float nearPlane;
+    float farPlane;
+    FogParams fog;
+} ubo;
+
+// Shared with any other pass that samples the environment, see ReflectiveRenderSystem
+layout(set = 1, binding = 0) uniform samplerCube environmentMap;
+
+// Only the color, column 3 of the normal matrix. The rest of the matrices are vertex only
+layout(push_constant) uniform Push {
+    layout(offset = 112) vec4 color; // rgb is the object color, w is the reflectivity
+} push;
+
+void main() {
+    vec3 diffuseLight = ubo.ambientLightColor.xyz * ubo.ambientLightColor.w;
+    vec3 surfaceNormal = normalize(fragNormalWorld);
+
+    if (ubo.directionalLightColor.w > 0.0) {
+        vec3 directionalLightColor = ubo.directionalLightColor.xyz * ubo.directionalLightColor.w;
+        vec3 directionToLight = -normalize(ubo.directionalLightDirection.xyz);
+        diffuseLight += directionalLightColor * max(dot(surfaceNormal, directionToLight), 0);
+    }
+
+    for (int i = 0; i < ubo.numLights; i++) {
+        PointLight light = ubo.pointLights[i];
+        vec3 directionToLight = light.position.xyz - fragPosWorld;
+        float attenuation = 1.0 / dot(directionToLight, directionToLight); // 1/r^2
+        float cosAngIncidence = max(dot(surfaceNormal, normalize(directionToLight)), 0);
+        vec3 intensity = light.color.xyz * light.color.w * attenuation;
+
+        diffuseLight += intensity * cosAngIncidence;
+    }
+
+    vec3 vertexColor = USE_VERTEX_COLOR ? fragColor : vec3(1.0);
+    vec3 objectColor = push.color.xyz;
+    float reflectivity = push.color.w;
+
+    vec3 baseColor = diffuseLight * vertexColor * objectColor;
+
+    vec3 cameraPosWorld = ubo.inverseViewMatrix[3].xyz;
+    vec3 viewDirection = normalize(fragPosWorld - cameraPosWorld);
+    vec3 environmentColor = texture(environmentMap, reflect(viewDirection, surfaceNormal)).rgb;
+
+    vec3 color = mix(baseColor, environmentColor, reflectivity);
+
+    outColor = vec4(applyFog(color, ubo.fog, ubo.nearPlane, ubo.farPlane), 1.0);
+}
\ No newline at end of file
@@ -9,16 +9,29 @@ layout(location = 0) out vec3 fragColor;
 layout(location = 1) out vec3 fragPosWorld;
 layout(location = 2) out vec3 fragNormalWorld;
 
+// Keeps the depth identical to depth_prepass.vert when the depth pre-pass is enabled
+invariant gl_Position;
+
+#define MAX_LIGHTS 10 // Must match MAX_LIGHTS in lve_frameinfo.rs
+
+struct PointLight {
+    vec4 position; // ignore w
+    vec4 color; // w is intensity
+};
+
 layout(set = 0, binding = 0) uniform GlobalUbo {
     mat4 projectionViewMatrix;
-    vec4 ambientLightColor;
-    vec4 lightPosition;
-    vec4 lightColor;
+    mat4 inverseViewMatrix; // column 3 is the camera position
+    vec4 ambientLightColor; // w is intensity
+    vec4 directionalLightDirection; // ignore w
+    vec4 directionalLightColor; // w is intensity
+    PointLight pointLights[MAX_LIGHTS];
+    int numLights;
 } ubo;
 
 layout(push_constant) uniform Push {
     mat4 modelMatrix; // projection * view * model
-    mat4 normalMatrix;
+    mat4 normalMatrix; // only the upper 3x3 is used, column 3 is the fragment shader's color
 } push;
 
 void main() {
@@ -0,0 +1,46 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 color;
+layout(location = 2) in vec3 normal;
+layout(location = 3) in vec2 uv;
+
+layout(location = 0) out vec3 fragColor;
+layout(location = 1) out vec3 fragPosWorld;
+layout(location = 2) out vec3 fragNormalWorld;
+
+// Keeps the depth identical to depth_prepass.vert when the depth pre-pass is enabled
+invariant gl_Position;
+
+#define MAX_LIGHTS 10 // Must match MAX_LIGHTS in lve_frameinfo.rs
+
+struct PointLight {
+    vec4 position; // ignore w
+    vec4 color; // w is intensity
+};
+
+layout(set = 0, binding = 0) uniform GlobalUbo {
+    mat4 projectionViewMatrix;
+    mat4 inverseViewMatrix; // column 3 is the camera position
+    vec4 ambientLightColor; // w is intensity
+    vec4 directionalLightDirection; // ignore w
+    vec4 directionalLightColor; // w is intensity
+    PointLight pointLights[MAX_LIGHTS];
+    int numLights;
+} ubo;
+
+// The same matrices simple_shader.vert gets from push constants, bound with a dynamic offset
+// for each object so there is room for more per object data
+layout(set = 1, binding = 0) uniform ObjectUbo {
+    mat4 modelMatrix;
+    mat4 normalMatrix; // only the upper 3x3 is used, the color is in the push constants
+} object;
+
+void main() {
+    vec4 positionWorld = object.modelMatrix * vec4(position, 1.0);
+    gl_Position = ubo.projectionViewMatrix * positionWorld;
+
+    fragNormalWorld = normalize(mat3(object.normalMatrix) * normal);
+    fragPosWorld = positionWorld.xyz;
+    fragColor = color;
+}
\ No newline at end of file
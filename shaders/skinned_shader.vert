@@ -0,0 +1,59 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 color;
+layout(location = 2) in vec3 normal;
+layout(location = 3) in vec2 uv;
+layout(location = 4) in uvec4 jointIndices;
+layout(location = 5) in vec4 jointWeights;
+
+layout(location = 0) out vec3 fragColor;
+layout(location = 1) out vec3 fragPosWorld;
+layout(location = 2) out vec3 fragNormalWorld;
+
+#define MAX_LIGHTS 10 // Must match MAX_LIGHTS in lve_frameinfo.rs
+#define MAX_BONES 64 // Must match MAX_BONES in skinning.rs
+
+struct PointLight {
+    vec4 position; // ignore w
+    vec4 color; // w is intensity
+};
+
+layout(set = 0, binding = 0) uniform GlobalUbo {
+    mat4 projectionViewMatrix;
+    mat4 inverseViewMatrix; // column 3 is the camera position
+    vec4 ambientLightColor; // w is intensity
+    vec4 directionalLightDirection; // ignore w
+    vec4 directionalLightColor; // w is intensity
+    PointLight pointLights[MAX_LIGHTS];
+    int numLights;
+} ubo;
+
+layout(push_constant) uniform Push {
+    mat4 modelMatrix; // projection * view * model
+    mat4 normalMatrix; // only the upper 3x3 is used, column 3 is the fragment shader's color
+} push;
+
+// Each bone takes a vertex from the bind pose to where its joint has moved it, bound with a
+// dynamic offset for each object
+layout(set = 1, binding = 0) uniform BoneMatrices {
+    mat4 bones[MAX_BONES];
+} skin;
+
+void main() {
+    // Linear blend skinning, the weights sum to 1
+    mat4 skinMatrix = jointWeights.x * skin.bones[jointIndices.x]
+        + jointWeights.y * skin.bones[jointIndices.y]
+        + jointWeights.z * skin.bones[jointIndices.z]
+        + jointWeights.w * skin.bones[jointIndices.w];
+
+    vec4 positionWorld = push.modelMatrix * skinMatrix * vec4(position, 1.0);
+    gl_Position = ubo.projectionViewMatrix * positionWorld;
+
+    // Assumes the bones don't scale unevenly, as the normal matrix would need inverting
+    vec3 normalModel = mat3(skinMatrix) * normal;
+
+    fragNormalWorld = normalize(mat3(push.normalMatrix) * normalModel);
+    fragPosWorld = positionWorld.xyz;
+    fragColor = color;
+}
@@ -0,0 +1,14 @@
+#version 450
+
+layout(location = 0) in vec2 fragUv;
+layout(location = 1) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+layout(set = 0, binding = 0) uniform sampler2D fontAtlas;
+
+void main() {
+    // The atlas stores glyph coverage in the alpha channel
+    float coverage = texture(fontAtlas, fragUv).a;
+    outColor = vec4(fragColor.rgb, fragColor.a * coverage);
+}
@@ -0,0 +1,34 @@
+#version 450
+
+// One instance per glyph, the quad corners come from the vertex index
+layout(location = 0) in vec4 rect; // x, y, width, height in pixels
+layout(location = 1) in vec4 uvRect; // u0, v0, u1, v1
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec2 fragUv;
+layout(location = 1) out vec4 fragColor;
+
+layout(push_constant) uniform Push {
+    vec2 screenSize;
+} push;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(0.0, 0.0),
+    vec2(1.0, 0.0),
+    vec2(1.0, 1.0),
+    vec2(0.0, 0.0),
+    vec2(1.0, 1.0),
+    vec2(0.0, 1.0)
+);
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex];
+    vec2 pixel = rect.xy + corner * rect.zw;
+
+    // Orthographic projection from pixels to clip space. Vulkan's y axis already points
+    // down, so a top left origin maps straight across
+    gl_Position = vec4(pixel / push.screenSize * 2.0 - 1.0, 0.0, 1.0);
+
+    fragUv = mix(uvRect.xy, uvRect.zw, corner);
+    fragColor = color;
+}
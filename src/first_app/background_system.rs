@@ -0,0 +1,153 @@
+use super::lve_device::*;
+use super::lve_frameinfo::FrameInfo;
+use super::lve_pipeline::*;
+use super::render_system::RenderSystem;
+
+use ash::{vk, Device};
+
+use std::mem::size_of;
+use std::rc::Rc;
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct BackgroundPushConstantData {
+    _top_color: [f32; 4],
+    _bottom_color: [f32; 4],
+}
+
+impl BackgroundPushConstantData {
+    pub unsafe fn as_bytes(&self) -> &[u8] {
+        let size_in_bytes = size_of::<Self>();
+        let start_ptr = self as *const Self as *const u8;
+        std::slice::from_raw_parts(start_ptr, size_in_bytes)
+    }
+}
+
+/// Fills the viewport with a vertical gradient by drawing a single full screen triangle, with
+/// no vertex buffer and without touching the depth buffer. Register it in the background stage
+/// so the scene draws over it
+pub struct BackgroundSystem {
+    lve_device: Rc<LveDevice>,
+    lve_pipeline: LvePipeline,
+    pipeline_layout: vk::PipelineLayout,
+    top_color: [f32; 3],
+    bottom_color: [f32; 3],
+}
+
+impl BackgroundSystem {
+    /// The colours are linear RGB, at the top and bottom of the viewport
+    pub fn new(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        top_color: [f32; 3],
+        bottom_color: [f32; 3],
+    ) -> Self {
+        let pipeline_layout = Self::create_pipeline_layout(&lve_device.device);
+
+        let lve_pipeline =
+            Self::create_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout);
+
+        Self {
+            lve_device,
+            lve_pipeline,
+            pipeline_layout,
+            top_color,
+            bottom_color,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn set_colors(&mut self, top_color: [f32; 3], bottom_color: [f32; 3]) {
+        self.top_color = top_color;
+        self.bottom_color = bottom_color;
+    }
+
+    fn create_pipeline(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        pipeline_layout: &vk::PipelineLayout,
+    ) -> LvePipeline {
+        assert!(
+            pipeline_layout != &vk::PipelineLayout::null(),
+            "Cannot create pipeline before pipeline layout"
+        );
+
+        // Everything else is drawn over the background
+        let mut pipeline_config = LvePipeline::no_depth_pipeline_config_info();
+
+        // The triangle's corners come from the vertex index
+        pipeline_config.binding_descriptions = Vec::new();
+        pipeline_config.attribute_descriptions = Vec::new();
+
+        LvePipeline::new(
+            lve_device,
+            "shaders/background_shader.vert.spv",
+            "shaders/background_shader.frag.spv",
+            pipeline_config,
+            render_pass,
+            pipeline_layout,
+        )
+    }
+
+    fn create_pipeline_layout(device: &Device) -> vk::PipelineLayout {
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(size_of::<BackgroundPushConstantData>() as u32)
+            .build();
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .push_constant_ranges(&[push_constant_range])
+            .build();
+
+        unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .map_err(|e| log::error!("Unable to create background pipeline layout: {}", e))
+                .unwrap()
+        }
+    }
+}
+
+impl RenderSystem for BackgroundSystem {
+    fn render(&mut self, frame_info: &FrameInfo) {
+        let [top_r, top_g, top_b] = self.top_color;
+        let [bottom_r, bottom_g, bottom_b] = self.bottom_color;
+
+        let push = BackgroundPushConstantData {
+            _top_color: [top_r, top_g, top_b, 1.0],
+            _bottom_color: [bottom_r, bottom_g, bottom_b, 1.0],
+        };
+
+        unsafe {
+            self.lve_pipeline
+                .bind(&self.lve_device.device, frame_info.command_buffer);
+            frame_info.record_pipeline_bind();
+
+            self.lve_device.device.cmd_push_constants(
+                frame_info.command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                push.as_bytes(),
+            );
+
+            self.lve_device
+                .device
+                .cmd_draw(frame_info.command_buffer, 3, 1, 0, 0);
+            frame_info.record_draw(1);
+        }
+    }
+}
+
+impl Drop for BackgroundSystem {
+    fn drop(&mut self) {
+        log::debug!("Dropping BackgroundSystem");
+
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+extern crate nalgebra as na;
+
+/// Frames rendered before timing starts, so pipeline creation and first use costs don't skew
+/// the results
+const WARMUP_FRAMES: u32 = 10;
+/// Distance from the centre of the scene the camera orbits at
+const ORBIT_RADIUS: f32 = 2.5;
+const ORBIT_HEIGHT: f32 = -0.5;
+
+/// How long the app took to start, in seconds, reported along with the frame times so startup
+/// changes (e.g. `MODEL_LOADING_THREADS`) can be compared between runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StartupTimes {
+    /// Loading the starting scene's models, parsing and uploading
+    pub scene_load: f32,
+    /// From creating the device to submitting the first frame, the scene load included
+    pub first_frame: f32,
+}
+
+/// Renders a fixed number of frames along a fixed camera path, then reports frame time stats.
+/// The camera only depends on the frame number, so every run draws the same frames
+pub struct Benchmark {
+    frame_count: u32,
+    frames_rendered: u32,
+    frame_times: Vec<f32>,
+    cpu_times: Vec<f32>,
+    /// Empty when the device can't count them
+    fragment_invocations: Vec<u64>,
+    startup: Option<StartupTimes>,
+}
+
+impl Benchmark {
+    pub fn new(frame_count: u32) -> Self {
+        Self {
+            frame_count,
+            frames_rendered: 0,
+            frame_times: Vec::with_capacity(frame_count as usize),
+            cpu_times: Vec::with_capacity(frame_count as usize),
+            fragment_invocations: Vec::with_capacity(frame_count as usize),
+            startup: None,
+        }
+    }
+
+    /// Only the first startup is kept, recovering from a lost device isn't one
+    pub fn record_startup(&mut self, startup: StartupTimes) {
+        self.startup.get_or_insert(startup);
+    }
+
+    /// The translation and rotation of the camera for the next frame. One orbit around the
+    /// scene over the timed frames, always looking at the centre
+    pub fn camera_transform(&self) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        let timed_frame = self.frames_rendered.saturating_sub(WARMUP_FRAMES);
+        let angle = std::f32::consts::TAU * timed_frame as f32 / self.frame_count.max(1) as f32;
+
+        let translation = na::vector![
+            ORBIT_RADIUS * angle.sin(),
+            ORBIT_HEIGHT,
+            -ORBIT_RADIUS * angle.cos()
+        ];
+
+        // Pitch down towards the centre of the scene
+        let pitch = (ORBIT_HEIGHT / ORBIT_RADIUS).atan();
+
+        (translation, na::vector![pitch, -angle, 0.0])
+    }
+
+    /// `frame_time` is the time since the previous frame and `cpu_time` the time spent
+    /// recording and submitting this one, both in seconds. `fragment_invocations` is from
+    /// `RenderStats`
+    pub fn record_frame(
+        &mut self,
+        frame_time: f32,
+        cpu_time: f32,
+        fragment_invocations: Option<u64>,
+    ) {
+        if self.frames_rendered >= WARMUP_FRAMES {
+            self.frame_times.push(frame_time);
+            self.cpu_times.push(cpu_time);
+            self.fragment_invocations.extend(fragment_invocations);
+        }
+
+        self.frames_rendered += 1;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frames_rendered >= self.frame_count + WARMUP_FRAMES
+    }
+
+    /// The results as a single line of JSON, times in milliseconds. There are no GPU timestamp
+    /// queries yet, so `gpu_time_ms` is always null. `fragment_invocations` is the average per
+    /// frame, or null if the device can't count them. Comparing it between runs with and
+    /// without `depth_prepass` gives the overdraw the pre-pass saves. `startup_ms` is null if
+    /// no startup was recorded
+    pub fn report_json(&self, depth_prepass: bool) -> String {
+        format!(
+            "{{\"frames\":{},\"frame_time_ms\":{},\"cpu_time_ms\":{},\"gpu_time_ms\":null,\
+             \"depth_prepass\":{},\"fragment_invocations\":{},\"startup_ms\":{}}}",
+            self.frame_times.len(),
+            FrameTimeStats::new(&self.frame_times).to_json(),
+            FrameTimeStats::new(&self.cpu_times).to_json(),
+            depth_prepass,
+            self.average_fragment_invocations()
+                .map_or("null".to_string(), |average| average.to_string()),
+            self.startup.map_or("null".to_string(), |startup| format!(
+                "{{\"scene_load\":{:.3},\"first_frame\":{:.3}}}",
+                startup.scene_load * 1000.0,
+                startup.first_frame * 1000.0
+            )),
+        )
+    }
+
+    fn average_fragment_invocations(&self) -> Option<u64> {
+        if self.fragment_invocations.is_empty() {
+            return None;
+        }
+
+        Some(self.fragment_invocations.iter().sum::<u64>() / self.fragment_invocations.len() as u64)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct FrameTimeStats {
+    min: f32,
+    avg: f32,
+    max: f32,
+    p99: f32,
+}
+
+impl FrameTimeStats {
+    /// Takes times in seconds, the stats are in milliseconds
+    fn new(times: &[f32]) -> Self {
+        if times.is_empty() {
+            return Self {
+                min: 0.0,
+                avg: 0.0,
+                max: 0.0,
+                p99: 0.0,
+            };
+        }
+
+        let mut sorted: Vec<f32> = times.iter().map(|time| time * 1000.0).collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+
+        // The smallest time at least 99% of frames were at or under
+        let p99_index = ((sorted.len() as f32 * 0.99).ceil() as usize).max(1) - 1;
+
+        Self {
+            min: sorted[0],
+            avg: sorted.iter().sum::<f32>() / sorted.len() as f32,
+            max: sorted[sorted.len() - 1],
+            p99: sorted[p99_index],
+        }
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"min\":{:.3},\"avg\":{:.3},\"max\":{:.3},\"p99\":{:.3}}}",
+            self.min, self.avg, self.max, self.p99
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_are_in_milliseconds() {
+        let stats = FrameTimeStats::new(&[0.001, 0.002, 0.003]);
+
+        assert!((stats.min - 1.0).abs() < 1e-4);
+        assert!((stats.avg - 2.0).abs() < 1e-4);
+        assert!((stats.max - 3.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn p99_ignores_the_slowest_percent() {
+        let mut times = vec![0.001; 99];
+        times.push(0.1);
+
+        let stats = FrameTimeStats::new(&times);
+
+        assert!((stats.p99 - 1.0).abs() < 1e-4);
+        assert!((stats.max - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn warmup_frames_are_not_timed() {
+        let mut benchmark = Benchmark::new(5);
+
+        while !benchmark.is_finished() {
+            benchmark.record_frame(0.01, 0.005, None);
+        }
+
+        assert_eq!(benchmark.frame_times.len(), 5);
+        assert_eq!(benchmark.frames_rendered, 5 + WARMUP_FRAMES);
+    }
+
+    #[test]
+    fn fragment_invocations_are_averaged_over_the_timed_frames() {
+        let mut benchmark = Benchmark::new(2);
+
+        for _ in 0..WARMUP_FRAMES {
+            benchmark.record_frame(0.01, 0.005, Some(1_000_000));
+        }
+        benchmark.record_frame(0.01, 0.005, Some(100));
+        benchmark.record_frame(0.01, 0.005, Some(300));
+
+        assert!(benchmark.is_finished());
+        assert!(benchmark
+            .report_json(true)
+            .contains("\"depth_prepass\":true,\"fragment_invocations\":200,"));
+        assert!(Benchmark::new(2)
+            .report_json(false)
+            .contains("\"fragment_invocations\":null,"));
+    }
+
+    #[test]
+    fn only_the_first_startup_is_reported() {
+        let mut benchmark = Benchmark::new(1);
+        assert!(benchmark
+            .report_json(false)
+            .ends_with("\"startup_ms\":null}"));
+
+        benchmark.record_startup(StartupTimes {
+            scene_load: 0.25,
+            first_frame: 1.5,
+        });
+        benchmark.record_startup(StartupTimes {
+            scene_load: 1.0,
+            first_frame: 2.0,
+        });
+
+        assert!(benchmark
+            .report_json(false)
+            .ends_with("\"startup_ms\":{\"scene_load\":250.000,\"first_frame\":1500.000}}"));
+    }
+}
@@ -0,0 +1,123 @@
+extern crate nalgebra as na;
+
+/// How much trauma is lost per second
+const TRAUMA_DECAY: f32 = 1.0;
+/// Phase offsets so each axis follows a different, uncorrelated looking curve
+const AXIS_SEEDS: [f32; 6] = [0.0, 17.3, 41.9, 73.1, 101.7, 137.5];
+
+/// Shakes the camera by an offset on top of the viewer's transform. Trauma (0 to 1) is added
+/// by events and decays over time; the shake scales with trauma squared so small knocks are
+/// subtle and big hits are violent. The offsets come from sums of sine waves rather than
+/// random numbers, so the camera moves smoothly instead of jittering
+pub struct CameraShake {
+    trauma: f32,
+    /// Roughly how many times a second the shake changes direction
+    frequency: f32,
+    /// The translation at full trauma
+    max_offset: na::Vector3<f32>,
+    /// The rotation (radians) at full trauma
+    max_rotation: na::Vector3<f32>,
+    time: f32,
+}
+
+impl CameraShake {
+    pub fn new(
+        frequency: f32,
+        max_offset: na::Vector3<f32>,
+        max_rotation: na::Vector3<f32>,
+    ) -> Self {
+        Self {
+            trauma: 0.0,
+            frequency,
+            max_offset,
+            max_rotation,
+            time: 0.0,
+        }
+    }
+
+    /// Adds to the current trauma, capped at 1
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).clamp(0.0, 1.0);
+    }
+
+    pub fn update(&mut self, frame_time: f32) {
+        self.time += frame_time;
+        self.trauma = (self.trauma - TRAUMA_DECAY * frame_time).max(0.0);
+    }
+
+    /// The (translation, rotation) to add to the viewer's transform this frame
+    pub fn offset(&self) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        let shake = self.trauma * self.trauma;
+
+        if shake == 0.0 {
+            return (na::Vector3::zeros(), na::Vector3::zeros());
+        }
+
+        let noise =
+            |axis: usize| shake * smooth_noise(self.time * self.frequency, AXIS_SEEDS[axis]);
+
+        (
+            self.max_offset
+                .component_mul(&na::vector![noise(0), noise(1), noise(2)]),
+            self.max_rotation
+                .component_mul(&na::vector![noise(3), noise(4), noise(5)]),
+        )
+    }
+}
+
+/// A smooth value in -1 to 1. Three sine waves at unrelated frequencies never quite repeat,
+/// which is enough to look random at camera shake speeds
+fn smooth_noise(t: f32, seed: f32) -> f32 {
+    let t = t + seed;
+
+    (t.sin() * 0.5 + (t * 2.31).sin() * 0.3 + (t * 4.73).sin() * 0.2).clamp(-1.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera_shake() -> CameraShake {
+        CameraShake::new(
+            10.0,
+            na::vector![0.1, 0.1, 0.1],
+            na::vector![0.05, 0.05, 0.05],
+        )
+    }
+
+    #[test]
+    fn no_trauma_means_no_offset() {
+        let mut shake = camera_shake();
+        shake.update(0.5);
+
+        assert_eq!(shake.offset(), (na::Vector3::zeros(), na::Vector3::zeros()));
+    }
+
+    #[test]
+    fn trauma_decays_to_nothing() {
+        let mut shake = camera_shake();
+        shake.add_trauma(2.0);
+        assert_eq!(shake.trauma, 1.0);
+
+        for _ in 0..100 {
+            shake.update(1.0 / 60.0);
+        }
+
+        assert_eq!(shake.trauma, 0.0);
+        assert_eq!(shake.offset().0, na::Vector3::zeros());
+    }
+
+    #[test]
+    fn offset_changes_smoothly() {
+        let mut shake = camera_shake();
+        shake.add_trauma(1.0);
+
+        let (before, _) = shake.offset();
+        shake.time += 0.001;
+        let (after, _) = shake.offset();
+
+        // At 10Hz a millisecond is a tiny fraction of a cycle
+        assert!((after - before).norm() < 0.01);
+        assert!(before.amax() <= 0.1);
+    }
+}
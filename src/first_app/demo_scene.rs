@@ -0,0 +1,96 @@
+use super::lve_game_object::TransformComponent;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::f32::consts::PI;
+
+extern crate nalgebra as na;
+
+/// The models demo scene objects are picked from, so however many objects there are only a
+/// few models are loaded
+pub const DEMO_SCENE_MODELS: [&str; 3] = [
+    "models/smooth_vase.obj",
+    "models/flat_vase.obj",
+    "models/cube.obj",
+];
+
+/// Objects are scattered over a square this far either side of the origin, on the floor
+const DEMO_SCENE_HALF_EXTENT: f32 = 20.0;
+const DEMO_SCENE_FLOOR_HEIGHT: f32 = 0.5;
+const DEMO_SCENE_MIN_SCALE: f32 = 0.5;
+const DEMO_SCENE_MAX_SCALE: f32 = 2.0;
+
+/// One object of a generated demo scene
+pub struct DemoSceneObject {
+    pub model_path: &'static str,
+    pub transform: TransformComponent,
+}
+
+/// `count` objects standing on the floor at random positions, sizes and headings. The same
+/// seed always gives the same scene, for as long as the `rand` version in use keeps
+/// `StdRng`'s output the same
+pub fn generate(count: usize, seed: u64) -> Vec<DemoSceneObject> {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    (0..count)
+        .map(|_| {
+            let model_path = DEMO_SCENE_MODELS[rng.gen_range(0..DEMO_SCENE_MODELS.len())];
+
+            let translation = na::vector![
+                rng.gen_range(-DEMO_SCENE_HALF_EXTENT..DEMO_SCENE_HALF_EXTENT),
+                DEMO_SCENE_FLOOR_HEIGHT,
+                rng.gen_range(-DEMO_SCENE_HALF_EXTENT..DEMO_SCENE_HALF_EXTENT)
+            ];
+            let scale = rng.gen_range(DEMO_SCENE_MIN_SCALE..DEMO_SCENE_MAX_SCALE);
+            let yaw = rng.gen_range(0.0..2.0 * PI);
+
+            DemoSceneObject {
+                model_path,
+                transform: TransformComponent::new(
+                    translation,
+                    na::Vector3::repeat(scale),
+                    na::vector![0.0, yaw, 0.0],
+                ),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(objects: &[DemoSceneObject]) -> Vec<(&str, [f32; 7])> {
+        objects
+            .iter()
+            .map(|object| {
+                let translation = object.transform.translation();
+                let scale = object.transform.scale();
+                let rotation = object.transform.rotation();
+
+                (
+                    object.model_path,
+                    [
+                        translation[0],
+                        translation[1],
+                        translation[2],
+                        scale[0],
+                        scale[1],
+                        scale[2],
+                        rotation[1],
+                    ],
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_same_seed_generates_the_same_scene() {
+        let scene = generate(50, 7);
+
+        assert_eq!(scene.len(), 50);
+        assert_eq!(summary(&scene), summary(&generate(50, 7)));
+        assert_ne!(summary(&scene), summary(&generate(50, 8)));
+    }
+}
@@ -0,0 +1,151 @@
+use super::lve_device::*;
+use super::lve_frameinfo::FrameInfo;
+use super::lve_pipeline::*;
+use super::render_system::RenderSystem;
+use super::simple_render_system::SimplePushConstantData;
+
+use ash::{vk, Device};
+
+use std::rc::Rc;
+
+/// Fills the depth buffer with every opaque object before the colour pass runs. Used together
+/// with a `SimpleRenderSystem` created with `depth_prepass` set, which then only shades the
+/// closest fragment of each pixel.
+///
+/// Both passes are recorded into the same render pass instance, so the depth attachment is
+/// only cleared once at the start of the frame and the pre-pass results carry over
+pub struct DepthPrepassSystem {
+    lve_device: Rc<LveDevice>,
+    lve_pipeline: LvePipeline,
+    pipeline_layout: vk::PipelineLayout,
+    push_constant_layout: PushConstantLayout,
+}
+
+impl DepthPrepassSystem {
+    pub fn new(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        global_set_layout: vk::DescriptorSetLayout,
+    ) -> Self {
+        // Only the vertex shader runs, so the fragment region of the push constants is dropped
+        let push_constant_layout = SimplePushConstantData::vertex_push_constant_layout(&lve_device);
+
+        let pipeline_layout = Self::create_pipeline_layout(
+            &lve_device.device,
+            global_set_layout,
+            &push_constant_layout,
+        );
+
+        let lve_pipeline =
+            Self::create_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout);
+
+        Self {
+            lve_device,
+            lve_pipeline,
+            pipeline_layout,
+            push_constant_layout,
+        }
+    }
+
+    fn create_pipeline(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        pipeline_layout: &vk::PipelineLayout,
+    ) -> LvePipeline {
+        assert!(
+            pipeline_layout != &vk::PipelineLayout::null(),
+            "Cannot create pipeline before pipeline layout"
+        );
+
+        let mut pipeline_config = LvePipeline::default_pipline_config_info();
+
+        LvePipeline::disable_color_writes(&mut pipeline_config);
+
+        LvePipeline::new_vertex_only(
+            lve_device,
+            "shaders/depth_prepass.vert.spv",
+            pipeline_config,
+            render_pass,
+            pipeline_layout,
+        )
+    }
+
+    fn create_pipeline_layout(
+        device: &Device,
+        global_set_layout: vk::DescriptorSetLayout,
+        push_constant_layout: &PushConstantLayout,
+    ) -> vk::PipelineLayout {
+        let descriptor_set_layouts = vec![global_set_layout];
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(descriptor_set_layouts.as_slice())
+            .push_constant_ranges(push_constant_layout.ranges())
+            .build();
+
+        unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .map_err(|e| log::error!("Unable to create depth pre-pass pipeline layout: {}", e))
+                .unwrap()
+        }
+    }
+}
+
+impl RenderSystem for DepthPrepassSystem {
+    fn render(&mut self, frame_info: &FrameInfo) {
+        unsafe {
+            self.lve_pipeline
+                .bind(&self.lve_device.device, frame_info.command_buffer);
+            frame_info.record_pipeline_bind();
+
+            self.lve_device.device.cmd_bind_descriptor_sets(
+                frame_info.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[frame_info.global_descriptor_set],
+                &[],
+            );
+        };
+
+        for (_, game_obj) in frame_info.game_objects.iter() {
+            // Wireframes don't cover the pixels their depth would hide, and skinned objects
+            // would only write the depth of their rest pose
+            if !game_obj.visible
+                || game_obj.point_light.is_some()
+                || game_obj.pipeline_tag == PipelineTag::Wireframe
+                || game_obj.skin.is_some()
+            {
+                continue;
+            }
+
+            let push = SimplePushConstantData::from_game_object(game_obj);
+
+            unsafe {
+                self.push_constant_layout.push(
+                    &self.lve_device.device,
+                    frame_info.command_buffer,
+                    self.pipeline_layout,
+                    push.as_bytes(),
+                );
+
+                let model = game_obj.model_at(frame_info.camera.position());
+                model.bind(&self.lve_device.device, frame_info.command_buffer);
+                model.draw(&self.lve_device.device, frame_info.command_buffer);
+                frame_info.record_draw(model.triangle_count());
+            }
+        }
+    }
+}
+
+impl Drop for DepthPrepassSystem {
+    fn drop(&mut self) {
+        log::debug!("Dropping DepthPrepassSystem");
+
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
@@ -0,0 +1,687 @@
+use super::lve_buffer::*;
+use super::lve_descriptors::*;
+use super::lve_device::*;
+use super::lve_frameinfo::FrameInfo;
+use super::lve_pipeline::*;
+use super::lve_swapchain::MAX_FRAMES_IN_FLIGHT;
+use super::lve_texture::*;
+use super::render_system::RenderSystem;
+
+use ash::{vk, Device};
+use egui::epaint::{ClippedMesh, Vertex};
+use winit::event::{
+    ElementState, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta, VirtualKeyCode,
+    WindowEvent,
+};
+
+use std::mem::size_of;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// Vertices each frame can hold, meshes past this are dropped with a warning
+const MAX_VERTICES: usize = 65536;
+const MAX_INDICES: usize = MAX_VERTICES * 3;
+/// How far one line of mouse wheel scrolls, in points
+const POINTS_PER_SCROLL_LINE: f32 = 50.0;
+
+#[derive(Debug)]
+pub struct EguiPushConstantData {
+    _screen_size: [f32; 2],
+}
+
+impl EguiPushConstantData {
+    pub unsafe fn as_bytes(&self) -> &[u8] {
+        let size_in_bytes = size_of::<Self>();
+        let start_ptr = self as *const Self as *const u8;
+        std::slice::from_raw_parts(start_ptr, size_in_bytes)
+    }
+}
+
+/// egui's font texture as uploaded for one frame in flight
+struct FontTexture {
+    version: u64,
+    _texture: Rc<LveTexture>,
+    descriptor_set: vk::DescriptorSet,
+}
+
+/// An immediate mode debug UI drawn with egui on top of the scene.
+///
+/// Each frame, window events go through `handle_window_event`, then the UI is built between
+/// `begin_frame` and `end_frame` and drawn by `render`. Clipboard and cursor icon requests
+/// from egui are ignored, and only egui's own font texture can be drawn
+pub struct EguiSystem {
+    lve_device: Rc<LveDevice>,
+    lve_pipeline: LvePipeline,
+    pipeline_layout: vk::PipelineLayout,
+    set_layout: Rc<LveDescriptorSetLayout>,
+    descriptor_pool: Rc<LveDescriptorPool>,
+    /// Replaced per frame in flight when egui's font texture changes, as the previous one may
+    /// still be in use by the other frames
+    font_textures: Vec<Option<FontTexture>>,
+    vertex_buffers: Vec<LveBuffer>,
+    index_buffers: Vec<LveBuffer>,
+    ctx: egui::CtxRef,
+    raw_input: egui::RawInput,
+    start_time: Instant,
+    modifiers: egui::Modifiers,
+    pointer_position: Option<egui::Pos2>,
+    pixels_per_point: f32,
+    viewport_offset: vk::Offset2D,
+    meshes: Vec<ClippedMesh>,
+}
+
+impl EguiSystem {
+    pub fn new(lve_device: Rc<LveDevice>, render_pass: &RenderPassInfo) -> Self {
+        let set_layout = LveDescriptorSetLayoutBuilder::new(Rc::clone(&lve_device))
+            .add_binding(
+                0,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::ShaderStageFlags::FRAGMENT,
+                1,
+            )
+            .build();
+
+        let descriptor_pool = LveDescriptorPoolBuilder::new(Rc::clone(&lve_device))
+            .set_max_sets(MAX_FRAMES_IN_FLIGHT as u32)
+            .add_pool_size(
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                MAX_FRAMES_IN_FLIGHT as u32,
+            )
+            .build();
+
+        let pipeline_layout =
+            Self::create_pipeline_layout(&lve_device.device, set_layout.descriptor_set_layout);
+
+        let lve_pipeline =
+            Self::create_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout);
+
+        let create_buffers = |instance_size: usize, instance_count: usize, usage, buffer_type| {
+            (0..MAX_FRAMES_IN_FLIGHT)
+                .map(|_| {
+                    let mut buffer = LveBuffer::new(
+                        Rc::clone(&lve_device),
+                        instance_size as u64,
+                        instance_count as u32,
+                        usage,
+                        vk::MemoryPropertyFlags::HOST_VISIBLE
+                            | vk::MemoryPropertyFlags::HOST_COHERENT,
+                        1,
+                        buffer_type,
+                    );
+
+                    unsafe { buffer.map(vk::WHOLE_SIZE, 0) };
+
+                    buffer
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let vertex_buffers = create_buffers(
+            size_of::<Vertex>(),
+            MAX_VERTICES,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            BufferType::Vertex,
+        );
+        let index_buffers = create_buffers(
+            size_of::<u32>(),
+            MAX_INDICES,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            BufferType::Index,
+        );
+
+        Self {
+            lve_device,
+            lve_pipeline,
+            pipeline_layout,
+            set_layout,
+            descriptor_pool,
+            font_textures: (0..MAX_FRAMES_IN_FLIGHT).map(|_| None).collect(),
+            vertex_buffers,
+            index_buffers,
+            ctx: egui::CtxRef::default(),
+            raw_input: egui::RawInput::default(),
+            start_time: Instant::now(),
+            modifiers: egui::Modifiers::default(),
+            pointer_position: None,
+            pixels_per_point: 1.0,
+            viewport_offset: vk::Offset2D::default(),
+            meshes: Vec::new(),
+        }
+    }
+
+    /// Passes a window event on to egui. Returns true if egui wants the input for itself (e.g.
+    /// a click on a window or typing in a text box), in which case the rest of the app should
+    /// ignore it. Releases are never captured, so nothing is left thinking a key is held, and
+    /// neither is the cursor moving, so the app always knows where the cursor is
+    pub fn handle_window_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::ModifiersChanged(state) => {
+                self.modifiers = Self::to_egui_modifiers(*state);
+                false
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = egui::pos2(
+                    (position.x as f32 - self.viewport_offset.x as f32) / self.pixels_per_point,
+                    (position.y as f32 - self.viewport_offset.y as f32) / self.pixels_per_point,
+                );
+
+                self.pointer_position = Some(position);
+                self.raw_input
+                    .events
+                    .push(egui::Event::PointerMoved(position));
+
+                false
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.pointer_position = None;
+                self.raw_input.events.push(egui::Event::PointerGone);
+                false
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let (position, button) =
+                    match (self.pointer_position, Self::to_egui_button(*button)) {
+                        (Some(position), Some(button)) => (position, button),
+                        _ => return false,
+                    };
+
+                let pressed = *state == ElementState::Pressed;
+
+                self.raw_input.events.push(egui::Event::PointerButton {
+                    pos: position,
+                    button,
+                    pressed,
+                    modifiers: self.modifiers,
+                });
+
+                pressed && self.ctx.wants_pointer_input()
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.raw_input.scroll_delta += match delta {
+                    MouseScrollDelta::LineDelta(x, y) => {
+                        egui::vec2(*x, *y) * POINTS_PER_SCROLL_LINE
+                    }
+                    MouseScrollDelta::PixelDelta(position) => {
+                        egui::vec2(position.x as f32, position.y as f32) / self.pixels_per_point
+                    }
+                };
+
+                self.ctx.wants_pointer_input()
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => {
+                let key = match Self::to_egui_key(*key) {
+                    Some(key) => key,
+                    None => return false,
+                };
+
+                let pressed = *state == ElementState::Pressed;
+
+                self.raw_input.events.push(egui::Event::Key {
+                    key,
+                    pressed,
+                    modifiers: self.modifiers,
+                });
+
+                pressed && self.ctx.wants_keyboard_input()
+            }
+            WindowEvent::ReceivedCharacter(character) => {
+                // Control characters arrive as key events instead
+                if character.is_control() {
+                    return false;
+                }
+
+                self.raw_input
+                    .events
+                    .push(egui::Event::Text(character.to_string()));
+
+                self.ctx.wants_keyboard_input()
+            }
+            WindowEvent::Focused(false) => {
+                self.modifiers = egui::Modifiers::default();
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Starts building this frame's UI, returning the context to build it with. `viewport` is
+    /// the area the UI covers in physical pixels, see `LveRenderer::get_viewport_rect`
+    pub fn begin_frame(&mut self, viewport: vk::Rect2D, pixels_per_point: f32) -> egui::CtxRef {
+        self.viewport_offset = viewport.offset;
+        self.pixels_per_point = pixels_per_point;
+
+        let mut raw_input = std::mem::take(&mut self.raw_input);
+
+        raw_input.screen_rect = Some(egui::Rect::from_min_size(
+            egui::Pos2::ZERO,
+            egui::vec2(viewport.extent.width as f32, viewport.extent.height as f32)
+                / pixels_per_point,
+        ));
+        raw_input.pixels_per_point = Some(pixels_per_point);
+        raw_input.time = Some(self.start_time.elapsed().as_secs_f64());
+        raw_input.modifiers = self.modifiers;
+
+        self.ctx.begin_frame(raw_input);
+
+        self.ctx.clone()
+    }
+
+    /// Finishes the UI started by `begin_frame`, ready for `render` to draw
+    pub fn end_frame(&mut self) {
+        let (_output, shapes) = self.ctx.end_frame();
+
+        self.meshes = self.ctx.tessellate(shapes);
+    }
+
+    /// Uploads egui's font texture for this frame if it has changed since the frame last used
+    /// it. The frame's previous submission has finished by now, so its old texture can go
+    fn update_font_texture(&mut self, frame_index: usize) -> vk::DescriptorSet {
+        let egui_texture = self.ctx.texture();
+
+        if let Some(font_texture) = &self.font_textures[frame_index] {
+            if font_texture.version == egui_texture.version {
+                return font_texture.descriptor_set;
+            }
+        }
+
+        log::debug!(
+            "Uploading {}x{} egui font texture for frame {}",
+            egui_texture.width,
+            egui_texture.height,
+            frame_index
+        );
+
+        let pixels: Vec<u8> = egui_texture
+            .pixels
+            .iter()
+            .flat_map(|&coverage| [255, 255, 255, coverage])
+            .collect();
+
+        let texture = LveTexture::from_pixels(
+            Rc::clone(&self.lve_device),
+            &pixels,
+            egui_texture.width as u32,
+            egui_texture.height as u32,
+            TextureFormat::Linear,
+            SamplerConfig {
+                address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                max_anisotropy: None,
+                max_lod: 0.0,
+                ..SamplerConfig::default()
+            },
+        );
+
+        let mut writer = LveDescriptorWriter::new(
+            Rc::clone(&self.set_layout),
+            Rc::clone(&self.descriptor_pool),
+        );
+        let image_info = [texture.descriptor_info()];
+        writer.write_image(0, &image_info);
+
+        let descriptor_set = match &self.font_textures[frame_index] {
+            Some(font_texture) => {
+                unsafe { writer.overwrite(&font_texture.descriptor_set) };
+                font_texture.descriptor_set
+            }
+            None => writer
+                .build()
+                .map_err(|_| log::error!("Unable to create the egui font descriptor set!"))
+                .unwrap(),
+        };
+
+        self.font_textures[frame_index] = Some(FontTexture {
+            version: egui_texture.version,
+            _texture: texture,
+            descriptor_set,
+        });
+
+        descriptor_set
+    }
+
+    fn create_pipeline(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        pipeline_layout: &vk::PipelineLayout,
+    ) -> LvePipeline {
+        assert!(
+            pipeline_layout != &vk::PipelineLayout::null(),
+            "Cannot create pipeline before pipeline layout"
+        );
+
+        let mut pipeline_config = LvePipeline::default_pipline_config_info();
+
+        LvePipeline::enable_premultiplied_alpha_blending(&mut pipeline_config);
+
+        // The UI is always drawn on top of the scene
+        LvePipeline::disable_depth_test(&mut pipeline_config);
+
+        pipeline_config.binding_descriptions = vec![vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<Vertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()];
+        pipeline_config.attribute_descriptions = vec![
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: 0,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 1,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: size_of::<egui::Pos2>() as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 2,
+                format: vk::Format::R8G8B8A8_UNORM,
+                offset: 2 * size_of::<egui::Pos2>() as u32,
+            },
+        ];
+
+        LvePipeline::new(
+            lve_device,
+            "shaders/egui_shader.vert.spv",
+            "shaders/egui_shader.frag.spv",
+            pipeline_config,
+            render_pass,
+            pipeline_layout,
+        )
+    }
+
+    fn create_pipeline_layout(
+        device: &Device,
+        set_layout: vk::DescriptorSetLayout,
+    ) -> vk::PipelineLayout {
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .offset(0)
+            .size(size_of::<EguiPushConstantData>() as u32)
+            .build();
+
+        let descriptor_set_layouts = vec![set_layout];
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(descriptor_set_layouts.as_slice())
+            .push_constant_ranges(&[push_constant_range])
+            .build();
+
+        unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .map_err(|e| log::error!("Unable to create egui pipeline layout: {}", e))
+                .unwrap()
+        }
+    }
+
+    fn to_egui_modifiers(state: ModifiersState) -> egui::Modifiers {
+        let is_mac = cfg!(target_os = "macos");
+
+        egui::Modifiers {
+            alt: state.alt(),
+            ctrl: state.ctrl(),
+            shift: state.shift(),
+            mac_cmd: is_mac && state.logo(),
+            command: if is_mac { state.logo() } else { state.ctrl() },
+        }
+    }
+
+    fn to_egui_button(button: MouseButton) -> Option<egui::PointerButton> {
+        match button {
+            MouseButton::Left => Some(egui::PointerButton::Primary),
+            MouseButton::Right => Some(egui::PointerButton::Secondary),
+            MouseButton::Middle => Some(egui::PointerButton::Middle),
+            MouseButton::Other(_) => None,
+        }
+    }
+
+    /// Only the keys egui uses for navigation, editing and shortcuts
+    fn to_egui_key(key: VirtualKeyCode) -> Option<egui::Key> {
+        use egui::Key;
+
+        Some(match key {
+            VirtualKeyCode::Down => Key::ArrowDown,
+            VirtualKeyCode::Left => Key::ArrowLeft,
+            VirtualKeyCode::Right => Key::ArrowRight,
+            VirtualKeyCode::Up => Key::ArrowUp,
+            VirtualKeyCode::Escape => Key::Escape,
+            VirtualKeyCode::Tab => Key::Tab,
+            VirtualKeyCode::Back => Key::Backspace,
+            VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => Key::Enter,
+            VirtualKeyCode::Space => Key::Space,
+            VirtualKeyCode::Insert => Key::Insert,
+            VirtualKeyCode::Delete => Key::Delete,
+            VirtualKeyCode::Home => Key::Home,
+            VirtualKeyCode::End => Key::End,
+            VirtualKeyCode::PageUp => Key::PageUp,
+            VirtualKeyCode::PageDown => Key::PageDown,
+            VirtualKeyCode::A => Key::A,
+            VirtualKeyCode::C => Key::C,
+            VirtualKeyCode::K => Key::K,
+            VirtualKeyCode::U => Key::U,
+            VirtualKeyCode::V => Key::V,
+            VirtualKeyCode::W => Key::W,
+            VirtualKeyCode::X => Key::X,
+            VirtualKeyCode::Y => Key::Y,
+            VirtualKeyCode::Z => Key::Z,
+            _ => return None,
+        })
+    }
+}
+
+/// The scissor for a mesh's clip rectangle, which is in points relative to the viewport.
+/// `None` if none of it is inside the viewport
+fn clip_rect_to_scissor(
+    clip_rect: egui::Rect,
+    pixels_per_point: f32,
+    viewport: vk::Rect2D,
+) -> Option<vk::Rect2D> {
+    let (width, height) = (viewport.extent.width as f32, viewport.extent.height as f32);
+
+    let min_x = (clip_rect.min.x * pixels_per_point)
+        .round()
+        .clamp(0.0, width);
+    let min_y = (clip_rect.min.y * pixels_per_point)
+        .round()
+        .clamp(0.0, height);
+    let max_x = (clip_rect.max.x * pixels_per_point)
+        .round()
+        .clamp(min_x, width);
+    let max_y = (clip_rect.max.y * pixels_per_point)
+        .round()
+        .clamp(min_y, height);
+
+    if max_x <= min_x || max_y <= min_y {
+        return None;
+    }
+
+    Some(vk::Rect2D {
+        offset: vk::Offset2D {
+            x: viewport.offset.x + min_x as i32,
+            y: viewport.offset.y + min_y as i32,
+        },
+        extent: vk::Extent2D {
+            width: (max_x - min_x) as u32,
+            height: (max_y - min_y) as u32,
+        },
+    })
+}
+
+impl RenderSystem for EguiSystem {
+    /// Draws the UI from the last `end_frame`. Register it in the UI stage so it draws after
+    /// the scene
+    fn render(&mut self, frame_info: &FrameInfo) {
+        let meshes = std::mem::take(&mut self.meshes);
+
+        if meshes.is_empty() {
+            return;
+        }
+
+        let frame_index = frame_info.frame_index as usize;
+        let descriptor_set = self.update_font_texture(frame_index);
+
+        let vertex_buffer = &self.vertex_buffers[frame_index];
+        let index_buffer = &self.index_buffers[frame_index];
+
+        // The scissor, index count, first index and vertex offset of each mesh
+        let mut draws = Vec::with_capacity(meshes.len());
+        let (mut vertex_count, mut index_count) = (0, 0);
+
+        for ClippedMesh(clip_rect, mesh) in meshes.iter() {
+            if mesh.texture_id != egui::TextureId::Egui || mesh.indices.is_empty() {
+                continue;
+            }
+
+            if vertex_count + mesh.vertices.len() > MAX_VERTICES
+                || index_count + mesh.indices.len() > MAX_INDICES
+            {
+                log::warn!("egui vertex limit of {} reached, dropping UI", MAX_VERTICES);
+                break;
+            }
+
+            let scissor = match clip_rect_to_scissor(
+                *clip_rect,
+                self.pixels_per_point,
+                frame_info.viewport,
+            ) {
+                Some(scissor) => scissor,
+                None => continue,
+            };
+
+            unsafe {
+                vertex_buffer.write_to_buffer(
+                    mesh.vertices.as_slice(),
+                    (mesh.vertices.len() * size_of::<Vertex>()) as u64,
+                    (vertex_count * size_of::<Vertex>()) as u64,
+                );
+                index_buffer.write_to_buffer(
+                    mesh.indices.as_slice(),
+                    (mesh.indices.len() * size_of::<u32>()) as u64,
+                    (index_count * size_of::<u32>()) as u64,
+                );
+            }
+
+            draws.push((
+                scissor,
+                mesh.indices.len() as u32,
+                index_count as u32,
+                vertex_count as i32,
+            ));
+
+            vertex_count += mesh.vertices.len();
+            index_count += mesh.indices.len();
+        }
+
+        let push = EguiPushConstantData {
+            _screen_size: [
+                frame_info.viewport.extent.width as f32 / self.pixels_per_point,
+                frame_info.viewport.extent.height as f32 / self.pixels_per_point,
+            ],
+        };
+
+        let device = &self.lve_device.device;
+        let command_buffer = frame_info.command_buffer;
+
+        unsafe {
+            self.lve_pipeline.bind(device, command_buffer);
+            frame_info.record_pipeline_bind();
+
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                push.as_bytes(),
+            );
+
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                index_buffer.buffer,
+                0,
+                vk::IndexType::UINT32,
+            );
+
+            for (scissor, index_count, first_index, vertex_offset) in draws {
+                device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+                device.cmd_draw_indexed(
+                    command_buffer,
+                    index_count,
+                    1,
+                    first_index,
+                    vertex_offset,
+                    0,
+                );
+                frame_info.record_draw(index_count as u64 / 3);
+            }
+
+            // Leave the scissor as the renderer set it for any systems after this one
+            device.cmd_set_scissor(command_buffer, 0, &[frame_info.viewport]);
+        }
+    }
+}
+
+impl Drop for EguiSystem {
+    fn drop(&mut self) {
+        log::debug!("Dropping EguiSystem");
+
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport(x: i32, y: i32, width: u32, height: u32) -> vk::Rect2D {
+        vk::Rect2D {
+            offset: vk::Offset2D { x, y },
+            extent: vk::Extent2D { width, height },
+        }
+    }
+
+    #[test]
+    fn scissor_is_scaled_and_offset_by_the_viewport() {
+        let clip_rect = egui::Rect::from_min_max(egui::pos2(10.0, 20.0), egui::pos2(50.0, 40.0));
+
+        let scissor = clip_rect_to_scissor(clip_rect, 2.0, viewport(100, 0, 800, 600));
+
+        assert_eq!(scissor, Some(viewport(120, 40, 80, 40)));
+    }
+
+    #[test]
+    fn scissor_is_clamped_to_the_viewport() {
+        let clip_rect = egui::Rect::from_min_max(egui::pos2(-10.0, 500.0), egui::pos2(50.0, 700.0));
+
+        let scissor = clip_rect_to_scissor(clip_rect, 1.0, viewport(0, 0, 800, 600));
+        assert_eq!(scissor, Some(viewport(0, 500, 50, 100)));
+
+        let outside = egui::Rect::from_min_max(egui::pos2(900.0, 0.0), egui::pos2(950.0, 10.0));
+        assert_eq!(
+            clip_rect_to_scissor(outside, 1.0, viewport(0, 0, 800, 600)),
+            None
+        );
+    }
+}
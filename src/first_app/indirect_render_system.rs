@@ -0,0 +1,460 @@
+use super::lve_buffer::*;
+use super::lve_device::*;
+use super::lve_frameinfo::FrameInfo;
+use super::lve_model::{LveModel, Vertex};
+use super::lve_pipeline::*;
+use super::lve_swapchain::MAX_FRAMES_IN_FLIGHT;
+use super::render_system::RenderSystem;
+use super::simple_render_system::{SimplePushConstantData, USE_VERTEX_COLOR_CONSTANT_ID};
+
+use ash::{vk, Device};
+
+use std::mem::{size_of, size_of_val};
+use std::ops::Range;
+use std::rc::Rc;
+
+extern crate nalgebra as na;
+
+/// Objects drawn per frame, any past this aren't drawn
+const MAX_INDIRECT_DRAWS: usize = 1024;
+/// `constant_id` of `APPLY_VERTEX_COLOR` in indirect_shader.vert
+const APPLY_VERTEX_COLOR_CONSTANT_ID: u32 = 1;
+
+/// Per instance data for one object, what `SimplePushConstantData` holds for the simple
+/// render system
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct IndirectInstance {
+    model_matrix: na::Matrix4<f32>,
+    normal_matrix: na::Matrix4<f32>,
+    color: na::Vector4<f32>, // w is ignored
+}
+
+impl IndirectInstance {
+    /// The model's vertices at binding 0, then the instances at binding 1
+    fn get_binding_descriptions() -> Vec<vk::VertexInputBindingDescription> {
+        let mut binding_descriptions = Vertex::get_binding_descriptions();
+
+        binding_descriptions.push(
+            vk::VertexInputBindingDescription::builder()
+                .binding(1)
+                .stride(size_of::<Self>() as u32)
+                .input_rate(vk::VertexInputRate::INSTANCE)
+                .build(),
+        );
+
+        binding_descriptions
+    }
+
+    /// The vertex attributes, then a vec4 for each matrix column and the color from location 4
+    fn get_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+        let mut attribute_descriptions = Vertex::get_attribute_descriptions();
+
+        attribute_descriptions.extend((0..9).map(|column| vk::VertexInputAttributeDescription {
+            location: 4 + column,
+            binding: 1,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+            offset: column * size_of::<[f32; 4]>() as u32,
+        }));
+
+        attribute_descriptions
+    }
+}
+
+/// Draws the same objects as the `SimpleRenderSystem`, but with one
+/// `cmd_draw_indexed_indirect` per model rather than a draw per object. Each frame the objects
+/// are sorted by model and every object gets a `vk::DrawIndexedIndirectCommand` in a per
+/// frame indirect buffer, with its transform and color in a matching instance buffer.
+///
+/// The commands are written on the CPU, but the buffer is also a storage buffer so a compute
+/// shader can fill it (e.g. zeroing `instance_count` to cull objects) without changing how it
+/// is drawn. Without the `multi_draw_indirect` feature each command is its own call. Without
+/// `draw_indirect_first_instance` every `first_instance` is 0, so each command is its own call
+/// with the instance buffer bound at its object's instance instead. Only indexed models are
+/// drawn, and pipeline tags are ignored
+pub struct IndirectRenderSystem {
+    lve_device: Rc<LveDevice>,
+    lve_pipeline: LvePipeline,
+    pipeline_layout: vk::PipelineLayout,
+    instance_buffers: Vec<LveBuffer>,
+    indirect_buffers: Vec<LveBuffer>,
+    /// Set once the object count has gone past `MAX_INDIRECT_DRAWS`, so it is only logged once
+    over_capacity_logged: bool,
+}
+
+impl IndirectRenderSystem {
+    pub fn new(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        global_set_layout: vk::DescriptorSetLayout,
+        depth_prepass: bool,
+        use_vertex_color: bool,
+    ) -> Self {
+        if !lve_device.enabled_features.multi_draw_indirect {
+            log::warn!("multiDrawIndirect is not enabled, indirect draws will be one per object");
+        }
+
+        if !lve_device.enabled_features.draw_indirect_first_instance {
+            log::warn!(
+                "drawIndirectFirstInstance is not enabled, indirect draws will be one per object"
+            );
+        }
+
+        let pipeline_layout = Self::create_pipeline_layout(&lve_device.device, global_set_layout);
+
+        let lve_pipeline = Self::create_pipeline(
+            Rc::clone(&lve_device),
+            render_pass,
+            &pipeline_layout,
+            depth_prepass,
+            use_vertex_color,
+        );
+
+        let create_buffers = |instance_size: usize, usage_flags: vk::BufferUsageFlags| {
+            (0..MAX_FRAMES_IN_FLIGHT)
+                .map(|_| {
+                    let mut buffer = LveBuffer::new(
+                        Rc::clone(&lve_device),
+                        instance_size as u64,
+                        MAX_INDIRECT_DRAWS as u32,
+                        usage_flags,
+                        vk::MemoryPropertyFlags::HOST_VISIBLE
+                            | vk::MemoryPropertyFlags::HOST_COHERENT,
+                        1,
+                        BufferType::Instance,
+                    );
+
+                    unsafe { buffer.map(vk::WHOLE_SIZE, 0) };
+
+                    buffer
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let instance_buffers = create_buffers(
+            size_of::<IndirectInstance>(),
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        );
+        let indirect_buffers = create_buffers(
+            size_of::<vk::DrawIndexedIndirectCommand>(),
+            vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+        );
+
+        Self {
+            lve_device,
+            lve_pipeline,
+            pipeline_layout,
+            instance_buffers,
+            indirect_buffers,
+            over_capacity_logged: false,
+        }
+    }
+
+    fn create_pipeline(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        pipeline_layout: &vk::PipelineLayout,
+        depth_prepass: bool,
+        use_vertex_color: bool,
+    ) -> LvePipeline {
+        assert!(
+            pipeline_layout != &vk::PipelineLayout::null(),
+            "Cannot create pipeline before pipeline layout"
+        );
+
+        let mut pipeline_config = LvePipeline::default_pipline_config_info();
+
+        // The same as the simple render system's lit pipeline
+        if depth_prepass {
+            pipeline_config.depth_stencil_info.depth_compare_op = vk::CompareOp::EQUAL;
+            LvePipeline::disable_depth_write(&mut pipeline_config);
+        }
+
+        // The object's color reaches the fragment shader as the vertex color, so the fragment
+        // shader always applies it and the vertex shader decides on the mesh's colors
+        LvePipeline::add_specialization_constant(
+            &mut pipeline_config,
+            USE_VERTEX_COLOR_CONSTANT_ID,
+            true,
+        );
+        LvePipeline::add_specialization_constant(
+            &mut pipeline_config,
+            APPLY_VERTEX_COLOR_CONSTANT_ID,
+            use_vertex_color,
+        );
+
+        pipeline_config.binding_descriptions = IndirectInstance::get_binding_descriptions();
+        pipeline_config.attribute_descriptions = IndirectInstance::get_attribute_descriptions();
+
+        LvePipeline::new(
+            lve_device,
+            "shaders/indirect_shader.vert.spv",
+            "shaders/simple_shader.frag.spv",
+            pipeline_config,
+            render_pass,
+            pipeline_layout,
+        )
+    }
+
+    /// Only the fragment push constants, simple_shader.frag still reads its color from them
+    fn create_pipeline_layout(
+        device: &Device,
+        global_set_layout: vk::DescriptorSetLayout,
+    ) -> vk::PipelineLayout {
+        let descriptor_set_layouts = vec![global_set_layout];
+        let push_constant_ranges = vec![SimplePushConstantData::fragment_range()];
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(descriptor_set_layouts.as_slice())
+            .push_constant_ranges(push_constant_ranges.as_slice())
+            .build();
+
+        unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .map_err(|e| log::error!("Unable to create indirect pipeline layout: {}", e))
+                .unwrap()
+        }
+    }
+
+    /// Whether the commands can pick their object's instance with `first_instance`
+    fn uses_first_instance(&self) -> bool {
+        self.lve_device
+            .enabled_features
+            .draw_indirect_first_instance
+    }
+
+    /// Records the draws for the commands in `batch`, which all use `model`
+    unsafe fn draw_batch(
+        &self,
+        frame_info: &FrameInfo,
+        model: &LveModel,
+        instance_buffer: vk::Buffer,
+        indirect_buffer: vk::Buffer,
+        batch: Range<usize>,
+    ) {
+        let device = &self.lve_device.device;
+        let stride = size_of::<vk::DrawIndexedIndirectCommand>();
+
+        model.bind(device, frame_info.command_buffer);
+
+        if self.lve_device.enabled_features.multi_draw_indirect && self.uses_first_instance() {
+            model.draw_indirect(
+                device,
+                frame_info.command_buffer,
+                indirect_buffer,
+                (batch.start * stride) as vk::DeviceSize,
+                batch.len() as u32,
+                stride as u32,
+            );
+            frame_info.record_draw(model.triangle_count() * batch.len() as u64);
+        } else {
+            for command in batch {
+                // Every command's instance 0 is its object's instance
+                if !self.uses_first_instance() {
+                    device.cmd_bind_vertex_buffers(
+                        frame_info.command_buffer,
+                        1,
+                        &[instance_buffer],
+                        &[(command * size_of::<IndirectInstance>()) as vk::DeviceSize],
+                    );
+                }
+
+                model.draw_indirect(
+                    device,
+                    frame_info.command_buffer,
+                    indirect_buffer,
+                    (command * stride) as vk::DeviceSize,
+                    1,
+                    stride as u32,
+                );
+                frame_info.record_draw(model.triangle_count());
+            }
+        }
+    }
+}
+
+impl RenderSystem for IndirectRenderSystem {
+    fn render(&mut self, frame_info: &FrameInfo) {
+        // This system replaces the simple render system, so it counts the hidden objects
+        let hidden_count = frame_info.game_objects.len() - frame_info.game_objects.visible_count();
+        frame_info.record_culled(hidden_count as u32);
+
+        let camera_position = frame_info.camera.position();
+
+        // The same objects the simple render system draws, less any without indices
+        let mut game_objects: Vec<_> = frame_info
+            .game_objects
+            .iter()
+            .filter(|(_, game_obj)| {
+                game_obj.visible
+                    && game_obj.point_light.is_none()
+                    && game_obj.reflection.is_none()
+                    && game_obj.skin.is_none()
+                    && game_obj.material.is_none()
+            })
+            .map(|(id, game_obj)| (*id, game_obj, game_obj.model_at(camera_position)))
+            .filter(|(_, _, model)| model.index_count() > 0)
+            .collect();
+
+        if game_objects.len() > MAX_INDIRECT_DRAWS && !self.over_capacity_logged {
+            log::warn!(
+                "{} objects to draw indirectly, only the first {} will be drawn",
+                game_objects.len(),
+                MAX_INDIRECT_DRAWS
+            );
+            self.over_capacity_logged = true;
+        }
+
+        // Objects with the same model are next to each other so they can share a call. The
+        // ids break ties so the draw order doesn't change from frame to frame
+        game_objects.sort_by_key(|(id, _, model)| (Rc::as_ptr(model), *id));
+        game_objects.truncate(MAX_INDIRECT_DRAWS);
+
+        if game_objects.is_empty() {
+            return;
+        }
+
+        let instances: Vec<_> = game_objects
+            .iter()
+            .map(|(_, game_obj, _)| IndirectInstance {
+                model_matrix: game_obj.transform.cached_mat4(),
+                normal_matrix: game_obj.transform.cached_normal_matrix(),
+                color: game_obj.color.push(1.0),
+            })
+            .collect();
+
+        // One instance each, `first_instance` picks the object's instance data when it can
+        let first_instance = self.uses_first_instance();
+        let commands: Vec<_> = game_objects
+            .iter()
+            .enumerate()
+            .map(|(index, (_, _, model))| vk::DrawIndexedIndirectCommand {
+                index_count: model.index_count(),
+                instance_count: 1,
+                first_index: 0,
+                vertex_offset: 0,
+                first_instance: if first_instance { index as u32 } else { 0 },
+            })
+            .collect();
+
+        let models: Vec<_> = game_objects
+            .iter()
+            .map(|(_, _, model)| Rc::as_ptr(model))
+            .collect();
+
+        let instance_buffer = &self.instance_buffers[frame_info.frame_index as usize];
+        let indirect_buffer = &self.indirect_buffers[frame_info.frame_index as usize];
+
+        // The color from the push constants multiplies the vertex color, which already has the
+        // object's color in it
+        let white = [1.0_f32, 1.0, 1.0, 0.0];
+
+        unsafe {
+            instance_buffer.write_to_buffer(
+                instances.as_slice(),
+                (instances.len() * size_of::<IndirectInstance>()) as u64,
+                0,
+            );
+            indirect_buffer.write_to_buffer(
+                commands.as_slice(),
+                (commands.len() * size_of::<vk::DrawIndexedIndirectCommand>()) as u64,
+                0,
+            );
+
+            self.lve_pipeline
+                .bind(&self.lve_device.device, frame_info.command_buffer);
+            frame_info.record_pipeline_bind();
+
+            self.lve_device.device.cmd_bind_descriptor_sets(
+                frame_info.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[frame_info.global_descriptor_set],
+                &[],
+            );
+
+            let fragment_range = SimplePushConstantData::fragment_range();
+
+            self.lve_device.device.cmd_push_constants(
+                frame_info.command_buffer,
+                self.pipeline_layout,
+                fragment_range.stage_flags,
+                fragment_range.offset,
+                std::slice::from_raw_parts(white.as_ptr() as *const u8, size_of_val(&white)),
+            );
+
+            // Binding 0 is rebound by each model
+            self.lve_device.device.cmd_bind_vertex_buffers(
+                frame_info.command_buffer,
+                1,
+                &[instance_buffer.buffer],
+                &[0],
+            );
+
+            for batch in batches(&models) {
+                let (_, _, model) = game_objects[batch.start];
+                self.draw_batch(
+                    frame_info,
+                    model,
+                    instance_buffer.buffer,
+                    indirect_buffer.buffer,
+                    batch,
+                );
+            }
+        }
+    }
+}
+
+impl Drop for IndirectRenderSystem {
+    fn drop(&mut self) {
+        log::debug!("Dropping IndirectRenderSystem");
+
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+/// Splits `keys` into runs of equal keys
+fn batches<T: PartialEq>(keys: &[T]) -> Vec<Range<usize>> {
+    let mut batches: Vec<Range<usize>> = Vec::new();
+
+    for (index, key) in keys.iter().enumerate() {
+        match batches.last_mut() {
+            Some(batch) if keys[batch.start] == *key => batch.end = index + 1,
+            _ => batches.push(index..index + 1),
+        }
+    }
+
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_are_runs_of_the_same_key() {
+        assert_eq!(
+            batches(&['a', 'a', 'b', 'c', 'c', 'c', 'a']),
+            vec![0..2, 2..3, 3..6, 6..7]
+        );
+        assert_eq!(batches::<u32>(&[]), Vec::<Range<usize>>::new());
+    }
+
+    #[test]
+    fn instance_attributes_cover_the_instance() {
+        let attributes = IndirectInstance::get_attribute_descriptions();
+        let last = attributes.last().unwrap();
+
+        assert_eq!(last.location, 12);
+        assert_eq!(
+            last.offset as usize + size_of::<[f32; 4]>(),
+            size_of::<IndirectInstance>()
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use winit::event::{
+    ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+
+use std::collections::HashSet;
+
+extern crate nalgebra as na;
+
+/// Roughly how many pixels a touchpad has to scroll to count as one line
+const PIXELS_PER_SCROLL_LINE: f32 = 20.0;
+
+/// Everything the user is currently doing with the keyboard and mouse. Updated from the window
+/// events in one place and queried by anything that reacts to input, so consumers don't need
+/// to track key state themselves.
+///
+/// The "this frame" values (presses, mouse delta and scroll) accumulate until `end_frame` is
+/// called, so nothing is lost if several events arrive between two frames
+#[derive(Debug, Default)]
+pub struct InputState {
+    keys_down: HashSet<VirtualKeyCode>,
+    keys_pressed: HashSet<VirtualKeyCode>,
+    mouse_buttons_down: HashSet<MouseButton>,
+    mouse_position: Option<na::Vector2<f32>>,
+    mouse_delta: na::Vector2<f32>,
+    scroll_delta: na::Vector2<f32>,
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => match state {
+                ElementState::Pressed => {
+                    // Key repeats send more presses while the key is held, which shouldn't
+                    // count as new presses
+                    if self.keys_down.insert(*key) {
+                        self.keys_pressed.insert(*key);
+                    }
+                }
+                ElementState::Released => {
+                    // Releasing a key we never saw pressed (e.g. it was held when the window
+                    // gained focus) is harmless
+                    self.keys_down.remove(key);
+                }
+            },
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    self.mouse_buttons_down.insert(*button);
+                }
+                ElementState::Released => {
+                    self.mouse_buttons_down.remove(button);
+                }
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = na::vector![position.x as f32, position.y as f32];
+
+                if let Some(last_position) = self.mouse_position {
+                    self.mouse_delta += position - last_position;
+                }
+
+                self.mouse_position = Some(position);
+            }
+            WindowEvent::CursorLeft { .. } => {
+                // Otherwise re-entering somewhere else would look like a huge jump
+                self.mouse_position = None;
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll_delta += match delta {
+                    MouseScrollDelta::LineDelta(x, y) => na::vector![*x, *y],
+                    MouseScrollDelta::PixelDelta(position) => {
+                        na::vector![position.x as f32, position.y as f32] / PIXELS_PER_SCROLL_LINE
+                    }
+                };
+            }
+            WindowEvent::Focused(false) => {
+                // Releases aren't sent to an unfocused window, so forget everything held
+                self.keys_down.clear();
+                self.mouse_buttons_down.clear();
+            }
+            _ => {}
+        }
+    }
+
+    /// Clears the per frame values, call once the frame has consumed them
+    pub fn end_frame(&mut self) {
+        self.keys_pressed.clear();
+        self.mouse_delta = na::Vector2::zeros();
+        self.scroll_delta = na::Vector2::zeros();
+    }
+
+    pub fn is_key_down(&self, key: VirtualKeyCode) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    /// Whether the key went down since the last `end_frame`, ignoring key repeats
+    pub fn was_key_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    #[allow(dead_code)]
+    pub fn is_mouse_button_down(&self, button: MouseButton) -> bool {
+        self.mouse_buttons_down.contains(&button)
+    }
+
+    /// In physical pixels from the top left of the window, `None` when the cursor is outside it
+    pub fn mouse_position(&self) -> Option<na::Vector2<f32>> {
+        self.mouse_position
+    }
+
+    /// How far the cursor moved since the last `end_frame`, in physical pixels
+    #[allow(dead_code)]
+    pub fn mouse_delta(&self) -> na::Vector2<f32> {
+        self.mouse_delta
+    }
+
+    /// How far the wheel scrolled since the last `end_frame`, in lines
+    pub fn scroll_delta(&self) -> na::Vector2<f32> {
+        self.scroll_delta
+    }
+}
@@ -1,3 +1,4 @@
+use super::input_state::InputState;
 use super::lve_game_object::*;
 
 use std::f32::consts::PI;
@@ -6,9 +7,66 @@ use winit::event::VirtualKeyCode;
 
 extern crate nalgebra as na;
 
+/// Speed multiplier while Shift is held
+const SPRINT_MULTIPLIER: f32 = 4.0;
+/// Speed multiplier while Ctrl is held, for fine positioning
+const SLOW_MULTIPLIER: f32 = 0.25;
+/// Each line scrolled up multiplies the base move speed by this, and each line down divides it
+const SCROLL_SPEED_STEP: f32 = 1.25;
+const MIN_MOVE_SPEED: f32 = 0.05;
+const MAX_MOVE_SPEED: f32 = 200.0;
+
+/// `move_speed` scaled by whichever modifier keys are held. Holding both applies both
+/// multipliers
+fn modified_move_speed(
+    move_speed: f32,
+    sprint_multiplier: f32,
+    slow_multiplier: f32,
+    input_state: &InputState,
+) -> f32 {
+    let mut speed = move_speed;
+
+    if input_state.is_key_down(VirtualKeyCode::LShift)
+        || input_state.is_key_down(VirtualKeyCode::RShift)
+    {
+        speed *= sprint_multiplier;
+    }
+    if input_state.is_key_down(VirtualKeyCode::LControl)
+        || input_state.is_key_down(VirtualKeyCode::RControl)
+    {
+        speed *= slow_multiplier;
+    }
+
+    speed
+}
+
+/// Scrolling changes the base speed for good, unlike the modifier keys
+fn scrolled_move_speed(move_speed: f32, input_state: &InputState) -> f32 {
+    let scroll = input_state.scroll_delta()[1];
+
+    if scroll != 0.0 {
+        (move_speed * SCROLL_SPEED_STEP.powf(scroll)).clamp(MIN_MOVE_SPEED, MAX_MOVE_SPEED)
+    } else {
+        move_speed
+    }
+}
+
+/// The sum of the directions whose keys are held
+fn key_axes(
+    input_state: &InputState,
+    keys: &[(VirtualKeyCode, na::Vector3<f32>)],
+) -> na::Vector3<f32> {
+    keys.iter()
+        .filter(|(key, _)| input_state.is_key_down(*key))
+        .map(|(_, direction)| direction)
+        .sum()
+}
+
 pub struct KeyboardMovementController {
     move_speed: f32,
     look_speed: f32,
+    sprint_multiplier: f32,
+    slow_multiplier: f32,
 }
 
 impl KeyboardMovementController {
@@ -25,65 +83,193 @@ impl KeyboardMovementController {
         Self {
             move_speed,
             look_speed,
+            sprint_multiplier: SPRINT_MULTIPLIER,
+            slow_multiplier: SLOW_MULTIPLIER,
         }
     }
 
+    /// Overrides the multipliers applied while Shift (sprint) and Ctrl (slow) are held
+    #[allow(dead_code)]
+    pub fn set_speed_multipliers(&mut self, sprint_multiplier: f32, slow_multiplier: f32) {
+        self.sprint_multiplier = sprint_multiplier;
+        self.slow_multiplier = slow_multiplier;
+    }
+
+    /// The base move speed, before any modifier keys are applied
+    #[allow(dead_code)]
+    pub fn move_speed(&self) -> f32 {
+        self.move_speed
+    }
+
+    /// The base move speed scaled by whichever modifier keys are held
+    fn current_move_speed(&self, input_state: &InputState) -> f32 {
+        modified_move_speed(
+            self.move_speed,
+            self.sprint_multiplier,
+            self.slow_multiplier,
+            input_state,
+        )
+    }
+
     pub fn move_in_plane_xz(
-        &self,
-        key_codes: &[VirtualKeyCode],
+        &mut self,
+        input_state: &InputState,
         dt: f32,
         game_object: &mut LveGameObject,
     ) {
+        self.move_speed = scrolled_move_speed(self.move_speed, input_state);
+
         let mut rotate = na::Vector3::<f32>::zeros();
 
-        if key_codes.contains(&VirtualKeyCode::Right) {
+        if input_state.is_key_down(VirtualKeyCode::Right) {
             rotate[1] += 1.0
         } // look right
-        if key_codes.contains(&VirtualKeyCode::Left) {
+        if input_state.is_key_down(VirtualKeyCode::Left) {
             rotate[1] -= 1.0
         } // look left
-        if key_codes.contains(&VirtualKeyCode::Up) {
+        if input_state.is_key_down(VirtualKeyCode::Up) {
             rotate[0] += 1.0
         } // look up
-        if key_codes.contains(&VirtualKeyCode::Down) {
+        if input_state.is_key_down(VirtualKeyCode::Down) {
             rotate[0] -= 1.0
         } // look down
 
+        let mut rotation = game_object.transform.rotation();
+
         if rotate.dot(&rotate) > EPSILON {
-            game_object.transform.rotation += self.look_speed * dt * rotate.normalize();
+            rotation += self.look_speed * dt * rotate.normalize();
         }
 
-        game_object.transform.rotation[0] = game_object.transform.rotation[0].clamp(-1.5, 1.5);
-        game_object.transform.rotation[1] = game_object.transform.rotation[1] % (2.0 * PI);
+        rotation[0] = rotation[0].clamp(-1.5, 1.5);
+        rotation[1] %= 2.0 * PI;
+
+        game_object.transform.set_rotation(rotation);
 
-        let yaw = game_object.transform.rotation[1];
+        let yaw = rotation[1];
         let forward_dir = na::vector![yaw.sin(), 0.0, yaw.cos()];
         let right_dir = na::vector![forward_dir[2], 0.0, -forward_dir[0]];
         let up_dir = na::vector![0.0, -1.0, 0.0];
 
         let mut move_dir = na::Vector3::<f32>::zeros();
 
-        if key_codes.contains(&VirtualKeyCode::W) {
+        if input_state.is_key_down(VirtualKeyCode::W) {
             move_dir += forward_dir
         } // move forward
-        if key_codes.contains(&VirtualKeyCode::S) {
+        if input_state.is_key_down(VirtualKeyCode::S) {
             move_dir -= forward_dir
         } // move backwards
-        if key_codes.contains(&VirtualKeyCode::D) {
+        if input_state.is_key_down(VirtualKeyCode::D) {
             move_dir += right_dir
         } // move right
-        if key_codes.contains(&VirtualKeyCode::A) {
+        if input_state.is_key_down(VirtualKeyCode::A) {
             move_dir -= right_dir
         } // move left
-        if key_codes.contains(&VirtualKeyCode::E) {
+        if input_state.is_key_down(VirtualKeyCode::E) {
             move_dir += up_dir
         } // move up
-        if key_codes.contains(&VirtualKeyCode::Q) {
+        if input_state.is_key_down(VirtualKeyCode::Q) {
             move_dir -= up_dir
         } // move down
 
         if move_dir.dot(&move_dir) > EPSILON {
-            game_object.transform.translation += self.move_speed * dt * move_dir.normalize();
+            let move_speed = self.current_move_speed(input_state);
+            let translation = game_object.transform.translation();
+            game_object
+                .transform
+                .set_translation(translation + move_speed * dt * move_dir.normalize());
+        }
+    }
+}
+
+/// Flies the viewer in any direction with all six degrees of freedom: the arrow keys pitch and
+/// yaw and Q / E roll, all about the viewer's own axes, W / S move along the view direction
+/// (up and down included), A / D along the viewer's right and Space / C along its up. Pitch
+/// isn't clamped, and the rotations are applied to the transform's orientation quaternion so
+/// looking straight up or down doesn't lock up the other axes. Shift, Ctrl and scrolling change
+/// the speed as for `KeyboardMovementController`
+pub struct FreeFlyController {
+    move_speed: f32,
+    look_speed: f32,
+    roll_speed: f32,
+    sprint_multiplier: f32,
+    slow_multiplier: f32,
+}
+
+impl FreeFlyController {
+    pub fn new(move_speed: Option<f32>, look_speed: Option<f32>) -> Self {
+        let look_speed = look_speed.unwrap_or(1.5);
+
+        Self {
+            move_speed: move_speed.unwrap_or(3.0),
+            look_speed,
+            roll_speed: look_speed,
+            sprint_multiplier: SPRINT_MULTIPLIER,
+            slow_multiplier: SLOW_MULTIPLIER,
+        }
+    }
+
+    /// Overrides how fast Q / E roll, in radians per second. Defaults to the look speed
+    #[allow(dead_code)]
+    pub fn set_roll_speed(&mut self, roll_speed: f32) {
+        self.roll_speed = roll_speed;
+    }
+
+    pub fn fly(&mut self, input_state: &InputState, dt: f32, game_object: &mut LveGameObject) {
+        self.move_speed = scrolled_move_speed(self.move_speed, input_state);
+
+        // Pitch, yaw and roll about the viewer's x, y and z axes
+        let look = key_axes(
+            input_state,
+            &[
+                (VirtualKeyCode::Up, na::Vector3::x()),
+                (VirtualKeyCode::Down, -na::Vector3::x()),
+                (VirtualKeyCode::Right, na::Vector3::y()),
+                (VirtualKeyCode::Left, -na::Vector3::y()),
+            ],
+        );
+        let roll = key_axes(
+            input_state,
+            &[
+                (VirtualKeyCode::E, na::Vector3::z()),
+                (VirtualKeyCode::Q, -na::Vector3::z()),
+            ],
+        );
+
+        let mut orientation = game_object.transform.orientation();
+
+        let turn = self.look_speed * dt * look + self.roll_speed * dt * roll;
+        if turn.dot(&turn) > f32::EPSILON {
+            // Applied on the right, so the rotation is about the viewer's own axes
+            orientation *= na::UnitQuaternion::from_scaled_axis(turn);
+            orientation.renormalize_fast();
+            game_object.transform.set_orientation(orientation);
+        }
+
+        // +y is down, so the viewer's up is its -y
+        let move_dir = orientation
+            * key_axes(
+                input_state,
+                &[
+                    (VirtualKeyCode::W, na::Vector3::z()),
+                    (VirtualKeyCode::S, -na::Vector3::z()),
+                    (VirtualKeyCode::D, na::Vector3::x()),
+                    (VirtualKeyCode::A, -na::Vector3::x()),
+                    (VirtualKeyCode::Space, -na::Vector3::y()),
+                    (VirtualKeyCode::C, na::Vector3::y()),
+                ],
+            );
+
+        if move_dir.dot(&move_dir) > f32::EPSILON {
+            let move_speed = modified_move_speed(
+                self.move_speed,
+                self.sprint_multiplier,
+                self.slow_multiplier,
+                input_state,
+            );
+            let translation = game_object.transform.translation();
+            game_object
+                .transform
+                .set_translation(translation + move_speed * dt * move_dir.normalize());
         }
     }
 }
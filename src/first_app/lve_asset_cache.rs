@@ -0,0 +1,186 @@
+use super::lve_device::*;
+use super::lve_model::*;
+use super::lve_texture::*;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+/// A cached model and when its file was last modified as of loading it, for hot reloading
+struct CachedModel {
+    model: Rc<LveModel>,
+    file_path: String,
+    modified: Option<SystemTime>,
+}
+
+/// Memoizes models and textures by their absolute file path, so loading the same asset twice
+/// hands back the existing GPU resources instead of creating duplicates
+pub struct LveAssetCache {
+    lve_device: Rc<LveDevice>,
+    models: HashMap<PathBuf, CachedModel>,
+    textures: HashMap<PathBuf, Rc<LveTexture>>,
+}
+
+impl LveAssetCache {
+    pub fn new(lve_device: Rc<LveDevice>) -> Self {
+        Self {
+            lve_device,
+            models: HashMap::new(),
+            textures: HashMap::new(),
+        }
+    }
+
+    pub fn create_model_cached(&mut self, file_path: &str) -> Rc<LveModel> {
+        let key = Self::cache_key(file_path);
+
+        if let Some(cached) = self.models.get(&key) {
+            log::debug!("Using cached model {:?}", key);
+            return Rc::clone(&cached.model);
+        }
+
+        let modified = Self::modified_time(&key);
+        let model = LveModel::create_model_from_file(Rc::clone(&self.lve_device), file_path, false);
+
+        self.models.insert(
+            key,
+            CachedModel {
+                model: Rc::clone(&model),
+                file_path: file_path.to_owned(),
+                modified,
+            },
+        );
+
+        model
+    }
+
+    /// Loads every model in `file_paths` that isn't cached yet, parsing the files in parallel
+    /// (see `LveModel::load_models_parallel`). Later `create_model_cached` calls for them are
+    /// then cache hits, so a scene can preload everything it uses up front
+    pub fn preload_models(&mut self, file_paths: &[&str]) {
+        let mut keys = Vec::new();
+        let mut uncached_paths = Vec::new();
+
+        for file_path in file_paths {
+            let key = Self::cache_key(file_path);
+
+            if !self.models.contains_key(&key) && !keys.contains(&key) {
+                keys.push(key);
+                uncached_paths.push(*file_path);
+            }
+        }
+
+        let modified = keys
+            .iter()
+            .map(|key| Self::modified_time(key))
+            .collect::<Vec<_>>();
+
+        let models = LveModel::load_models_parallel(&self.lve_device, &uncached_paths);
+
+        for (((key, file_path), model), modified) in keys
+            .into_iter()
+            .zip(uncached_paths)
+            .zip(models)
+            .zip(modified)
+        {
+            self.models.insert(
+                key,
+                CachedModel {
+                    model,
+                    file_path: file_path.to_owned(),
+                    modified,
+                },
+            );
+        }
+    }
+
+    /// Reloads every cached model whose file has changed on disk since it was last loaded. The
+    /// `Rc<LveModel>` handles stay the same, so game objects draw the new geometry without
+    /// being touched. Returns the replaced geometry, which frames in flight may still be
+    /// drawing, to pass to `LveRenderer::defer_deletion`. A model that fails to reload keeps
+    /// its old geometry, and is tried again the next time its file changes
+    pub fn reload_changed_models(&mut self) -> Vec<ModelGeometry> {
+        let mut replaced = Vec::new();
+
+        for (key, cached) in self.models.iter_mut() {
+            let modified = Self::modified_time(key);
+
+            // Files that can't be read right now (e.g. mid save) are left for a later check
+            if modified.is_none() || modified == cached.modified {
+                continue;
+            }
+
+            cached.modified = modified;
+
+            log::info!("Model {:?} changed on disk, reloading", key);
+
+            if let Some(geometry) = cached
+                .model
+                .reload_from_file(&self.lve_device, &cached.file_path)
+            {
+                replaced.push(geometry);
+            }
+        }
+
+        replaced
+    }
+
+    /// Textures are keyed by path alone, so the format and sampler config of the first load
+    /// are the ones used for every later request of the same file
+    #[allow(dead_code)]
+    pub fn load_texture_cached(
+        &mut self,
+        file_path: &str,
+        format: TextureFormat,
+        sampler_config: SamplerConfig,
+    ) -> Rc<LveTexture> {
+        let key = Self::cache_key(file_path);
+
+        if let Some(texture) = self.textures.get(&key) {
+            log::debug!("Using cached texture {:?}", key);
+            return Rc::clone(texture);
+        }
+
+        let texture = LveTexture::from_file(
+            Rc::clone(&self.lve_device),
+            file_path,
+            format,
+            sampler_config,
+        );
+        self.textures.insert(key, Rc::clone(&texture));
+
+        texture
+    }
+
+    /// Drops every asset that is only referenced by the cache itself
+    #[allow(dead_code)]
+    pub fn clear_unused(&mut self) {
+        let model_count = self.models.len();
+        let texture_count = self.textures.len();
+
+        self.models
+            .retain(|_, cached| Rc::strong_count(&cached.model) > 1);
+        self.textures
+            .retain(|_, texture| Rc::strong_count(texture) > 1);
+
+        log::debug!(
+            "Cleared {} unused models and {} unused textures",
+            model_count - self.models.len(),
+            texture_count - self.textures.len()
+        );
+    }
+
+    fn modified_time(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Falls back to the path as given if it can't be resolved (e.g. the file doesn't exist),
+    /// in which case the load itself will report the error
+    fn cache_key(file_path: &str) -> PathBuf {
+        Path::new(file_path)
+            .canonicalize()
+            .unwrap_or_else(|_| PathBuf::from(file_path))
+    }
+}
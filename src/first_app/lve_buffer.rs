@@ -2,13 +2,14 @@ use super::lve_device::LveDevice;
 
 use ash::vk;
 
-use std::{ffi::c_void, ptr, rc::Rc};
+use std::{ffi::c_void, marker::PhantomData, mem::size_of, ptr, rc::Rc};
 
+#[derive(Debug, Clone, Copy)]
 pub enum BufferType {
-    Staging,
     Vertex,
     Index,
     Uniform,
+    Instance,
 }
 
 pub struct LveBuffer {
@@ -16,12 +17,16 @@ pub struct LveBuffer {
     pub buffer: vk::Buffer,
     pub buffer_size: vk::DeviceSize,
     pub memory: vk::DeviceMemory,
+    #[allow(dead_code)]
     pub memory_property_flags: vk::MemoryPropertyFlags,
     pub mapped: *mut c_void,
     pub instance_count: u32,
     pub instance_size: vk::DeviceSize,
     pub alignment_size: vk::DeviceSize,
+    #[allow(dead_code)]
     pub usage_flags: vk::BufferUsageFlags,
+    /// Host writes to coherent memory are visible to the device without flushing
+    is_coherent: bool,
     buffer_type: BufferType,
 }
 
@@ -51,6 +56,7 @@ impl LveBuffer {
             instance_size,
             alignment_size,
             usage_flags,
+            is_coherent: memory_property_flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT),
             buffer_type,
         }
     }
@@ -121,7 +127,8 @@ impl LveBuffer {
     /**
      * Flush a memory range of the buffer to make it visible to the device
      *
-     * @note Only required for non-coherent memory
+     * @note Only required for non-coherent memory, does nothing for coherent memory. The range
+     * is widened to multiples of nonCoherentAtomSize
      *
      * @param size (Optional) Size of the memory range to flush. Pass VK_WHOLE_SIZE to flush the
      * complete buffer range.
@@ -134,13 +141,11 @@ impl LveBuffer {
         size: vk::DeviceSize,
         offset: vk::DeviceSize,
     ) -> Result<(), vk::Result> {
-        let mapped_range = vk::MappedMemoryRange::builder()
-            .memory(self.memory)
-            .size(size)
-            .offset(offset)
-            .build();
+        if self.is_coherent {
+            return Ok(());
+        }
 
-        let ranges = [mapped_range];
+        let ranges = [self.mapped_memory_range(size, offset)];
 
         self.lve_device.device.flush_mapped_memory_ranges(&ranges)
     }
@@ -148,7 +153,8 @@ impl LveBuffer {
     /**
      * Invalidate a memory range of the buffer to make it visible to the host
      *
-     * @note Only required for non-coherent memory
+     * @note Only required for non-coherent memory, does nothing for coherent memory. The range
+     * is widened to multiples of nonCoherentAtomSize
      *
      * @param size (Optional) Size of the memory range to invalidate. Pass VK_WHOLE_SIZE to invalidate
      * the complete buffer range.
@@ -161,19 +167,40 @@ impl LveBuffer {
         size: vk::DeviceSize,
         offset: vk::DeviceSize,
     ) -> Result<(), vk::Result> {
-        let mapped_range = vk::MappedMemoryRange::builder()
-            .memory(self.memory)
-            .size(size)
-            .offset(offset)
-            .build();
+        if self.is_coherent {
+            return Ok(());
+        }
 
-        let ranges = [mapped_range];
+        let ranges = [self.mapped_memory_range(size, offset)];
 
         self.lve_device
             .device
             .invalidate_mapped_memory_ranges(&ranges)
     }
 
+    /**
+     * Flushed and invalidated ranges must start and end on a multiple of nonCoherentAtomSize
+     * (or at the end of the memory), so round the start down and the end up
+     */
+    fn mapped_memory_range(
+        &self,
+        size: vk::DeviceSize,
+        offset: vk::DeviceSize,
+    ) -> vk::MappedMemoryRange {
+        let (aligned_offset, aligned_size) = align_to_atom_size(
+            offset,
+            size,
+            self.lve_device.non_coherent_atom_size(),
+            self.buffer_size,
+        );
+
+        vk::MappedMemoryRange::builder()
+            .memory(self.memory)
+            .offset(aligned_offset)
+            .size(aligned_size)
+            .build()
+    }
+
     /**
      * Create a buffer info descriptor
      *
@@ -201,7 +228,7 @@ impl LveBuffer {
      * @param index Used in offset calculation
      *
      */
-    pub unsafe fn _write_to_index<T: Copy>(&self, data: &[T], index: u64) {
+    pub unsafe fn write_to_index<T: Copy>(&self, data: &[T], index: u64) {
         self.write_to_buffer(data, self.instance_size, index * self.alignment_size)
     }
 
@@ -211,7 +238,7 @@ impl LveBuffer {
      * @param index Used in offset calculation
      *
      */
-    pub unsafe fn _flush_index(&self, index: u64) -> Result<(), vk::Result> {
+    pub unsafe fn flush_index(&self, index: u64) -> Result<(), vk::Result> {
         self.flush(self.alignment_size, index * self.alignment_size)
     }
 
@@ -222,7 +249,7 @@ impl LveBuffer {
      *
      * @return VkDescriptorBufferInfo for instance at index
      */
-    pub fn _descriptor_info_for_index(&self, index: u64) -> Rc<vk::DescriptorBufferInfo> {
+    pub fn descriptor_info_for_index(&self, index: u64) -> Rc<vk::DescriptorBufferInfo> {
         self.descriptor_info(self.alignment_size, index * self.alignment_size)
     }
 
@@ -251,13 +278,104 @@ impl LveBuffer {
     }
 }
 
+/// Returns the (offset, size) of the smallest range made of whole atoms that covers the given
+/// range. If that would run past the end of the buffer, the range runs to the end of the memory
+/// instead (`vk::WHOLE_SIZE`), as the memory may be bigger than the buffer and a size that
+/// isn't a multiple of the atom size is only valid when it reaches the end of the memory
+fn align_to_atom_size(
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    atom_size: vk::DeviceSize,
+    buffer_size: vk::DeviceSize,
+) -> (vk::DeviceSize, vk::DeviceSize) {
+    let aligned_offset = offset / atom_size * atom_size;
+
+    if size == vk::WHOLE_SIZE {
+        return (aligned_offset, vk::WHOLE_SIZE);
+    }
+
+    let aligned_end = (offset + size).div_ceil(atom_size) * atom_size;
+
+    if aligned_end > buffer_size {
+        (aligned_offset, vk::WHOLE_SIZE)
+    } else {
+        (aligned_offset, aligned_end - aligned_offset)
+    }
+}
+
+/// A host visible uniform buffer holding one `T` per frame in flight, each at its own offset
+/// aligned to `minUniformBufferOffsetAlignment`. Each frame only writes and binds its own copy,
+/// so the CPU never overwrites data a frame still in flight is reading
+pub struct UboBuffer<T> {
+    buffer: LveBuffer,
+    _data: PhantomData<T>,
+}
+
+impl<T: Copy> UboBuffer<T> {
+    pub fn new(lve_device: Rc<LveDevice>, frame_count: usize) -> Self {
+        let min_offset_alignment = lve_device
+            .properties
+            .limits
+            .min_uniform_buffer_offset_alignment;
+
+        let mut buffer = LveBuffer::new(
+            lve_device,
+            size_of::<T>() as vk::DeviceSize,
+            frame_count as u32,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE,
+            min_offset_alignment,
+            BufferType::Uniform,
+        );
+
+        // Stays mapped for the buffer's whole life, it's written every frame
+        unsafe { buffer.map(vk::WHOLE_SIZE, 0) };
+
+        Self {
+            buffer,
+            _data: PhantomData,
+        }
+    }
+
+    /// Writes `data` into the frame's copy and flushes it so the device sees it
+    pub fn update(&mut self, frame_index: usize, data: &T) {
+        let index = self.checked_index(frame_index);
+
+        unsafe {
+            self.buffer
+                .write_to_index(std::slice::from_ref(data), index);
+            self.buffer
+                .flush_index(index)
+                .map_err(|e| log::error!("Unable to flush memory: {}", e))
+                .unwrap();
+        }
+    }
+
+    /// Covers only the frame's copy, for writing into that frame's descriptor set
+    pub fn descriptor_info(&self, frame_index: usize) -> Rc<vk::DescriptorBufferInfo> {
+        self.buffer
+            .descriptor_info_for_index(self.checked_index(frame_index))
+    }
+
+    fn checked_index(&self, frame_index: usize) -> u64 {
+        assert!(
+            frame_index < self.buffer.instance_count as usize,
+            "Frame index {} is out of range for a UBO buffer with {} frames",
+            frame_index,
+            self.buffer.instance_count
+        );
+
+        frame_index as u64
+    }
+}
+
 impl Drop for LveBuffer {
     fn drop(&mut self) {
         match &self.buffer_type {
-            BufferType::Staging => log::debug!("Dropping Staging Buffer"),
             BufferType::Vertex => log::debug!("Dropping Vertex Buffer"),
             BufferType::Index => log::debug!("Dropping Index Buffer"),
             BufferType::Uniform => log::debug!("Dropping Uniform Buffer"),
+            BufferType::Instance => log::debug!("Dropping Instance Buffer"),
         }
 
         unsafe {
@@ -267,3 +385,141 @@ impl Drop for LveBuffer {
         }
     }
 }
+
+/// A host visible uniform buffer with `objects_per_frame` slots of `T` for each frame in
+/// flight, for per object data bound as a `UNIFORM_BUFFER_DYNAMIC` with a different offset for
+/// each draw. Every slot is aligned to `minUniformBufferOffsetAlignment`, so any slot's offset
+/// is a valid dynamic offset. This is where per object data goes once it outgrows the push
+/// constants
+pub struct DynamicUboBuffer<T> {
+    buffer: LveBuffer,
+    objects_per_frame: u32,
+    _data: PhantomData<T>,
+}
+
+impl<T: Copy> DynamicUboBuffer<T> {
+    pub fn new(lve_device: Rc<LveDevice>, frame_count: usize, objects_per_frame: u32) -> Self {
+        let min_offset_alignment = lve_device
+            .properties
+            .limits
+            .min_uniform_buffer_offset_alignment;
+
+        let mut buffer = LveBuffer::new(
+            lve_device,
+            size_of::<T>() as vk::DeviceSize,
+            frame_count as u32 * objects_per_frame,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE,
+            min_offset_alignment,
+            BufferType::Uniform,
+        );
+
+        // Stays mapped for the buffer's whole life, it's written every frame
+        unsafe { buffer.map(vk::WHOLE_SIZE, 0) };
+
+        Self {
+            buffer,
+            objects_per_frame,
+            _data: PhantomData,
+        }
+    }
+
+    /// Writes `data` into the object's slot for the frame. Call `flush_frame` once every object
+    /// has been written so the device sees them
+    pub fn write(&self, frame_index: usize, object_index: u32, data: &T) {
+        let index = self.checked_index(frame_index, object_index);
+
+        unsafe {
+            self.buffer
+                .write_to_index(std::slice::from_ref(data), index);
+        }
+    }
+
+    /// Makes every slot of the frame visible to the device
+    pub fn flush_frame(&self, frame_index: usize) {
+        let first_index = self.checked_index(frame_index, 0);
+
+        unsafe {
+            self.buffer
+                .flush(
+                    self.buffer.alignment_size * self.objects_per_frame as u64,
+                    self.buffer.alignment_size * first_index,
+                )
+                .map_err(|e| log::error!("Unable to flush memory: {}", e))
+                .unwrap();
+        }
+    }
+
+    /// Covers a single slot from the start of the buffer, the dynamic offset picks which one
+    pub fn descriptor_info(&self) -> Rc<vk::DescriptorBufferInfo> {
+        self.buffer.descriptor_info_for_index(0)
+    }
+
+    /// The dynamic offset to bind the descriptor with to read the object's slot for the frame
+    pub fn dynamic_offset(&self, frame_index: usize, object_index: u32) -> u32 {
+        (self.checked_index(frame_index, object_index) * self.buffer.alignment_size) as u32
+    }
+
+    fn checked_index(&self, frame_index: usize, object_index: u32) -> u64 {
+        assert!(
+            object_index < self.objects_per_frame,
+            "Object index {} is out of range for a dynamic UBO buffer with {} objects per frame",
+            object_index,
+            self.objects_per_frame
+        );
+
+        let index = frame_index as u64 * self.objects_per_frame as u64 + object_index as u64;
+
+        assert!(
+            index < self.buffer.instance_count as u64,
+            "Frame index {} is out of range for a dynamic UBO buffer with {} frames",
+            frame_index,
+            self.buffer.instance_count / self.objects_per_frame
+        );
+
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sub_range_is_widened_to_whole_atoms() {
+        assert_eq!(align_to_atom_size(70, 20, 64, 1024), (64, 64));
+        assert_eq!(align_to_atom_size(64, 64, 64, 1024), (64, 64));
+        assert_eq!(align_to_atom_size(10, 200, 64, 1024), (0, 256));
+    }
+
+    #[test]
+    fn aligned_range_is_a_multiple_of_the_atom_size() {
+        let atom_size = 64;
+
+        for offset in 0..200 {
+            for size in 1..200 {
+                let (aligned_offset, aligned_size) =
+                    align_to_atom_size(offset, size, atom_size, 1024);
+
+                assert_eq!(aligned_offset % atom_size, 0);
+                assert_eq!(aligned_size % atom_size, 0);
+                assert!(aligned_offset <= offset);
+                assert!(aligned_offset + aligned_size >= offset + size);
+            }
+        }
+    }
+
+    #[test]
+    fn range_past_the_end_of_the_buffer_runs_to_the_end_of_the_memory() {
+        assert_eq!(align_to_atom_size(0, 100, 64, 100), (0, vk::WHOLE_SIZE));
+        assert_eq!(align_to_atom_size(70, 30, 64, 100), (64, vk::WHOLE_SIZE));
+    }
+
+    #[test]
+    fn whole_size_keeps_the_rounded_down_offset() {
+        assert_eq!(
+            align_to_atom_size(70, vk::WHOLE_SIZE, 64, 1024),
+            (64, vk::WHOLE_SIZE)
+        );
+    }
+}
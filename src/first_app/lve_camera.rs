@@ -1,10 +1,197 @@
+use super::lve_game_object::SceneBounds;
+
+use ash::vk;
+
 extern crate nalgebra as na;
 
 use std::f32::EPSILON;
 
+/// How much room `frame_bounds` leaves around the bounds, 1.0 would just touch the edges of
+/// the view
+const FRAMING_MARGIN: f32 = 1.2;
+/// The view rotation `frame_bounds` looks from: along +z and a little down onto the scene
+const FRAMING_ROTATION: [f32; 3] = [-0.4, 0.0, 0.0];
+
+/// The parameters the projection matrix was built from, kept so the projection can be
+/// rebuilt for a new aspect ratio without rebuilding the whole camera
+#[derive(Debug, Clone, Copy)]
+pub enum Projection {
+    Orthographic {
+        left: f32,
+        right: f32,
+        top: f32,
+        bottom: f32,
+        near: f32,
+        far: f32,
+    },
+    Perspective {
+        fovy: f32,
+        aspect: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+impl Projection {
+    pub fn matrix(&self) -> na::Matrix4<f32> {
+        match *self {
+            Projection::Orthographic {
+                left,
+                right,
+                top,
+                bottom,
+                near,
+                far,
+            } => na::matrix![
+                2.0 / (right - left), 0.0                 , 0.0               , -(right + left) / (right - left);
+                0.0                 , 2.0 / (bottom - top), 0.0               , -(bottom + top) / (bottom - top);
+                0.0                 , 0.0                 , 1.0 / (far - near), -near / (far - near);
+                0.0                 , 0.0                 , 0.0               , 1.0;
+            ],
+            Projection::Perspective {
+                fovy,
+                aspect,
+                near,
+                far,
+            } => {
+                assert!((aspect - EPSILON).abs() > 0.0);
+
+                let tan_half_fovy = (fovy / 2.0).tan();
+
+                na::matrix![
+                    1.0 / (aspect * tan_half_fovy), 0.0                  , 0.0               , 0.0;
+                    0.0                           , 1.0 / (tan_half_fovy), 0.0               , 0.0;
+                    0.0                           , 0.0                  , far / (far - near), -(far * near) / (far - near);
+                    0.0                           , 0.0                  , 1.0               , 0.0;
+                ]
+            }
+        }
+    }
+
+    /// The distances to the near and far clip planes
+    pub fn near_far(&self) -> (f32, f32) {
+        match *self {
+            Projection::Orthographic { near, far, .. }
+            | Projection::Perspective { near, far, .. } => (near, far),
+        }
+    }
+
+    /// How far from the centre of a sphere of `radius` the camera has to be to see all of it.
+    /// An orthographic projection's size doesn't change with distance, so it only backs off
+    /// far enough for the sphere to be in front of the near plane
+    pub fn framing_distance(&self, radius: f32) -> f32 {
+        match *self {
+            Projection::Orthographic { near, .. } => radius * FRAMING_MARGIN + near,
+            Projection::Perspective { fovy, aspect, .. } => {
+                let half_fovy = fovy / 2.0;
+                let half_fovx = (half_fovy.tan() * aspect).atan();
+
+                radius * FRAMING_MARGIN / half_fovy.min(half_fovx).sin()
+            }
+        }
+    }
+
+    /// Returns the same projection adjusted to a new width / height ratio. Orthographic
+    /// projections keep their vertical extent and centre, and widen or narrow horizontally
+    pub fn with_aspect(&self, new_aspect: f32) -> Self {
+        match *self {
+            Projection::Orthographic {
+                left,
+                right,
+                top,
+                bottom,
+                near,
+                far,
+            } => {
+                let centre = (left + right) / 2.0;
+                let half_width = (bottom - top).abs() / 2.0 * new_aspect;
+                Projection::Orthographic {
+                    left: centre - half_width,
+                    right: centre + half_width,
+                    top,
+                    bottom,
+                    near,
+                    far,
+                }
+            }
+            Projection::Perspective {
+                fovy, near, far, ..
+            } => Projection::Perspective {
+                fovy,
+                aspect: new_aspect,
+                near,
+                far,
+            },
+        }
+    }
+}
+
+/// Shifts everything `matrix` projects by `jitter` in normalized device coordinates, i.e. adds
+/// it to the projection's translation terms. Adding `jitter` times the w row to the x and y rows
+/// moves the clip space position by `jitter * w`, which the divide by w turns into an even
+/// shift however far away the point is, for both kinds of projection
+fn jittered(matrix: &na::Matrix4<f32>, jitter: na::Vector2<f32>) -> na::Matrix4<f32> {
+    let mut jittered = *matrix;
+    let w_row = matrix.row(3).into_owned();
+
+    jittered.set_row(0, &(matrix.row(0) + w_row * jitter[0]));
+    jittered.set_row(1, &(matrix.row(1) + w_row * jitter[1]));
+
+    jittered
+}
+
+/// The `index`th number (from 1) of the Halton sequence in `base`, between 0 and 1. Successive
+/// numbers fill the range evenly without repeating, so a few frames of them cover a pixel well
+pub fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+
+    result
+}
+
+/// The projection jitter for `frame`, cycling through the first `phases` points of the
+/// (2, 3) Halton sequence. Each point is an offset of up to half a pixel either way in a view
+/// of size `extent`, given in normalized device coordinates, see
+/// `LveCameraBuilder::set_perspective_projection_jittered`
+pub fn halton_jitter(frame: u64, phases: u32, extent: vk::Extent2D) -> na::Vector2<f32> {
+    // The sequence starts at 1, as 0 is the same in every base
+    let index = (frame % phases as u64) as u32 + 1;
+
+    // One pixel is 2 / size across NDC's -1 to 1
+    na::vector![
+        (halton(index, 2) - 0.5) * 2.0 / extent.width as f32,
+        (halton(index, 3) - 0.5) * 2.0 / extent.height as f32
+    ]
+}
+
+/// Which kind of projection the app builds its camera with
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectionMode {
+    #[default]
+    Perspective,
+    Orthographic,
+}
+
+impl ProjectionMode {
+    pub fn toggled(self) -> Self {
+        match self {
+            ProjectionMode::Perspective => ProjectionMode::Orthographic,
+            ProjectionMode::Orthographic => ProjectionMode::Perspective,
+        }
+    }
+}
+
 pub struct LveCameraBuilder {
     pub projection_matrix: na::Matrix4<f32>,
     pub view_matrix: na::Matrix4<f32>,
+    projection: Option<Projection>,
+    jitter: na::Vector2<f32>,
 }
 
 impl LveCameraBuilder {
@@ -12,10 +199,11 @@ impl LveCameraBuilder {
         LveCameraBuilder {
             projection_matrix: na::Matrix4::identity(),
             view_matrix: na::Matrix4::identity(),
+            projection: None,
+            jitter: na::Vector2::zeros(),
         }
     }
 
-    #[allow(dead_code)]
     pub fn set_orthographic_projection<'a>(
         &'a mut self,
         left: f32,
@@ -25,17 +213,22 @@ impl LveCameraBuilder {
         near: f32,
         far: f32,
     ) -> &'a mut LveCameraBuilder {
-        self.projection_matrix = na::matrix![
-            2.0 / (right - left), 0.0                 , 0.0               , -(right + left) / (right - left);
-            0.0                 , 2.0 / (bottom - top), 0.0               , -(bottom + top) / (bottom - top);
-            0.0                 , 0.0                 , 1.0 / (far - near), -near / (far - near);
-            0.0                 , 0.0                 , 0.0               , 1.0;
-        ];
+        let projection = Projection::Orthographic {
+            left,
+            right,
+            top,
+            bottom,
+            near,
+            far,
+        };
+
+        self.projection_matrix = projection.matrix();
+        self.projection = Some(projection);
+        self.jitter = na::Vector2::zeros();
 
         self
     }
 
-    #[allow(dead_code)]
     pub fn set_perspective_projection<'a>(
         &'a mut self,
         fovy: f32,
@@ -43,16 +236,38 @@ impl LveCameraBuilder {
         near: f32,
         far: f32,
     ) -> &'a mut Self {
-        assert!((aspect - EPSILON).abs() > 0.0);
+        let projection = Projection::Perspective {
+            fovy,
+            aspect,
+            near,
+            far,
+        };
 
-        let tan_half_fovy = (fovy / 2.0).tan();
+        self.projection_matrix = projection.matrix();
+        self.projection = Some(projection);
+        self.jitter = na::Vector2::zeros();
 
-        self.projection_matrix = na::matrix![
-            1.0 / (aspect * tan_half_fovy), 0.0                  , 0.0               , 0.0;
-            0.0                           , 1.0 / (tan_half_fovy), 0.0               , 0.0;
-            0.0                           , 0.0                  , far / (far - near), -(far * near) / (far - near);
-            0.0                           , 0.0                  , 1.0               , 0.0;
-        ];
+        self
+    }
+
+    /// A perspective projection shifted by `jitter`, in normalized device coordinates (x and y
+    /// from -1 to 1 across the view, +y down), so one pixel is `2 / width` across and
+    /// `2 / height` down. Jittering by a different sub-pixel offset every frame samples a
+    /// different point in each pixel, which temporal anti-aliasing blends back together. See
+    /// `halton_jitter` and `LveCamera::set_jitter`
+    #[allow(dead_code)]
+    pub fn set_perspective_projection_jittered(
+        &mut self,
+        fovy: f32,
+        aspect: f32,
+        near: f32,
+        far: f32,
+        jitter: na::Vector2<f32>,
+    ) -> &mut Self {
+        self.set_perspective_projection(fovy, aspect, near, far);
+
+        self.projection_matrix = jittered(&self.projection_matrix, jitter);
+        self.jitter = jitter;
 
         self
     }
@@ -93,45 +308,39 @@ impl LveCameraBuilder {
         self.set_view_direction(position, target - position, up)
     }
 
-    #[allow(dead_code)]
     pub fn set_view_xyz<'a>(
         &'a mut self,
         position: na::Vector3<f32>,
         rotation: na::Vector3<f32>,
     ) -> &'a mut LveCameraBuilder {
-        let c3 = rotation[2].cos();
-        let s3 = rotation[2].sin();
-        let c2 = rotation[0].cos();
-        let s2 = rotation[0].sin();
-        let c1 = rotation[1].cos();
-        let s1 = rotation[1].sin();
-
-        let u = na::vector![
-            (c1 * c3 + s1 * s2 * s3),
-            (c2 * s3),
-            (c1 * s2 * s3 - c3 * s1)
-        ];
-        let v = na::vector![
-            (c3 * s1 * s2 - c1 * s3),
-            (c2 * c3),
-            (c1 * c3 * s2 + s1 * s3)
-        ];
-        let w = na::vector![(c2 * s1), (-s2), (c1 * c2)];
-
-        self.view_matrix = na::matrix![
-            u[0], u[1], u[2], -u.dot(&position);
-            v[0], v[1], v[2], -v.dot(&position);
-            w[0], w[1], w[2], -w.dot(&position);
-            0.0 , 0.0 , 0.0 , 1.0;
-        ];
+        self.view_matrix = view_xyz(position, rotation);
 
         self
     }
 
+    /// Moves and turns the camera so all of `bounds` is in view with some margin, using the
+    /// projection already set, adjusted to `aspect`
+    #[allow(dead_code)]
+    pub fn frame_bounds<'a>(&'a mut self, bounds: &SceneBounds, aspect: f32) -> &'a mut Self {
+        match self.projection {
+            Some(projection) => {
+                let (position, rotation) = framing_view(bounds, &projection.with_aspect(aspect));
+                self.set_view_xyz(position, rotation)
+            }
+            None => {
+                log::warn!("Cannot frame bounds without a projection");
+                self
+            }
+        }
+    }
+
     pub fn build(&self) -> LveCamera {
         LveCamera {
             projection_matrix: self.projection_matrix,
             view_matrix: self.view_matrix,
+            inverse_view_matrix: inverse_view(&self.view_matrix),
+            projection: self.projection,
+            jitter: self.jitter,
         }
     }
 }
@@ -139,4 +348,337 @@ impl LveCameraBuilder {
 pub struct LveCamera {
     pub projection_matrix: na::Matrix4<f32>,
     pub view_matrix: na::Matrix4<f32>,
+    /// Camera to world space, the last column is the camera's world position
+    pub inverse_view_matrix: na::Matrix4<f32>,
+    projection: Option<Projection>,
+    /// Already applied to `projection_matrix`, in normalized device coordinates
+    jitter: na::Vector2<f32>,
+}
+
+impl LveCamera {
+    /// Rebuilds only the projection matrix for a new aspect ratio, leaving the view untouched.
+    /// The aspect should come from the swapchain extent (physical pixels) rather than the
+    /// window's logical size, as that is what actually gets rasterised
+    pub fn set_aspect(&mut self, aspect: f32) {
+        match self.projection {
+            Some(projection) => {
+                let projection = projection.with_aspect(aspect);
+                self.projection_matrix = jittered(&projection.matrix(), self.jitter);
+                self.projection = Some(projection);
+            }
+            None => log::warn!("Cannot set the aspect of a camera without a projection"),
+        }
+    }
+
+    /// Replaces the projection's jitter, see
+    /// `LveCameraBuilder::set_perspective_projection_jittered` for the convention
+    pub fn set_jitter(&mut self, jitter: na::Vector2<f32>) {
+        self.projection_matrix = jittered(&self.projection_matrix, jitter - self.jitter);
+        self.jitter = jitter;
+    }
+
+    /// The jitter applied to `projection_matrix`, zero unless one has been set
+    pub fn jitter(&self) -> na::Vector2<f32> {
+        self.jitter
+    }
+
+    /// `projection_matrix` without the jitter, e.g. for reprojecting a point into the last
+    /// frame without the jitter moving it around
+    pub fn unjittered_projection_matrix(&self) -> na::Matrix4<f32> {
+        jittered(&self.projection_matrix, -self.jitter)
+    }
+
+    pub fn set_view_xyz(&mut self, position: na::Vector3<f32>, rotation: na::Vector3<f32>) {
+        self.view_matrix = view_xyz(position, rotation);
+        self.inverse_view_matrix = inverse_view(&self.view_matrix);
+    }
+
+    /// The distance to the near clip plane, `None` if the camera has no projection
+    pub fn near(&self) -> Option<f32> {
+        self.projection.map(|projection| projection.near_far().0)
+    }
+
+    /// The distance to the far clip plane, `None` if the camera has no projection
+    pub fn far(&self) -> Option<f32> {
+        self.projection.map(|projection| projection.near_far().1)
+    }
+
+    /// The position and XYZ rotation that fit all of `bounds` in view with this camera's
+    /// projection, see `LveCameraBuilder::frame_bounds`. `None` if the camera has no projection
+    pub fn framing_view(
+        &self,
+        bounds: &SceneBounds,
+    ) -> Option<(na::Vector3<f32>, na::Vector3<f32>)> {
+        self.projection
+            .map(|projection| framing_view(bounds, &projection))
+    }
+
+    /// Projects a world space point into normalized device coordinates: x and y from -1 to 1
+    /// across the view (+y down, as in Vulkan), z from 0 at the near plane to 1 at the far
+    /// plane. `None` if the point is level with or behind the camera
+    #[allow(dead_code)]
+    pub fn world_to_ndc(&self, point: na::Vector3<f32>) -> Option<na::Vector3<f32>> {
+        let clip = self.projection_matrix * self.view_matrix * point.push(1.0);
+
+        if clip[3] <= f32::EPSILON {
+            return None;
+        }
+
+        Some(clip.xyz() / clip[3])
+    }
+
+    /// The world space point at `ndc`, the inverse of `world_to_ndc`. `None` if the projection
+    /// can't be inverted (e.g. the camera has none)
+    #[allow(dead_code)]
+    pub fn ndc_to_world(&self, ndc: na::Vector3<f32>) -> Option<na::Vector3<f32>> {
+        let inverse_projection = self.projection_matrix.try_inverse()?;
+        let world = self.inverse_view_matrix * inverse_projection * ndc.push(1.0);
+
+        if world[3].abs() <= f32::EPSILON {
+            return None;
+        }
+
+        Some(world.xyz() / world[3])
+    }
+
+    /// Projects a world space point to pixel coordinates in a view of size `extent`, from
+    /// (0, 0) at the top left. Points outside the view give coordinates outside the extent.
+    /// `None` if the point is level with or behind the camera
+    #[allow(dead_code)]
+    pub fn world_to_screen(
+        &self,
+        point: na::Vector3<f32>,
+        extent: vk::Extent2D,
+    ) -> Option<na::Vector2<f32>> {
+        let ndc = self.world_to_ndc(point)?;
+
+        Some(na::vector![
+            (ndc[0] + 1.0) / 2.0 * extent.width as f32,
+            (ndc[1] + 1.0) / 2.0 * extent.height as f32
+        ])
+    }
+
+    /// The camera's position in world space
+    pub fn position(&self) -> na::Vector3<f32> {
+        self.inverse_view_matrix.fixed_slice::<3, 1>(0, 3).into()
+    }
+}
+
+/// Looks at the centre of `bounds` from `FRAMING_ROTATION`, backed off until it all fits
+fn framing_view(
+    bounds: &SceneBounds,
+    projection: &Projection,
+) -> (na::Vector3<f32>, na::Vector3<f32>) {
+    let rotation = na::Vector3::from(FRAMING_ROTATION);
+
+    // The view matrix's third row is the direction the camera looks in
+    let forward = view_xyz(na::Vector3::zeros(), rotation)
+        .fixed_slice::<1, 3>(2, 0)
+        .transpose();
+
+    let distance = projection.framing_distance(bounds.radius());
+
+    (bounds.center() - forward * distance, rotation)
+}
+
+fn inverse_view(view_matrix: &na::Matrix4<f32>) -> na::Matrix4<f32> {
+    view_matrix
+        .try_inverse()
+        .expect("View matrix should always be invertible")
+}
+
+fn view_xyz(position: na::Vector3<f32>, rotation: na::Vector3<f32>) -> na::Matrix4<f32> {
+    let c3 = rotation[2].cos();
+    let s3 = rotation[2].sin();
+    let c2 = rotation[0].cos();
+    let s2 = rotation[0].sin();
+    let c1 = rotation[1].cos();
+    let s1 = rotation[1].sin();
+
+    let u = na::vector![
+        (c1 * c3 + s1 * s2 * s3),
+        (c2 * s3),
+        (c1 * s2 * s3 - c3 * s1)
+    ];
+    let v = na::vector![
+        (c3 * s1 * s2 - c1 * s3),
+        (c2 * c3),
+        (c1 * c3 * s2 + s1 * s3)
+    ];
+    let w = na::vector![(c2 * s1), (-s2), (c1 * c2)];
+
+    na::matrix![
+        u[0], u[1], u[2], -u.dot(&position);
+        v[0], v[1], v[2], -v.dot(&position);
+        w[0], w[1], w[2], -w.dot(&position);
+        0.0 , 0.0 , 0.0 , 1.0;
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_camera() -> LveCamera {
+        LveCameraBuilder::new()
+            .set_perspective_projection(50.0_f32.to_radians(), 1.5, 0.1, 100.0)
+            .set_view_xyz(na::vector![1.0, -2.0, -3.0], na::vector![-0.3, 0.5, 0.0])
+            .build()
+    }
+
+    #[test]
+    fn world_points_round_trip_through_ndc() {
+        let camera = test_camera();
+
+        let forward = camera.view_matrix.fixed_slice::<1, 3>(2, 0).transpose();
+        let points = [
+            camera.position() + forward * 5.0,
+            camera.position() + forward * 50.0 + na::vector![1.0, 2.0, 0.5],
+            camera.position() + forward * 0.5 + na::vector![-0.1, 0.1, 0.0],
+        ];
+
+        for point in points {
+            let ndc = camera.world_to_ndc(point).unwrap();
+            let world = camera.ndc_to_world(ndc).unwrap();
+
+            // Depth precision falls off with distance
+            let tolerance = 1e-4 * (point - camera.position()).norm();
+
+            assert!((world - point).norm() < tolerance, "{} != {}", world, point);
+        }
+    }
+
+    #[test]
+    fn points_behind_the_camera_have_no_ndc() {
+        let camera = test_camera();
+
+        let forward = camera.view_matrix.fixed_slice::<1, 3>(2, 0).transpose();
+
+        assert_eq!(camera.world_to_ndc(camera.position() - forward), None);
+        assert_eq!(camera.world_to_ndc(camera.position()), None);
+    }
+
+    #[test]
+    fn view_centre_is_the_screen_centre() {
+        let camera = test_camera();
+        let extent = vk::Extent2D {
+            width: 800,
+            height: 600,
+        };
+
+        // The near plane corners map to the screen corners
+        let top_left = camera.ndc_to_world(na::vector![-1.0, -1.0, 0.0]).unwrap();
+        let bottom_right = camera.ndc_to_world(na::vector![1.0, 1.0, 0.0]).unwrap();
+        let forward = camera.view_matrix.fixed_slice::<1, 3>(2, 0).transpose();
+
+        let screen = |point| camera.world_to_screen(point, extent).unwrap();
+
+        assert!(
+            (screen(camera.position() + forward * 10.0) - na::vector![400.0, 300.0]).norm() < 1e-2
+        );
+        assert!(screen(top_left).norm() < 1e-2);
+        assert!((screen(bottom_right) - na::vector![800.0, 600.0]).norm() < 1e-2);
+    }
+
+    #[test]
+    fn framed_bounds_are_inside_the_view() {
+        let bounds = SceneBounds {
+            min: na::vector![-3.0, -1.0, 2.0],
+            max: na::vector![1.0, 0.5, 6.0],
+        };
+
+        for aspect in [0.5, 1.0, 2.0] {
+            let camera = LveCameraBuilder::new()
+                .set_perspective_projection(50.0_f32.to_radians(), 1.0, 0.1, 100.0)
+                .frame_bounds(&bounds, aspect)
+                .build();
+
+            let view_projection = camera.projection_matrix * camera.view_matrix;
+
+            for corner in 0..8 {
+                let point = na::vector![
+                    if corner & 1 == 0 {
+                        bounds.min[0]
+                    } else {
+                        bounds.max[0]
+                    },
+                    if corner & 2 == 0 {
+                        bounds.min[1]
+                    } else {
+                        bounds.max[1]
+                    },
+                    if corner & 4 == 0 {
+                        bounds.min[2]
+                    } else {
+                        bounds.max[2]
+                    },
+                    1.0
+                ];
+
+                let clip = view_projection * point;
+                let ndc = clip.xyz() / clip[3];
+
+                assert!(clip[3] > 0.0, "corner {} is behind the camera", corner);
+                assert!(ndc[0].abs() <= 1.0 && ndc[1].abs() <= 1.0, "{:?}", ndc);
+                assert!(ndc[2] >= 0.0 && ndc[2] <= 1.0, "{:?}", ndc);
+            }
+        }
+    }
+
+    #[test]
+    fn jitter_shifts_ndc_evenly_at_any_depth() {
+        let jitter = na::vector![0.002, -0.003];
+        let camera = test_camera();
+        let jittered_camera = LveCameraBuilder::new()
+            .set_perspective_projection_jittered(50.0_f32.to_radians(), 1.5, 0.1, 100.0, jitter)
+            .set_view_xyz(na::vector![1.0, -2.0, -3.0], na::vector![-0.3, 0.5, 0.0])
+            .build();
+
+        let forward = camera.view_matrix.fixed_slice::<1, 3>(2, 0).transpose();
+
+        for distance in [0.5, 5.0, 50.0] {
+            let point = camera.position() + forward * distance + na::vector![0.3, -0.2, 0.1];
+
+            let ndc = camera.world_to_ndc(point).unwrap();
+            let jittered_ndc = jittered_camera.world_to_ndc(point).unwrap();
+
+            assert!((jittered_ndc.xy() - ndc.xy() - jitter).norm() < 1e-5);
+            assert!((jittered_ndc[2] - ndc[2]).abs() < 1e-6);
+        }
+
+        // Changing the aspect keeps the jitter, and it can be taken off again
+        let mut jittered_camera = jittered_camera;
+        jittered_camera.set_aspect(1.5);
+
+        assert!(
+            (jittered_camera.unjittered_projection_matrix() - camera.projection_matrix).norm()
+                < 1e-6
+        );
+
+        jittered_camera.set_jitter(na::Vector2::zeros());
+
+        assert!((jittered_camera.projection_matrix - camera.projection_matrix).norm() < 1e-6);
+    }
+
+    #[test]
+    fn halton_jitter_stays_within_half_a_pixel() {
+        assert_eq!(halton(1, 2), 0.5);
+        assert_eq!(halton(2, 2), 0.25);
+        assert_eq!(halton(3, 2), 0.75);
+        assert!((halton(2, 3) - 2.0 / 3.0).abs() < 1e-6);
+
+        let extent = vk::Extent2D {
+            width: 800,
+            height: 600,
+        };
+
+        for frame in 0..16 {
+            let pixels = halton_jitter(frame, 8, extent)
+                .component_mul(&na::vector![800.0 / 2.0, 600.0 / 2.0]);
+
+            assert!(pixels.abs().max() <= 0.5, "{}", pixels);
+        }
+
+        assert_eq!(halton_jitter(3, 8, extent), halton_jitter(11, 8, extent));
+    }
 }
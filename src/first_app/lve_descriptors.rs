@@ -7,6 +7,8 @@ use std::rc::Rc;
 
 pub struct LveDescriptorSetLayout {
     lve_device: Rc<LveDevice>,
+    /// The bindings' `p_immutable_samplers` are always null here, the samplers are only
+    /// needed while the layout is created
     bindings: HashMap<u32, vk::DescriptorSetLayoutBinding>,
     pub descriptor_set_layout: vk::DescriptorSetLayout,
 }
@@ -15,11 +17,20 @@ impl LveDescriptorSetLayout {
     pub fn new(
         lve_device: Rc<LveDevice>,
         bindings: HashMap<u32, vk::DescriptorSetLayoutBinding>,
+        immutable_samplers: &HashMap<u32, Vec<vk::Sampler>>,
     ) -> Rc<LveDescriptorSetLayout> {
         let mut set_layout_bindings: Vec<vk::DescriptorSetLayoutBinding> = Vec::new();
 
-        bindings.iter().for_each(|(_, binding)| {
-            set_layout_bindings.push(*binding);
+        bindings.iter().for_each(|(binding_index, binding)| {
+            let mut binding = *binding;
+
+            // Vulkan only reads the samplers when the layout is created, so pointing into
+            // `immutable_samplers` is fine as it is borrowed until this function returns
+            if let Some(samplers) = immutable_samplers.get(binding_index) {
+                binding.p_immutable_samplers = samplers.as_ptr();
+            }
+
+            set_layout_bindings.push(binding);
         });
 
         let descriptor_set_layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
@@ -56,6 +67,9 @@ impl Drop for LveDescriptorSetLayout {
 pub struct LveDescriptorSetLayoutBuilder {
     lve_device: Rc<LveDevice>,
     bindings: HashMap<u32, vk::DescriptorSetLayoutBinding>,
+    /// Kept here rather than as pointers in `bindings`, which would dangle if the builder
+    /// moved or was dropped before `build`
+    immutable_samplers: HashMap<u32, Vec<vk::Sampler>>,
 }
 
 impl LveDescriptorSetLayoutBuilder {
@@ -63,6 +77,7 @@ impl LveDescriptorSetLayoutBuilder {
         LveDescriptorSetLayoutBuilder {
             lve_device,
             bindings: HashMap::<u32, vk::DescriptorSetLayoutBinding>::new(),
+            immutable_samplers: HashMap::new(),
         }
     }
 
@@ -89,8 +104,41 @@ impl LveDescriptorSetLayoutBuilder {
         self
     }
 
+    /// Adds a sampler or combined image sampler binding that always uses `samplers`, one per
+    /// descriptor, so the descriptor writes don't need a sampler. The samplers are copied into
+    /// the builder and must stay alive until `build` is called, as Vulkan reads them when the
+    /// layout is created. The spec requires them to outlive any use of the layout too, so in
+    /// practice they should outlive the layout
+    #[allow(dead_code)]
+    pub fn add_binding_with_immutable_samplers<'a>(
+        &'a mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        stage_flags: vk::ShaderStageFlags,
+        samplers: &[vk::Sampler],
+    ) -> &'a mut LveDescriptorSetLayoutBuilder {
+        assert!(
+            descriptor_type == vk::DescriptorType::SAMPLER
+                || descriptor_type == vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            "Only sampler bindings can have immutable samplers"
+        );
+        assert!(
+            !samplers.is_empty(),
+            "Immutable sampler binding has no samplers"
+        );
+
+        self.add_binding(binding, descriptor_type, stage_flags, samplers.len() as u32);
+        self.immutable_samplers.insert(binding, samplers.to_vec());
+
+        self
+    }
+
     pub fn build(&self) -> Rc<LveDescriptorSetLayout> {
-        LveDescriptorSetLayout::new(Rc::clone(&self.lve_device), HashMap::clone(&self.bindings))
+        LveDescriptorSetLayout::new(
+            Rc::clone(&self.lve_device),
+            HashMap::clone(&self.bindings),
+            &self.immutable_samplers,
+        )
     }
 }
 
@@ -135,8 +183,7 @@ impl LveDescriptorPool {
             .set_layouts(&[descriptor_set_layout])
             .build();
 
-        // Might want to create a "DescriptorPoolManager" class that handles this case, and builds
-        // a new pool whenever an old pool fills up. But this is beyond our current scope
+        // Fails once the pool is full, see `LveDescriptorPoolManager` for pools that grow
         let descriptor_set_result =
             unsafe { self.lve_device.device.allocate_descriptor_sets(&alloc_info) };
 
@@ -174,6 +221,123 @@ impl Drop for LveDescriptorPool {
     }
 }
 
+/// What a descriptor pool is sized for, in terms of what the app expects to draw rather than raw
+/// descriptor counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DescriptorPoolConfig {
+    /// Sets with a single uniform buffer, e.g. the global UBO set of each frame in flight
+    pub uniform_buffer_sets: u32,
+    /// Sets with a single dynamic uniform buffer, e.g. the per object UBO
+    pub dynamic_uniform_buffer_sets: u32,
+    /// Sets of textures, e.g. one per material
+    pub texture_sets: u32,
+    /// Combined image samplers in each texture set
+    pub textures_per_set: u32,
+    /// Sets with a single storage image, e.g. a compute shader's output
+    pub storage_image_sets: u32,
+}
+
+impl DescriptorPoolConfig {
+    pub fn max_sets(&self) -> u32 {
+        self.uniform_buffer_sets
+            + self.dynamic_uniform_buffer_sets
+            + self.texture_sets
+            + self.storage_image_sets
+    }
+
+    /// Types with no descriptors are left out, as Vulkan doesn't allow empty pool sizes
+    pub fn pool_sizes(&self) -> Vec<vk::DescriptorPoolSize> {
+        [
+            (vk::DescriptorType::UNIFORM_BUFFER, self.uniform_buffer_sets),
+            (
+                vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                self.dynamic_uniform_buffer_sets,
+            ),
+            (
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                self.texture_sets * self.textures_per_set,
+            ),
+            (vk::DescriptorType::STORAGE_IMAGE, self.storage_image_sets),
+        ]
+        .iter()
+        .filter(|(_, descriptor_count)| *descriptor_count > 0)
+        .map(|(ty, descriptor_count)| vk::DescriptorPoolSize {
+            ty: *ty,
+            descriptor_count: *descriptor_count,
+        })
+        .collect()
+    }
+}
+
+/// A growable set of descriptor pools, all sized from the same `DescriptorPoolConfig`. When
+/// the current pool is full another one is created, so going past the expected counts costs
+/// a new pool rather than failing
+pub struct LveDescriptorPoolManager {
+    lve_device: Rc<LveDevice>,
+    config: DescriptorPoolConfig,
+    pools: Vec<Rc<LveDescriptorPool>>,
+}
+
+impl LveDescriptorPoolManager {
+    pub fn new(lve_device: Rc<LveDevice>, config: DescriptorPoolConfig) -> Self {
+        let mut manager = Self {
+            lve_device,
+            config,
+            pools: Vec::new(),
+        };
+
+        manager.grow();
+
+        manager
+    }
+
+    /// The pool new sets are allocated from
+    pub fn current_pool(&self) -> Rc<LveDescriptorPool> {
+        Rc::clone(self.pools.last().unwrap())
+    }
+
+    /// Allocates a set from the current pool, creating a new pool if it is full
+    pub fn allocate(
+        &mut self,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+    ) -> vk::DescriptorSet {
+        if let Ok(descriptor_set) = self
+            .current_pool()
+            .allocate_descriptor(descriptor_set_layout)
+        {
+            return descriptor_set;
+        }
+
+        self.grow();
+
+        self.current_pool()
+            .allocate_descriptor(descriptor_set_layout)
+            .map_err(|_| {
+                log::error!(
+                    "Unable to allocate a descriptor set from a new pool, the layout needs more \
+                     descriptors than {:?} provides",
+                    self.config
+                )
+            })
+            .unwrap()
+    }
+
+    fn grow(&mut self) {
+        log::debug!(
+            "Creating descriptor pool {} for {:?}",
+            self.pools.len(),
+            self.config
+        );
+
+        self.pools.push(LveDescriptorPool::new(
+            Rc::clone(&self.lve_device),
+            self.config.max_sets(),
+            vk::DescriptorPoolCreateFlags::empty(),
+            &self.config.pool_sizes(),
+        ));
+    }
+}
+
 pub struct LveDescriptorPoolBuilder {
     lve_device: Rc<LveDevice>,
     pool_sizes: Vec<vk::DescriptorPoolSize>,
@@ -273,10 +437,10 @@ impl LveDescriptorWriter {
         self
     }
 
-    pub fn _write_image<'a>(
+    pub fn write_image<'a>(
         &'a mut self,
         binding: u32,
-        image_info: vk::DescriptorImageInfo,
+        image_info: &[vk::DescriptorImageInfo],
     ) -> &'a mut LveDescriptorWriter {
         assert!(
             self.set_layout.bindings.contains_key(&binding),
@@ -293,7 +457,7 @@ impl LveDescriptorWriter {
         let write = vk::WriteDescriptorSet::builder()
             .descriptor_type(binding_description.descriptor_type)
             .dst_binding(binding)
-            .image_info(&[image_info])
+            .image_info(image_info)
             .build();
 
         self.writes.push(write);
@@ -301,6 +465,36 @@ impl LveDescriptorWriter {
         self
     }
 
+    /// Writes images a shader stores to, which have to be in the `GENERAL` layout, to a
+    /// `STORAGE_IMAGE` binding (see `LveImage::storage_descriptor_info`)
+    #[allow(dead_code)]
+    pub fn write_storage_image<'a>(
+        &'a mut self,
+        binding: u32,
+        image_info: &[vk::DescriptorImageInfo],
+    ) -> &'a mut LveDescriptorWriter {
+        let descriptor_type = self
+            .set_layout
+            .bindings
+            .get(&binding)
+            .map(|binding_description| binding_description.descriptor_type);
+
+        assert_eq!(
+            descriptor_type,
+            Some(vk::DescriptorType::STORAGE_IMAGE),
+            "Binding {} is not a storage image",
+            binding
+        );
+        assert!(
+            image_info
+                .iter()
+                .all(|info| info.image_layout == vk::ImageLayout::GENERAL),
+            "Storage images must be written in the GENERAL layout"
+        );
+
+        self.write_image(binding, image_info)
+    }
+
     pub fn build(&mut self) -> Result<vk::DescriptorSet, ()> {
         match self
             .pool
@@ -313,6 +507,15 @@ impl LveDescriptorWriter {
             Err(_) => Err(()),
         }
     }
+    /// Like `build`, but allocates from `pools`, which grows rather than failing when full
+    pub fn build_from(&mut self, pools: &mut LveDescriptorPoolManager) -> vk::DescriptorSet {
+        let descriptor_set = pools.allocate(self.set_layout.descriptor_set_layout);
+
+        unsafe { self.overwrite(&descriptor_set) };
+
+        descriptor_set
+    }
+
     pub unsafe fn overwrite(&mut self, set: &vk::DescriptorSet) {
         self.writes.iter_mut().for_each(|write| {
             write.dst_set = *set;
@@ -324,3 +527,64 @@ impl LveDescriptorWriter {
             .update_descriptor_sets(self.writes.as_slice(), &[])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_sizes_leave_out_unused_types() {
+        let config = DescriptorPoolConfig {
+            uniform_buffer_sets: 2,
+            dynamic_uniform_buffer_sets: 0,
+            texture_sets: 0,
+            textures_per_set: 3,
+            storage_image_sets: 0,
+        };
+
+        let pool_sizes = config.pool_sizes();
+
+        assert_eq!(config.max_sets(), 2);
+        assert_eq!(pool_sizes.len(), 1);
+        assert_eq!(pool_sizes[0].ty, vk::DescriptorType::UNIFORM_BUFFER);
+        assert_eq!(pool_sizes[0].descriptor_count, 2);
+    }
+
+    #[test]
+    fn texture_descriptors_scale_with_the_set_count() {
+        let config = DescriptorPoolConfig {
+            uniform_buffer_sets: 2,
+            dynamic_uniform_buffer_sets: 0,
+            texture_sets: 10,
+            textures_per_set: 3,
+            storage_image_sets: 0,
+        };
+
+        let samplers = config
+            .pool_sizes()
+            .into_iter()
+            .find(|pool_size| pool_size.ty == vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .unwrap();
+
+        assert_eq!(config.max_sets(), 12);
+        assert_eq!(samplers.descriptor_count, 30);
+    }
+
+    #[test]
+    fn storage_images_get_their_own_pool_size() {
+        let config = DescriptorPoolConfig {
+            uniform_buffer_sets: 0,
+            dynamic_uniform_buffer_sets: 0,
+            texture_sets: 0,
+            textures_per_set: 3,
+            storage_image_sets: 2,
+        };
+
+        let pool_sizes = config.pool_sizes();
+
+        assert_eq!(config.max_sets(), 2);
+        assert_eq!(pool_sizes.len(), 1);
+        assert_eq!(pool_sizes[0].ty, vk::DescriptorType::STORAGE_IMAGE);
+        assert_eq!(pool_sizes[0].descriptor_count, 2);
+    }
+}
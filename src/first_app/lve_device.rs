@@ -15,6 +15,8 @@ use ash_window;
 use winit::window::Window;
 
 use std::{
+    cell::RefCell,
+    collections::HashSet,
     ffi::{CStr, CString},
     os::raw::c_void,
     rc::Rc,
@@ -25,6 +27,16 @@ pub const ENABLE_VALIDATION_LAYERS: bool = true;
 #[cfg(not(debug_assertions))]
 pub const ENABLE_VALIDATION_LAYERS: bool = false;
 
+/// Run buffer and image uploads on a dedicated transfer queue family when the device has one
+pub const USE_DEDICATED_TRANSFER_QUEUE: bool = true;
+/// When a dedicated transfer queue is in use, create buffers and images with concurrent sharing
+/// between the graphics and transfer families instead of transferring ownership after every
+/// copy. Simpler, but access to concurrent resources can be slower on some hardware
+pub const CONCURRENT_TRANSFER_SHARING: bool = false;
+/// The reusable staging buffer grows to fit the largest upload up to this size. Anything bigger
+/// gets a one-off staging buffer that is freed as soon as the copy is done
+pub const MAX_STAGING_BUFFER_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
 // What validation layers we want to use in out application
 const VALIDATION_LAYERS: [&'static str; 1] = ["VK_LAYER_KHRONOS_validation"];
 
@@ -33,16 +45,34 @@ unsafe extern "system" fn vulkan_debug_callback(
     flag: vk::DebugUtilsMessageSeverityFlagsEXT,
     typ: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _p_user_data: *mut c_void,
+    p_user_data: *mut c_void,
 ) -> vk::Bool32 {
+    let message_id = (*p_callback_data).message_id_number;
+
+    // The user data is the messenger's config, owned by the device for as long as the messenger
+    // exists
+    if !p_user_data.is_null() {
+        let config = &*(p_user_data as *const DebugMessengerConfig);
+
+        if config.is_suppressed(message_id) {
+            return vk::FALSE;
+        }
+    }
+
     // Extract the message from the Callback Data
     let message = CStr::from_ptr((*p_callback_data).p_message);
 
-    // Log the message depending on severity
+    // Log the message depending on severity, with the id to suppress it by
     match flag {
-        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => log::error!("{:?} - {:?}", typ, message),
-        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => log::info!("{:?} - {:?}", typ, message),
-        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => log::warn!("{:?} - {:?}", typ, message),
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => {
+            log::error!("{:?} [{}] - {:?}", typ, message_id, message)
+        }
+        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => {
+            log::info!("{:?} [{}] - {:?}", typ, message_id, message)
+        }
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => {
+            log::warn!("{:?} [{}] - {:?}", typ, message_id, message)
+        }
         _ => {} // Any verbose logging goes here
     }
 
@@ -72,14 +102,23 @@ pub struct SwapChainSupportDetails {
 /// ```
 /// graphics_family: u32
 /// present_family: u32
+/// transfer_family: Option<u32>
+/// compute_family: Option<u32>
 /// graphics_family_has_value: bool
 /// present_family_has_value: bool
 /// ```
 pub struct QueueFamilyIndices {
     pub graphics_family: u32,
     pub present_family: u32,
-    graphics_family_has_value: bool,
-    present_family_has_value: bool,
+    /// A family that supports transfers but not graphics, if the device has one
+    pub transfer_family: Option<u32>,
+    /// The graphics family when it supports compute, which it does on any device that has one,
+    /// otherwise the first family that does
+    pub compute_family: Option<u32>,
+    /// `graphics_family` is only meaningful when this is set
+    pub graphics_family_has_value: bool,
+    /// `present_family` is only meaningful when this is set
+    pub present_family_has_value: bool,
 }
 
 impl QueueFamilyIndices {
@@ -88,37 +127,346 @@ impl QueueFamilyIndices {
     }
 }
 
+/// How the application identifies itself to the driver and to tools such as RenderDoc
+#[derive(Debug, Clone)]
+pub struct AppInfo {
+    pub app_name: String,
+    /// Made with `vk::make_api_version`
+    pub app_version: u32,
+    /// The highest Vulkan version the app uses. Clamped to what the loader supports
+    pub api_version: u32,
+}
+
+impl Default for AppInfo {
+    fn default() -> Self {
+        Self {
+            app_name: String::from("LittleVulkanEngine App"),
+            app_version: vk::make_api_version(0, 0, 1, 0),
+            api_version: vk::make_api_version(0, 1, 2, 176),
+        }
+    }
+}
+
+/// What the validation layers' debug messenger logs
+#[derive(Debug, Clone, Default)]
+pub struct DebugMessengerConfig {
+    /// The `message_id_number`s of messages to drop rather than log, e.g. a false positive a
+    /// driver or layer update keeps repeating. The id is logged in brackets with every message
+    pub suppressed_message_ids: HashSet<i32>,
+}
+
+impl DebugMessengerConfig {
+    pub fn suppressing(message_ids: &[i32]) -> Self {
+        Self {
+            suppressed_message_ids: message_ids.iter().copied().collect(),
+        }
+    }
+
+    fn is_suppressed(&self, message_id: i32) -> bool {
+        self.suppressed_message_ids.contains(&message_id)
+    }
+}
+
+/// Device features a physical device must support to be picked. These are enabled on the
+/// logical device along with whichever of `DeviceConfig::optional_features` the device
+/// supports, so anything relying on an optional feature should check
+/// `LveDevice::enabled_features` first
+#[derive(Debug, Clone, Copy)]
+pub struct RequiredFeatures {
+    pub sampler_anisotropy: bool,
+    /// Line and point polygon modes, for wireframe pipelines
+    pub fill_mode_non_solid: bool,
+    /// Shading every sample rather than every pixel, see `LvePipeline::enable_sample_shading`
+    pub sample_rate_shading: bool,
+    /// More than one draw per indirect draw call, see `IndirectRenderSystem`
+    pub multi_draw_indirect: bool,
+    /// A non zero `first_instance` in indirect draw commands, see `IndirectRenderSystem`
+    pub draw_indirect_first_instance: bool,
+    /// Counting fragment shader invocations, see `RenderStats::fragment_invocations`
+    pub pipeline_statistics_query: bool,
+}
+
+impl Default for RequiredFeatures {
+    fn default() -> Self {
+        Self {
+            sampler_anisotropy: true,
+            fill_mode_non_solid: false,
+            sample_rate_shading: false,
+            multi_draw_indirect: false,
+            draw_indirect_first_instance: false,
+            pipeline_statistics_query: false,
+        }
+    }
+}
+
+impl RequiredFeatures {
+    /// Nothing beyond core Vulkan, for software rasterisers such as llvmpipe
+    pub fn minimal() -> Self {
+        Self {
+            sampler_anisotropy: false,
+            fill_mode_non_solid: false,
+            sample_rate_shading: false,
+            multi_draw_indirect: false,
+            draw_indirect_first_instance: false,
+            pipeline_statistics_query: false,
+        }
+    }
+
+    /// Only the features the device supports, for `DeviceConfig::optional_features`
+    fn supported_subset(self, supported_features: &vk::PhysicalDeviceFeatures) -> Self {
+        Self {
+            sampler_anisotropy: self.sampler_anisotropy
+                && supported_features.sampler_anisotropy != 0,
+            fill_mode_non_solid: self.fill_mode_non_solid
+                && supported_features.fill_mode_non_solid != 0,
+            sample_rate_shading: self.sample_rate_shading
+                && supported_features.sample_rate_shading != 0,
+            multi_draw_indirect: self.multi_draw_indirect
+                && supported_features.multi_draw_indirect != 0,
+            draw_indirect_first_instance: self.draw_indirect_first_instance
+                && supported_features.draw_indirect_first_instance != 0,
+            pipeline_statistics_query: self.pipeline_statistics_query
+                && supported_features.pipeline_statistics_query != 0,
+        }
+    }
+
+    /// Every feature in either
+    fn union(self, other: Self) -> Self {
+        Self {
+            sampler_anisotropy: self.sampler_anisotropy || other.sampler_anisotropy,
+            fill_mode_non_solid: self.fill_mode_non_solid || other.fill_mode_non_solid,
+            sample_rate_shading: self.sample_rate_shading || other.sample_rate_shading,
+            multi_draw_indirect: self.multi_draw_indirect || other.multi_draw_indirect,
+            draw_indirect_first_instance: self.draw_indirect_first_instance
+                || other.draw_indirect_first_instance,
+            pipeline_statistics_query: self.pipeline_statistics_query
+                || other.pipeline_statistics_query,
+        }
+    }
+
+    fn is_supported_by(&self, supported_features: &vk::PhysicalDeviceFeatures) -> bool {
+        (!self.sampler_anisotropy || supported_features.sampler_anisotropy != 0)
+            && (!self.fill_mode_non_solid || supported_features.fill_mode_non_solid != 0)
+            && (!self.sample_rate_shading || supported_features.sample_rate_shading != 0)
+            && (!self.multi_draw_indirect || supported_features.multi_draw_indirect != 0)
+            && (!self.draw_indirect_first_instance
+                || supported_features.draw_indirect_first_instance != 0)
+            && (!self.pipeline_statistics_query
+                || supported_features.pipeline_statistics_query != 0)
+    }
+
+    fn to_physical_device_features(self) -> vk::PhysicalDeviceFeatures {
+        vk::PhysicalDeviceFeatures::builder()
+            .sampler_anisotropy(self.sampler_anisotropy)
+            .fill_mode_non_solid(self.fill_mode_non_solid)
+            .sample_rate_shading(self.sample_rate_shading)
+            .multi_draw_indirect(self.multi_draw_indirect)
+            .draw_indirect_first_instance(self.draw_indirect_first_instance)
+            .pipeline_statistics_query(self.pipeline_statistics_query)
+            .build()
+    }
+}
+
+/// Which physical device to pick when more than one is suitable
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DevicePreference {
+    /// The first the loader lists
+    #[default]
+    FirstSuitable,
+    /// A software (CPU) device such as llvmpipe, falling back to the first suitable device
+    Software,
+}
+
+/// What the device is created for, which decides the queues and extensions it needs
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DevicePurpose {
+    /// Drawing to a window: graphics and present queues, a surface and the swapchain
+    /// extension
+    #[default]
+    Graphics,
+    /// Running compute work and reading back the results with no window at all. Only a compute
+    /// queue is needed, there is no surface, and `graphics_queue` and `present_queue` are null
+    ComputeOnly,
+}
+
+/// What `LveDevice::new` requires of a physical device, and which it picks out of those that
+/// meet the requirements
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceConfig {
+    pub required_features: RequiredFeatures,
+    /// Enabled when the picked device supports them, but don't rule any device out
+    pub optional_features: RequiredFeatures,
+    pub preference: DevicePreference,
+    pub purpose: DevicePurpose,
+}
+
+impl Default for DeviceConfig {
+    /// Wireframes are optional, without them wireframe objects are drawn filled. So are the
+    /// fragment invocation counts, which are just left out of the stats
+    fn default() -> Self {
+        Self {
+            required_features: RequiredFeatures::default(),
+            optional_features: RequiredFeatures {
+                fill_mode_non_solid: true,
+                pipeline_statistics_query: true,
+                ..RequiredFeatures::minimal()
+            },
+            preference: DevicePreference::default(),
+            purpose: DevicePurpose::default(),
+        }
+    }
+}
+
+impl DeviceConfig {
+    /// For rendering on Mesa's llvmpipe, e.g. in CI without a GPU. Only core Vulkan is
+    /// required, as llvmpipe lacks features such as anisotropic filtering that the default
+    /// requires, and the software device is picked even when there is a GPU as well. The
+    /// features left out are already skipped when they aren't enabled. Pair it with FIFO
+    /// presentation, the one present mode every surface supports
+    pub fn software_rendering() -> Self {
+        Self {
+            required_features: RequiredFeatures::minimal(),
+            preference: DevicePreference::Software,
+            ..Self::default()
+        }
+    }
+
+    /// For using the device as a small compute harness. None of the features are needed for
+    /// compute, and any device with a compute queue will do
+    #[allow(dead_code)]
+    pub fn compute_only() -> Self {
+        Self {
+            required_features: RequiredFeatures::minimal(),
+            optional_features: RequiredFeatures::minimal(),
+            preference: DevicePreference::FirstSuitable,
+            purpose: DevicePurpose::ComputeOnly,
+        }
+    }
+}
+
+/// The resource a queue family ownership transfer applies to. The queue families and access
+/// masks are filled in when the barrier is recorded
+#[derive(Clone, Copy)]
+enum OwnershipBarrier {
+    Buffer(vk::BufferMemoryBarrier),
+    Image(vk::ImageMemoryBarrier),
+}
+
+/// The queues `LveDevice::create_logical_device` gets from the device
+struct DeviceQueues {
+    /// Null for compute only devices
+    graphics: vk::Queue,
+    /// Null for compute only devices
+    present: vk::Queue,
+    compute: vk::Queue,
+    /// The dedicated transfer family and its queue, if there is one and it's used
+    transfer: Option<(u32, vk::Queue)>,
+}
+
+/// A persistently mapped, host coherent buffer that uploads are copied through
+struct StagingBuffer {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: *mut c_void,
+    size: vk::DeviceSize,
+}
+
 pub struct LveDevice {
     _entry: Entry,
     pub instance: Instance,
-    debug_messenger: Option<(DebugUtils, vk::DebugUtilsMessengerEXT)>,
-    surface: Surface,
+    /// The config is boxed so the pointer the callback is given stays put, and is only dropped
+    /// after the messenger is destroyed
+    debug_messenger: Option<(
+        DebugUtils,
+        vk::DebugUtilsMessengerEXT,
+        Box<DebugMessengerConfig>,
+    )>,
+    /// `None` for compute only devices, whose `surface_khr` is null
+    surface: Option<Surface>,
     pub surface_khr: vk::SurfaceKHR,
     physical_device: vk::PhysicalDevice,
     pub properties: vk::PhysicalDeviceProperties,
+    pub enabled_features: RequiredFeatures,
     pub device: Device,
     pub command_pool: vk::CommandPool,
     pub graphics_queue: vk::Queue,
     pub present_queue: vk::Queue,
+    /// May be the same queue as `graphics_queue`
+    pub compute_queue: vk::Queue,
+    purpose: DevicePurpose,
+    graphics_family: u32,
+    present_family: u32,
+    compute_family: u32,
+    /// The dedicated transfer family, its queue and command pool. `None` when the device has
+    /// no such family or `USE_DEDICATED_TRANSFER_QUEUE` is off, in which case uploads go
+    /// through the graphics queue
+    transfer: Option<(u32, vk::Queue, vk::CommandPool)>,
+    /// Created by the first upload, see `upload_via_staging`
+    staging_buffer: RefCell<Option<StagingBuffer>>,
 }
 
 impl LveDevice {
-    /// Will create a new instance of a vulkan device and all of it's associated functions
-    pub fn new(window: &Window) -> Rc<Self> {
+    /// Will create a new instance of a vulkan device and all of it's associated functions. The
+    /// window is needed for `DevicePurpose::Graphics`, and ignored for compute only devices
+    pub fn new(
+        window: Option<&Window>,
+        app_info: &AppInfo,
+        device_config: DeviceConfig,
+        debug_config: DebugMessengerConfig,
+    ) -> Rc<Self> {
+        let purpose = device_config.purpose;
+
         let entry = unsafe {
             Entry::new()
                 .map_err(|e| log::error!("Failed to create entry: {}", e))
                 .unwrap()
         };
-        let instance = Self::create_instance(&entry);
-        let debug_messenger = Self::setup_debug_messenger(&entry, &instance);
-        let (surface, surface_khr) = Self::create_surface(&entry, &instance, window);
+        let instance = Self::create_instance(&entry, app_info, purpose);
+        let debug_messenger = Self::setup_debug_messenger(&entry, &instance, debug_config);
+        let (surface, surface_khr) = match purpose {
+            DevicePurpose::Graphics => {
+                let window = window.expect("A graphics device needs a window to present to");
+                let (surface, surface_khr) = Self::create_surface(&entry, &instance, window);
+                (Some(surface), surface_khr)
+            }
+            DevicePurpose::ComputeOnly => (None, vk::SurfaceKHR::null()),
+        };
+        let surface_and_khr = surface.as_ref().map(|surface| (surface, surface_khr));
         let (physical_device, properties) =
-            Self::pick_physical_device(&instance, &surface, surface_khr);
-        let (device, graphics_queue, present_queue) =
-            Self::create_logical_device(&instance, &surface, surface_khr, physical_device);
-        let command_pool =
-            Self::create_command_pool(&instance, &surface, surface_khr, physical_device, &device);
+            Self::pick_physical_device(&instance, surface_and_khr, &device_config);
+        let supported_features = unsafe { instance.get_physical_device_features(physical_device) };
+        let enabled_features = device_config.required_features.union(
+            device_config
+                .optional_features
+                .supported_subset(&supported_features),
+        );
+        let queue_family_indices =
+            Self::find_queue_families(&instance, surface_and_khr, physical_device);
+        let (device, queues) = Self::create_logical_device(
+            &instance,
+            &queue_family_indices,
+            physical_device,
+            enabled_features,
+            purpose,
+        );
+        let compute_family = queue_family_indices.compute_family.unwrap();
+
+        // Single time commands and uploads go through the compute queue when there is no
+        // graphics queue
+        let command_pool = Self::create_command_pool_for_family(
+            &device,
+            match purpose {
+                DevicePurpose::Graphics => queue_family_indices.graphics_family,
+                DevicePurpose::ComputeOnly => compute_family,
+            },
+        );
+
+        let transfer = queues.transfer.map(|(transfer_family, transfer_queue)| {
+            log::info!("Using dedicated transfer queue family {}", transfer_family);
+            let transfer_command_pool =
+                Self::create_command_pool_for_family(&device, transfer_family);
+            (transfer_family, transfer_queue, transfer_command_pool)
+        });
 
         Rc::new(Self {
             _entry: entry,
@@ -128,15 +476,28 @@ impl LveDevice {
             surface_khr,
             physical_device,
             properties: properties,
+            enabled_features,
             device,
-            graphics_queue,
-            present_queue,
+            graphics_queue: queues.graphics,
+            present_queue: queues.present,
+            compute_queue: queues.compute,
             command_pool,
+            purpose,
+            graphics_family: queue_family_indices.graphics_family,
+            present_family: queue_family_indices.present_family,
+            compute_family,
+            transfer,
+            staging_buffer: RefCell::new(None),
         })
     }
 
     pub fn get_swapchain_support(&self) -> SwapChainSupportDetails {
-        Self::query_swapchain_support(&self.surface, self.surface_khr, self.physical_device)
+        let surface = self
+            .surface
+            .as_ref()
+            .expect("Compute only devices have no surface to make a swapchain for");
+
+        Self::query_swapchain_support(surface, self.surface_khr, self.physical_device)
     }
 
     pub fn find_memory_type(
@@ -163,15 +524,51 @@ impl LveDevice {
         memory_type
     }
 
+    /// The family `graphics_queue` belongs to, for libraries (e.g. UI backends) that submit to
+    /// the same queue
+    #[allow(dead_code)]
+    pub fn graphics_queue_family(&self) -> u32 {
+        self.graphics_family
+    }
+
+    /// The family `present_queue` belongs to, which may be the graphics family
+    #[allow(dead_code)]
+    pub fn present_queue_family(&self) -> u32 {
+        self.present_family
+    }
+
+    /// The family `compute_queue` belongs to
+    #[allow(dead_code)]
+    pub fn compute_queue_family(&self) -> u32 {
+        self.compute_family
+    }
+
+    /// The family and queue `command_pool` belongs to, which single time commands and uploads
+    /// that don't use the transfer queue are submitted to
+    fn main_queue(&self) -> (u32, vk::Queue) {
+        match self.purpose {
+            DevicePurpose::Graphics => (self.graphics_family, self.graphics_queue),
+            DevicePurpose::ComputeOnly => (self.compute_family, self.compute_queue),
+        }
+    }
+
     pub fn find_physical_queue_families(&self) -> QueueFamilyIndices {
         Self::find_queue_families(
             &self.instance,
-            &self.surface,
-            self.surface_khr,
+            self.surface
+                .as_ref()
+                .map(|surface| (surface, self.surface_khr)),
             self.physical_device,
         )
     }
 
+    pub fn get_format_properties(&self, format: vk::Format) -> vk::FormatProperties {
+        unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.physical_device, format)
+        }
+    }
+
     pub fn find_supported_format(
         &self,
         candidates: &Vec<vk::Format>,
@@ -202,10 +599,13 @@ impl LveDevice {
         usage: vk::BufferUsageFlags,
         properties: vk::MemoryPropertyFlags,
     ) -> (vk::Buffer, vk::DeviceMemory) {
+        let (sharing_mode, queue_family_indices) = self.resource_sharing_mode();
+
         let create_info = vk::BufferCreateInfo::builder()
             .size(size)
             .usage(usage)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            .sharing_mode(sharing_mode)
+            .queue_family_indices(&queue_family_indices);
 
         let buffer = unsafe {
             self.device
@@ -241,11 +641,130 @@ impl LveDevice {
         (buffer, buffer_memory)
     }
 
+    /// Copies `data` into a staging buffer, then calls `copy` with it to copy the data on to its
+    /// destination, e.g. with `copy_buffer` or `copy_buffer_to_image`. Uploads up to
+    /// `MAX_STAGING_BUFFER_SIZE` reuse one buffer, so `copy` must wait for the transfer to finish
+    /// before returning, which the copy functions do
+    pub fn upload_via_staging<T: Copy>(&self, data: &[T], copy: impl FnOnce(vk::Buffer)) {
+        let size = std::mem::size_of_val(data) as vk::DeviceSize;
+
+        if size > MAX_STAGING_BUFFER_SIZE {
+            log::debug!("Using a one-off staging buffer for a {} byte upload", size);
+
+            let staging_buffer = self.create_staging_buffer(size);
+
+            unsafe {
+                Self::write_to_staging_buffer(&staging_buffer, data);
+                copy(staging_buffer.buffer);
+                self.destroy_staging_buffer(staging_buffer);
+            }
+
+            return;
+        }
+
+        let mut pooled = self.staging_buffer.borrow_mut();
+
+        let too_small = match pooled.as_ref() {
+            Some(staging_buffer) => staging_buffer.size < size,
+            None => true,
+        };
+
+        if too_small {
+            // Grown in powers of two so a run of slightly bigger uploads doesn't reallocate
+            // every time
+            let new_size = size.next_power_of_two().min(MAX_STAGING_BUFFER_SIZE);
+            log::debug!("Growing the staging buffer to {} bytes", new_size);
+
+            if let Some(old_staging_buffer) = pooled.take() {
+                unsafe { self.destroy_staging_buffer(old_staging_buffer) };
+            }
+
+            *pooled = Some(self.create_staging_buffer(new_size));
+        }
+
+        let staging_buffer = pooled.as_ref().unwrap();
+
+        unsafe { Self::write_to_staging_buffer(staging_buffer, data) };
+        copy(staging_buffer.buffer);
+    }
+
+    fn create_staging_buffer(&self, size: vk::DeviceSize) -> StagingBuffer {
+        let (buffer, memory) = self.create_buffer(
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        );
+
+        let mapped = unsafe {
+            self.device
+                .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+                .map_err(|e| log::error!("Unable to map staging buffer memory: {}", e))
+                .unwrap()
+        };
+
+        StagingBuffer {
+            buffer,
+            memory,
+            mapped,
+            size,
+        }
+    }
+
+    /// The memory is coherent, so the write needs no flush
+    unsafe fn write_to_staging_buffer<T: Copy>(staging_buffer: &StagingBuffer, data: &[T]) {
+        let size = std::mem::size_of_val(data);
+        assert!(
+            size as vk::DeviceSize <= staging_buffer.size,
+            "Upload does not fit in the staging buffer"
+        );
+
+        std::ptr::copy_nonoverlapping(
+            data.as_ptr() as *const u8,
+            staging_buffer.mapped as *mut u8,
+            size,
+        );
+    }
+
+    unsafe fn destroy_staging_buffer(&self, staging_buffer: StagingBuffer) {
+        self.device.unmap_memory(staging_buffer.memory);
+        self.device.destroy_buffer(staging_buffer.buffer, None);
+        self.device.free_memory(staging_buffer.memory, None);
+    }
+
+    /// Mapped ranges of non-coherent memory have to be flushed and invalidated in multiples of
+    /// this many bytes
+    pub fn non_coherent_atom_size(&self) -> vk::DeviceSize {
+        self.properties.limits.non_coherent_atom_size
+    }
+
+    /// Blocks until every queue has finished all submitted work, e.g. before destroying
+    /// resources that may still be in use
+    /// Returns straight away if the device has been lost, as nothing is running on it any more
+    pub fn wait_idle(&self) {
+        match unsafe { self.device.device_wait_idle() } {
+            Ok(()) | Err(vk::Result::ERROR_DEVICE_LOST) => {}
+            Err(e) => {
+                log::error!("Unable to wait for the device to idle: {}", e);
+                panic!("Unable to handle this error")
+            }
+        }
+    }
+
     #[allow(dead_code)]
     pub fn begin_single_time_commands(&self) -> vk::CommandBuffer {
+        self.begin_single_time_commands_in(self.command_pool)
+    }
+
+    #[allow(dead_code)]
+    pub fn end_single_time_commands(&self, command_buffer: vk::CommandBuffer) {
+        let (_, queue) = self.main_queue();
+        self.end_single_time_commands_in(command_buffer, self.command_pool, queue)
+    }
+
+    fn begin_single_time_commands_in(&self, command_pool: vk::CommandPool) -> vk::CommandBuffer {
         let alloc_info = vk::CommandBufferAllocateInfo::builder()
             .level(vk::CommandBufferLevel::PRIMARY)
-            .command_pool(self.command_pool)
+            .command_pool(command_pool)
             .command_buffer_count(1);
 
         let command_buffer = unsafe {
@@ -269,8 +788,12 @@ impl LveDevice {
         command_buffer
     }
 
-    #[allow(dead_code)]
-    pub fn end_single_time_commands(&self, command_buffer: vk::CommandBuffer) {
+    fn end_single_time_commands_in(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+    ) {
         unsafe {
             self.device
                 .end_command_buffer(command_buffer)
@@ -283,25 +806,21 @@ impl LveDevice {
 
         unsafe {
             self.device
-                .queue_submit(
-                    self.graphics_queue,
-                    std::slice::from_ref(&submit_info),
-                    vk::Fence::null(),
-                )
+                .queue_submit(queue, std::slice::from_ref(&submit_info), vk::Fence::null())
                 .map_err(|e| log::error!("Unable to submit queue: {}", e))
                 .unwrap()
         };
 
         unsafe {
             self.device
-                .queue_wait_idle(self.graphics_queue)
+                .queue_wait_idle(queue)
                 .map_err(|e| log::error!("Unable to idle queue: {}", e))
                 .unwrap()
         };
 
         unsafe {
             self.device
-                .free_command_buffers(self.command_pool, &[command_buffer])
+                .free_command_buffers(command_pool, &[command_buffer])
         };
     }
 
@@ -312,25 +831,34 @@ impl LveDevice {
         dst_buffer: vk::Buffer,
         size: vk::DeviceSize,
     ) {
-        let command_buffer = self.begin_single_time_commands();
-
         let copy_region = vk::BufferCopy::builder()
             .src_offset(0)
             .dst_offset(0)
             .size(size);
 
-        unsafe {
-            self.device.cmd_copy_buffer(
-                command_buffer,
-                src_buffer,
-                dst_buffer,
-                std::slice::from_ref(&copy_region),
-            )
-        };
+        let ownership_barrier = vk::BufferMemoryBarrier::builder()
+            .buffer(dst_buffer)
+            .offset(0)
+            .size(size)
+            .build();
 
-        self.end_single_time_commands(command_buffer);
+        self.submit_transfer(
+            |command_buffer| unsafe {
+                self.device.cmd_copy_buffer(
+                    command_buffer,
+                    src_buffer,
+                    dst_buffer,
+                    std::slice::from_ref(&copy_region),
+                )
+            },
+            OwnershipBarrier::Buffer(ownership_barrier),
+        );
     }
 
+    /// Copies into a newly created image, whose contents are discarded. The image is moved to
+    /// `TRANSFER_DST_OPTIMAL` by the queue doing the copy, so its first use is on that queue's
+    /// family and ownership can be released from there. It is left in that layout for the
+    /// graphics queue to transition after the copy
     #[allow(dead_code)]
     pub fn copy_buffer_to_image(
         &self,
@@ -340,8 +868,6 @@ impl LveDevice {
         height: u32,
         layer_count: u32,
     ) {
-        let command_buffer = self.begin_single_time_commands();
-
         let image_subresource_info = vk::ImageSubresourceLayers::builder()
             .aspect_mask(vk::ImageAspectFlags::COLOR)
             .mip_level(0)
@@ -365,13 +891,230 @@ impl LveDevice {
             .image_offset(offset)
             .image_extent(extent);
 
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count,
+        };
+
+        let transfer_dst_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource_range)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
+
+        // The layout is left alone, the graphics queue transitions it after the copy
+        let ownership_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build();
+
+        self.submit_transfer(
+            |command_buffer| unsafe {
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    std::slice::from_ref(&transfer_dst_barrier),
+                );
+                self.device.cmd_copy_buffer_to_image(
+                    command_buffer,
+                    buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    std::slice::from_ref(&region),
+                )
+            },
+            OwnershipBarrier::Image(ownership_barrier),
+        );
+    }
+
+    /// Records `record_copy` on the dedicated transfer queue if there is one, otherwise on the
+    /// graphics queue. When the copy crosses queue families into an exclusively owned resource,
+    /// the destination is released by the transfer family and then acquired by the graphics
+    /// family
+    fn submit_transfer<F: Fn(vk::CommandBuffer)>(
+        &self,
+        record_copy: F,
+        ownership_barrier: OwnershipBarrier,
+    ) {
+        let (transfer_family, transfer_queue, transfer_command_pool) = match self.transfer {
+            Some(transfer) => transfer,
+            None => {
+                let command_buffer = self.begin_single_time_commands();
+                record_copy(command_buffer);
+                self.end_single_time_commands(command_buffer);
+                return;
+            }
+        };
+
+        let command_buffer = self.begin_single_time_commands_in(transfer_command_pool);
+        record_copy(command_buffer);
+
+        if CONCURRENT_TRANSFER_SHARING {
+            self.end_single_time_commands_in(command_buffer, transfer_command_pool, transfer_queue);
+            return;
+        }
+
+        // Release on the transfer queue...
+        self.cmd_ownership_barrier(
+            command_buffer,
+            ownership_barrier,
+            transfer_family,
+            (vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::empty()),
+            (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            ),
+        );
+        self.end_single_time_commands_in(command_buffer, transfer_command_pool, transfer_queue);
+
+        // ...then acquire on the graphics queue. The transfer queue has already been waited on,
+        // so the release has completed before the acquire is submitted
+        let command_buffer = self.begin_single_time_commands();
+        self.cmd_ownership_barrier(
+            command_buffer,
+            ownership_barrier,
+            transfer_family,
+            (vk::AccessFlags::empty(), vk::AccessFlags::MEMORY_READ),
+            (
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+            ),
+        );
+        self.end_single_time_commands(command_buffer);
+    }
+
+    /// Records one half of a transfer -> graphics (or compute, without graphics) queue family
+    /// ownership transfer
+    fn cmd_ownership_barrier(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        barrier: OwnershipBarrier,
+        transfer_family: u32,
+        (src_access_mask, dst_access_mask): (vk::AccessFlags, vk::AccessFlags),
+        (src_stage, dst_stage): (vk::PipelineStageFlags, vk::PipelineStageFlags),
+    ) {
+        let (main_family, _) = self.main_queue();
+
+        let (buffer_barriers, image_barriers) = match barrier {
+            OwnershipBarrier::Buffer(mut barrier) => {
+                barrier.src_queue_family_index = transfer_family;
+                barrier.dst_queue_family_index = main_family;
+                barrier.src_access_mask = src_access_mask;
+                barrier.dst_access_mask = dst_access_mask;
+                (vec![barrier], vec![])
+            }
+            OwnershipBarrier::Image(mut barrier) => {
+                barrier.src_queue_family_index = transfer_family;
+                barrier.dst_queue_family_index = main_family;
+                barrier.src_access_mask = src_access_mask;
+                barrier.dst_access_mask = dst_access_mask;
+                (vec![], vec![barrier])
+            }
+        };
+
         unsafe {
-            self.device.cmd_copy_buffer_to_image(
+            self.device.cmd_pipeline_barrier(
                 command_buffer,
-                buffer,
-                image,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                std::slice::from_ref(&region),
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &buffer_barriers,
+                &image_barriers,
+            )
+        };
+    }
+
+    /// Resources are exclusive to one queue family unless a dedicated transfer queue is in use
+    /// and `CONCURRENT_TRANSFER_SHARING` is on
+    fn resource_sharing_mode(&self) -> (vk::SharingMode, Vec<u32>) {
+        match self.transfer {
+            Some((transfer_family, _, _)) if CONCURRENT_TRANSFER_SHARING => (
+                vk::SharingMode::CONCURRENT,
+                vec![self.main_queue().0, transfer_family],
+            ),
+            _ => (vk::SharingMode::EXCLUSIVE, vec![]),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn transition_image_layout(
+        &self,
+        image: vk::Image,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        layer_count: u32,
+    ) {
+        let (src_access_mask, dst_access_mask, src_stage, dst_stage) =
+            match (old_layout, new_layout) {
+                (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::TRANSFER,
+                ),
+                (
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ) => (
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                ),
+                // Storage images, which are written by compute shaders first
+                (vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL) => (
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                ),
+                _ => panic!(
+                    "Unsupported layout transition: {:?} -> {:?}",
+                    old_layout, new_layout
+                ),
+            };
+
+        let command_buffer = self.begin_single_time_commands();
+
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count,
+            })
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask);
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                std::slice::from_ref(&barrier),
             )
         };
 
@@ -383,9 +1126,16 @@ impl LveDevice {
         image_info: &vk::ImageCreateInfo,
         properties: vk::MemoryPropertyFlags,
     ) -> (vk::Image, vk::DeviceMemory) {
+        let (sharing_mode, queue_family_indices) = self.resource_sharing_mode();
+
+        let mut image_info = *image_info;
+        image_info.sharing_mode = sharing_mode;
+        image_info.queue_family_index_count = queue_family_indices.len() as u32;
+        image_info.p_queue_family_indices = queue_family_indices.as_ptr();
+
         let image = unsafe {
             self.device
-                .create_image(image_info, None)
+                .create_image(&image_info, None)
                 .map_err(|e| log::error!("Unable to create image: {}", e))
                 .unwrap()
         };
@@ -416,18 +1166,35 @@ impl LveDevice {
         (image, image_memory)
     }
 
-    fn create_instance(entry: &Entry) -> Instance {
-        let app_name = CString::new("LittleVulkanEngine App").unwrap();
+    fn create_instance(entry: &Entry, app_info: &AppInfo, purpose: DevicePurpose) -> Instance {
+        let app_name = CString::new(app_info.app_name.as_str())
+            .map_err(|e| log::error!("Invalid application name: {}", e))
+            .unwrap();
         let engine_name = CString::new("No Engine").unwrap();
 
+        let api_version = Self::choose_api_version(entry, app_info.api_version);
+
         let app_info = vk::ApplicationInfo::builder()
             .application_name(app_name.as_c_str())
-            .application_version(vk::make_api_version(0, 0, 1, 0))
+            .application_version(app_info.app_version)
             .engine_name(engine_name.as_c_str())
             .engine_version(vk::make_api_version(0, 0, 1, 0))
-            .api_version(vk::make_api_version(0, 1, 2, 176));
-
-        let extensions = Self::get_required_extensions();
+            .api_version(api_version);
+
+        let mut extensions = Self::get_required_extensions(purpose);
+
+        // Needed for the surface to report any of the HDR / wide gamut color spaces
+        let colorspace_extension = vk::ExtSwapchainColorspaceFn::name();
+        if purpose == DevicePurpose::ComputeOnly {
+            // No surface to report them
+        } else if Self::is_instance_extension_available(entry, colorspace_extension) {
+            extensions.push(colorspace_extension.as_ptr());
+        } else {
+            log::info!(
+                "{:?} not available, HDR surface formats disabled",
+                colorspace_extension
+            );
+        }
 
         let mut create_info = vk::InstanceCreateInfo::builder()
             .application_info(&app_info)
@@ -448,19 +1215,61 @@ impl LveDevice {
         }
     }
 
+    /// Instances can't be created with a newer major or minor version than the loader
+    /// supports, so fall back to the loader's version. The patch version doesn't matter
+    fn choose_api_version(entry: &Entry, requested_api_version: u32) -> u32 {
+        // Vulkan 1.0 loaders don't have vkEnumerateInstanceVersion
+        let loader_version = entry
+            .try_enumerate_instance_version()
+            .map_err(|e| log::error!("Unable to enumerate instance version: {}", e))
+            .unwrap()
+            .unwrap_or_else(|| vk::make_api_version(0, 1, 0, 0));
+
+        let major_minor = |version: u32| {
+            vk::make_api_version(
+                0,
+                vk::api_version_major(version),
+                vk::api_version_minor(version),
+                0,
+            )
+        };
+
+        if major_minor(requested_api_version) > major_minor(loader_version) {
+            log::warn!(
+                "Requested Vulkan {}.{}, but the loader only supports {}.{}",
+                vk::api_version_major(requested_api_version),
+                vk::api_version_minor(requested_api_version),
+                vk::api_version_major(loader_version),
+                vk::api_version_minor(loader_version)
+            );
+
+            loader_version
+        } else {
+            requested_api_version
+        }
+    }
+
     fn setup_debug_messenger(
         entry: &Entry,
         instance: &Instance,
-    ) -> Option<(DebugUtils, vk::DebugUtilsMessengerEXT)> {
+        debug_config: DebugMessengerConfig,
+    ) -> Option<(
+        DebugUtils,
+        vk::DebugUtilsMessengerEXT,
+        Box<DebugMessengerConfig>,
+    )> {
         if !ENABLE_VALIDATION_LAYERS {
             return None;
         }
 
+        let debug_config = Box::new(debug_config);
+
         let create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
             .flags(vk::DebugUtilsMessengerCreateFlagsEXT::all())
             .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::all())
             .message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
-            .pfn_user_callback(Some(vulkan_debug_callback));
+            .pfn_user_callback(Some(vulkan_debug_callback))
+            .user_data(&*debug_config as *const DebugMessengerConfig as *mut c_void);
 
         let debug_report = DebugUtils::new(entry, instance);
         let debug_report_callback = unsafe {
@@ -469,7 +1278,7 @@ impl LveDevice {
                 .unwrap()
         };
 
-        Some((debug_report, debug_report_callback))
+        Some((debug_report, debug_report_callback, debug_config))
     }
 
     fn create_surface(
@@ -490,8 +1299,8 @@ impl LveDevice {
 
     fn pick_physical_device(
         instance: &Instance,
-        surface: &Surface,
-        surface_khr: vk::SurfaceKHR,
+        surface: Option<(&Surface, vk::SurfaceKHR)>,
+        device_config: &DeviceConfig,
     ) -> (vk::PhysicalDevice, vk::PhysicalDeviceProperties) {
         // Get all of the GPUs connected to the PC
         let devices = unsafe {
@@ -503,12 +1312,41 @@ impl LveDevice {
 
         log::info!("Device Count: {}", devices.len());
 
-        let device = devices
+        let suitable_devices: Vec<(vk::PhysicalDevice, vk::PhysicalDeviceProperties)> = devices
             .into_iter()
-            .find(|device| Self::is_device_suitable(instance, surface, surface_khr, *device))
-            .expect("No suitable physical device");
+            .filter(|device| {
+                Self::is_device_suitable(
+                    instance,
+                    surface,
+                    *device,
+                    &device_config.required_features,
+                )
+            })
+            .map(|device| {
+                (device, unsafe {
+                    instance.get_physical_device_properties(device)
+                })
+            })
+            .collect();
+
+        let preferred = match device_config.preference {
+            DevicePreference::FirstSuitable => None,
+            DevicePreference::Software => {
+                let software = suitable_devices
+                    .iter()
+                    .find(|(_, properties)| properties.device_type == vk::PhysicalDeviceType::CPU);
 
-        let device_properties = unsafe { instance.get_physical_device_properties(device) };
+                if software.is_none() {
+                    log::warn!("No suitable software device, using the first suitable device");
+                }
+
+                software
+            }
+        };
+
+        let (device, device_properties) = *preferred
+            .or_else(|| suitable_devices.first())
+            .expect("No suitable physical device");
 
         // Tell the user the name of the device
         log::info!("Selected physical device: {:?}", unsafe {
@@ -518,15 +1356,25 @@ impl LveDevice {
         (device, device_properties)
     }
 
+    /// Without a surface only a compute queue and the features are needed
     fn is_device_suitable(
         instance: &Instance,
-        surface: &Surface,
-        surface_khr: vk::SurfaceKHR,
+        surface: Option<(&Surface, vk::SurfaceKHR)>,
         device: vk::PhysicalDevice,
+        required_features: &RequiredFeatures,
     ) -> bool {
-        let indices = Self::find_queue_families(instance, surface, surface_khr, device);
+        let indices = Self::find_queue_families(instance, surface, device);
+
+        let supported_features = unsafe { instance.get_physical_device_features(device) };
+        let features_supported = required_features.is_supported_by(&supported_features);
+
+        let (surface, surface_khr) = match surface {
+            Some(surface) => surface,
+            None => return indices.compute_family.is_some() && features_supported,
+        };
 
-        let extensions_supported = Self::check_device_extension_support(instance, device);
+        let extensions_supported =
+            Self::check_device_extension_support(instance, device, DevicePurpose::Graphics);
 
         let mut swap_chain_adequate = false;
 
@@ -538,25 +1386,25 @@ impl LveDevice {
             };
         }
 
-        let supported_features = unsafe { instance.get_physical_device_features(device) };
-
         {
-            indices.is_complete()
+            indices.compute_family.is_some()
+                && indices.is_complete()
                 && extensions_supported
                 && swap_chain_adequate
-                && supported_features.sampler_anisotropy != 0
+                && features_supported
         }
     }
 
     fn create_logical_device(
         instance: &Instance,
-        surface: &Surface,
-        surface_khr: vk::SurfaceKHR,
+        queue_indices: &QueueFamilyIndices,
         physical_device: vk::PhysicalDevice,
-    ) -> (Device, vk::Queue, vk::Queue) {
-        // Get the indices of the valid queue families
-        let queue_indices =
-            Self::find_queue_families(instance, surface, surface_khr, physical_device);
+        required_features: RequiredFeatures,
+        purpose: DevicePurpose,
+    ) -> (Device, DeviceQueues) {
+        let compute_family = queue_indices
+            .compute_family
+            .expect("Device has no compute queue family");
 
         // Give the queue a priority (only want one queue so we shall set it to 1.0)
         let queue_priorities = [1.0f32];
@@ -566,7 +1414,22 @@ impl LveDevice {
             // Vulkan specs does not allow passing an array containing duplicated family indices.
             // And since the family for graphics and presentation could be the same we need to
             // deduplicate it.
-            let mut indices = vec![queue_indices.graphics_family, queue_indices.present_family];
+            let mut indices = match purpose {
+                DevicePurpose::Graphics => vec![
+                    queue_indices.graphics_family,
+                    queue_indices.present_family,
+                    compute_family,
+                ],
+                DevicePurpose::ComputeOnly => vec![compute_family],
+            };
+
+            if let Some(transfer_family) = queue_indices.transfer_family {
+                if USE_DEDICATED_TRANSFER_QUEUE {
+                    indices.push(transfer_family);
+                }
+            }
+
+            indices.sort_unstable();
             indices.dedup();
 
             // Now we build an array of `DeviceQueueCreateInfo`.
@@ -582,10 +1445,10 @@ impl LveDevice {
                 .collect::<Vec<_>>()
         };
 
-        // Get the physical device features
-        let physical_device_features = vk::PhysicalDeviceFeatures::builder().build();
+        // Only enable what was asked for, support was checked in is_device_suitable
+        let physical_device_features = required_features.to_physical_device_features();
 
-        let (_, device_extensions_ptrs) = Self::get_device_extensions();
+        let (_, device_extensions_ptrs) = Self::get_device_extensions(purpose);
 
         let mut create_info = vk::DeviceCreateInfo::builder()
             .queue_create_infos(&queue_create_infos)
@@ -606,24 +1469,39 @@ impl LveDevice {
         };
 
         // Allocate the queues
-        let graphics_queue = unsafe { device.get_device_queue(queue_indices.graphics_family, 0) };
-        let present_queue = unsafe { device.get_device_queue(queue_indices.present_family, 0) };
+        let (graphics_queue, present_queue) = match purpose {
+            DevicePurpose::Graphics => unsafe {
+                (
+                    device.get_device_queue(queue_indices.graphics_family, 0),
+                    device.get_device_queue(queue_indices.present_family, 0),
+                )
+            },
+            DevicePurpose::ComputeOnly => (vk::Queue::null(), vk::Queue::null()),
+        };
+        let compute_queue = unsafe { device.get_device_queue(compute_family, 0) };
+        let transfer_queue = queue_indices
+            .transfer_family
+            .filter(|_| USE_DEDICATED_TRANSFER_QUEUE)
+            .map(|transfer_family| {
+                (transfer_family, unsafe {
+                    device.get_device_queue(transfer_family, 0)
+                })
+            });
 
-        (device, graphics_queue, present_queue)
+        (
+            device,
+            DeviceQueues {
+                graphics: graphics_queue,
+                present: present_queue,
+                compute: compute_queue,
+                transfer: transfer_queue,
+            },
+        )
     }
 
-    fn create_command_pool(
-        instance: &Instance,
-        surface: &Surface,
-        surface_khr: vk::SurfaceKHR,
-        physical_device: vk::PhysicalDevice,
-        device: &Device,
-    ) -> vk::CommandPool {
-        let queue_family_indices =
-            Self::find_queue_families(instance, surface, surface_khr, physical_device);
-
+    fn create_command_pool_for_family(device: &Device, queue_family: u32) -> vk::CommandPool {
         let create_info = vk::CommandPoolCreateInfo::builder()
-            .queue_family_index(queue_family_indices.graphics_family)
+            .queue_family_index(queue_family)
             .flags(
                 vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER
                     | vk::CommandPoolCreateFlags::TRANSIENT,
@@ -637,15 +1515,17 @@ impl LveDevice {
         }
     }
 
-    fn get_required_extensions() -> Vec<*const i8> {
+    fn get_required_extensions(purpose: DevicePurpose) -> Vec<*const i8> {
         let mut extensions: Vec<*const i8> = Vec::new();
 
-        extensions.push(Surface::name().as_ptr());
+        if purpose == DevicePurpose::Graphics {
+            extensions.push(Surface::name().as_ptr());
 
-        #[cfg(target_os = "windows")]
-        extensions.push(Win32Surface::name().as_ptr());
-        #[cfg(target_os = "linux")]
-        extensions.push(XlibSurface::name().as_ptr());
+            #[cfg(target_os = "windows")]
+            extensions.push(Win32Surface::name().as_ptr());
+            #[cfg(target_os = "linux")]
+            extensions.push(XlibSurface::name().as_ptr());
+        }
 
         if ENABLE_VALIDATION_LAYERS {
             extensions.push(DebugUtils::name().as_ptr());
@@ -656,6 +1536,18 @@ impl LveDevice {
         extensions
     }
 
+    fn is_instance_extension_available(entry: &Entry, extension: &CStr) -> bool {
+        entry
+            .enumerate_instance_extension_properties()
+            .map_err(|e| log::error!("Unable to enumerate instance extensions: {}", e))
+            .unwrap()
+            .iter()
+            .any(|ext| {
+                let name = unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) };
+                extension == name
+            })
+    }
+
     fn check_validation_layer_support(entry: &Entry) {
         // Iterate through all the requested validation layers
         for required in VALIDATION_LAYERS.iter() {
@@ -698,8 +1590,11 @@ impl LveDevice {
         (layer_names, layer_names_ptrs)
     }
 
-    fn get_device_extensions() -> ([&'static CStr; 1], Vec<*const i8>) {
-        let device_extensions: [&'static CStr; 1] = [Swapchain::name()];
+    fn get_device_extensions(purpose: DevicePurpose) -> (Vec<&'static CStr>, Vec<*const i8>) {
+        let device_extensions = match purpose {
+            DevicePurpose::Graphics => vec![Swapchain::name()],
+            DevicePurpose::ComputeOnly => vec![],
+        };
 
         // Store a list of all the device extensions pointers
         let ext_names_pts = device_extensions
@@ -710,10 +1605,10 @@ impl LveDevice {
         (device_extensions, ext_names_pts)
     }
 
+    /// Without a surface no present family is found
     fn find_queue_families(
         instance: &Instance,
-        surface: &Surface,
-        surface_khr: vk::SurfaceKHR,
+        surface: Option<(&Surface, vk::SurfaceKHR)>,
         device: vk::PhysicalDevice,
     ) -> QueueFamilyIndices {
         let mut graphics_family: u32 = 0;
@@ -736,11 +1631,11 @@ impl LveDevice {
                 graphics_family_has_value = true;
             }
 
-            let present_support = unsafe {
+            let present_support = surface.is_some_and(|(surface, surface_khr)| unsafe {
                 surface
                     .get_physical_device_surface_support(device, index, surface_khr)
                     .unwrap()
-            };
+            });
 
             if present_support {
                 present_family = index;
@@ -752,22 +1647,49 @@ impl LveDevice {
             }
         }
 
+        // Dedicated transfer families are usually backed by the GPU's DMA engines
+        let transfer_family = queue_families
+            .iter()
+            .enumerate()
+            .find(|(_, queue_family)| {
+                queue_family.queue_count > 0
+                    && queue_family.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                    && !queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+            })
+            .map(|(index, _)| index as u32);
+
+        let supports_compute = |index: u32| {
+            let queue_family = &queue_families[index as usize];
+            queue_family.queue_count > 0
+                && queue_family.queue_flags.contains(vk::QueueFlags::COMPUTE)
+        };
+
+        let compute_family = Some(graphics_family)
+            .filter(|family| graphics_family_has_value && supports_compute(*family))
+            .or_else(|| (0..queue_families.len() as u32).find(|index| supports_compute(*index)));
+
         QueueFamilyIndices {
             graphics_family,
             present_family,
+            transfer_family,
+            compute_family,
             graphics_family_has_value,
             present_family_has_value,
         }
     }
 
-    fn check_device_extension_support(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    fn check_device_extension_support(
+        instance: &Instance,
+        device: vk::PhysicalDevice,
+        purpose: DevicePurpose,
+    ) -> bool {
         let available_extensions = unsafe {
             instance
                 .enumerate_device_extension_properties(device)
                 .unwrap()
         };
 
-        let (required_extensions, _) = Self::get_device_extensions();
+        let (required_extensions, _) = Self::get_device_extensions(purpose);
 
         for extension in required_extensions.iter() {
             let found = available_extensions.iter().any(|ext| {
@@ -822,18 +1744,28 @@ impl Drop for LveDevice {
     fn drop(&mut self) {
         log::debug!("Dropping device");
         unsafe {
+            if let Some(staging_buffer) = self.staging_buffer.get_mut().take() {
+                self.destroy_staging_buffer(staging_buffer);
+            }
+
             // log::debug!("Destroying command pool");
             self.device.destroy_command_pool(self.command_pool, None);
+            if let Some((_, _, transfer_command_pool)) = self.transfer {
+                self.device
+                    .destroy_command_pool(transfer_command_pool, None);
+            }
 
             // log::debug!("Destroying device");
             self.device.destroy_device(None);
 
             // log::debug!("Destroying surface");
-            self.surface.destroy_surface(self.surface_khr, None);
+            if let Some(surface) = &self.surface {
+                surface.destroy_surface(self.surface_khr, None);
+            }
 
             // log::debug!("Destroying debug messenger");
             // Destroy the Debug messenger
-            if let Some((report, callback)) = self.debug_messenger.take() {
+            if let Some((report, callback, _config)) = self.debug_messenger.take() {
                 report.destroy_debug_utils_messenger(callback, None);
             }
 
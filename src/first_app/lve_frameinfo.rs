@@ -1,15 +1,162 @@
 use super::lve_camera::LveCamera;
-use super::lve_game_object::LveGameObject;
+use super::lve_game_object::GameObjectManager;
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::mem::size_of;
 
 use ash::vk;
 
+extern crate nalgebra as na;
+
+/// Must match the `MAX_LIGHTS` define in the shaders that use the global UBO
+pub const MAX_LIGHTS: usize = 10;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PointLight {
+    pub position: na::Vector4<f32>, // w is ignored
+    pub color: na::Vector4<f32>,    // w is light intensity
+}
+
+impl Default for PointLight {
+    fn default() -> Self {
+        Self {
+            position: na::Vector4::zeros(),
+            color: na::Vector4::zeros(),
+        }
+    }
+}
+
+/// Linear distance fog, blended in by every lit fragment shader through shaders/include/fog.glsl.
+/// Distances are view space depth, which the shaders reconstruct assuming a perspective projection
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FogParams {
+    pub color: na::Vector4<f32>, // w is ignored
+    /// How much fog there is at `end` and beyond, from 0 (off) to 1 (fully fogged)
+    pub density: f32,
+    /// Where the fog starts
+    pub start: f32,
+    /// Where the fog reaches `density`
+    pub end: f32,
+    // std140 rounds the struct up to a multiple of 16 bytes
+    _padding: f32,
+}
+
+impl FogParams {
+    pub fn new(color: na::Vector3<f32>, density: f32, start: f32, end: f32) -> Self {
+        Self {
+            color: na::vector![color[0], color[1], color[2], 1.0],
+            density,
+            start,
+            end,
+            _padding: 0.0,
+        }
+    }
+
+    /// A density of 0, which leaves the scene untouched
+    pub fn disabled() -> Self {
+        Self::new(na::Vector3::zeros(), 0.0, 0.0, 1.0)
+    }
+}
+
+/// Laid out to match the std140 `GlobalUbo` block in the shaders. Every member is a
+/// multiple of 16 bytes apart from the light count and clip planes, which share a 16 byte slot
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct GlobalUBO {
+    pub projection_view: na::Matrix4<f32>,
+    pub inverse_view: na::Matrix4<f32>,
+    pub ambient_light_color: na::Vector4<f32>, // w is intensity
+    pub directional_light_direction: na::Vector4<f32>, // w is ignored
+    pub directional_light_color: na::Vector4<f32>, // w is intensity
+    pub point_lights: [PointLight; MAX_LIGHTS],
+    pub num_lights: i32,
+    /// The camera's clip planes, for turning depth back into view space distance
+    pub near_plane: f32,
+    pub far_plane: f32,
+    _padding: i32,
+    pub fog: FogParams,
+    /// Last frame's `projection_view` without its jitter, for reprojecting into the last frame
+    pub previous_projection_view: na::Matrix4<f32>,
+    /// xy is this frame's projection jitter and zw last frame's, see `LveCamera::set_jitter`
+    pub jitter: na::Vector4<f32>,
+}
+
+// The size the shader expects: 2 mat4s + 3 vec4s + the light array + the (padded) light count
+// and clip planes + the fog + the previous mat4 and the jitter
+const _: () = assert!(
+    size_of::<GlobalUBO>()
+        == 2 * 64 + 3 * 16 + MAX_LIGHTS * size_of::<PointLight>() + 16 + 32 + 64 + 16,
+    "GlobalUBO does not match the std140 layout of the shader's GlobalUbo"
+);
+
+impl GlobalUBO {
+    /// Ambient light only, no fog and no jitter, lights are added by the caller. The clip planes
+    /// are only needed for fog. The previous frame starts out the same as this one
+    pub fn new(projection_view: na::Matrix4<f32>, inverse_view: na::Matrix4<f32>) -> Self {
+        Self {
+            projection_view,
+            inverse_view,
+            ambient_light_color: na::vector![1.0, 1.0, 1.0, 0.015],
+            directional_light_direction: na::Vector4::zeros(),
+            directional_light_color: na::Vector4::zeros(),
+            point_lights: [PointLight::default(); MAX_LIGHTS],
+            num_lights: 0,
+            near_plane: 0.0,
+            far_plane: 1.0,
+            _padding: 0,
+            fog: FogParams::disabled(),
+            previous_projection_view: projection_view,
+            jitter: na::Vector4::zeros(),
+        }
+    }
+}
+
+/// What the render systems drew in a frame, see `LveRenderer::last_frame_stats`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    /// Summed over every instance of every draw
+    pub triangles: u64,
+    pub pipeline_binds: u32,
+    /// Objects skipped because they are hidden
+    pub culled_objects: u32,
+    /// Fragment shader invocations in the swapchain render pass, i.e. how much overdraw the
+    /// depth pre-pass saves. Counted on the GPU, so this is from `MAX_FRAMES_IN_FLIGHT` frames
+    /// before the rest of the stats. `None` without the `pipeline_statistics_query` feature
+    pub fragment_invocations: Option<u64>,
+}
+
 pub struct FrameInfo<'a> {
+    /// In `0..MAX_FRAMES_IN_FLIGHT`, for indexing per frame resources. This is not the
+    /// swapchain image index, see `MAX_FRAMES_IN_FLIGHT`
     pub frame_index: u64,
     pub frame_time: f32,
     pub command_buffer: vk::CommandBuffer,
     pub camera: &'a LveCamera,
     pub global_descriptor_set: vk::DescriptorSet,
-    pub game_objects: &'a mut HashMap<u64, LveGameObject>
+    pub game_objects: &'a mut GameObjectManager,
+    /// The area of the swapchain image the scene is drawn to, smaller than the image when
+    /// letterboxing
+    pub viewport: vk::Rect2D,
+    /// Where the render systems record what they draw, see the `record_*` functions
+    pub stats: &'a RefCell<RenderStats>,
+}
+
+impl<'a> FrameInfo<'a> {
+    /// Counts a draw call of `triangles` triangles, over all its instances
+    pub fn record_draw(&self, triangles: u64) {
+        let mut stats = self.stats.borrow_mut();
+        stats.draw_calls += 1;
+        stats.triangles += triangles;
+    }
+
+    pub fn record_pipeline_bind(&self) {
+        self.stats.borrow_mut().pipeline_binds += 1;
+    }
+
+    pub fn record_culled(&self, object_count: u32) {
+        self.stats.borrow_mut().culled_objects += object_count;
+    }
 }
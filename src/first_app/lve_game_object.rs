@@ -1,17 +1,141 @@
 use super::lve_model::*;
+use super::lve_pipeline::PipelineTag;
+use super::skinning::{AnimationPlayer, LveSkinnedModel};
 
+use std::cell::Cell;
+use std::collections::{hash_map, HashMap};
 use std::rc::Rc;
 
 extern crate nalgebra as na;
 
+/// The fields are only changed through the setters so the cached matrices know when they
+/// need rebuilding. Static objects only ever compute their matrices once
 pub struct TransformComponent {
-    pub translation: na::Vector3<f32>,
-    pub scale: na::Vector3<f32>,
-    pub rotation: na::Vector3<f32>,
+    translation: na::Vector3<f32>,
+    scale: na::Vector3<f32>,
+    rotation: na::Vector3<f32>,
+    /// Replaces `rotation` once set with `set_orientation`, which also keeps `rotation` at the
+    /// same rotation for code that only reads the angles
+    orientation: Option<na::UnitQuaternion<f32>>,
+    /// Set whenever the translation, scale or rotation change
+    dirty: Cell<bool>,
+    cached_mat4: Cell<na::Matrix4<f32>>,
+    cached_normal_matrix: Cell<na::Matrix4<f32>>,
+}
+
+impl Default for TransformComponent {
+    fn default() -> Self {
+        Self::new(
+            na::vector![0.0, 0.0, 0.0],
+            na::vector![1.0, 1.0, 1.0],
+            na::vector![0.0, 0.0, 0.0],
+        )
+    }
 }
 
 impl TransformComponent {
+    pub fn new(
+        translation: na::Vector3<f32>,
+        scale: na::Vector3<f32>,
+        rotation: na::Vector3<f32>,
+    ) -> Self {
+        Self {
+            translation,
+            scale,
+            rotation,
+            orientation: None,
+            dirty: Cell::new(true),
+            cached_mat4: Cell::new(na::Matrix4::identity()),
+            cached_normal_matrix: Cell::new(na::Matrix4::identity()),
+        }
+    }
+
+    pub fn translation(&self) -> na::Vector3<f32> {
+        self.translation
+    }
+
+    #[allow(dead_code)]
+    pub fn scale(&self) -> na::Vector3<f32> {
+        self.scale
+    }
+
+    pub fn rotation(&self) -> na::Vector3<f32> {
+        self.rotation
+    }
+
+    pub fn set_translation(&mut self, translation: na::Vector3<f32>) {
+        if self.translation != translation {
+            self.translation = translation;
+            self.dirty.set(true);
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn set_scale(&mut self, scale: na::Vector3<f32>) {
+        if self.scale != scale {
+            self.scale = scale;
+            self.dirty.set(true);
+        }
+    }
+
+    /// Also replaces any orientation set with `set_orientation`
+    pub fn set_rotation(&mut self, rotation: na::Vector3<f32>) {
+        if self.rotation != rotation || self.orientation.is_some() {
+            self.rotation = rotation;
+            self.orientation = None;
+            self.dirty.set(true);
+        }
+    }
+
+    /// The rotation as a quaternion, made from the Euler angles unless one was set with
+    /// `set_orientation`
+    pub fn orientation(&self) -> na::UnitQuaternion<f32> {
+        self.orientation
+            .unwrap_or_else(|| orientation_from_rotation(self.rotation))
+    }
+
+    /// Rotates the object by a quaternion rather than Euler angles, so rotations can be built
+    /// up without gimbal lock, e.g. by `FreeFlyController`. `rotation` returns the same
+    /// rotation as angles from then on, until `set_rotation` is called
+    pub fn set_orientation(&mut self, orientation: na::UnitQuaternion<f32>) {
+        if self.orientation != Some(orientation) {
+            self.orientation = Some(orientation);
+            self.rotation = rotation_from_orientation(&orientation);
+            self.dirty.set(true);
+        }
+    }
+
+    /// Same as `mat4`, but only recomputed when the transform has changed since the last call
+    pub fn cached_mat4(&self) -> na::Matrix4<f32> {
+        self.update_cache();
+        self.cached_mat4.get()
+    }
+
+    /// Same as `normal_matrix`, but only recomputed when the transform has changed since the
+    /// last call
+    pub fn cached_normal_matrix(&self) -> na::Matrix4<f32> {
+        self.update_cache();
+        self.cached_normal_matrix.get()
+    }
+
+    fn update_cache(&self) {
+        if self.dirty.get() {
+            self.cached_mat4.set(self.mat4());
+            self.cached_normal_matrix.set(self.normal_matrix());
+            self.dirty.set(false);
+        }
+    }
+
     pub fn mat4(&self) -> na::Matrix4<f32> {
+        if let Some(orientation) = self.orientation {
+            let mut mat4 = (orientation.to_rotation_matrix().matrix()
+                * na::Matrix3::from_diagonal(&self.scale))
+            .to_homogeneous();
+            mat4.fixed_slice_mut::<3, 1>(0, 3)
+                .copy_from(&self.translation);
+            return mat4;
+        }
+
         let c3 = self.rotation[2].cos();
         let s3 = self.rotation[2].sin();
         let c2 = self.rotation[0].cos();
@@ -26,7 +150,54 @@ impl TransformComponent {
         )
     }
 
+    /// The world space axis aligned bounds of a model space box. All eight corners are
+    /// transformed, so a rotated box gets the (larger) bounds around its rotated corners
+    pub fn transform_aabb(
+        &self,
+        local_min: na::Vector3<f32>,
+        local_max: na::Vector3<f32>,
+    ) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        let mat4 = self.mat4();
+
+        let mut world_min = na::Vector3::repeat(f32::INFINITY);
+        let mut world_max = na::Vector3::repeat(f32::NEG_INFINITY);
+
+        for corner in 0..8 {
+            let local_corner = na::vector![
+                if corner & 1 == 0 {
+                    local_min[0]
+                } else {
+                    local_max[0]
+                },
+                if corner & 2 == 0 {
+                    local_min[1]
+                } else {
+                    local_max[1]
+                },
+                if corner & 4 == 0 {
+                    local_min[2]
+                } else {
+                    local_max[2]
+                },
+                1.0
+            ];
+            let world_corner = (mat4 * local_corner).xyz();
+
+            world_min = world_min.inf(&world_corner);
+            world_max = world_max.sup(&world_corner);
+        }
+
+        (world_min, world_max)
+    }
+
     pub fn normal_matrix(&self) -> na::Matrix4<f32> {
+        if let Some(orientation) = self.orientation {
+            let inv_scale = self.scale.map(|scale| 1.0 / scale);
+            return (orientation.to_rotation_matrix().matrix()
+                * na::Matrix3::from_diagonal(&inv_scale))
+            .to_homogeneous();
+        }
+
         let c3 = self.rotation[2].cos();
         let s3 = self.rotation[2].sin();
         let c2 = self.rotation[0].cos();
@@ -48,10 +219,187 @@ impl TransformComponent {
     }
 }
 
+/// The quaternion for Euler angles applied as `TransformComponent::mat4` applies them: y
+/// (yaw), then x (pitch), then z (roll)
+fn orientation_from_rotation(rotation: na::Vector3<f32>) -> na::UnitQuaternion<f32> {
+    na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), rotation[1])
+        * na::UnitQuaternion::from_axis_angle(&na::Vector3::x_axis(), rotation[0])
+        * na::UnitQuaternion::from_axis_angle(&na::Vector3::z_axis(), rotation[2])
+}
+
+/// The inverse of `orientation_from_rotation`. Looking straight up or down the yaw and roll
+/// turn about the same axis, so it's all put in the yaw
+fn rotation_from_orientation(orientation: &na::UnitQuaternion<f32>) -> na::Vector3<f32> {
+    let m = orientation.to_rotation_matrix().into_inner();
+
+    // m[(1, 2)] is -sin(pitch)
+    let sin_pitch = (-m[(1, 2)]).clamp(-1.0, 1.0);
+    let pitch = sin_pitch.asin();
+
+    if sin_pitch.abs() > 0.9999 {
+        na::vector![pitch, (-m[(2, 0)]).atan2(m[(0, 0)]), 0.0]
+    } else {
+        na::vector![
+            pitch,
+            m[(0, 2)].atan2(m[(2, 2)]),
+            m[(1, 0)].atan2(m[(1, 1)])
+        ]
+    }
+}
+
+pub struct PointLightComponent {
+    pub light_intensity: f32,
+}
+
+/// Makes the object reflect the environment cube map. `reflectivity` blends from the
+/// object's lit base color (0.0) to a perfect mirror (1.0)
+pub struct ReflectionComponent {
+    pub reflectivity: f32,
+}
+
+/// Moves the object under gravity and stops it passing through other physics objects, see
+/// `PhysicsWorld`. Collisions use the bounding box of the object's model
+pub struct PhysicsComponent {
+    pub velocity: na::Vector3<f32>,
+    /// 1 for normal gravity, 0 for objects that float
+    pub gravity_scale: f32,
+    /// How much speed is kept when bouncing off something, from 0 (none) to 1 (all of it)
+    pub restitution: f32,
+    /// Fixed objects are collided with but never move, e.g. the floor
+    pub fixed: bool,
+}
+
+impl PhysicsComponent {
+    pub fn new(gravity_scale: f32, restitution: f32) -> Self {
+        Self {
+            velocity: na::Vector3::zeros(),
+            gravity_scale,
+            restitution,
+            fixed: false,
+        }
+    }
+
+    pub fn fixed() -> Self {
+        Self {
+            fixed: true,
+            ..Self::new(0.0, 0.0)
+        }
+    }
+}
+
+/// Poses the object with a skinned model instead of drawing `model`, which should be the
+/// skinned model's `model` so the bounds match. Drawn by the `SkinnedRenderSystem` rather than
+/// the simple render system
+pub struct SkinComponent {
+    pub model: Rc<LveSkinnedModel>,
+    pub player: AnimationPlayer,
+}
+
+/// Shades the object with the `MaterialRenderSystem` instead of the simple render system.
+/// The albedo is used in place of `color`
+pub struct MaterialComponent {
+    pub albedo: na::Vector3<f32>,
+    /// 0 is a mirror-like highlight, 1 spreads it out until it is barely visible
+    pub roughness: f32,
+    /// 0 for dielectrics, 1 for metals, which tint their highlight and have no diffuse
+    pub metallic: f32,
+}
+
+pub struct LodLevel {
+    pub model: Rc<LveModel>,
+    /// The furthest distance from the camera this level is drawn at
+    pub max_distance: f32,
+}
+
+/// Swaps the object's model for simpler ones as it gets further from the camera. Each level is
+/// drawn up to its `max_distance`, and anything past the last level keeps drawing the last one
+pub struct LodComponent {
+    levels: Vec<LodLevel>,
+}
+
+impl LodComponent {
+    pub fn new(mut levels: Vec<LodLevel>) -> Self {
+        assert!(
+            !levels.is_empty(),
+            "A LodComponent needs at least one level"
+        );
+
+        levels.sort_by(|a, b| a.max_distance.total_cmp(&b.max_distance));
+
+        Self { levels }
+    }
+
+    pub fn model_at(&self, distance: f32) -> &Rc<LveModel> {
+        let level = self
+            .levels
+            .iter()
+            .find(|level| distance <= level.max_distance)
+            .unwrap_or_else(|| self.levels.last().unwrap());
+
+        &level.model
+    }
+}
+
+/// The world space box around a group of objects, e.g. for framing them with the camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SceneBounds {
+    pub min: na::Vector3<f32>,
+    pub max: na::Vector3<f32>,
+}
+
+impl SceneBounds {
+    /// The union of the world space bounds of every visible object with a model. `None` if
+    /// there are no such objects
+    pub fn from_objects<'a>(
+        objects: impl IntoIterator<Item = (&'a u64, &'a LveGameObject)>,
+    ) -> Option<Self> {
+        objects
+            .into_iter()
+            .filter(|(_, game_obj)| game_obj.visible && game_obj.model.vertex_count() > 0)
+            .map(|(_, game_obj)| {
+                let (local_min, local_max) = game_obj.model.aabb();
+                let (min, max) = game_obj.transform.transform_aabb(local_min, local_max);
+
+                Self { min, max }
+            })
+            .reduce(|bounds, other| bounds.union(&other))
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+        }
+    }
+
+    pub fn center(&self) -> na::Vector3<f32> {
+        (self.min + self.max) / 2.0
+    }
+
+    /// The radius of the sphere around the box, which contains it from any direction
+    pub fn radius(&self) -> f32 {
+        (self.max - self.min).norm() / 2.0
+    }
+}
+
 pub struct LveGameObject {
     pub model: Rc<LveModel>,
     pub color: na::Vector3<f32>,
     pub transform: TransformComponent,
+    pub point_light: Option<PointLightComponent>,
+    pub reflection: Option<ReflectionComponent>,
+    /// When set, replaces `model` with the level for the object's distance from the camera
+    pub lod: Option<LodComponent>,
+    pub physics: Option<PhysicsComponent>,
+    pub skin: Option<SkinComponent>,
+    pub material: Option<MaterialComponent>,
+    /// Which of the render system's pipelines the object is drawn with
+    pub pipeline_tag: PipelineTag,
+    /// Hidden objects stay in the scene but are skipped by every render system
+    pub visible: bool,
+    /// Draws a solid outline around the object, e.g. to show it is selected
+    pub outlined: bool,
+    pub outline_color: na::Vector3<f32>,
 }
 
 impl LveGameObject {
@@ -62,22 +410,200 @@ impl LveGameObject {
     ) -> Self {
         let color = match color {
             Some(c) => c,
-            None => na::vector![0.0, 0.0, 0.0],
+            None => na::vector![1.0, 1.0, 1.0],
         };
 
-        let transform = match transform {
-            Some(t) => t,
-            None => TransformComponent {
-                translation: na::vector![0.0, 0.0, 0.0],
-                scale: na::vector![1.0, 1.0, 1.0],
-                rotation: na::vector![0.0, 0.0, 0.0],
-            },
-        };
+        let transform = transform.unwrap_or_default();
 
         Self {
             model,
             color,
             transform,
+            point_light: None,
+            reflection: None,
+            lod: None,
+            physics: None,
+            skin: None,
+            material: None,
+            pipeline_tag: PipelineTag::default(),
+            visible: true,
+            outlined: false,
+            outline_color: na::vector![1.0, 0.6, 0.0],
+        }
+    }
+
+    /// The model to draw when viewed from `camera_position`
+    pub fn model_at(&self, camera_position: na::Vector3<f32>) -> &Rc<LveModel> {
+        match &self.lod {
+            Some(lod) => lod.model_at((self.transform.translation() - camera_position).norm()),
+            None => &self.model,
+        }
+    }
+
+    /// Creates a model-less object that acts as a point light, the light color is taken
+    /// from the object's color
+    pub fn make_point_light(
+        intensity: f32,
+        color: na::Vector3<f32>,
+        translation: na::Vector3<f32>,
+    ) -> Self {
+        let transform = TransformComponent::new(
+            translation,
+            na::vector![1.0, 1.0, 1.0],
+            na::vector![0.0, 0.0, 0.0],
+        );
+
+        let mut game_object = Self::new(
+            LveModel::new_null("point light"),
+            Some(color),
+            Some(transform),
+        );
+
+        game_object.point_light = Some(PointLightComponent {
+            light_intensity: intensity,
+        });
+
+        game_object
+    }
+}
+
+/// Owns every game object in the scene and hands out their ids. Ids are never reused, so an
+/// id kept after its object is removed just won't be found rather than pointing at something
+/// else
+pub struct GameObjectManager {
+    game_objects: HashMap<u64, LveGameObject>,
+    next_id: u64,
+}
+
+impl GameObjectManager {
+    pub fn new() -> Self {
+        Self {
+            game_objects: HashMap::new(),
+            next_id: 0,
         }
     }
+
+    /// Takes ownership of the object and returns its new id
+    pub fn create_object(&mut self, game_object: LveGameObject) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.game_objects.insert(id, game_object);
+
+        id
+    }
+
+    #[allow(dead_code)]
+    pub fn get(&self, id: u64) -> Option<&LveGameObject> {
+        self.game_objects.get(&id)
+    }
+
+    #[allow(dead_code)]
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut LveGameObject> {
+        self.game_objects.get_mut(&id)
+    }
+
+    #[allow(dead_code)]
+    pub fn remove(&mut self, id: u64) -> Option<LveGameObject> {
+        self.game_objects.remove(&id)
+    }
+
+    pub fn iter(&self) -> hash_map::Iter<'_, u64, LveGameObject> {
+        self.game_objects.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.game_objects.len()
+    }
+
+    pub fn visible_count(&self) -> usize {
+        self.game_objects
+            .values()
+            .filter(|game_obj| game_obj.visible)
+            .count()
+    }
+
+    #[allow(dead_code)]
+    pub fn iter_mut(&mut self) -> hash_map::IterMut<'_, u64, LveGameObject> {
+        self.game_objects.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_vectors_eq(actual: na::Vector3<f32>, expected: na::Vector3<f32>) {
+        assert!(
+            (actual - expected).norm() < 1e-5,
+            "expected {:?}, got {:?}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn aabb_is_scaled_and_translated() {
+        let transform = TransformComponent::new(
+            na::vector![1.0, 2.0, 3.0],
+            na::vector![2.0, 1.0, 0.5],
+            na::Vector3::zeros(),
+        );
+
+        let (min, max) =
+            transform.transform_aabb(na::vector![-1.0, -1.0, -1.0], na::vector![1.0, 1.0, 1.0]);
+
+        assert_vectors_eq(min, na::vector![-1.0, 1.0, 2.5]);
+        assert_vectors_eq(max, na::vector![3.0, 3.0, 3.5]);
+    }
+
+    #[test]
+    fn rotated_aabb_covers_the_rotated_corners() {
+        // 45 degrees about y turns the unit cube's corners out to sqrt(2) along x and z
+        let transform = TransformComponent::new(
+            na::Vector3::zeros(),
+            na::vector![1.0, 1.0, 1.0],
+            na::vector![0.0, std::f32::consts::FRAC_PI_4, 0.0],
+        );
+
+        let (min, max) =
+            transform.transform_aabb(na::vector![-1.0, -1.0, -1.0], na::vector![1.0, 1.0, 1.0]);
+
+        let half_diagonal = std::f32::consts::SQRT_2;
+        assert_vectors_eq(min, na::vector![-half_diagonal, -1.0, -half_diagonal]);
+        assert_vectors_eq(max, na::vector![half_diagonal, 1.0, half_diagonal]);
+    }
+
+    #[test]
+    fn orientations_match_their_euler_angles() {
+        let rotation = na::vector![0.4, -2.1, 0.7];
+        let mut transform = TransformComponent::new(
+            na::vector![1.0, 2.0, 3.0],
+            na::vector![2.0, 1.0, 0.5],
+            rotation,
+        );
+        let euler_mat4 = transform.mat4();
+        let euler_normal_matrix = transform.normal_matrix();
+
+        transform.set_orientation(orientation_from_rotation(rotation));
+
+        assert!((transform.mat4() - euler_mat4).norm() < 1e-5);
+        assert!((transform.normal_matrix() - euler_normal_matrix).norm() < 1e-5);
+        assert_vectors_eq(transform.rotation(), rotation);
+    }
+
+    #[test]
+    fn looking_straight_up_keeps_the_orientation() {
+        // Pitching by 90 degrees about x, then rolling, is where Euler angles lose an axis
+        let orientation = na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 0.3)
+            * na::UnitQuaternion::from_axis_angle(
+                &na::Vector3::x_axis(),
+                std::f32::consts::FRAC_PI_2,
+            )
+            * na::UnitQuaternion::from_axis_angle(&na::Vector3::z_axis(), 0.5);
+
+        let round_trip = orientation_from_rotation(rotation_from_orientation(&orientation));
+
+        assert!(round_trip.angle_to(&orientation) < 1e-3);
+    }
 }
@@ -0,0 +1,467 @@
+use super::lve_device::*;
+
+use ash::vk;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// What an `LveImage` is about to be used for, which decides the layout it has to be in and
+/// what a barrier before that use has to wait for
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageUsage {
+    ColorAttachment,
+    DepthStencilAttachment,
+    /// Depth testing without depth writes, while shaders may sample it too
+    DepthStencilRead,
+    /// Sampled or read as an input attachment in a fragment shader
+    FragmentShaderRead,
+    ComputeShaderRead,
+    /// A storage image a compute shader writes, and may read. Needs `STORAGE` usage. Moving on
+    /// to a read, e.g. `FragmentShaderRead`, makes the writes visible to it
+    ComputeShaderWrite,
+    TransferSrc,
+    TransferDst,
+}
+
+/// The layout, stages and access one `ImageUsage` needs
+#[derive(Debug, Clone, Copy)]
+struct UsageState {
+    layout: vk::ImageLayout,
+    stage: vk::PipelineStageFlags,
+    access: vk::AccessFlags,
+    writes: bool,
+}
+
+impl ImageUsage {
+    fn state(self) -> UsageState {
+        let (layout, stage, access) = match self {
+            ImageUsage::ColorAttachment => (
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ),
+            ImageUsage::DepthStencilAttachment => (
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+            ImageUsage::DepthStencilRead => (
+                vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::SHADER_READ,
+            ),
+            ImageUsage::FragmentShaderRead => (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            ),
+            ImageUsage::ComputeShaderRead => (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            ),
+            ImageUsage::ComputeShaderWrite => (
+                vk::ImageLayout::GENERAL,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            ),
+            ImageUsage::TransferSrc => (
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+            ImageUsage::TransferDst => (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+        };
+
+        UsageState {
+            layout,
+            stage,
+            access,
+            writes: access.intersects(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+                    | vk::AccessFlags::SHADER_WRITE
+                    | vk::AccessFlags::TRANSFER_WRITE,
+            ),
+        }
+    }
+}
+
+/// The layout change and dependency one image barrier makes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Transition {
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_stage: vk::PipelineStageFlags,
+    dst_stage: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+}
+
+/// The barrier needed between the last use of an image, `None` when its contents are
+/// undefined, and the next. Reads following reads of the same kind need none
+fn transition_between(previous: Option<ImageUsage>, next: ImageUsage) -> Option<Transition> {
+    let next_state = next.state();
+
+    let (old_layout, src_stage, src_access) = match previous {
+        Some(previous) if previous == next && !next_state.writes => return None,
+        // Only writes have to be made available, reads just have to finish first
+        Some(previous) => {
+            let previous_state = previous.state();
+            let src_access = if previous_state.writes {
+                previous_state.access
+            } else {
+                vk::AccessFlags::empty()
+            };
+
+            (previous_state.layout, previous_state.stage, src_access)
+        }
+        None => (
+            vk::ImageLayout::UNDEFINED,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::AccessFlags::empty(),
+        ),
+    };
+
+    Some(Transition {
+        old_layout,
+        new_layout: next_state.layout,
+        src_stage,
+        dst_stage: next_state.stage,
+        src_access,
+        dst_access: next_state.access,
+    })
+}
+
+/// The features `usage` needs the image's format to have with optimal tiling that `supported`
+/// doesn't include. Usages with no format feature of their own, e.g. transfers on Vulkan 1.0,
+/// are ignored
+fn missing_format_features(
+    supported: vk::FormatFeatureFlags,
+    usage: vk::ImageUsageFlags,
+) -> vk::FormatFeatureFlags {
+    [
+        (
+            vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            vk::FormatFeatureFlags::COLOR_ATTACHMENT,
+        ),
+        (
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        ),
+        (
+            vk::ImageUsageFlags::SAMPLED,
+            vk::FormatFeatureFlags::SAMPLED_IMAGE,
+        ),
+        (
+            vk::ImageUsageFlags::STORAGE,
+            vk::FormatFeatureFlags::STORAGE_IMAGE,
+        ),
+    ]
+    .iter()
+    .filter(|(needed_for, _)| usage.contains(*needed_for))
+    .fold(vk::FormatFeatureFlags::empty(), |missing, (_, feature)| {
+        missing | (*feature & !supported)
+    })
+}
+
+/// A single sample 2D device local image with one view covering the whole image, for render
+/// targets such as depth buffers and offscreen colour attachments. The image, its memory and
+/// the view are all freed on drop
+pub struct LveImage {
+    lve_device: Rc<LveDevice>,
+    pub image: vk::Image,
+    image_memory: vk::DeviceMemory,
+    pub image_view: vk::ImageView,
+    #[allow(dead_code)]
+    pub format: vk::Format,
+    #[allow(dead_code)]
+    pub extent: vk::Extent2D,
+    aspect_mask: vk::ImageAspectFlags,
+    /// What the image was created for
+    usage_flags: vk::ImageUsageFlags,
+    /// What the last barrier recorded by `transition_for` readied the image for, `None` while
+    /// its contents are undefined
+    usage: Cell<Option<ImageUsage>>,
+}
+
+impl LveImage {
+    /// Panics, after logging what's missing, if the device can't use `format` for `usage`
+    pub fn new(
+        lve_device: Rc<LveDevice>,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        aspect_mask: vk::ImageAspectFlags,
+        extent: vk::Extent2D,
+    ) -> Self {
+        let missing_features = missing_format_features(
+            lve_device
+                .get_format_properties(format)
+                .optimal_tiling_features,
+            usage,
+        );
+
+        if !missing_features.is_empty() {
+            log::error!(
+                "{:?} can't be used for {:?} images, the device doesn't support {:?} for it",
+                format,
+                usage,
+                missing_features
+            );
+            panic!("Unsupported image format {:?}", format);
+        }
+
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .format(format)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(usage)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .flags(vk::ImageCreateFlags::empty());
+
+        let (image, image_memory) =
+            lve_device.create_image_with_info(&image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL);
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            });
+
+        let image_view = unsafe {
+            lve_device
+                .device
+                .create_image_view(&view_info, None)
+                .map_err(|e| log::error!("Unable to create image view: {}", e))
+                .unwrap()
+        };
+
+        Self {
+            lve_device,
+            image,
+            image_memory,
+            image_view,
+            format,
+            extent,
+            aspect_mask,
+            usage_flags: usage,
+            usage: Cell::new(None),
+        }
+    }
+
+    /// An offscreen colour target, e.g. for a post-processing pass to render into and the next
+    /// one to sample. The format is up to the caller, as it decides what the chain stores:
+    /// `R16G16B16A16_SFLOAT` for linear HDR colour, `R8G8B8A8_UNORM` for LDR data that mustn't
+    /// be touched, and an sRGB format only for display ready colour, which is encoded on
+    /// write and decoded on read. Panics if the device can't render to or sample `format`
+    #[allow(dead_code)]
+    pub fn color_target(
+        lve_device: Rc<LveDevice>,
+        format: vk::Format,
+        extra_usage: vk::ImageUsageFlags,
+        extent: vk::Extent2D,
+    ) -> Self {
+        Self::new(
+            lve_device,
+            format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED | extra_usage,
+            vk::ImageAspectFlags::COLOR,
+            extent,
+        )
+    }
+
+    /// Records the barrier that readies the image for `usage`, moving it to the layout `usage`
+    /// needs and waiting for the last use to be done with it. Nothing is recorded between
+    /// reads of the same kind.
+    ///
+    /// The image's state is tracked in recording order, so command buffers using it must be
+    /// submitted in the order they were recorded. Layout changes made any other way, such as
+    /// a render pass's final layout, have to be reported with `set_usage`
+    pub fn transition_for(&self, command_buffer: vk::CommandBuffer, usage: ImageUsage) {
+        let transition = match transition_between(self.usage.get(), usage) {
+            Some(transition) => transition,
+            None => return,
+        };
+
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(transition.old_layout)
+            .new_layout(transition.new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: self.aspect_mask,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .src_access_mask(transition.src_access)
+            .dst_access_mask(transition.dst_access);
+
+        unsafe {
+            self.lve_device.device.cmd_pipeline_barrier(
+                command_buffer,
+                transition.src_stage,
+                transition.dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                std::slice::from_ref(&barrier),
+            )
+        };
+
+        self.usage.set(Some(usage));
+    }
+
+    /// For binding the image to a compute shader as a storage image, with
+    /// `LveDescriptorWriter::write_storage_image`. Storage images are accessed in the `GENERAL`
+    /// layout, which `transition_for(command_buffer, ImageUsage::ComputeShaderWrite)` moves
+    /// the image to before the dispatch
+    #[allow(dead_code)]
+    pub fn storage_descriptor_info(&self) -> vk::DescriptorImageInfo {
+        assert!(
+            self.usage_flags.contains(vk::ImageUsageFlags::STORAGE),
+            "Image was not created with STORAGE usage"
+        );
+
+        vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: self.image_view,
+            image_layout: vk::ImageLayout::GENERAL,
+        }
+    }
+
+    /// For when something other than `transition_for` readied the image for `usage`, e.g. a
+    /// render pass whose final layout is the one `usage` needs
+    #[allow(dead_code)]
+    pub fn set_usage(&self, usage: ImageUsage) {
+        self.usage.set(Some(usage));
+    }
+
+    /// The next transition won't keep the image's contents, which can save the GPU work when
+    /// they are about to be overwritten anyway
+    #[allow(dead_code)]
+    pub fn discard_contents(&self) {
+        self.usage.set(None);
+    }
+}
+
+impl Drop for LveImage {
+    fn drop(&mut self) {
+        log::debug!("Dropping Image");
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_image_view(self.image_view, None);
+            self.lve_device.device.destroy_image(self.image, None);
+            self.lve_device.device.free_memory(self.image_memory, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_missing_a_feature_the_usage_needs_are_caught() {
+        let supported =
+            vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::COLOR_ATTACHMENT;
+
+        assert!(missing_format_features(
+            supported,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED
+        )
+        .is_empty());
+        assert_eq!(
+            missing_format_features(
+                supported,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::STORAGE
+            ),
+            vk::FormatFeatureFlags::STORAGE_IMAGE
+        );
+        assert!(missing_format_features(supported, vk::ImageUsageFlags::TRANSFER_SRC).is_empty());
+    }
+
+    #[test]
+    fn undefined_images_transition_from_the_undefined_layout() {
+        let transition = transition_between(None, ImageUsage::ColorAttachment).unwrap();
+
+        assert_eq!(transition.old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(
+            transition.new_layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+        assert_eq!(transition.src_stage, vk::PipelineStageFlags::TOP_OF_PIPE);
+        assert!(transition.src_access.is_empty());
+    }
+
+    #[test]
+    fn writes_are_made_visible_to_the_reads_after_them() {
+        let transition = transition_between(
+            Some(ImageUsage::ColorAttachment),
+            ImageUsage::FragmentShaderRead,
+        )
+        .unwrap();
+
+        assert_eq!(
+            transition.old_layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+        assert_eq!(
+            transition.new_layout,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        );
+        assert!(transition
+            .src_access
+            .contains(vk::AccessFlags::COLOR_ATTACHMENT_WRITE));
+        assert_eq!(transition.dst_access, vk::AccessFlags::SHADER_READ);
+    }
+
+    #[test]
+    fn only_repeated_reads_skip_the_barrier() {
+        assert!(transition_between(
+            Some(ImageUsage::FragmentShaderRead),
+            ImageUsage::FragmentShaderRead
+        )
+        .is_none());
+
+        // Two compute dispatches writing the same image still have to be ordered
+        assert!(transition_between(
+            Some(ImageUsage::ComputeShaderWrite),
+            ImageUsage::ComputeShaderWrite
+        )
+        .is_some());
+
+        // Writing after reading only waits for the reads to finish
+        let transition =
+            transition_between(Some(ImageUsage::TransferSrc), ImageUsage::TransferDst).unwrap();
+        assert!(transition.src_access.is_empty());
+        assert_eq!(transition.src_stage, vk::PipelineStageFlags::TRANSFER);
+    }
+}
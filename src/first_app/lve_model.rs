@@ -3,13 +3,17 @@ use super::lve_device::*;
 
 use ash::{vk, Device};
 
+use std::cell::RefCell;
 use std::collections::{hash_map::DefaultHasher, HashMap};
 use std::hash::{Hash, Hasher};
 use std::mem::size_of;
 use std::rc::Rc;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
 
 use ordered_float::OrderedFloat;
+use rayon::prelude::*;
 
 extern crate nalgebra as na;
 
@@ -20,6 +24,28 @@ type Color = na::Vector3<Hf32>;
 type Normal = na::Vector3<Hf32>;
 type TextureCoord = na::Vector2<Hf32>;
 
+/// Run `ModelData::validate_and_repair` on every model loaded from a file
+const REPAIR_MODELS: bool = true;
+
+/// Run `ModelData::optimize` on every model loaded from a file, after any repair
+const OPTIMIZE_MODELS: bool = true;
+
+/// Threads `LveModel::load_models_parallel` parses files on, 0 for one per CPU core
+const MODEL_LOADING_THREADS: usize = 0;
+
+/// Convert the vertex colors loaded from OBJ files from sRGB to linear, see
+/// `ModelData::convert_colors_to_linear`. Enable this when the colors were picked in a paint
+/// program or DCC tool (they are almost always sRGB), so the lighting works on linear values and
+/// the sRGB swapchain encodes the color that was picked rather than a washed out one. Off by
+/// default as it darkens every existing colored model
+const LINEARIZE_VERTEX_COLORS: bool = false;
+/// The gamma of the approximate sRGB curve used by `ModelData::convert_colors_to_linear`
+const SRGB_GAMMA: f32 = 2.2;
+
+/// Running totals over every live model with GPU buffers, see `LveModel::total_gpu_bytes`
+static MODEL_COUNT: AtomicUsize = AtomicUsize::new(0);
+static MODEL_GPU_BYTES: AtomicU64 = AtomicU64::new(0);
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Vertex {
     pub position: Pos,
@@ -71,17 +97,62 @@ impl Vertex {
     }
 }
 
+/// How many problems `ModelData::validate_and_repair` found and fixed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MeshRepairReport {
+    /// Triangles that used the same vertex index more than once
+    pub degenerate_triangles: usize,
+    /// NaN or infinite components, which were replaced with 0
+    pub non_finite_values: usize,
+    /// Normals that weren't unit length
+    pub renormalized_normals: usize,
+    /// Zero length normals that were rebuilt from the faces using the vertex
+    pub regenerated_normals: usize,
+}
+
+impl MeshRepairReport {
+    pub fn total(&self) -> usize {
+        self.degenerate_triangles
+            + self.non_finite_values
+            + self.renormalized_normals
+            + self.regenerated_normals
+    }
+}
+
 pub struct ModelData {
     pub vertices: Vec<Vertex>,
     pub indices: Option<Vec<u32>>,
 }
 
 impl ModelData {
-    pub fn load_model(file_path: &str) -> (Self, Vec<String>) {
-        let model_file = tobj::load_obj(file_path, &tobj::GPU_LOAD_OPTIONS);
-        let (models, _materials) = model_file
-            .map_err(|e| log::error!("Unable to load model: {}", e))
-            .unwrap();
+    /// The (min, max) corners of the box around every vertex, both zero if there are none
+    pub fn aabb(&self) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        if self.vertices.is_empty() {
+            return (na::Vector3::zeros(), na::Vector3::zeros());
+        }
+
+        self.vertices.iter().fold(
+            (
+                na::Vector3::repeat(f32::INFINITY),
+                na::Vector3::repeat(f32::NEG_INFINITY),
+            ),
+            |(min, max), vertex| {
+                let position = vertex.position.map(|component| component.into_inner());
+                (min.inf(&position), max.sup(&position))
+            },
+        )
+    }
+
+    /// Loads every model in an OBJ file, merging identical vertices. Any attribute the file
+    /// doesn't provide for every vertex falls back to a default: white for colors, the face's
+    /// normal for normals and (0, 0) for texture coordinates. `flip_winding` fixes files exported
+    /// with the opposite winding, see `flip_winding`. Returns the names of the models alongside
+    /// the data
+    pub fn load_model(
+        file_path: &str,
+        flip_winding: bool,
+    ) -> Result<(Self, Vec<String>), tobj::LoadError> {
+        let (models, _materials) = tobj::load_obj(file_path, &tobj::GPU_LOAD_OPTIONS)?;
 
         // Stores the hash of the vertex as the key, and the index of the unique vertex
         let mut unique_vertices: HashMap<usize, u32> = HashMap::new();
@@ -93,37 +164,65 @@ impl ModelData {
             .iter()
             .map(|model| {
                 let positions = &model.mesh.positions;
-                let colors = match &model.mesh.vertex_color.as_slice() {
-                    [] => vec![1_f32; positions.len()],
-                    v => v.to_vec(),
+                let vertex_count = positions.len() / 3;
+
+                let colors = attribute(&model.mesh.vertex_color, 3, vertex_count, &model.name);
+                let normals = attribute(&model.mesh.normals, 3, vertex_count, &model.name);
+                let uvs = attribute(&model.mesh.texcoords, 2, vertex_count, &model.name);
+
+                let position = |index: usize| {
+                    na::vector![
+                        positions[3 * index + 0],
+                        positions[3 * index + 1],
+                        positions[3 * index + 2]
+                    ]
                 };
-                let normals = &model.mesh.normals;
-                let uvs = &model.mesh.texcoords;
+
                 model
                     .mesh
                     .indices
-                    .iter()
-                    .filter_map(|index| {
+                    .chunks_exact(3)
+                    .flat_map(|triangle| {
+                        let [a, b, c] = [
+                            triangle[0] as usize,
+                            triangle[1] as usize,
+                            triangle[2] as usize,
+                        ];
+
+                        // Used when the file has no normals, which shades the model flat
+                        let face_normal = (position(b) - position(a))
+                            .cross(&(position(c) - position(a)))
+                            .try_normalize(f32::EPSILON)
+                            .unwrap_or_else(na::Vector3::zeros);
+
+                        [a, b, c].map(|index| (index, face_normal))
+                    })
+                    .filter_map(|(index, face_normal)| {
                         let vertex = Vertex {
-                            position: na::vector![
-                                OrderedFloat(positions[(3 * index + 0) as usize]),
-                                OrderedFloat(positions[(3 * index + 1) as usize]),
-                                OrderedFloat(positions[(3 * index + 2) as usize])
-                            ],
-                            color: na::vector![
-                                OrderedFloat(colors[(3 * index + 0) as usize]),
-                                OrderedFloat(colors[(3 * index + 1) as usize]),
-                                OrderedFloat(colors[(3 * index + 2) as usize])
-                            ],
-                            normal: na::vector![
-                                OrderedFloat(normals[(3 * index + 0) as usize]),
-                                OrderedFloat(normals[(3 * index + 1) as usize]),
-                                OrderedFloat(normals[(3 * index + 2) as usize])
-                            ],
-                            uv: na::vector![
-                                OrderedFloat(uvs[(2 * index + 0) as usize]),
-                                OrderedFloat(uvs[(2 * index + 1) as usize])
-                            ],
+                            position: position(index).map(OrderedFloat),
+                            color: match colors {
+                                Some(colors) => na::vector![
+                                    OrderedFloat(colors[3 * index + 0]),
+                                    OrderedFloat(colors[3 * index + 1]),
+                                    OrderedFloat(colors[3 * index + 2])
+                                ],
+                                None => na::Vector3::repeat(OrderedFloat(1.0)),
+                            },
+                            normal: match normals {
+                                Some(normals) => na::vector![
+                                    OrderedFloat(normals[3 * index + 0]),
+                                    OrderedFloat(normals[3 * index + 1]),
+                                    OrderedFloat(normals[3 * index + 2])
+                                ],
+                                None => face_normal.map(OrderedFloat),
+                            },
+                            uv: match uvs {
+                                Some(uvs) => na::vector![
+                                    OrderedFloat(uvs[2 * index + 0]),
+                                    OrderedFloat(uvs[2 * index + 1])
+                                ],
+                                None => na::Vector2::repeat(OrderedFloat(0.0)),
+                            },
                         };
 
                         let mut hasher = DefaultHasher::new();
@@ -153,81 +252,562 @@ impl ModelData {
             names.push(model.name)
         }
 
-        (
-            Self {
-                vertices,
-                indices: Some(indices),
-            },
-            names,
-        )
+        let mut model_data = Self {
+            vertices,
+            indices: Some(indices),
+        };
+
+        if flip_winding {
+            model_data.flip_winding();
+        }
+
+        if LINEARIZE_VERTEX_COLORS {
+            model_data.convert_colors_to_linear();
+        }
+
+        Ok((model_data, names))
+    }
+
+    /// Reverses the winding of every triangle and points the normals the other way, turning
+    /// the model inside out. For models that render inside out with back-face culling
+    pub fn flip_winding(&mut self) {
+        match &mut self.indices {
+            Some(indices) => indices
+                .chunks_exact_mut(3)
+                .for_each(|triangle| triangle.swap(1, 2)),
+            None => self
+                .vertices
+                .chunks_exact_mut(3)
+                .for_each(|triangle| triangle.swap(1, 2)),
+        }
+
+        for vertex in self.vertices.iter_mut() {
+            let normal = vertex.normal.map(|component| component.into_inner());
+            vertex.normal = (-normal).map(OrderedFloat);
+        }
+    }
+
+    /// Converts every vertex color from sRGB to linear with a gamma of 2.2. White and black are
+    /// unchanged, so the default white of models without colors stays white
+    pub fn convert_colors_to_linear(&mut self) {
+        for vertex in self.vertices.iter_mut() {
+            for component in vertex.color.iter_mut() {
+                *component = OrderedFloat(component.into_inner().max(0.0).powf(SRGB_GAMMA));
+            }
+        }
+    }
+
+    /// Fixes the problems that commonly come with exported meshes: removes degenerate triangles,
+    /// replaces NaN or infinite values with 0 and makes every normal unit length, rebuilding
+    /// zero length normals from the faces that use the vertex. Non-indexed meshes keep their
+    /// triangles as there are no indices to compare
+    pub fn validate_and_repair(&mut self) -> MeshRepairReport {
+        let mut report = MeshRepairReport::default();
+
+        for vertex in self.vertices.iter_mut() {
+            let components = vertex
+                .position
+                .iter_mut()
+                .chain(vertex.color.iter_mut())
+                .chain(vertex.normal.iter_mut())
+                .chain(vertex.uv.iter_mut());
+
+            for component in components {
+                if !component.is_finite() {
+                    *component = OrderedFloat(0.0);
+                    report.non_finite_values += 1;
+                }
+            }
+        }
+
+        if let Some(indices) = &mut self.indices {
+            let index_count = indices.len();
+
+            *indices = indices
+                .chunks_exact(3)
+                .filter(|triangle| {
+                    triangle[0] != triangle[1]
+                        && triangle[1] != triangle[2]
+                        && triangle[0] != triangle[2]
+                })
+                .flatten()
+                .copied()
+                .collect();
+
+            report.degenerate_triangles = (index_count - indices.len()) / 3;
+        }
+
+        let face_normals = self.accumulate_face_normals();
+
+        for (vertex, face_normal) in self.vertices.iter_mut().zip(face_normals) {
+            let normal = vertex.normal.map(|component| component.into_inner());
+            let length = normal.norm();
+
+            let repaired_normal = if length == 0.0 {
+                match face_normal.try_normalize(f32::EPSILON) {
+                    Some(face_normal) => {
+                        report.regenerated_normals += 1;
+                        face_normal
+                    }
+                    // Not used by any (non-degenerate) triangle, so there's nothing to go on
+                    None => continue,
+                }
+            } else if (length - 1.0).abs() > 1e-3 {
+                report.renormalized_normals += 1;
+                normal / length
+            } else {
+                continue;
+            };
+
+            vertex.normal = repaired_normal.map(OrderedFloat);
+        }
+
+        if report.total() > 0 {
+            log::warn!("Repaired {} mesh issues: {:?}", report.total(), report);
+        }
+
+        report
+    }
+
+    /// The sum of the (area weighted) normals of every triangle each vertex is part of
+    fn accumulate_face_normals(&self) -> Vec<na::Vector3<f32>> {
+        let mut face_normals = vec![na::Vector3::zeros(); self.vertices.len()];
+
+        let indices = match &self.indices {
+            Some(indices) => indices.clone(),
+            None => (0..self.vertices.len() as u32).collect(),
+        };
+
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [
+                triangle[0] as usize,
+                triangle[1] as usize,
+                triangle[2] as usize,
+            ];
+
+            let position = |index: usize| self.vertices[index].position.map(|c| c.into_inner());
+
+            // The cross product's length is twice the triangle's area, so bigger faces count
+            // for more
+            let face_normal = (position(b) - position(a)).cross(&(position(c) - position(a)));
+
+            for index in [a, b, c] {
+                face_normals[index] += face_normal;
+            }
+        }
+
+        face_normals
     }
 }
 
-pub struct LveModel {
+/// A mesh attribute with `components` values per vertex, or `None` if the file doesn't give it
+/// for every vertex. An OBJ can give normals or texture coordinates for some faces and not
+/// others, and the values that are there can't be matched back to their vertices, so a
+/// partial attribute is ignored
+fn attribute<'a>(
+    values: &'a [f32],
+    components: usize,
+    vertex_count: usize,
+    model_name: &str,
+) -> Option<&'a [f32]> {
+    match values.len() {
+        0 => None,
+        len if len == components * vertex_count => Some(values),
+        len => {
+            log::warn!(
+                "Model {} has an attribute for {} of its {} vertices, ignoring it",
+                model_name,
+                len / components,
+                vertex_count
+            );
+            None
+        }
+    }
+}
+
+/// A model's GPU buffers and what was uploaded to them. Kept separate from `LveModel` so the
+/// geometry can be swapped out from under a shared model when its file is reloaded
+pub struct ModelGeometry {
     vertex_buffer: Option<Rc<LveBuffer>>,
     vertex_count: u32,
     index_buffer: Option<Rc<LveBuffer>>,
     index_count: u32,
-    name: String,
+    /// Model space bounds of the vertices, (min, max)
+    aabb: (na::Vector3<f32>, na::Vector3<f32>),
 }
 
-impl LveModel {
-    pub fn new(lve_device: Rc<LveDevice>, model_data: &ModelData, name: &str) -> Rc<Self> {
+impl ModelGeometry {
+    fn new(lve_device: &Rc<LveDevice>, model_data: &ModelData) -> Self {
         let (vertex_buffer, vertex_count) =
-            Self::create_vertex_buffers(&lve_device, &model_data.vertices);
+            LveModel::create_vertex_buffers(lve_device, &model_data.vertices);
         let (index_buffer, index_count) =
-            Self::create_index_buffer(&lve_device, &model_data.indices);
-        Rc::new(Self {
+            LveModel::create_index_buffer(lve_device, &model_data.indices);
+
+        Self {
             vertex_buffer,
             vertex_count,
             index_buffer,
             index_count,
-            name: String::from_str(name).unwrap(),
-        })
+            aabb: model_data.aabb(),
+        }
     }
 
-    pub fn new_null(name: &str) -> Rc<Self> {
-        Rc::new(Self {
+    fn null() -> Self {
+        Self {
             vertex_buffer: None,
             vertex_count: 0,
             index_buffer: None,
             index_count: 0,
+            aabb: (na::Vector3::zeros(), na::Vector3::zeros()),
+        }
+    }
+
+    fn gpu_bytes(&self) -> vk::DeviceSize {
+        [&self.vertex_buffer, &self.index_buffer]
+            .iter()
+            .filter_map(|buffer| buffer.as_ref())
+            .map(|buffer| buffer.buffer_size)
+            .sum()
+    }
+}
+
+pub struct LveModel {
+    geometry: RefCell<ModelGeometry>,
+    name: String,
+    /// Whether the model was loaded with its winding flipped, so reloads flip it too
+    flip_winding: bool,
+}
+
+impl LveModel {
+    /// `flip_winding` is whether `model_data` was loaded with its winding flipped, which
+    /// `reload_from_file` repeats
+    pub fn new(
+        lve_device: Rc<LveDevice>,
+        model_data: &ModelData,
+        name: &str,
+        flip_winding: bool,
+    ) -> Rc<Self> {
+        let model = Self {
+            geometry: RefCell::new(ModelGeometry::new(&lve_device, model_data)),
             name: String::from_str(name).unwrap(),
+            flip_winding,
+        };
+
+        MODEL_COUNT.fetch_add(1, Ordering::Relaxed);
+        MODEL_GPU_BYTES.fetch_add(model.gpu_bytes(), Ordering::Relaxed);
+
+        Rc::new(model)
+    }
+
+    pub fn new_null(name: &str) -> Rc<Self> {
+        Rc::new(Self {
+            geometry: RefCell::new(ModelGeometry::null()),
+            name: String::from_str(name).unwrap(),
+            flip_winding: false,
         })
     }
 
-    pub fn create_model_from_file(lve_device: Rc<LveDevice>, file_path: &str) -> Rc<Self> {
-        let (model_data, names) = ModelData::load_model(file_path);
-        log::info!("Model Name: {}", names[0]);
+    /// The model space (min, max) corners of the box around every vertex
+    pub fn aabb(&self) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        self.geometry.borrow().aabb
+    }
+
+    /// Loads the model in `file_path`, flipping its winding if it was exported inside out (see
+    /// `ModelData::flip_winding`)
+    pub fn create_model_from_file(
+        lve_device: Rc<LveDevice>,
+        file_path: &str,
+        flip_winding: bool,
+    ) -> Rc<Self> {
+        let (model_data, name) = Self::parse(file_path, flip_winding)
+            .map_err(|e| log::error!("Unable to load model {}: {}", file_path, e))
+            .unwrap();
+
+        Self::upload(lve_device, &model_data, &name, flip_winding)
+    }
+
+    /// Loads the model in `file_path` and a simpler version of it for each of `ratios`, the
+    /// share of the triangles to keep (see `ModelData::simplify`). For the levels of a
+    /// `LodComponent`, the full detail model comes first
+    pub fn create_lods_from_file(
+        lve_device: &Rc<LveDevice>,
+        file_path: &str,
+        flip_winding: bool,
+        ratios: &[f32],
+    ) -> Vec<Rc<Self>> {
+        let (model_data, name) = Self::parse(file_path, flip_winding)
+            .map_err(|e| log::error!("Unable to load model {}: {}", file_path, e))
+            .unwrap();
+
+        let simplified = ratios.iter().enumerate().map(|(level, ratio)| {
+            (
+                model_data.simplify(*ratio),
+                format!("{} (LOD {})", name, level + 1),
+            )
+        });
+
+        std::iter::once(Self::upload(
+            Rc::clone(lve_device),
+            &model_data,
+            &name,
+            flip_winding,
+        ))
+        .chain(simplified.map(|(level_data, level_name)| {
+            Self::upload(
+                Rc::clone(lve_device),
+                &level_data,
+                &level_name,
+                flip_winding,
+            )
+        }))
+        .collect()
+    }
+
+    /// Loads every file in `file_paths`, in the same order. The files are parsed in parallel on
+    /// a pool of `MODEL_LOADING_THREADS` threads, then uploaded to the GPU one at a time on
+    /// this thread
+    pub fn load_models_parallel(lve_device: &Rc<LveDevice>, file_paths: &[&str]) -> Vec<Rc<Self>> {
+        let parse_start = Instant::now();
+
+        let thread_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(MODEL_LOADING_THREADS)
+            .build()
+            .map_err(|e| log::error!("Unable to create model loading thread pool: {}", e))
+            .unwrap();
+
+        let parsed = thread_pool.install(|| {
+            file_paths
+                .par_iter()
+                .map(|file_path| Self::parse(file_path, false))
+                .collect::<Vec<_>>()
+        });
+
+        let parse_time = parse_start.elapsed();
+        let upload_start = Instant::now();
+
+        let models = file_paths
+            .iter()
+            .zip(parsed)
+            .map(|(file_path, result)| {
+                let (model_data, name) = result
+                    .map_err(|e| log::error!("Unable to load model {}: {}", file_path, e))
+                    .unwrap();
+
+                Self::upload(Rc::clone(lve_device), &model_data, &name, false)
+            })
+            .collect();
+
+        log::info!(
+            "Parsed {} models in {:.1} ms on {} threads, uploaded them in {:.1} ms",
+            file_paths.len(),
+            parse_time.as_secs_f64() * 1000.0,
+            thread_pool.current_num_threads(),
+            upload_start.elapsed().as_secs_f64() * 1000.0
+        );
+
+        models
+    }
+
+    /// The CPU half of loading a model: the data in `file_path` and the name of its first mesh.
+    /// Needs no device, so it can run on any thread
+    pub fn parse(
+        file_path: &str,
+        flip_winding: bool,
+    ) -> Result<(ModelData, String), tobj::LoadError> {
+        let (mut model_data, mut names) = ModelData::load_model(file_path, flip_winding)?;
+
+        if REPAIR_MODELS {
+            model_data.validate_and_repair();
+        }
+
+        if OPTIMIZE_MODELS {
+            model_data.optimize();
+        }
+
+        Ok((model_data, names.swap_remove(0)))
+    }
+
+    /// The GPU half of loading a model, creates its buffers from data returned by `parse`
+    pub fn upload(
+        lve_device: Rc<LveDevice>,
+        model_data: &ModelData,
+        name: &str,
+        flip_winding: bool,
+    ) -> Rc<Self> {
+        log::info!("Model Name: {}", name);
         log::info!("Vertex count: {}", model_data.vertices.len());
-        Self::new(lve_device, &model_data, &names[0])
+        Self::new(lve_device, model_data, name, flip_winding)
     }
 
+    /// Loads `file_path` again and swaps the new geometry in, so everything holding this model
+    /// draws the new version from the next frame on. Returns the old geometry, which frames
+    /// in flight may still be drawing, so it must be kept alive until they have finished (e.g.
+    /// with `LveRenderer::defer_deletion`). If the file can't be loaded the model keeps its
+    /// current geometry and `None` is returned
+    pub fn reload_from_file(
+        &self,
+        lve_device: &Rc<LveDevice>,
+        file_path: &str,
+    ) -> Option<ModelGeometry> {
+        let model_data = match Self::parse(file_path, self.flip_winding) {
+            Ok((model_data, _)) => model_data,
+            Err(e) => {
+                log::warn!(
+                    "Unable to reload model {}, keeping the old one: {}",
+                    file_path,
+                    e
+                );
+                return None;
+            }
+        };
+
+        if model_data.vertices.len() < 3 {
+            log::warn!(
+                "Reloaded model {} has no triangles, keeping the old one",
+                file_path
+            );
+            return None;
+        }
+
+        let geometry = self
+            .geometry
+            .replace(ModelGeometry::new(lve_device, &model_data));
+
+        MODEL_GPU_BYTES.fetch_sub(geometry.gpu_bytes(), Ordering::Relaxed);
+        MODEL_GPU_BYTES.fetch_add(self.gpu_bytes(), Ordering::Relaxed);
+
+        log::info!(
+            "Reloaded model {} with {} vertices",
+            file_path,
+            model_data.vertices.len()
+        );
+
+        Some(geometry)
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.geometry.borrow().vertex_count
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.geometry.borrow().index_count
+    }
+
+    /// How many triangles `draw` draws
+    pub fn triangle_count(&self) -> u64 {
+        let geometry = self.geometry.borrow();
+
+        match geometry.index_buffer {
+            Some(_) => geometry.index_count as u64 / 3,
+            None => geometry.vertex_count as u64 / 3,
+        }
+    }
+
+    /// The combined size of the vertex and index buffers
+    pub fn gpu_bytes(&self) -> vk::DeviceSize {
+        self.geometry.borrow().gpu_bytes()
+    }
+
+    /// How many models with GPU buffers are alive, null models aren't counted
+    pub fn live_model_count() -> usize {
+        MODEL_COUNT.load(Ordering::Relaxed)
+    }
+
+    /// The sum of `gpu_bytes` over every live model
+    pub fn total_gpu_bytes() -> vk::DeviceSize {
+        MODEL_GPU_BYTES.load(Ordering::Relaxed)
+    }
+
+    /// Draws one instance of the whole model
     pub unsafe fn draw(&self, device: &Device, command_buffer: vk::CommandBuffer) {
-        match &self.index_buffer {
-            Some(_) => device.cmd_draw_indexed(command_buffer, self.index_count, 1, 0, 0, 0),
-            None => device.cmd_draw(command_buffer, self.vertex_count, 1, 0, 0),
+        let (has_index_buffer, index_count, vertex_count) = {
+            let geometry = self.geometry.borrow();
+            (
+                geometry.index_buffer.is_some(),
+                geometry.index_count,
+                geometry.vertex_count,
+            )
+        };
+
+        if has_index_buffer {
+            self.draw_indexed(device, command_buffer, index_count, 1, 0, 0, 0);
+        } else {
+            device.cmd_draw(command_buffer, vertex_count, 1, 0, 0);
         }
     }
 
+    /// Draws part of the index buffer, for instancing and batching several meshes into one
+    /// buffer. `vertex_offset` is added to each index before it is looked up in the vertex
+    /// buffer, and `first_instance` is the first value of `gl_InstanceIndex`. The model must
+    /// have an index buffer and have been bound
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn draw_indexed(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        index_count: u32,
+        instance_count: u32,
+        first_index: u32,
+        vertex_offset: i32,
+        first_instance: u32,
+    ) {
+        debug_assert!(
+            self.geometry.borrow().index_buffer.is_some(),
+            "Cannot draw indexed without an index buffer"
+        );
+        debug_assert!(
+            first_index + index_count <= self.geometry.borrow().index_count,
+            "Index range is past the end of the index buffer"
+        );
+
+        device.cmd_draw_indexed(
+            command_buffer,
+            index_count,
+            instance_count,
+            first_index,
+            vertex_offset,
+            first_instance,
+        );
+    }
+
+    /// Draws `draw_count` `vk::DrawIndexedIndirectCommand`s from `indirect_buffer`, starting
+    /// `offset` bytes in and `stride` bytes apart, all indexing into this model's index
+    /// buffer. A `draw_count` over 1 needs the `multi_draw_indirect` feature. The model must
+    /// have an index buffer and have been bound
+    pub unsafe fn draw_indirect(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        indirect_buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        draw_count: u32,
+        stride: u32,
+    ) {
+        debug_assert!(
+            self.geometry.borrow().index_buffer.is_some(),
+            "Cannot draw indexed without an index buffer"
+        );
+
+        device.cmd_draw_indexed_indirect(
+            command_buffer,
+            indirect_buffer,
+            offset,
+            draw_count,
+            stride,
+        );
+    }
+
     pub unsafe fn bind(&self, device: &Device, command_buffer: vk::CommandBuffer) {
-        match &self.vertex_buffer {
-            Some(vert_buff) => {
-                let buffers = [vert_buff.buffer];
-                let offsets = [0 as u64];
-                device.cmd_bind_vertex_buffers(command_buffer, 0, &buffers, &offsets);
-            }
-            None => {}
+        let geometry = self.geometry.borrow();
+
+        if let Some(vert_buff) = &geometry.vertex_buffer {
+            let buffers = [vert_buff.buffer];
+            let offsets = [0 as u64];
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &buffers, &offsets);
         }
 
-        match &self.index_buffer {
-            Some(ind_buff) => device.cmd_bind_index_buffer(
-                command_buffer,
-                ind_buff.buffer,
-                0,
-                vk::IndexType::UINT32,
-            ),
-            None => {}
+        if let Some(ind_buff) = &geometry.index_buffer {
+            device.cmd_bind_index_buffer(command_buffer, ind_buff.buffer, 0, vk::IndexType::UINT32);
         }
     }
 
@@ -242,21 +822,6 @@ impl LveModel {
 
         let vertex_size: vk::DeviceSize = size_of::<Vertex>() as u64;
 
-        let mut staging_buffer = LveBuffer::new(
-            Rc::clone(lve_device),
-            vertex_size,
-            vertex_count as u32,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            1,
-            BufferType::Staging,
-        );
-
-        unsafe {
-            staging_buffer.map(vk::WHOLE_SIZE, 0);
-            staging_buffer.write_to_buffer(vertices.as_slice(), vk::WHOLE_SIZE, 0);
-        }
-
         let vertex_buffer = LveBuffer::new(
             Rc::clone(lve_device),
             vertex_size,
@@ -267,8 +832,10 @@ impl LveModel {
             BufferType::Vertex,
         );
 
-        // Copy vertex data from the staging buffer to the local device memory
-        lve_device.copy_buffer(staging_buffer.buffer, vertex_buffer.buffer, buffer_size);
+        // Copy the data through a staging buffer to the device local memory
+        lve_device.upload_via_staging(vertices.as_slice(), |staging_buffer| {
+            lve_device.copy_buffer(staging_buffer, vertex_buffer.buffer, buffer_size)
+        });
 
         (Some(Rc::new(vertex_buffer)), vertex_count as u32)
     }
@@ -288,21 +855,6 @@ impl LveModel {
 
         let index_size: vk::DeviceSize = size_of::<u32>() as u64;
 
-        let mut staging_buffer = LveBuffer::new(
-            Rc::clone(lve_device),
-            index_size,
-            index_count as u32,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            1,
-            BufferType::Staging,
-        );
-
-        unsafe {
-            staging_buffer.map(vk::WHOLE_SIZE, 0);
-            staging_buffer.write_to_buffer(indices.as_slice(), vk::WHOLE_SIZE, 0);
-        }
-
         let index_buffer = LveBuffer::new(
             Rc::clone(lve_device),
             index_size,
@@ -313,8 +865,10 @@ impl LveModel {
             BufferType::Index,
         );
 
-        // Copy vertex data from the staging buffer to the local device memory
-        lve_device.copy_buffer(staging_buffer.buffer, index_buffer.buffer, buffer_size);
+        // Copy the data through a staging buffer to the device local memory
+        lve_device.upload_via_staging(indices.as_slice(), |staging_buffer| {
+            lve_device.copy_buffer(staging_buffer, index_buffer.buffer, buffer_size)
+        });
 
         (Some(Rc::new(index_buffer)), index_count as u32)
     }
@@ -323,5 +877,278 @@ impl LveModel {
 impl Drop for LveModel {
     fn drop(&mut self) {
         log::debug!("Dropping Model: {}", self.name);
+
+        // Null models were never counted
+        if self.geometry.borrow().vertex_buffer.is_some() {
+            MODEL_COUNT.fetch_sub(1, Ordering::Relaxed);
+            MODEL_GPU_BYTES.fetch_sub(self.gpu_bytes(), Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    /// Writes `contents` to a uniquely named OBJ file in the temp directory, which is removed
+    /// again when dropped
+    struct TempObj(PathBuf);
+
+    impl TempObj {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "lve_model_test_{}_{}.obj",
+                std::process::id(),
+                name
+            ));
+            std::fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempObj {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn vector3(vertex_attribute: &na::Vector3<Hf32>) -> [f32; 3] {
+        [
+            vertex_attribute[0].into_inner(),
+            vertex_attribute[1].into_inner(),
+            vertex_attribute[2].into_inner(),
+        ]
+    }
+
+    const TRIANGLE: &str = "\
+o triangle
+v 0.0 0.0 0.0
+v 1.0 0.0 0.0
+v 0.0 1.0 0.0
+vn 0.0 0.0 1.0
+vt 0.0 0.0
+vt 1.0 0.0
+vt 0.0 1.0
+f 1/1/1 2/2/1 3/3/1
+";
+
+    #[test]
+    fn loads_vertex_and_index_counts() {
+        let obj = TempObj::new("counts", TRIANGLE);
+
+        let (model_data, names) = ModelData::load_model(obj.path(), false).unwrap();
+
+        assert_eq!(names, vec![String::from("triangle")]);
+        assert_eq!(model_data.vertices.len(), 3);
+        assert_eq!(model_data.indices, Some(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn deduplicates_shared_vertices() {
+        // A quad as two triangles, where each triangle lists its own copy of the two vertices
+        // on the shared edge
+        let obj = TempObj::new(
+            "dedup",
+            "\
+o quad
+v 0.0 0.0 0.0
+v 1.0 0.0 0.0
+v 1.0 1.0 0.0
+v 0.0 0.0 0.0
+v 1.0 1.0 0.0
+v 0.0 1.0 0.0
+vn 0.0 0.0 1.0
+f 1//1 2//1 3//1
+f 4//1 5//1 6//1
+",
+        );
+
+        let (model_data, _) = ModelData::load_model(obj.path(), false).unwrap();
+
+        assert_eq!(model_data.vertices.len(), 4);
+        assert_eq!(model_data.indices, Some(vec![0, 1, 2, 0, 2, 3]));
+    }
+
+    #[test]
+    fn flipped_winding_reverses_triangles_and_normals() {
+        let obj = TempObj::new("flipped", TRIANGLE);
+
+        let (model_data, _) = ModelData::load_model(obj.path(), true).unwrap();
+
+        assert_eq!(model_data.indices, Some(vec![0, 2, 1]));
+
+        for vertex in model_data.vertices.iter() {
+            assert_eq!(vector3(&vertex.normal), [0.0, 0.0, -1.0]);
+        }
+    }
+
+    #[test]
+    fn missing_attributes_use_fallbacks() {
+        let obj = TempObj::new(
+            "fallbacks",
+            "\
+v 0.0 0.0 0.0
+v 1.0 0.0 0.0
+v 0.0 1.0 0.0
+f 1 2 3
+",
+        );
+
+        let (model_data, _) = ModelData::load_model(obj.path(), false).unwrap();
+
+        assert_eq!(model_data.vertices.len(), 3);
+
+        for vertex in model_data.vertices.iter() {
+            assert_eq!(vector3(&vertex.color), [1.0, 1.0, 1.0]);
+            assert_eq!(vector3(&vertex.normal), [0.0, 0.0, 1.0]);
+            assert_eq!(
+                [vertex.uv[0].into_inner(), vertex.uv[1].into_inner()],
+                [0.0, 0.0]
+            );
+        }
+    }
+
+    #[test]
+    fn attributes_on_only_some_faces_are_ignored() {
+        // Normals on the first face and texture coordinates on the last, as some exporters
+        // write when parts of a mesh are unwrapped or smoothed
+        let obj = TempObj::new(
+            "partial",
+            "\
+v 0.0 0.0 0.0
+v 1.0 0.0 0.0
+v 0.0 1.0 0.0
+v 1.0 1.0 0.0
+vn 0.0 0.0 -1.0
+vt 0.5 0.5
+f 1//1 2//1 3//1
+f 2 4 3
+f 1/1 2/1 4/1
+",
+        );
+
+        let (model_data, _) = ModelData::load_model(obj.path(), false).unwrap();
+
+        assert_eq!(model_data.indices.as_ref().unwrap().len(), 9);
+
+        for vertex in model_data.vertices.iter() {
+            assert_eq!(vector3(&vertex.normal), [0.0, 0.0, 1.0]);
+            assert_eq!(
+                [vertex.uv[0].into_inner(), vertex.uv[1].into_inner()],
+                [0.0, 0.0]
+            );
+        }
+    }
+
+    #[test]
+    fn linear_colors_are_darker_except_black_and_white() {
+        let mut model_data = ModelData {
+            vertices: vec![Vertex {
+                position: na::Vector3::zeros().map(OrderedFloat),
+                color: na::vector![0.0, 0.5, 1.0].map(OrderedFloat),
+                normal: na::Vector3::zeros().map(OrderedFloat),
+                uv: na::Vector2::zeros().map(OrderedFloat),
+            }],
+            indices: None,
+        };
+
+        model_data.convert_colors_to_linear();
+
+        let color = vector3(&model_data.vertices[0].color);
+
+        assert_eq!(color[0], 0.0);
+        assert!((color[1] - 0.5_f32.powf(2.2)).abs() < 1e-6);
+        assert_eq!(color[2], 1.0);
+    }
+
+    #[test]
+    fn malformed_obj_is_an_error() {
+        let obj = TempObj::new(
+            "malformed",
+            "\
+v 0.0 0.0 zero
+v 1.0 0.0 0.0
+v 0.0 1.0 0.0
+f 1 2 3
+",
+        );
+
+        assert!(ModelData::load_model(obj.path(), false).is_err());
+    }
+
+    #[test]
+    fn out_of_bounds_face_is_an_error() {
+        let obj = TempObj::new(
+            "out_of_bounds",
+            "\
+v 0.0 0.0 0.0
+v 1.0 0.0 0.0
+f 1 2 3
+",
+        );
+
+        assert!(ModelData::load_model(obj.path(), false).is_err());
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        assert!(ModelData::load_model("models/does_not_exist.obj", false).is_err());
+    }
+
+    fn vertex(position: [f32; 3], normal: [f32; 3], uv: [f32; 2]) -> Vertex {
+        Vertex {
+            position: na::Vector3::from(position).map(OrderedFloat),
+            color: na::vector![1.0, 1.0, 1.0].map(OrderedFloat),
+            normal: na::Vector3::from(normal).map(OrderedFloat),
+            uv: na::Vector2::from(uv).map(OrderedFloat),
+        }
+    }
+
+    #[test]
+    fn repair_removes_degenerate_triangles_and_non_finite_values() {
+        let mut model_data = ModelData {
+            vertices: vec![
+                vertex([0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [f32::NAN, 0.0]),
+                vertex([1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, f32::INFINITY]),
+                vertex([0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0]),
+            ],
+            indices: Some(vec![0, 1, 2, 0, 0, 1]),
+        };
+
+        let report = model_data.validate_and_repair();
+
+        assert_eq!(report.degenerate_triangles, 1);
+        assert_eq!(report.non_finite_values, 2);
+        assert_eq!(report.total(), 3);
+        assert_eq!(model_data.indices, Some(vec![0, 1, 2]));
+        assert_eq!(model_data.vertices[0].uv[0].into_inner(), 0.0);
+        assert_eq!(model_data.vertices[1].uv[1].into_inner(), 0.0);
+    }
+
+    #[test]
+    fn repair_fixes_normals() {
+        let mut model_data = ModelData {
+            vertices: vec![
+                vertex([0.0, 0.0, 0.0], [0.0, 0.0, 0.0], [0.0, 0.0]),
+                vertex([1.0, 0.0, 0.0], [0.0, 0.0, 2.0], [1.0, 0.0]),
+                vertex([0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0]),
+            ],
+            indices: Some(vec![0, 1, 2]),
+        };
+
+        let report = model_data.validate_and_repair();
+
+        assert_eq!(report.regenerated_normals, 1);
+        assert_eq!(report.renormalized_normals, 1);
+
+        for vertex in model_data.vertices.iter() {
+            assert_eq!(vector3(&vertex.normal), [0.0, 0.0, 1.0]);
+        }
     }
 }
@@ -3,27 +3,197 @@ use super::lve_model::*;
 
 use ash::{vk, Device};
 
+use std::collections::HashMap;
 use std::ffi::CString;
 use std::rc::Rc;
 
+/// A scalar a shader's specialization constant can be set to, as the bytes Vulkan reads it from
+pub trait SpecializationConstant {
+    fn to_bytes(self) -> [u8; 4];
+}
+
+impl SpecializationConstant for u32 {
+    fn to_bytes(self) -> [u8; 4] {
+        self.to_ne_bytes()
+    }
+}
+
+impl SpecializationConstant for i32 {
+    fn to_bytes(self) -> [u8; 4] {
+        self.to_ne_bytes()
+    }
+}
+
+impl SpecializationConstant for f32 {
+    fn to_bytes(self) -> [u8; 4] {
+        self.to_ne_bytes()
+    }
+}
+
+/// A GLSL `bool` is a 32 bit `vk::Bool32`
+impl SpecializationConstant for bool {
+    fn to_bytes(self) -> [u8; 4] {
+        (if self { vk::TRUE } else { vk::FALSE }).to_ne_bytes()
+    }
+}
+
+/// A render pass, one of its subpasses and the formats of that subpass's colour attachments,
+/// in attachment order. Pipelines are checked against the subpass and formats when they are
+/// created, so a mismatch fails with a readable message instead of a validation error (or
+/// nothing at all) at draw time
+#[derive(Debug, Clone)]
+pub struct RenderPassInfo {
+    pub render_pass: vk::RenderPass,
+    pub color_formats: Vec<vk::Format>,
+    pub subpass: u32,
+}
+
+/// Offsets the depth of everything a pipeline draws, e.g. a slope scaled bias against shadow
+/// acne, or pulling decals in front of the surface they sit on
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DepthBias {
+    /// In units of the smallest depth difference the depth format can resolve
+    pub constant_factor: f32,
+    /// Scaled by how steeply the polygon's depth changes across the screen
+    pub slope_factor: f32,
+    /// The largest (or for negative values, smallest) bias applied, 0 for no clamp
+    pub clamp: f32,
+}
+
 pub struct PipelineConfigInfo {
     viewport_info: vk::PipelineViewportStateCreateInfo,
     input_assembly_info: vk::PipelineInputAssemblyStateCreateInfo,
     rasterization_info: vk::PipelineRasterizationStateCreateInfo,
     multisample_info: vk::PipelineMultisampleStateCreateInfo,
-    _color_blend_attachment: Rc<vk::PipelineColorBlendAttachmentState>,
+    color_blend_attachment: Rc<vk::PipelineColorBlendAttachmentState>,
     color_blend_info: Rc<vk::PipelineColorBlendStateCreateInfo>,
-    depth_stencil_info: vk::PipelineDepthStencilStateCreateInfo,
-    _dynamic_state_enables: Vec<vk::DynamicState>,
+    pub depth_stencil_info: vk::PipelineDepthStencilStateCreateInfo,
+    dynamic_state_enables: Vec<vk::DynamicState>,
     dynamic_state_info: vk::PipelineDynamicStateCreateInfo,
     subpass: u32,
+    pub binding_descriptions: Vec<vk::VertexInputBindingDescription>,
+    pub attribute_descriptions: Vec<vk::VertexInputAttributeDescription>,
+    /// Specialization constants as (constant_id, value bytes), given to every shader stage. A
+    /// stage that doesn't declare a `constant_id` ignores it, so the vertex and fragment shaders
+    /// can share ids for the same value (e.g. a max light count). See
+    /// `LvePipeline::add_specialization_constant`
+    pub specialization: Vec<(u32, Vec<u8>)>,
+}
+
+/// Selects which of a render system's pipelines an object is drawn with
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PipelineTag {
+    /// Filled and lit
+    #[default]
+    Lit,
+    /// Only the triangle edges, needs the `fill_mode_non_solid` device feature
+    Wireframe,
+}
+
+/// The pipelines a render system draws with, by tag. Every pipeline must use the same layout,
+/// so descriptor sets stay bound when switching between them
+pub struct PipelineRegistry {
+    pipelines: HashMap<PipelineTag, LvePipeline>,
+    fallback: PipelineTag,
+}
+
+impl PipelineRegistry {
+    /// `fallback` is used for any tag without a pipeline, so it must be registered before drawing
+    pub fn new(fallback: PipelineTag) -> Self {
+        Self {
+            pipelines: HashMap::new(),
+            fallback,
+        }
+    }
+
+    pub fn register(&mut self, tag: PipelineTag, pipeline: LvePipeline) {
+        self.pipelines.insert(tag, pipeline);
+    }
+
+    /// The tag the pipeline for `tag` is registered under, which is `tag` itself unless it
+    /// falls back
+    pub fn resolve(&self, tag: PipelineTag) -> PipelineTag {
+        if self.pipelines.contains_key(&tag) {
+            tag
+        } else {
+            self.fallback
+        }
+    }
+
+    pub fn get(&self, tag: PipelineTag) -> &LvePipeline {
+        self.pipelines
+            .get(&self.resolve(tag))
+            .expect("The fallback pipeline has not been registered")
+    }
+}
+
+/// Which bytes of a push constant block each shader stage can see. Each range is pushed
+/// separately with its own stage flags, so a stage only ever receives its own region
+#[derive(Debug, Clone)]
+pub struct PushConstantLayout {
+    ranges: Vec<vk::PushConstantRange>,
+}
+
+impl PushConstantLayout {
+    /// Panics if the ranges don't fit in the device's `maxPushConstantsSize`, which can be as
+    /// low as the 128 bytes guaranteed by the spec
+    pub fn new(lve_device: &LveDevice, ranges: Vec<vk::PushConstantRange>) -> Self {
+        let layout = Self { ranges };
+
+        let max_push_constants_size = lve_device.properties.limits.max_push_constants_size;
+
+        assert!(
+            layout.size() <= max_push_constants_size,
+            "Push constants need {} bytes, but the device only supports {}",
+            layout.size(),
+            max_push_constants_size
+        );
+
+        layout
+    }
+
+    pub fn ranges(&self) -> &[vk::PushConstantRange] {
+        &self.ranges
+    }
+
+    /// The end of the furthest range, i.e. how many bytes of push constants are used
+    pub fn size(&self) -> u32 {
+        self.ranges
+            .iter()
+            .map(|range| range.offset + range.size)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Pushes the bytes of `data` covered by each range to that range's stages. `data` starts at
+    /// offset 0 of the push constant block
+    pub unsafe fn push(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        data: &[u8],
+    ) {
+        for range in self.ranges.iter() {
+            let start = range.offset as usize;
+            let end = start + range.size as usize;
+
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                range.stage_flags,
+                range.offset,
+                &data[start..end],
+            );
+        }
+    }
 }
 
 pub struct LvePipeline {
     lve_device: Rc<LveDevice>,
     graphics_pipeline: vk::Pipeline,
     vert_shader_module: vk::ShaderModule,
-    frag_shader_module: vk::ShaderModule,
+    frag_shader_module: Option<vk::ShaderModule>,
 }
 
 impl LvePipeline {
@@ -32,14 +202,48 @@ impl LvePipeline {
         vert_file_path: &str,
         frag_file_path: &str,
         config_info: PipelineConfigInfo,
-        render_pass: &vk::RenderPass,
+        render_pass: &RenderPassInfo,
+        pipeline_layout: &vk::PipelineLayout,
+    ) -> Self {
+        Self::check_color_attachments(&lve_device, &config_info, render_pass);
+        Self::check_sample_shading(&lve_device, &config_info);
+        Self::check_subpass(&config_info, render_pass);
+
+        let (graphics_pipeline, vert_shader_module, frag_shader_module) =
+            Self::create_graphics_pipeline(
+                &lve_device.device,
+                vert_file_path,
+                Some(frag_file_path),
+                config_info,
+                render_pass,
+                pipeline_layout,
+            );
+
+        Self {
+            lve_device,
+            graphics_pipeline,
+            vert_shader_module,
+            frag_shader_module,
+        }
+    }
+
+    /// A pipeline without a fragment stage, for passes that only write depth
+    pub fn new_vertex_only(
+        lve_device: Rc<LveDevice>,
+        vert_file_path: &str,
+        config_info: PipelineConfigInfo,
+        render_pass: &RenderPassInfo,
         pipeline_layout: &vk::PipelineLayout,
     ) -> Self {
+        Self::check_color_attachments(&lve_device, &config_info, render_pass);
+        Self::check_sample_shading(&lve_device, &config_info);
+        Self::check_subpass(&config_info, render_pass);
+
         let (graphics_pipeline, vert_shader_module, frag_shader_module) =
             Self::create_graphics_pipeline(
                 &lve_device.device,
                 vert_file_path,
-                frag_file_path,
+                None,
                 config_info,
                 render_pass,
                 pipeline_layout,
@@ -140,15 +344,186 @@ impl LvePipeline {
             input_assembly_info,
             rasterization_info,
             multisample_info,
-            _color_blend_attachment: color_blend_attachment,
+            color_blend_attachment,
             color_blend_info,
             depth_stencil_info,
-            _dynamic_state_enables: dynamic_state_enables,
+            dynamic_state_enables,
             dynamic_state_info,
             subpass: 0,
+            binding_descriptions: Vertex::get_binding_descriptions(),
+            attribute_descriptions: Vertex::get_attribute_descriptions(),
+            specialization: Vec::new(),
         }
     }
 
+    /// The default config without depth testing or writing, for things drawn on top of the
+    /// scene such as gizmos, selection highlights and overlays
+    pub fn no_depth_pipeline_config_info() -> PipelineConfigInfo {
+        let mut config_info = Self::default_pipline_config_info();
+        Self::disable_depth_test(&mut config_info);
+        config_info
+    }
+
+    /// Draws over whatever is already there, and leaves the depth buffer as it is
+    pub fn disable_depth_test(config_info: &mut PipelineConfigInfo) {
+        config_info.depth_stencil_info.depth_test_enable = vk::FALSE;
+        Self::disable_depth_write(config_info);
+    }
+
+    /// Still hidden behind what is already drawn, but doesn't hide anything drawn afterwards
+    pub fn disable_depth_write(config_info: &mut PipelineConfigInfo) {
+        config_info.depth_stencil_info.depth_write_enable = vk::FALSE;
+    }
+
+    /// Disables all colour writes, for passes that only fill the depth buffer
+    pub fn disable_color_writes(config_info: &mut PipelineConfigInfo) {
+        let color_blend_attachment = Rc::get_mut(&mut config_info.color_blend_attachment)
+            .expect("Colour blend attachment should not be shared");
+
+        color_blend_attachment.color_write_mask = vk::ColorComponentFlags::empty();
+    }
+
+    /// Writes `reference` to the stencil buffer wherever the pipeline draws, regardless of what
+    /// is already there. Needs a depth format with a stencil component
+    pub fn enable_stencil_write(config_info: &mut PipelineConfigInfo, reference: u32) {
+        let stencil_op_state = vk::StencilOpState::builder()
+            .fail_op(vk::StencilOp::REPLACE)
+            .pass_op(vk::StencilOp::REPLACE)
+            .depth_fail_op(vk::StencilOp::REPLACE)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .compare_mask(0xff)
+            .write_mask(0xff)
+            .reference(reference)
+            .build();
+
+        config_info.depth_stencil_info.stencil_test_enable = vk::TRUE;
+        config_info.depth_stencil_info.front = stencil_op_state;
+        config_info.depth_stencil_info.back = stencil_op_state;
+    }
+
+    /// Only draws where the stencil buffer does not hold `reference`, leaving the stencil
+    /// buffer untouched
+    pub fn enable_stencil_not_equal_test(config_info: &mut PipelineConfigInfo, reference: u32) {
+        let stencil_op_state = vk::StencilOpState::builder()
+            .fail_op(vk::StencilOp::KEEP)
+            .pass_op(vk::StencilOp::KEEP)
+            .depth_fail_op(vk::StencilOp::KEEP)
+            .compare_op(vk::CompareOp::NOT_EQUAL)
+            .compare_mask(0xff)
+            .write_mask(0x00)
+            .reference(reference)
+            .build();
+
+        config_info.depth_stencil_info.stencil_test_enable = vk::TRUE;
+        config_info.depth_stencil_info.front = stencil_op_state;
+        config_info.depth_stencil_info.back = stencil_op_state;
+    }
+
+    /// Bakes `value` into the shaders' `constant_id` specialization constant. The value must
+    /// match the constant's type in the shader
+    pub fn add_specialization_constant<T: SpecializationConstant>(
+        config_info: &mut PipelineConfigInfo,
+        constant_id: u32,
+        value: T,
+    ) {
+        config_info
+            .specialization
+            .push((constant_id, value.to_bytes().to_vec()));
+    }
+
+    pub fn enable_wireframe(config_info: &mut PipelineConfigInfo) {
+        config_info.rasterization_info.polygon_mode = vk::PolygonMode::LINE;
+    }
+
+    /// Draws in `subpass` of the render pass rather than the first one, see
+    /// `LveRenderPassBuilder`
+    #[allow(dead_code)]
+    pub fn set_subpass(config_info: &mut PipelineConfigInfo, subpass: u32) {
+        config_info.subpass = subpass;
+    }
+
+    /// Runs the fragment shader for each sample rather than once per pixel, so alpha tested
+    /// materials such as foliage are antialiased along their cutout edges rather than only
+    /// along triangle edges. `min_sample_shading` is the fraction of samples shaded separately,
+    /// from 0 to 1, where 1 shades every sample.
+    ///
+    /// Only has an effect when rendering with more than one sample, and is expensive: at 1.0
+    /// with 4x MSAA the fragment shader runs up to 4 times as often, so keep it to the
+    /// pipelines that need it. Needs the `sample_rate_shading` device feature
+    #[allow(dead_code)]
+    pub fn enable_sample_shading(config_info: &mut PipelineConfigInfo, min_sample_shading: f32) {
+        config_info.multisample_info.sample_shading_enable = vk::TRUE;
+        config_info.multisample_info.min_sample_shading = min_sample_shading.clamp(0.0, 1.0);
+    }
+
+    /// Depth bias is disabled by default, so geometry lands exactly where it is drawn
+    #[allow(dead_code)]
+    pub fn enable_depth_bias(config_info: &mut PipelineConfigInfo, depth_bias: DepthBias) {
+        let rasterization_info = &mut config_info.rasterization_info;
+
+        rasterization_info.depth_bias_enable = vk::TRUE;
+        rasterization_info.depth_bias_constant_factor = depth_bias.constant_factor;
+        rasterization_info.depth_bias_slope_factor = depth_bias.slope_factor;
+        rasterization_info.depth_bias_clamp = depth_bias.clamp;
+    }
+
+    /// Enables depth bias, with the values set while recording by
+    /// `LveRenderer::cmd_set_depth_bias` instead of baked into the pipeline. It must be set
+    /// after binding the pipeline and before drawing with it
+    #[allow(dead_code)]
+    pub fn enable_dynamic_depth_bias(config_info: &mut PipelineConfigInfo) {
+        Self::enable_depth_bias(config_info, DepthBias::default());
+
+        config_info
+            .dynamic_state_enables
+            .push(vk::DynamicState::DEPTH_BIAS);
+
+        // The push may have moved the list, so point the create info at it again
+        config_info.dynamic_state_info.dynamic_state_count =
+            config_info.dynamic_state_enables.len() as u32;
+        config_info.dynamic_state_info.p_dynamic_states =
+            config_info.dynamic_state_enables.as_ptr();
+    }
+
+    /// Standard "over" blending, output = src.a * src + (1 - src.a) * dst
+    pub fn enable_alpha_blending(config_info: &mut PipelineConfigInfo) {
+        // The colour blend info points at the attachment, so it has to be modified in place
+        let color_blend_attachment = Rc::get_mut(&mut config_info.color_blend_attachment)
+            .expect("Colour blend attachment should not be shared");
+
+        color_blend_attachment.blend_enable = vk::TRUE;
+        color_blend_attachment.color_write_mask = vk::ColorComponentFlags::all();
+        color_blend_attachment.src_color_blend_factor = vk::BlendFactor::SRC_ALPHA;
+        color_blend_attachment.dst_color_blend_factor = vk::BlendFactor::ONE_MINUS_SRC_ALPHA;
+        color_blend_attachment.color_blend_op = vk::BlendOp::ADD;
+        color_blend_attachment.src_alpha_blend_factor = vk::BlendFactor::ONE;
+        color_blend_attachment.dst_alpha_blend_factor = vk::BlendFactor::ZERO;
+        color_blend_attachment.alpha_blend_op = vk::BlendOp::ADD;
+    }
+
+    /// Blending for colours that have already been multiplied by their alpha, such as egui's
+    pub fn enable_premultiplied_alpha_blending(config_info: &mut PipelineConfigInfo) {
+        Self::enable_alpha_blending(config_info);
+
+        let color_blend_attachment = Rc::get_mut(&mut config_info.color_blend_attachment)
+            .expect("Colour blend attachment should not be shared");
+
+        color_blend_attachment.src_color_blend_factor = vk::BlendFactor::ONE;
+    }
+
+    /// Adds the colour to what is already there, for light emitting effects such as particles.
+    /// The result doesn't depend on draw order, so nothing needs sorting
+    pub fn enable_additive_blending(config_info: &mut PipelineConfigInfo) {
+        Self::enable_alpha_blending(config_info);
+
+        let color_blend_attachment = Rc::get_mut(&mut config_info.color_blend_attachment)
+            .expect("Colour blend attachment should not be shared");
+
+        color_blend_attachment.src_color_blend_factor = vk::BlendFactor::ONE;
+        color_blend_attachment.dst_color_blend_factor = vk::BlendFactor::ONE;
+        color_blend_attachment.dst_alpha_blend_factor = vk::BlendFactor::ONE;
+    }
+
     fn read_file<P: AsRef<std::path::Path>>(file_path: P) -> Vec<u32> {
         log::debug!(
             "Loading shader file {}",
@@ -162,59 +537,157 @@ impl LvePipeline {
             .unwrap()
     }
 
+    /// Panics if the pipeline's colour blend attachments don't line up with the render pass's
+    /// colour attachments, or if blending is enabled on an attachment whose format can't blend
+    fn check_color_attachments(
+        lve_device: &LveDevice,
+        config_info: &PipelineConfigInfo,
+        render_pass: &RenderPassInfo,
+    ) {
+        let attachment_count = config_info.color_blend_info.attachment_count as usize;
+
+        if attachment_count != render_pass.color_formats.len() {
+            log::error!(
+                "Pipeline has {} colour blend attachment(s) but the render pass has {} colour \
+                 attachment(s) {:?}",
+                attachment_count,
+                render_pass.color_formats.len(),
+                render_pass.color_formats
+            );
+            panic!("Pipeline colour attachments don't match the render pass");
+        }
+
+        let blend_attachments = unsafe {
+            std::slice::from_raw_parts(config_info.color_blend_info.p_attachments, attachment_count)
+        };
+
+        for (index, (blend_attachment, format)) in blend_attachments
+            .iter()
+            .zip(render_pass.color_formats.iter())
+            .enumerate()
+        {
+            let blend_supported = lve_device
+                .get_format_properties(*format)
+                .optimal_tiling_features
+                .contains(vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND);
+
+            if blend_attachment.blend_enable == vk::TRUE && !blend_supported {
+                log::error!(
+                    "Pipeline enables blending on colour attachment {} but its format {:?} \
+                     doesn't support blending",
+                    index,
+                    format
+                );
+                panic!("Pipeline colour attachments don't match the render pass");
+            }
+        }
+    }
+
+    fn check_subpass(config_info: &PipelineConfigInfo, render_pass: &RenderPassInfo) {
+        if config_info.subpass != render_pass.subpass {
+            log::error!(
+                "Pipeline targets subpass {} but was given the render pass info for subpass {}",
+                config_info.subpass,
+                render_pass.subpass
+            );
+            panic!("Pipeline subpass doesn't match the render pass");
+        }
+    }
+
+    fn check_sample_shading(lve_device: &LveDevice, config_info: &PipelineConfigInfo) {
+        if config_info.multisample_info.sample_shading_enable == vk::TRUE
+            && !lve_device.enabled_features.sample_rate_shading
+        {
+            log::error!(
+                "Pipeline enables sample shading but the device was created without the \
+                 sample_rate_shading feature, see RequiredFeatures"
+            );
+            panic!("Pipeline needs a device feature that isn't enabled");
+        }
+    }
+
     fn create_graphics_pipeline(
         device: &Device,
         vert_file_path: &str,
-        frag_file_path: &str,
+        frag_file_path: Option<&str>,
         config_info: PipelineConfigInfo,
-        render_pass: &vk::RenderPass,
+        render_pass: &RenderPassInfo,
         pipeline_layout: &vk::PipelineLayout,
-    ) -> (vk::Pipeline, vk::ShaderModule, vk::ShaderModule) {
+    ) -> (vk::Pipeline, vk::ShaderModule, Option<vk::ShaderModule>) {
         assert_ne!(
             pipeline_layout,
             &vk::PipelineLayout::null(),
             "Cannot create graphics pipeline:: no pipeline_layout provided in config_info"
         );
         assert_ne!(
-            render_pass,
-            &vk::RenderPass::null(),
+            render_pass.render_pass,
+            vk::RenderPass::null(),
             "Cannot create graphics pipeline:: no render_pass provided in config_info"
         );
 
         let vert_code = Self::read_file(vert_file_path);
-        let frag_code = Self::read_file(frag_file_path);
 
         let vert_shader_module = Self::create_shader_module(device, &vert_code);
-        let frag_shader_module = Self::create_shader_module(device, &frag_code);
+        let frag_shader_module = frag_file_path.map(|frag_file_path| {
+            let frag_code = Self::read_file(frag_file_path);
+            Self::create_shader_module(device, &frag_code)
+        });
 
         let entry_point_name = CString::new("main").unwrap();
 
-        let vert_shader_stage_info = vk::PipelineShaderStageCreateInfo::builder()
-            .stage(vk::ShaderStageFlags::VERTEX)
-            .module(vert_shader_module)
-            .name(&entry_point_name)
-            // .flags(vk::PipelineShaderStageCreateFlags::empty())
-            // .next()
-            // .specialization_info()
-            .build();
+        // The constants are packed one after the other into a single block of data. The
+        // specialization info only points at the entries and data, so both have to stay alive
+        // until the pipeline has been created below. They aren't needed afterwards
+        let mut specialization_map_entries = Vec::new();
+        let mut specialization_data = Vec::new();
+
+        for (constant_id, value) in config_info.specialization.iter() {
+            specialization_map_entries.push(
+                vk::SpecializationMapEntry::builder()
+                    .constant_id(*constant_id)
+                    .offset(specialization_data.len() as u32)
+                    .size(value.len())
+                    .build(),
+            );
+            specialization_data.extend_from_slice(value);
+        }
+
+        let specialization_info = vk::SpecializationInfo::builder()
+            .map_entries(&specialization_map_entries)
+            .data(&specialization_data);
 
-        let frag_shader_stage_info = vk::PipelineShaderStageCreateInfo::builder()
-            .stage(vk::ShaderStageFlags::FRAGMENT)
-            .module(frag_shader_module)
-            .name(&entry_point_name)
+        let shader_stage_info = |stage: vk::ShaderStageFlags, module: vk::ShaderModule| {
+            let shader_stage_info = vk::PipelineShaderStageCreateInfo::builder()
+                .stage(stage)
+                .module(module)
+                .name(&entry_point_name);
             // .flags(vk::PipelineShaderStageCreateFlags::empty())
             // .next()
-            // .specialization_info()
-            .build();
 
-        let shader_stages = [vert_shader_stage_info, frag_shader_stage_info];
+            if specialization_map_entries.is_empty() {
+                shader_stage_info.build()
+            } else {
+                shader_stage_info
+                    .specialization_info(&specialization_info)
+                    .build()
+            }
+        };
 
-        let binding_descriptions = Vertex::get_binding_descriptions();
-        let attribute_descriptions = Vertex::get_attribute_descriptions();
+        let mut shader_stages = vec![shader_stage_info(
+            vk::ShaderStageFlags::VERTEX,
+            vert_shader_module,
+        )];
+
+        if let Some(frag_shader_module) = frag_shader_module {
+            let frag_shader_stage_info =
+                shader_stage_info(vk::ShaderStageFlags::FRAGMENT, frag_shader_module);
+
+            shader_stages.push(frag_shader_stage_info);
+        }
 
         let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
-            .vertex_binding_descriptions(&binding_descriptions)
-            .vertex_attribute_descriptions(&attribute_descriptions);
+            .vertex_binding_descriptions(&config_info.binding_descriptions)
+            .vertex_attribute_descriptions(&config_info.attribute_descriptions);
 
         let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
             .stages(&shader_stages)
@@ -227,7 +700,7 @@ impl LvePipeline {
             .depth_stencil_state(&config_info.depth_stencil_info)
             .dynamic_state(&config_info.dynamic_state_info)
             .layout(*pipeline_layout)
-            .render_pass(*render_pass)
+            .render_pass(render_pass.render_pass)
             .subpass(config_info.subpass)
             .base_pipeline_index(-1)
             .base_pipeline_handle(vk::Pipeline::null());
@@ -266,12 +739,39 @@ impl Drop for LvePipeline {
             self.lve_device
                 .device
                 .destroy_shader_module(self.vert_shader_module, None);
-            self.lve_device
-                .device
-                .destroy_shader_module(self.frag_shader_module, None);
+            if let Some(frag_shader_module) = self.frag_shader_module {
+                self.lve_device
+                    .device
+                    .destroy_shader_module(frag_shader_module, None);
+            }
             self.lve_device
                 .device
                 .destroy_pipeline(self.graphics_pipeline, None);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn specialization_constants_are_four_byte_scalars() {
+        let mut config_info = LvePipeline::default_pipline_config_info();
+
+        LvePipeline::add_specialization_constant(&mut config_info, 0, true);
+        LvePipeline::add_specialization_constant(&mut config_info, 1, false);
+        LvePipeline::add_specialization_constant(&mut config_info, 2, 1.5f32);
+        LvePipeline::add_specialization_constant(&mut config_info, 3, -2i32);
+
+        assert_eq!(
+            config_info.specialization,
+            vec![
+                (0, vk::TRUE.to_ne_bytes().to_vec()),
+                (1, vk::FALSE.to_ne_bytes().to_vec()),
+                (2, 1.5f32.to_ne_bytes().to_vec()),
+                (3, (-2i32).to_ne_bytes().to_vec()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,339 @@
+use super::lve_device::*;
+use super::lve_pipeline::RenderPassInfo;
+
+use ash::vk;
+
+use std::rc::Rc;
+
+/// The attachments one subpass uses, as indices into the render pass's attachments
+#[derive(Debug, Clone, Default)]
+struct SubpassAttachments {
+    color: Vec<u32>,
+    input: Vec<u32>,
+    depth: Option<u32>,
+}
+
+/// The attachment references for one subpass, in the layouts Vulkan wants them in
+#[derive(Debug, Clone)]
+struct SubpassReferences {
+    color: Vec<vk::AttachmentReference>,
+    input: Vec<vk::AttachmentReference>,
+    depth: Option<vk::AttachmentReference>,
+}
+
+impl SubpassAttachments {
+    fn references(&self, attachments: &[vk::AttachmentDescription]) -> SubpassReferences {
+        let reference = |attachment: u32, layout: vk::ImageLayout| {
+            vk::AttachmentReference::builder()
+                .attachment(attachment)
+                .layout(layout)
+                .build()
+        };
+
+        // Input attachments are only read, depth ones through the read only depth layout
+        let input_layout = |attachment: u32| {
+            if is_depth_format(attachments[attachment as usize].format) {
+                vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+            } else {
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+            }
+        };
+
+        SubpassReferences {
+            color: self
+                .color
+                .iter()
+                .map(|attachment| reference(*attachment, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL))
+                .collect(),
+            input: self
+                .input
+                .iter()
+                .map(|attachment| reference(*attachment, input_layout(*attachment)))
+                .collect(),
+            depth: self.depth.map(|attachment| {
+                reference(
+                    attachment,
+                    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                )
+            }),
+        }
+    }
+}
+
+fn is_depth_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D16_UNORM
+            | vk::Format::X8_D24_UNORM_PACK32
+            | vk::Format::D32_SFLOAT
+            | vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT
+    )
+}
+
+/// Builds a render pass with any number of subpasses, e.g. a G-buffer subpass followed by a
+/// lighting subpass that reads it through input attachments. Keeping the passes in one render
+/// pass lets tiled GPUs keep the attachments in tile memory between them. Attachments and
+/// subpasses are numbered in the order they are added
+pub struct LveRenderPassBuilder {
+    lve_device: Rc<LveDevice>,
+    attachments: Vec<vk::AttachmentDescription>,
+    subpasses: Vec<SubpassAttachments>,
+    dependencies: Vec<vk::SubpassDependency>,
+}
+
+#[allow(dead_code)]
+impl LveRenderPassBuilder {
+    pub fn new(lve_device: Rc<LveDevice>) -> LveRenderPassBuilder {
+        LveRenderPassBuilder {
+            lve_device,
+            attachments: Vec::new(),
+            subpasses: Vec::new(),
+            dependencies: Vec::new(),
+        }
+    }
+
+    pub fn add_attachment(
+        &mut self,
+        attachment: vk::AttachmentDescription,
+    ) -> &mut LveRenderPassBuilder {
+        self.attachments.push(attachment);
+
+        self
+    }
+
+    /// Adds a subpass writing `color` (in the order of the fragment shader's outputs) and
+    /// `depth`, and reading `input` through `subpassInput`s (in `input_attachment_index`
+    /// order). The attachments must have been added already
+    pub fn add_subpass(
+        &mut self,
+        color: &[u32],
+        input: &[u32],
+        depth: Option<u32>,
+    ) -> &mut LveRenderPassBuilder {
+        for attachment in color.iter().chain(input).chain(depth.iter()) {
+            assert!(
+                (*attachment as usize) < self.attachments.len(),
+                "Subpass uses attachment {} but there are only {} attachments",
+                attachment,
+                self.attachments.len()
+            );
+        }
+
+        self.subpasses.push(SubpassAttachments {
+            color: color.to_vec(),
+            input: input.to_vec(),
+            depth,
+        });
+
+        self
+    }
+
+    /// Orders the subpasses, e.g. so a subpass reading an input attachment waits for the one
+    /// writing it. `vk::SUBPASS_EXTERNAL` stands for the commands before or after the pass
+    pub fn add_dependency(
+        &mut self,
+        dependency: vk::SubpassDependency,
+    ) -> &mut LveRenderPassBuilder {
+        self.dependencies.push(dependency);
+
+        self
+    }
+
+    /// The usual dependency between a subpass writing colour attachments and a later one
+    /// reading them as input attachments in its fragment shader
+    pub fn add_input_dependency(
+        &mut self,
+        src_subpass: u32,
+        dst_subpass: u32,
+    ) -> &mut LveRenderPassBuilder {
+        self.add_dependency(
+            vk::SubpassDependency::builder()
+                .src_subpass(src_subpass)
+                .dst_subpass(dst_subpass)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                )
+                .src_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(vk::AccessFlags::INPUT_ATTACHMENT_READ)
+                .dependency_flags(vk::DependencyFlags::BY_REGION)
+                .build(),
+        )
+    }
+
+    pub fn build(&self) -> Rc<LveRenderPass> {
+        assert!(
+            !self.subpasses.is_empty(),
+            "A render pass needs at least one subpass"
+        );
+
+        for dependency in &self.dependencies {
+            for subpass in [dependency.src_subpass, dependency.dst_subpass] {
+                assert!(
+                    subpass == vk::SUBPASS_EXTERNAL || (subpass as usize) < self.subpasses.len(),
+                    "Dependency on subpass {} but there are only {} subpasses",
+                    subpass,
+                    self.subpasses.len()
+                );
+            }
+        }
+
+        // The descriptions point into these, so they have to outlive the create call
+        let references: Vec<SubpassReferences> = self
+            .subpasses
+            .iter()
+            .map(|subpass| subpass.references(&self.attachments))
+            .collect();
+
+        let subpass_descriptions: Vec<vk::SubpassDescription> = references
+            .iter()
+            .map(|references| {
+                let description = vk::SubpassDescription::builder()
+                    .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+                    .color_attachments(&references.color)
+                    .input_attachments(&references.input);
+
+                match &references.depth {
+                    Some(depth) => description.depth_stencil_attachment(depth).build(),
+                    None => description.build(),
+                }
+            })
+            .collect();
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&self.attachments)
+            .subpasses(&subpass_descriptions)
+            .dependencies(&self.dependencies);
+
+        let render_pass = unsafe {
+            self.lve_device
+                .device
+                .create_render_pass(&render_pass_info, None)
+                .map_err(|e| log::error!("Unable to create render pass: {}", e))
+                .unwrap()
+        };
+
+        let subpass_color_formats = self
+            .subpasses
+            .iter()
+            .map(|subpass| {
+                subpass
+                    .color
+                    .iter()
+                    .map(|attachment| self.attachments[*attachment as usize].format)
+                    .collect()
+            })
+            .collect();
+
+        Rc::new(LveRenderPass {
+            lve_device: Rc::clone(&self.lve_device),
+            render_pass,
+            subpass_color_formats,
+        })
+    }
+}
+
+/// A render pass made by `LveRenderPassBuilder`, destroyed on drop
+pub struct LveRenderPass {
+    lve_device: Rc<LveDevice>,
+    pub render_pass: vk::RenderPass,
+    /// The formats of each subpass's colour attachments
+    subpass_color_formats: Vec<Vec<vk::Format>>,
+}
+
+#[allow(dead_code)]
+impl LveRenderPass {
+    /// For creating a pipeline that draws in `subpass`, whose config must target the same
+    /// subpass (see `LvePipeline::set_subpass`)
+    pub fn subpass_info(&self, subpass: u32) -> RenderPassInfo {
+        let color_formats = self
+            .subpass_color_formats
+            .get(subpass as usize)
+            .unwrap_or_else(|| {
+                panic!(
+                    "Render pass has no subpass {}, there are only {}",
+                    subpass,
+                    self.subpass_color_formats.len()
+                )
+            });
+
+        RenderPassInfo {
+            render_pass: self.render_pass,
+            color_formats: color_formats.clone(),
+            subpass,
+        }
+    }
+
+    pub fn subpass_count(&self) -> u32 {
+        self.subpass_color_formats.len() as u32
+    }
+
+    /// Moves a render pass begun with this render pass on to its next subpass
+    pub unsafe fn cmd_next_subpass(&self, command_buffer: vk::CommandBuffer) {
+        self.lve_device
+            .device
+            .cmd_next_subpass(command_buffer, vk::SubpassContents::INLINE);
+    }
+}
+
+impl Drop for LveRenderPass {
+    fn drop(&mut self) {
+        log::debug!("Dropping render pass");
+
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(format: vk::Format) -> vk::AttachmentDescription {
+        vk::AttachmentDescription::builder().format(format).build()
+    }
+
+    #[test]
+    fn subpass_references_use_the_layout_for_how_they_are_used() {
+        let attachments = [
+            attachment(vk::Format::R16G16B16A16_SFLOAT),
+            attachment(vk::Format::D32_SFLOAT),
+            attachment(vk::Format::B8G8R8A8_SRGB),
+        ];
+
+        let lighting = SubpassAttachments {
+            color: vec![2],
+            input: vec![0, 1],
+            depth: None,
+        };
+
+        let references = lighting.references(&attachments);
+
+        assert_eq!(references.color[0].attachment, 2);
+        assert_eq!(
+            references.color[0].layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+        assert_eq!(
+            references
+                .input
+                .iter()
+                .map(|reference| reference.layout)
+                .collect::<Vec<_>>(),
+            vec![
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+            ]
+        );
+        assert!(references.depth.is_none());
+    }
+}
@@ -1,39 +1,375 @@
 use super::lve_device::*;
+use super::lve_frameinfo::{FrameInfo, RenderStats};
+use super::lve_pipeline::{DepthBias, RenderPassInfo};
 use super::lve_swapchain::*;
+use super::picking_system::PickingTarget;
+use super::render_system::RenderSystemList;
 
 use winit::window::Window;
 
 use ash::{vk, Device};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
 use std::rc::Rc;
 
+extern crate nalgebra as na;
+
+/// The background of the scene
+const CLEAR_COLOR: [f32; 4] = [0.01, 0.01, 0.01, 1.0];
+/// The bars either side of the viewport when letterboxing, see `set_target_aspect`
+const LETTERBOX_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+/// How many times `begin_frame` tries to acquire an image, recreating the swapchain between
+/// attempts, before giving up on the frame
+const MAX_ACQUIRE_ATTEMPTS: u32 = 3;
+
+/// Why a frame couldn't be drawn. An out of date swapchain isn't one of these, the renderer
+/// recreates the swapchain itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// The GPU hung or the driver was reset, e.g. by a Windows TDR. Nothing made with the
+    /// device can be used again, see `VulkanApp::recover`
+    DeviceLost,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::DeviceLost => write!(f, "the device was lost"),
+        }
+    }
+}
+
+/// A message for a condition that can last many frames, e.g. the swapchain being out of date
+/// while the window is resized. It is logged when the condition starts, suppressed while it
+/// keeps happening, and once it clears a summary says how many times it was suppressed
+struct RepeatedLog {
+    level: log::Level,
+    message: &'static str,
+    active: bool,
+    suppressed: u32,
+}
+
+impl RepeatedLog {
+    fn new(level: log::Level, message: &'static str) -> Self {
+        Self {
+            level,
+            message,
+            active: false,
+            suppressed: 0,
+        }
+    }
+
+    fn occurred(&mut self) {
+        if self.active {
+            self.suppressed += 1;
+        } else {
+            log::log!(self.level, "{}", self.message);
+            self.active = true;
+        }
+    }
+
+    fn cleared(&mut self) {
+        if self.active && self.suppressed > 0 {
+            log::log!(
+                self.level,
+                "{} (repeated {} more times)",
+                self.message,
+                self.suppressed
+            );
+        }
+
+        self.active = false;
+        self.suppressed = 0;
+    }
+}
+
+/// How the renderer's primary command buffers are allocated and picked each frame
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CommandBufferAllocation {
+    /// One per frame in flight, picked by the frame index
+    #[default]
+    PerFrame,
+    /// One per swapchain image, picked by the acquired image index. Each image is always
+    /// recorded into the same command buffer, which can make captures easier to follow.
+    /// Recording waits for the image's previous submission rather than the frame's
+    PerImage,
+}
+
+/// A render pass instance to draw the scene into other than the swapchain's, see
+/// `LveRenderer::render_scene_from`. The render systems drawing into it must have been created
+/// with a compatible render pass
+pub struct SceneTarget<'a> {
+    pub render_pass: vk::RenderPass,
+    pub framebuffer: vk::Framebuffer,
+    /// The area drawn to, also used as the viewport and scissor
+    pub render_area: vk::Rect2D,
+    /// One per attachment, in attachment order
+    pub clear_values: &'a [vk::ClearValue],
+}
+
+/// Resources that command buffers still in flight may be using, kept alive until those frames
+/// have finished. Each is tagged with the number of the frame being recorded when it was
+/// deferred, every frame submitted before it has that frame's number or less
+struct DeletionQueue {
+    pending: VecDeque<(u64, Box<dyn Any>)>,
+}
+
+impl DeletionQueue {
+    fn new() -> Self {
+        Self {
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, frame_number: u64, resource: Box<dyn Any>) {
+        self.pending.push_back((frame_number, resource));
+    }
+
+    /// Frees everything deferred before `completed_frames` frames had finished on the GPU
+    fn flush_completed(&mut self, completed_frames: u64) {
+        let count = self
+            .pending
+            .iter()
+            .take_while(|(frame_number, _)| *frame_number < completed_frames)
+            .count();
+
+        if count > 0 {
+            log::debug!("Freeing {} deferred resources", count);
+            self.pending.drain(..count);
+        }
+    }
+
+    /// Only once the device is idle
+    fn flush_all(&mut self) {
+        self.pending.clear();
+    }
+}
+
+/// How many frames have finished on the GPU once the fence of frame `frame_number`'s frame
+/// index has been waited on, i.e. the frame `MAX_FRAMES_IN_FLIGHT` before it and all before that
+fn completed_frames(frame_number: u64) -> u64 {
+    (frame_number + 1).saturating_sub(MAX_FRAMES_IN_FLIGHT as u64)
+}
+
+/// One pipeline statistics query per frame in flight, counting the fragment shader invocations
+/// of the swapchain render pass. A frame's count is read back once its frame index comes round
+/// again, when its fence has been waited on
+struct FragmentInvocationQueries {
+    lve_device: Rc<LveDevice>,
+    query_pool: vk::QueryPool,
+    /// Whether each frame index's query was ended in the last frame recorded with it
+    written: [bool; MAX_FRAMES_IN_FLIGHT],
+}
+
+impl FragmentInvocationQueries {
+    /// `None` without the `pipeline_statistics_query` feature
+    fn new(lve_device: Rc<LveDevice>) -> Option<Self> {
+        if !lve_device.enabled_features.pipeline_statistics_query {
+            log::info!("pipelineStatisticsQuery is not enabled, fragments won't be counted");
+            return None;
+        }
+
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::PIPELINE_STATISTICS)
+            .query_count(MAX_FRAMES_IN_FLIGHT as u32)
+            .pipeline_statistics(vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS);
+
+        let query_pool = unsafe {
+            lve_device
+                .device
+                .create_query_pool(&create_info, None)
+                .map_err(|e| log::error!("Unable to create query pool: {}", e))
+                .unwrap()
+        };
+
+        Some(Self {
+            lve_device,
+            query_pool,
+            written: [false; MAX_FRAMES_IN_FLIGHT],
+        })
+    }
+
+    /// The count from the last frame recorded with `frame_index`, whose fence must have been
+    /// waited on. Then resets the query for the frame about to be recorded, outside of any
+    /// render pass
+    fn read_and_reset(&mut self, command_buffer: vk::CommandBuffer, frame_index: usize) -> u64 {
+        let mut invocations = [0_u64];
+
+        if self.written[frame_index] {
+            unsafe {
+                self.lve_device
+                    .device
+                    .get_query_pool_results(
+                        self.query_pool,
+                        frame_index as u32,
+                        1,
+                        &mut invocations,
+                        vk::QueryResultFlags::TYPE_64,
+                    )
+                    .map_err(|e| log::error!("Unable to get query results: {}", e))
+                    .unwrap()
+            };
+        }
+
+        unsafe {
+            self.lve_device.device.cmd_reset_query_pool(
+                command_buffer,
+                self.query_pool,
+                frame_index as u32,
+                1,
+            );
+        }
+        self.written[frame_index] = false;
+
+        invocations[0]
+    }
+
+    fn begin(&self, command_buffer: vk::CommandBuffer, frame_index: usize) {
+        unsafe {
+            self.lve_device.device.cmd_begin_query(
+                command_buffer,
+                self.query_pool,
+                frame_index as u32,
+                vk::QueryControlFlags::empty(),
+            );
+        }
+    }
+
+    fn end(&mut self, command_buffer: vk::CommandBuffer, frame_index: usize) {
+        unsafe {
+            self.lve_device.device.cmd_end_query(
+                command_buffer,
+                self.query_pool,
+                frame_index as u32,
+            );
+        }
+        self.written[frame_index] = true;
+    }
+}
+
+impl Drop for FragmentInvocationQueries {
+    fn drop(&mut self) {
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_query_pool(self.query_pool, None);
+        }
+    }
+}
+
 pub struct LveRenderer {
     lve_device: Rc<LveDevice>,
     pub lve_swapchain: LveSwapchain,
     command_buffers: Vec<vk::CommandBuffer>,
+    command_buffer_allocation: CommandBufferAllocation,
+    /// The swapchain image being rendered into, picks the framebuffer
     current_image_index: usize,
+    /// Picks the command buffer and every other per frame resource, see `MAX_FRAMES_IN_FLIGHT`
     current_frame_index: usize,
+    /// How many frames have been submitted, only ever goes up. Also the number of the frame
+    /// being recorded
+    frame_number: u64,
     pub is_frame_started: bool,
+    surface_format_preference: SurfaceFormatPreference,
+    image_count: SwapchainImageCount,
+    extra_image_usage: vk::ImageUsageFlags,
+    preferred_present_modes: Vec<vk::PresentModeKHR>,
+    render_pass_config: RenderPassConfig,
+    out_of_date_log: RepeatedLog,
+    suboptimal_log: RepeatedLog,
+    deletion_queue: DeletionQueue,
+    target_aspect: Option<f32>,
+    /// Set when acquiring or presenting reports the swapchain no longer matches the surface.
+    /// The swapchain is recreated at the start of the next frame
+    needs_recreate: bool,
+    /// Set when recreating the swapchain changed its formats, and so its render pass, see
+    /// `take_render_pass_changed`
+    render_pass_changed: bool,
+    /// Only created once something asks for picking, see `enable_picking`
+    picking_target: Option<PickingTarget>,
+    /// Recorded into by the render systems through the frame info, see `frame_stats`
+    frame_stats: Rc<RefCell<RenderStats>>,
+    last_frame_stats: RenderStats,
+    /// `None` without the `pipeline_statistics_query` feature
+    fragment_queries: Option<FragmentInvocationQueries>,
 }
 
 impl LveRenderer {
-    pub fn new(lve_device: Rc<LveDevice>, window: &Window) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        lve_device: Rc<LveDevice>,
+        window: &Window,
+        surface_format_preference: SurfaceFormatPreference,
+        image_count: SwapchainImageCount,
+        extra_image_usage: vk::ImageUsageFlags,
+        preferred_present_modes: &[vk::PresentModeKHR],
+        render_pass_config: RenderPassConfig,
+        command_buffer_allocation: CommandBufferAllocation,
+    ) -> Self {
         let window_extent = Self::get_window_extent(window);
 
-        let lve_swapchain = LveSwapchain::new(Rc::clone(&lve_device), window_extent, None);
+        let lve_swapchain = LveSwapchain::new(
+            Rc::clone(&lve_device),
+            window_extent,
+            None,
+            surface_format_preference,
+            image_count,
+            extra_image_usage,
+            preferred_present_modes,
+            render_pass_config,
+        );
 
-        let command_buffers =
-            Self::create_command_buffers(&lve_device.device, lve_device.command_pool);
+        let command_buffers = Self::create_command_buffers(
+            &lve_device.device,
+            lve_device.command_pool,
+            Self::command_buffer_count(command_buffer_allocation, &lve_swapchain),
+        );
+
+        let fragment_queries = FragmentInvocationQueries::new(Rc::clone(&lve_device));
 
         Self {
             lve_device,
             lve_swapchain,
             command_buffers,
+            command_buffer_allocation,
             current_image_index: 0,
             current_frame_index: 0,
+            frame_number: 0,
             is_frame_started: false,
+            surface_format_preference,
+            image_count,
+            extra_image_usage,
+            preferred_present_modes: preferred_present_modes.to_vec(),
+            render_pass_config,
+            out_of_date_log: RepeatedLog::new(log::Level::Error, "Out of date KHR!"),
+            suboptimal_log: RepeatedLog::new(
+                log::Level::Warn,
+                "Swapchain is suboptimal for surface",
+            ),
+            deletion_queue: DeletionQueue::new(),
+            target_aspect: None,
+            needs_recreate: false,
+            render_pass_changed: false,
+            picking_target: None,
+            frame_stats: Rc::new(RefCell::new(RenderStats::default())),
+            last_frame_stats: RenderStats::default(),
+            fragment_queries,
         }
     }
 
+    /// The stats the frame being recorded adds to, for `FrameInfo::stats`. Shared rather than
+    /// borrowed so the frame info doesn't hold a borrow of the renderer
+    pub fn frame_stats(&self) -> Rc<RefCell<RenderStats>> {
+        Rc::clone(&self.frame_stats)
+    }
+
+    /// What the render systems drew in the last frame that was recorded
+    pub fn last_frame_stats(&self) -> RenderStats {
+        self.last_frame_stats
+    }
+
     #[allow(dead_code)]
     pub fn get_frame_index(&self) -> usize {
         assert!(
@@ -48,50 +384,139 @@ impl LveRenderer {
             self.is_frame_started,
             "Cannot get command buffer when frame not in progress"
         );
-        self.command_buffers[self.current_frame_index]
+        match self.command_buffer_allocation {
+            CommandBufferAllocation::PerFrame => self.command_buffers[self.current_frame_index],
+            CommandBufferAllocation::PerImage => self.command_buffers[self.current_image_index],
+        }
+    }
+
+    pub fn get_swapchain_render_pass(&self) -> RenderPassInfo {
+        self.lve_swapchain.render_pass_info()
+    }
+
+    #[allow(dead_code)]
+    pub fn get_surface_format(&self) -> vk::SurfaceFormatKHR {
+        self.lve_swapchain.surface_format()
+    }
+
+    #[allow(dead_code)]
+    pub fn get_swapchain_extent(&self) -> vk::Extent2D {
+        self.lve_swapchain.swapchain_extent
     }
 
-    pub fn get_swapchain_render_pass(&self) -> vk::RenderPass {
-        self.lve_swapchain.render_pass
+    /// Goes after the projection matrix, see `LveSwapchain::pre_rotation`
+    pub fn get_pre_rotation(&self) -> na::Matrix4<f32> {
+        self.lve_swapchain.pre_rotation()
     }
 
+    /// The aspect ratio of the viewport, which is the swapchain's unless letterboxing
     pub fn get_aspect_ratio(&self) -> f32 {
-        self.lve_swapchain.extent_aspect_ratio()
+        self.target_aspect
+            .unwrap_or_else(|| self.lve_swapchain.extent_aspect_ratio())
     }
 
-    pub fn begin_frame(&mut self, window: &Window) -> Option<vk::CommandBuffer> {
+    /// Letterboxes the viewport to `target_aspect` (width / height) inside the swapchain
+    /// image, rather than stretching the scene to fill it. `None` fills the whole image
+    #[allow(dead_code)]
+    pub fn set_target_aspect(&mut self, target_aspect: Option<f32>) {
+        self.target_aspect = target_aspect;
+    }
+
+    /// The area of the swapchain image the scene is drawn to
+    pub fn get_viewport_rect(&self) -> vk::Rect2D {
+        letterbox_rect(self.lve_swapchain.swapchain_extent, self.target_aspect)
+    }
+
+    /// The command buffer to record the frame into, or `None` if the frame should be skipped
+    pub fn begin_frame(
+        &mut self,
+        window: &Window,
+    ) -> Result<Option<vk::CommandBuffer>, FrameError> {
         assert!(
             !self.is_frame_started,
             "Can't call begin_frame while already in progress"
         );
 
-        let result = unsafe {
-            self.lve_swapchain
-                .acquire_next_image(&self.lve_device.device)
-        };
+        if self.needs_recreate {
+            self.recreate_swapchain(window);
+        }
 
-        match result {
-            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                log::error!("Out of date KHR!");
-                self.recreate_swapchain(window);
-                return None;
-            }
-            Err(_) => {
-                log::error!("Unable to acquire next image");
-                panic!("Unable to handle this error")
-            }
-            Ok((current_image_index, is_subopt)) => {
-                match is_subopt {
-                    true => {
-                        log::warn!("Swapchain is suboptimal for surface");
-                        self.recreate_swapchain(window);
+        // An out of date swapchain can't be acquired from, so recreate it and try again rather
+        // than dropping the frame. A suboptimal image is still usable, so it is rendered and
+        // the swapchain recreated before the next frame instead, as re-acquiring would signal
+        // the image available semaphore a second time
+        let mut attempts = 0;
+
+        let current_image_index = loop {
+            attempts += 1;
+
+            let result = unsafe {
+                self.lve_swapchain
+                    .acquire_next_image(&self.lve_device.device)
+            };
+
+            match result {
+                Ok((current_image_index, is_subopt)) => {
+                    self.out_of_date_log.cleared();
+
+                    match is_subopt {
+                        true => {
+                            self.suboptimal_log.occurred();
+                            self.needs_recreate = true;
+                        }
+                        false => self.suboptimal_log.cleared(),
                     }
-                    false => {}
+
+                    break current_image_index;
                 }
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                    self.out_of_date_log.occurred();
 
-                self.is_frame_started = true;
-                self.current_image_index = current_image_index as usize;
+                    if attempts >= MAX_ACQUIRE_ATTEMPTS {
+                        self.needs_recreate = true;
+                        return Ok(None);
+                    }
+
+                    self.recreate_swapchain(window);
+                }
+                Err(vk::Result::ERROR_DEVICE_LOST) => return Err(FrameError::DeviceLost),
+                Err(e) => {
+                    log::error!("Unable to acquire next image: {}", e);
+                    panic!("Unable to handle this error")
+                }
             }
+        };
+
+        // The fence for this frame index has been waited on, so anything deferred while the
+        // frames up to the last one recorded with it were recorded is no longer in use
+        self.deletion_queue
+            .flush_completed(completed_frames(self.frame_number));
+
+        if let Some(picking_target) = &mut self.picking_target {
+            picking_target.read_back(self.current_frame_index);
+        }
+
+        self.last_frame_stats = self.frame_stats.replace(RenderStats::default());
+
+        self.is_frame_started = true;
+        self.current_image_index = current_image_index as usize;
+
+        // Waiting on the frame's fence only covers the frame's command buffer, an image's
+        // command buffer may have last been submitted by another frame that is still running
+        if self.command_buffer_allocation == CommandBufferAllocation::PerImage {
+            self.lve_swapchain
+                .wait_for_image(&self.lve_device.device, self.current_image_index)
+                .map_err(|e| match e {
+                    vk::Result::ERROR_DEVICE_LOST => FrameError::DeviceLost,
+                    e => {
+                        log::error!(
+                            "Unable to wait for image {}: {}",
+                            self.current_image_index,
+                            e
+                        );
+                        panic!("Unable to handle this error")
+                    }
+                })?;
         }
 
         let command_buffer = self.get_current_command_buffer();
@@ -106,10 +531,15 @@ impl LveRenderer {
                 .unwrap()
         };
 
-        return Some(command_buffer);
+        if let Some(fragment_queries) = &mut self.fragment_queries {
+            self.last_frame_stats.fragment_invocations =
+                Some(fragment_queries.read_and_reset(command_buffer, self.current_frame_index));
+        }
+
+        return Ok(Some(command_buffer));
     }
 
-    pub fn end_frame(&mut self) {
+    pub fn end_frame(&mut self) -> Result<(), FrameError> {
         assert!(
             self.is_frame_started,
             "Can't call end_frame while frame is not in progress"
@@ -124,50 +554,52 @@ impl LveRenderer {
                 .unwrap()
         };
 
-        let _result = self
-            .lve_swapchain
-            .submit_command_buffers(
-                &self.lve_device.device,
-                &self.lve_device.graphics_queue,
-                &self.lve_device.present_queue,
-                command_buffer,
-                self.current_image_index,
-            )
-            .map_err(|e| log::error!("Unable to present swapchain image: {}", e))
-            .unwrap();
-
-        unsafe {
-            self.lve_device
-                .device
-                .device_wait_idle()
-                .map_err(|e| log::error!("Cannot wait: {}", e))
-                .unwrap()
-        };
+        let result = self.lve_swapchain.submit_command_buffers(
+            &self.lve_device.device,
+            &self.lve_device.graphics_queue,
+            &self.lve_device.present_queue,
+            command_buffer,
+            self.current_image_index,
+        );
 
         self.is_frame_started = false;
-        self.current_frame_index = (self.current_frame_index + 1) % MAX_FRAMES_IN_FLIGHT;
+        self.current_frame_index = next_frame_index(self.current_frame_index);
+        self.frame_number += 1;
+
+        match result {
+            Ok(false) => {}
+            Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.needs_recreate = true,
+            Err(vk::Result::ERROR_DEVICE_LOST) => return Err(FrameError::DeviceLost),
+            Err(e) => {
+                log::error!("Unable to submit or present swapchain image: {}", e);
+                panic!("Unable to handle this error")
+            }
+        }
+
+        Ok(())
     }
 
     pub fn begin_swapchain_render_pass(&self, command_buffer: vk::CommandBuffer) {
-        assert!(
-            self.is_frame_started,
-            "Can't call begin_swpachain_render_pass while frame is not in progress"
-        );
-
-        assert_eq!(
-            command_buffer,
-            self.get_current_command_buffer(),
-            "Can't begin render pass on a command buffer from a different frame"
-        );
+        self.check_command_buffer(command_buffer, "begin_swapchain_render_pass");
 
         let render_area = vk::Rect2D {
             offset: vk::Offset2D { x: 0, y: 0 },
             extent: self.lve_swapchain.swapchain_extent,
         };
 
+        let viewport_rect = self.get_viewport_rect();
+
+        let is_letterboxed = viewport_rect != render_area;
+
+        // When letterboxing the whole image is cleared to the bar colour, then the viewport is
+        // cleared again to the scene's background
         let color_clear = vk::ClearValue {
             color: vk::ClearColorValue {
-                float32: [0.01, 0.01, 0.01, 1.0],
+                float32: if is_letterboxed {
+                    LETTERBOX_COLOR
+                } else {
+                    CLEAR_COLOR
+                },
             },
         };
 
@@ -194,46 +626,252 @@ impl LveRenderer {
                 vk::SubpassContents::INLINE,
             );
 
-            let viewport = vk::Viewport::builder()
-                .x(0.0)
-                .y(0.0)
-                .width(self.lve_swapchain.width() as f32)
-                .height(self.lve_swapchain.height() as f32)
-                .min_depth(0.0)
-                .max_depth(1.0)
-                .build();
-
-            let scissor = vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: self.lve_swapchain.swapchain_extent,
-            };
-
-            self.lve_device
-                .device
-                .cmd_set_viewport(command_buffer, 0, &[viewport]);
-            self.lve_device
-                .device
-                .cmd_set_scissor(command_buffer, 0, &[scissor]);
+            if is_letterboxed {
+                self.lve_device.device.cmd_clear_attachments(
+                    command_buffer,
+                    &[vk::ClearAttachment {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        color_attachment: 0,
+                        clear_value: vk::ClearValue {
+                            color: vk::ClearColorValue {
+                                float32: CLEAR_COLOR,
+                            },
+                        },
+                    }],
+                    &[vk::ClearRect {
+                        rect: viewport_rect,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    }],
+                );
+            }
         };
+
+        self.set_viewport_and_scissor(command_buffer, viewport_rect);
     }
 
     pub fn end_swapchain_render_pass(&self, command_buffer: vk::CommandBuffer) {
+        self.check_command_buffer(command_buffer, "end_swapchain_render_pass");
+
+        unsafe {
+            self.lve_device.device.cmd_end_render_pass(command_buffer);
+        }
+    }
+
+    /// Records `render_systems` into one instance of `target`'s render pass, instead of the
+    /// swapchain's. The camera, and the matrices in the global descriptor set, come from
+    /// `frame_info`, so the same systems can draw the scene from several viewpoints in one
+    /// frame, e.g. for shadow maps or the faces of a dynamic cubemap. `frame_info.viewport`
+    /// should be the target's render area. Must be recorded outside the swapchain render pass
+    pub fn render_scene_from(
+        &self,
+        frame_info: &FrameInfo,
+        target: &SceneTarget,
+        render_systems: &RenderSystemList,
+    ) {
+        let command_buffer = frame_info.command_buffer;
+
+        self.check_command_buffer(command_buffer, "render_scene_from");
+
+        debug_assert_eq!(
+            frame_info.viewport, target.render_area,
+            "The frame info's viewport should match the target being rendered to"
+        );
+
+        let render_pass_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(target.render_pass)
+            .framebuffer(target.framebuffer)
+            .render_area(target.render_area)
+            .clear_values(target.clear_values)
+            .build();
+
+        unsafe {
+            self.lve_device.device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_info,
+                vk::SubpassContents::INLINE,
+            );
+        }
+
+        self.set_viewport_and_scissor(command_buffer, target.render_area);
+
+        render_systems.render(frame_info);
+
+        unsafe {
+            self.lve_device.device.cmd_end_render_pass(command_buffer);
+        }
+    }
+
+    /// Panics unless a frame is in progress and `command_buffer` is the one it records into
+    fn check_command_buffer(&self, command_buffer: vk::CommandBuffer, caller: &str) {
         assert!(
             self.is_frame_started,
-            "Can't call end_swpachain_render_pass while frame is not in progress"
+            "Can't call {} while frame is not in progress",
+            caller
         );
 
         assert_eq!(
             command_buffer,
             self.get_current_command_buffer(),
-            "Can't end render pass on a command buffer from a different frame"
+            "Can't call {} with a command buffer from a different frame (frame {}, image {}, \
+             {:?} command buffers)",
+            caller,
+            self.current_frame_index,
+            self.current_image_index,
+            self.command_buffer_allocation
         );
+    }
+
+    fn set_viewport_and_scissor(&self, command_buffer: vk::CommandBuffer, rect: vk::Rect2D) {
+        let viewport = vk::Viewport::builder()
+            .x(rect.offset.x as f32)
+            .y(rect.offset.y as f32)
+            .width(rect.extent.width as f32)
+            .height(rect.extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0)
+            .build();
 
         unsafe {
-            self.lve_device.device.cmd_end_render_pass(command_buffer);
+            self.lve_device
+                .device
+                .cmd_set_viewport(command_buffer, 0, &[viewport]);
+            self.lve_device
+                .device
+                .cmd_set_scissor(command_buffer, 0, &[rect]);
+        }
+    }
+
+    /// Records every registered render system, in stage order, into one instance of the
+    /// swapchain render pass. Its fragment shader invocations are counted when the device
+    /// supports it
+    pub fn render_swapchain_pass(
+        &mut self,
+        frame_info: &FrameInfo,
+        render_systems: &RenderSystemList,
+    ) {
+        self.begin_swapchain_render_pass(frame_info.command_buffer);
+
+        if let Some(fragment_queries) = &self.fragment_queries {
+            fragment_queries.begin(frame_info.command_buffer, self.current_frame_index);
+        }
+
+        render_systems.render(frame_info);
+
+        if let Some(fragment_queries) = &mut self.fragment_queries {
+            fragment_queries.end(frame_info.command_buffer, self.current_frame_index);
+        }
+
+        self.end_swapchain_render_pass(frame_info.command_buffer);
+    }
+
+    /// Creates the offscreen target object ids are drawn into, see `pick_at`. Returns its render
+    /// pass, which the `PickingSystem` must be created with
+    pub fn enable_picking(&mut self) -> RenderPassInfo {
+        if self.picking_target.is_none() {
+            self.picking_target = Some(PickingTarget::new(
+                Rc::clone(&self.lve_device),
+                self.lve_swapchain.swapchain_extent,
+            ));
+        }
+
+        self.picking_target.as_ref().unwrap().render_pass_info()
+    }
+
+    /// The id of the game object at `(x, y)`, in pixels from the top left of the window.
+    ///
+    /// The id has to be drawn and copied back from the GPU, which takes until the frame doing
+    /// it has finished. So this asks for `(x, y)` to be picked this frame, and returns the
+    /// latest pick that has finished, which is `MAX_FRAMES_IN_FLIGHT` frames old. Called every
+    /// frame with the cursor position, this lags slightly behind the cursor. `None` if there is
+    /// no object there, the point is outside the viewport or picking isn't enabled
+    pub fn pick_at(&mut self, x: u32, y: u32) -> Option<u64> {
+        let viewport = self.get_viewport_rect();
+        let picking_target = self.picking_target.as_mut()?;
+
+        let (viewport_x, viewport_y) = (x as i32 - viewport.offset.x, y as i32 - viewport.offset.y);
+
+        if viewport_x < 0
+            || viewport_y < 0
+            || viewport_x as u32 >= viewport.extent.width
+            || viewport_y as u32 >= viewport.extent.height
+        {
+            return None;
+        }
+
+        picking_target.request(x, y)
+    }
+
+    /// Draws the object ids with `picking_systems` and copies back the pixel asked for by
+    /// `pick_at`, if it was called since the last picking pass. Must be recorded outside the
+    /// swapchain render pass
+    pub fn render_picking_pass(
+        &mut self,
+        frame_info: &FrameInfo,
+        picking_systems: &RenderSystemList,
+    ) {
+        let pixel = match self
+            .picking_target
+            .as_mut()
+            .and_then(|picking_target| picking_target.take_request())
+        {
+            Some(pixel) => pixel,
+            None => return,
+        };
+
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue { uint32: [0; 4] },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+
+        // Only `pick_at` creates a request, and it returns early without a target
+        let picking_target = self.picking_target.as_ref().unwrap();
+
+        let target = SceneTarget {
+            render_pass: picking_target.render_pass(),
+            framebuffer: picking_target.framebuffer(),
+            render_area: self.get_viewport_rect(),
+            clear_values: &clear_values,
+        };
+
+        self.render_scene_from(frame_info, &target, picking_systems);
+
+        self.picking_target.as_mut().unwrap().cmd_copy_pixel(
+            frame_info.command_buffer,
+            frame_info.frame_index as usize,
+            pixel,
+        );
+    }
+
+    /// Sets the depth bias for the bound pipeline, which must have been created with
+    /// `LvePipeline::enable_dynamic_depth_bias`
+    #[allow(dead_code)]
+    pub fn cmd_set_depth_bias(&self, command_buffer: vk::CommandBuffer, depth_bias: DepthBias) {
+        unsafe {
+            self.lve_device.device.cmd_set_depth_bias(
+                command_buffer,
+                depth_bias.constant_factor,
+                depth_bias.clamp,
+                depth_bias.slope_factor,
+            );
         }
     }
 
+    /// Keeps `resource` alive until every frame that may use it has finished on the GPU, for
+    /// GPU resources (models, buffers, textures) that are no longer needed but may still be
+    /// referenced by a frame in flight
+    pub fn defer_deletion(&mut self, resource: impl Any) {
+        self.deletion_queue
+            .push(self.frame_number, Box::new(resource));
+    }
+
     pub fn recreate_swapchain(&mut self, window: &Window) {
         let extent = Self::get_window_extent(window);
 
@@ -243,6 +881,8 @@ impl LveRenderer {
 
         log::debug!("Recreating swapchain");
 
+        self.needs_recreate = false;
+
         unsafe {
             self.lve_device
                 .device
@@ -251,22 +891,71 @@ impl LveRenderer {
                 .unwrap()
         };
 
+        self.deletion_queue.flush_all();
+
         let new_lve_swapchain = LveSwapchain::new(
             Rc::clone(&self.lve_device),
             extent,
             Some(self.lve_swapchain.swapchain_khr),
+            self.surface_format_preference,
+            self.image_count,
+            self.extra_image_usage,
+            &self.preferred_present_modes,
+            self.render_pass_config,
         );
 
-        self.lve_swapchain
+        // The render systems' pipelines were built against the old render pass, which is only
+        // compatible with the new one if the formats match. Exclusive fullscreen can change
+        // the surface formats on offer, so this can happen when toggling fullscreen
+        if self
+            .lve_swapchain
             .compare_swap_formats(&new_lve_swapchain)
-            .map_err(|_| log::error!("Swapchain image (or depth) format has changed"))
-            .unwrap();
+            .is_err()
+        {
+            log::warn!(
+                "Swapchain image (or depth) format has changed from {:?} to {:?}, the pipelines \
+                 need rebuilding",
+                self.lve_swapchain.surface_format(),
+                new_lve_swapchain.surface_format()
+            );
+            self.render_pass_changed = true;
+        }
 
         self.lve_swapchain = new_lve_swapchain;
 
+        let command_buffer_count =
+            Self::command_buffer_count(self.command_buffer_allocation, &self.lve_swapchain);
+
+        // The device is idle, so none of the old command buffers are in use
+        if command_buffer_count != self.command_buffers.len() {
+            unsafe {
+                self.lve_device
+                    .device
+                    .free_command_buffers(self.lve_device.command_pool, &self.command_buffers);
+            }
+
+            self.command_buffers = Self::create_command_buffers(
+                &self.lve_device.device,
+                self.lve_device.command_pool,
+                command_buffer_count,
+            );
+        }
+
+        if let Some(picking_target) = &mut self.picking_target {
+            picking_target.resize(self.lve_swapchain.swapchain_extent);
+        }
+
         // We'll come back to this
     }
 
+    /// Whether the swapchain render pass has changed since this was last called, in which case
+    /// every pipeline made with `get_swapchain_render_pass` must be made again before drawing.
+    /// The device is idle whenever it has changed, so the old pipelines can be dropped straight
+    /// away
+    pub fn take_render_pass_changed(&mut self) -> bool {
+        std::mem::take(&mut self.render_pass_changed)
+    }
+
     pub fn get_window_extent(window: &Window) -> vk::Extent2D {
         let window_inner_size = window.inner_size();
         vk::Extent2D {
@@ -275,14 +964,25 @@ impl LveRenderer {
         }
     }
 
+    fn command_buffer_count(
+        command_buffer_allocation: CommandBufferAllocation,
+        lve_swapchain: &LveSwapchain,
+    ) -> usize {
+        match command_buffer_allocation {
+            CommandBufferAllocation::PerFrame => MAX_FRAMES_IN_FLIGHT,
+            CommandBufferAllocation::PerImage => lve_swapchain.image_count(),
+        }
+    }
+
     fn create_command_buffers(
         device: &Device,
         command_pool: vk::CommandPool,
+        count: usize,
     ) -> Vec<vk::CommandBuffer> {
         let alloc_info = vk::CommandBufferAllocateInfo::builder()
             .level(vk::CommandBufferLevel::PRIMARY)
             .command_pool(command_pool)
-            .command_buffer_count(MAX_FRAMES_IN_FLIGHT as u32)
+            .command_buffer_count(count as u32)
             .build();
 
         let command_buffers = unsafe {
@@ -296,6 +996,44 @@ impl LveRenderer {
     }
 }
 
+/// The largest rect of `target_aspect` that fits in `extent`, centred so the bars either side
+/// are the same size. The whole extent if there is no target
+fn letterbox_rect(extent: vk::Extent2D, target_aspect: Option<f32>) -> vk::Rect2D {
+    let full_rect = vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent,
+    };
+
+    let target_aspect = match target_aspect {
+        Some(target_aspect) => target_aspect,
+        None => return full_rect,
+    };
+
+    let extent_aspect = extent.width as f32 / extent.height as f32;
+
+    let viewport_extent = if extent_aspect > target_aspect {
+        // Too wide, bars on the left and right
+        vk::Extent2D {
+            width: ((extent.height as f32 * target_aspect).round() as u32).clamp(1, extent.width),
+            height: extent.height,
+        }
+    } else {
+        // Too tall, bars on the top and bottom
+        vk::Extent2D {
+            width: extent.width,
+            height: ((extent.width as f32 / target_aspect).round() as u32).clamp(1, extent.height),
+        }
+    };
+
+    vk::Rect2D {
+        offset: vk::Offset2D {
+            x: ((extent.width - viewport_extent.width) / 2) as i32,
+            y: ((extent.height - viewport_extent.height) / 2) as i32,
+        },
+        extent: viewport_extent,
+    }
+}
+
 impl Drop for LveRenderer {
     fn drop(&mut self) {
         log::debug!("Dropping renderer");
@@ -307,3 +1045,62 @@ impl Drop for LveRenderer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(width: u32, height: u32) -> vk::Extent2D {
+        vk::Extent2D { width, height }
+    }
+
+    #[test]
+    fn no_target_fills_the_extent() {
+        let rect = letterbox_rect(extent(800, 600), None);
+
+        assert_eq!((rect.offset.x, rect.offset.y), (0, 0));
+        assert_eq!(rect.extent, extent(800, 600));
+    }
+
+    #[test]
+    fn wide_extent_gets_bars_on_the_sides() {
+        let rect = letterbox_rect(extent(1000, 450), Some(16.0 / 9.0));
+
+        assert_eq!(rect.extent, extent(800, 450));
+        assert_eq!((rect.offset.x, rect.offset.y), (100, 0));
+    }
+
+    #[test]
+    fn tall_extent_gets_bars_on_the_top_and_bottom() {
+        let rect = letterbox_rect(extent(800, 600), Some(16.0 / 9.0));
+
+        assert_eq!(rect.extent, extent(800, 450));
+        assert_eq!((rect.offset.x, rect.offset.y), (0, 75));
+    }
+
+    #[test]
+    fn matching_aspect_fills_the_extent() {
+        let rect = letterbox_rect(extent(1600, 900), Some(16.0 / 9.0));
+
+        assert_eq!((rect.offset.x, rect.offset.y), (0, 0));
+        assert_eq!(rect.extent, extent(1600, 900));
+    }
+
+    #[test]
+    fn deferred_resources_outlive_every_frame_in_flight() {
+        let resource = Rc::new(());
+        let mut deletion_queue = DeletionQueue::new();
+
+        // Deferred while frame 3 is recorded, so frame 3 may still be using it
+        deletion_queue.push(3, Box::new(Rc::clone(&resource)));
+
+        for frame_number in 4..3 + MAX_FRAMES_IN_FLIGHT as u64 {
+            deletion_queue.flush_completed(completed_frames(frame_number));
+            assert_eq!(Rc::strong_count(&resource), 2);
+        }
+
+        // Frame 3's frame index comes round again, so its fence has been waited on
+        deletion_queue.flush_completed(completed_frames(3 + MAX_FRAMES_IN_FLIGHT as u64));
+        assert_eq!(Rc::strong_count(&resource), 1);
+    }
+}
@@ -1,46 +1,219 @@
 use super::lve_device::*;
+use super::lve_image::{ImageUsage, LveImage};
+use super::lve_pipeline::RenderPassInfo;
 
 use ash::extensions::khr::Swapchain;
 use ash::{vk, Device};
 
 use std::rc::Rc;
 
+extern crate nalgebra as na;
+
+/// How many frames the CPU may record ahead of the GPU. This is independent of the number of
+/// swapchain images (see `SwapchainImageCount`), so there are two index spaces that must not be
+/// mixed up:
+///
+/// - The frame index, `0..MAX_FRAMES_IN_FLIGHT`, picks the per frame resources: sync objects,
+///   command buffers, UBOs, instance buffers and the deletion queue
+/// - The image index, `0..image_count`, is whichever swapchain image the driver handed out and
+///   only picks the framebuffer and the image's slot in `ImagesInFlight`
+///
+/// The driver may hand out more images than there are frames in flight, and in any order.
+/// Having more frames in flight than swapchain images gains nothing, as acquiring an image
+/// will block anyway
 pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
 
+/// The frame index after `frame_index`, wrapping back to 0
+pub fn next_frame_index(frame_index: usize) -> usize {
+    assert!(
+        frame_index < MAX_FRAMES_IN_FLIGHT,
+        "Frame index {} is out of range, there are only {} frames in flight",
+        frame_index,
+        MAX_FRAMES_IN_FLIGHT
+    );
+
+    (frame_index + 1) % MAX_FRAMES_IN_FLIGHT
+}
+
+/// Remembers, for each swapchain image, the in flight fence of the last frame submitted to
+/// render into it. Indexed by image index, never by frame index
+struct ImagesInFlight {
+    fences: Vec<vk::Fence>,
+}
+
+impl ImagesInFlight {
+    fn new(image_count: usize) -> Self {
+        assert!(image_count > 0, "Swapchain has no images");
+
+        if image_count < MAX_FRAMES_IN_FLIGHT {
+            log::warn!(
+                "Swapchain has {} images but there are {} frames in flight, frames will wait \
+                 on image acquisition",
+                image_count,
+                MAX_FRAMES_IN_FLIGHT
+            );
+        }
+
+        Self {
+            fences: vec![vk::Fence::null(); image_count],
+        }
+    }
+
+    /// The fence to wait on before rendering into the image again, `None` if it hasn't been
+    /// rendered into yet
+    fn fence(&self, image_index: usize) -> Option<vk::Fence> {
+        let fence = self.fences[self.checked_index(image_index)];
+
+        (fence != vk::Fence::null()).then_some(fence)
+    }
+
+    fn set_fence(&mut self, image_index: usize, fence: vk::Fence) {
+        let image_index = self.checked_index(image_index);
+        self.fences[image_index] = fence;
+    }
+
+    fn checked_index(&self, image_index: usize) -> usize {
+        assert!(
+            image_index < self.fences.len(),
+            "Image index {} is out of range for a swapchain with {} images",
+            image_index,
+            self.fences.len()
+        );
+
+        image_index
+    }
+}
+
+/// How many images to ask the surface for. The result is always clamped to the surface's
+/// supported range. More images means smoother presentation (particularly with mailbox) at the
+/// cost of memory and, for FIFO, added present latency
+#[allow(dead_code)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SwapchainImageCount {
+    /// Exactly the surface's minimum, the least memory and latency
+    Minimum,
+    /// One more than the minimum, so the driver never has to wait on us to get an image
+    #[default]
+    MinimumPlusOne,
+    /// Two more than the minimum, for triple buffering with mailbox presentation
+    MinimumPlusTwo,
+    /// A specific number of images, e.g. 2 for double or 3 for triple buffering
+    Exact(u32),
+}
+
+/// Which kind of surface format the swapchain should try to use. The HDR options are only
+/// picked if the surface advertises them, otherwise the SDR sRGB format is used
+#[allow(dead_code)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SurfaceFormatPreference {
+    /// 8-bit B8G8R8A8_SRGB with the sRGB non-linear color space
+    #[default]
+    Sdr,
+    /// 10-bit A2B10G10R10_UNORM_PACK32 with the HDR10 (ST2084) color space
+    Hdr10,
+    /// 16-bit float R16G16B16A16_SFLOAT with the extended linear sRGB (scRGB) color space
+    ScRgb,
+}
+
+/// How the swapchain render pass starts each frame. `CLEAR` starts from the clear values,
+/// `LOAD` keeps what was last rendered to the attachments for incremental rendering (e.g.
+/// accumulation or a persistent overlay). Each swapchain image has its own depth image, so
+/// `LOAD` gives back the last frame drawn to the same image rather than the previous frame,
+/// and the presentation engine isn't required to keep a presented image's contents. Effects
+/// that need the previous frame should render into an image of their own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderPassConfig {
+    pub color_load_op: vk::AttachmentLoadOp,
+    pub depth_load_op: vk::AttachmentLoadOp,
+}
+
+impl Default for RenderPassConfig {
+    fn default() -> Self {
+        Self {
+            color_load_op: vk::AttachmentLoadOp::CLEAR,
+            depth_load_op: vk::AttachmentLoadOp::CLEAR,
+        }
+    }
+}
+
+impl RenderPassConfig {
+    /// The store op and initial layout for an attachment with `load_op` that ends the render
+    /// pass in `final_layout`. A loaded attachment has to have been stored last frame, and
+    /// is still in the layout the last frame left it in, which the images are created in too
+    fn attachment_start(
+        load_op: vk::AttachmentLoadOp,
+        final_layout: vk::ImageLayout,
+    ) -> (vk::AttachmentStoreOp, vk::ImageLayout) {
+        if load_op == vk::AttachmentLoadOp::LOAD {
+            (vk::AttachmentStoreOp::STORE, final_layout)
+        } else {
+            (vk::AttachmentStoreOp::DONT_CARE, vk::ImageLayout::UNDEFINED)
+        }
+    }
+}
+
 pub struct LveSwapchain {
     lve_device: Rc<LveDevice>,
     swapchain: Swapchain,
     pub swapchain_khr: vk::SwapchainKHR,
     swapchain_image_format: vk::Format,
+    swapchain_color_space: vk::ColorSpaceKHR,
+    swapchain_image_usage: vk::ImageUsageFlags,
     swapchain_depth_format: vk::Format,
     pub swapchain_extent: vk::Extent2D,
+    /// How the presentation engine rotates the images onto the display, see
+    /// `surface_pre_transform`
+    pre_transform: vk::SurfaceTransformFlagsKHR,
     _swapchain_images: Vec<vk::Image>,
     swapchain_image_views: Vec<vk::ImageView>,
     pub swapchain_framebuffers: Vec<vk::Framebuffer>,
     pub render_pass: vk::RenderPass,
-    depth_images: Vec<vk::Image>,
-    depth_image_memories: Vec<vk::DeviceMemory>,
-    depth_image_views: Vec<vk::ImageView>,
+    /// Dropped after `drop` has destroyed the framebuffers using them
+    _depth_images: Vec<LveImage>,
     image_available_semaphores: Vec<vk::Semaphore>,
     render_finished_semaphores: Vec<vk::Semaphore>,
     in_flight_fences: Vec<vk::Fence>,
-    images_in_flight: Vec<vk::Fence>,
+    images_in_flight: ImagesInFlight,
     current_frame: usize,
 }
 
 impl LveSwapchain {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         lve_device: Rc<LveDevice>,
         window_extent: vk::Extent2D,
         old_swapchain: Option<vk::SwapchainKHR>,
+        surface_format_preference: SurfaceFormatPreference,
+        image_count: SwapchainImageCount,
+        extra_image_usage: vk::ImageUsageFlags,
+        preferred_present_modes: &[vk::PresentModeKHR],
+        render_pass_config: RenderPassConfig,
     ) -> Self {
         let old_swapchain = match old_swapchain {
             Some(swapchain) => swapchain,
             None => vk::SwapchainKHR::null(),
         };
 
-        let (swapchain, swapchain_khr, swapchain_images, swapchain_image_format, swapchain_extent) =
-            Self::create_swapchain(&lve_device, window_extent, old_swapchain);
+        let (
+            swapchain,
+            swapchain_khr,
+            swapchain_images,
+            surface_format,
+            swapchain_image_usage,
+            swapchain_extent,
+            pre_transform,
+        ) = Self::create_swapchain(
+            &lve_device,
+            window_extent,
+            old_swapchain,
+            surface_format_preference,
+            image_count,
+            extra_image_usage,
+            preferred_present_modes,
+        );
+
+        let swapchain_image_format = surface_format.format;
+        let swapchain_color_space = surface_format.color_space;
 
         let swapchain_image_views = Self::create_image_views(
             &lve_device.device,
@@ -48,16 +221,30 @@ impl LveSwapchain {
             swapchain_image_format,
         );
 
-        let render_pass = Self::create_render_pass(&lve_device, swapchain_image_format);
+        let render_pass =
+            Self::create_render_pass(&lve_device, swapchain_image_format, render_pass_config);
+
+        let swapchain_depth_format = Self::find_depth_format(&lve_device);
+
+        let depth_images = Self::create_depth_resources(
+            &lve_device,
+            &swapchain_images,
+            swapchain_depth_format,
+            swapchain_extent,
+        );
 
-        let (depth_images, depth_image_memories, depth_image_views, swapchain_depth_format) =
-            Self::create_depth_resources(&lve_device, &swapchain_images, swapchain_extent);
+        Self::prepare_loaded_attachments(
+            &lve_device,
+            &swapchain_images,
+            &depth_images,
+            render_pass_config,
+        );
 
         let swapchain_framebuffers = Self::create_framebuffers(
             &lve_device.device,
             swapchain_extent,
             &swapchain_image_views,
-            &depth_image_views,
+            &depth_images,
             render_pass,
         );
 
@@ -73,15 +260,16 @@ impl LveSwapchain {
             swapchain,
             swapchain_khr,
             swapchain_image_format,
+            swapchain_color_space,
+            swapchain_image_usage,
             swapchain_depth_format,
             swapchain_extent,
+            pre_transform,
             _swapchain_images: swapchain_images,
             swapchain_image_views,
             swapchain_framebuffers,
             render_pass,
-            depth_images,
-            depth_image_memories,
-            depth_image_views,
+            _depth_images: depth_images,
             image_available_semaphores,
             render_finished_semaphores,
             in_flight_fences,
@@ -100,28 +288,100 @@ impl LveSwapchain {
         }
     }
 
+    /// The format and color space the swapchain images were created with. Anything writing
+    /// directly to the swapchain (e.g. a tonemapping pass) should encode its output to match
+    pub fn surface_format(&self) -> vk::SurfaceFormatKHR {
+        vk::SurfaceFormatKHR {
+            format: self.swapchain_image_format,
+            color_space: self.swapchain_color_space,
+        }
+    }
+
+    /// The usage the swapchain images were actually created with. Always contains
+    /// `COLOR_ATTACHMENT`, plus whichever of the requested extra usages the surface supports
     #[allow(dead_code)]
+    pub fn image_usage(&self) -> vk::ImageUsageFlags {
+        self.swapchain_image_usage
+    }
+
     pub fn image_count(&self) -> usize {
         self._swapchain_images.len()
     }
 
+    #[allow(dead_code)]
     pub fn width(&self) -> u32 {
         self.swapchain_extent.width
     }
 
+    #[allow(dead_code)]
     pub fn height(&self) -> u32 {
         self.swapchain_extent.height
     }
 
+    /// The render pass has a single colour attachment, the swapchain image
+    pub fn render_pass_info(&self) -> RenderPassInfo {
+        RenderPassInfo {
+            render_pass: self.render_pass,
+            color_formats: vec![self.swapchain_image_format],
+            subpass: 0,
+        }
+    }
+
+    /// The aspect ratio of the images as they are shown. A display rotated a quarter turn
+    /// shows the images on their side, so their width runs up the display
     pub fn extent_aspect_ratio(&self) -> f32 {
-        self.swapchain_extent.width as f32 / self.swapchain_extent.height as f32
+        let aspect = self.swapchain_extent.width as f32 / self.swapchain_extent.height as f32;
+
+        if Self::is_quarter_turn(self.pre_transform) {
+            1.0 / aspect
+        } else {
+            aspect
+        }
+    }
+
+    /// The rotation the images are rendered with ahead of the presentation engine rotating
+    /// them onto the display. Always the identity on desktops, but common on phones and tablets
+    #[allow(dead_code)]
+    pub fn surface_pre_transform(&self) -> vk::SurfaceTransformFlagsKHR {
+        self.pre_transform
     }
 
+    /// Applied after the projection matrix so the scene is drawn rotated to match
+    /// `surface_pre_transform` and comes out upright on the display
+    pub fn pre_rotation(&self) -> na::Matrix4<f32> {
+        Self::pre_rotation_matrix(self.pre_transform)
+    }
+
+    /// Rotates clip space the way the presentation engine's `transform` does, so drawing with
+    /// it cancels the rotation out. Mirrored transforms aren't supported and get the identity
+    pub fn pre_rotation_matrix(transform: vk::SurfaceTransformFlagsKHR) -> na::Matrix4<f32> {
+        // The transforms turn clockwise, which in the y down clip space is the positive
+        // direction around z
+        let quarter_turns = match transform {
+            vk::SurfaceTransformFlagsKHR::ROTATE_90 => 1.0,
+            vk::SurfaceTransformFlagsKHR::ROTATE_180 => 2.0,
+            vk::SurfaceTransformFlagsKHR::ROTATE_270 => 3.0,
+            _ => 0.0,
+        };
+
+        na::Matrix4::from_axis_angle(
+            &na::Vector3::z_axis(),
+            quarter_turns * std::f32::consts::FRAC_PI_2,
+        )
+    }
+
+    fn is_quarter_turn(transform: vk::SurfaceTransformFlagsKHR) -> bool {
+        transform == vk::SurfaceTransformFlagsKHR::ROTATE_90
+            || transform == vk::SurfaceTransformFlagsKHR::ROTATE_270
+    }
+
+    /// Prefers formats with a stencil component so stencil effects (like outlining) work, only
+    /// falling back to depth alone if the device supports neither
     pub fn find_depth_format(lve_device: &Rc<LveDevice>) -> vk::Format {
         let candidates = vec![
-            vk::Format::D32_SFLOAT,
             vk::Format::D32_SFLOAT_S8_UINT,
             vk::Format::D24_UNORM_S8_UINT,
+            vk::Format::D32_SFLOAT,
         ];
         lve_device.find_supported_format(
             &candidates,
@@ -130,18 +390,20 @@ impl LveSwapchain {
         )
     }
 
+    pub fn has_stencil_component(format: vk::Format) -> bool {
+        format == vk::Format::D32_SFLOAT_S8_UINT || format == vk::Format::D24_UNORM_S8_UINT
+    }
+
     pub unsafe fn acquire_next_image(
         &mut self,
         device: &Device,
     ) -> Result<(u32, bool), vk::Result> {
-        device
-            .wait_for_fences(
-                &[self.in_flight_fences[self.current_frame]],
-                false,
-                u64::MAX,
-            )
-            .map_err(|e| log::error!("Unable to wait for fences: {}", e))
-            .unwrap();
+        // A lost device shows up here first, as a hung frame's fence never signals
+        device.wait_for_fences(
+            &[self.in_flight_fences[self.current_frame]],
+            false,
+            u64::MAX,
+        )?;
 
         self.swapchain.acquire_next_image(
             self.swapchain_khr,
@@ -151,6 +413,20 @@ impl LveSwapchain {
         ) // Return the result of acquire next image
     }
 
+    /// Waits until the last submission that rendered to `image_index` has finished, if there
+    /// has been one
+    pub fn wait_for_image(&self, device: &Device, image_index: usize) -> Result<(), vk::Result> {
+        if let Some(image_fence) = self.images_in_flight.fence(image_index) {
+            unsafe { device.wait_for_fences(&[image_fence], true, u64::MAX)? };
+        }
+
+        Ok(())
+    }
+
+    /// Submits to `graphics_queue`, then presents on `present_queue` once the submit has
+    /// signalled the render finished semaphore. They may be the same queue. See
+    /// `create_swapchain` for why no ownership transfer is needed when they aren't. Waiting,
+    /// submitting and presenting errors are all returned, e.g. `ERROR_DEVICE_LOST`
     pub fn submit_command_buffers(
         &mut self,
         device: &Device,
@@ -159,16 +435,10 @@ impl LveSwapchain {
         buffer: vk::CommandBuffer,
         image_index: usize,
     ) -> Result<bool, vk::Result> {
-        if self.images_in_flight[image_index] != vk::Fence::null() {
-            unsafe {
-                device
-                    .wait_for_fences(&[self.images_in_flight[image_index]], true, u64::MAX)
-                    .map_err(|e| log::error!("Unable to wait for fences: {}", e))
-                    .unwrap()
-            };
-        }
+        self.wait_for_image(device, image_index)?;
 
-        self.images_in_flight[image_index] = self.in_flight_fences[self.current_frame];
+        self.images_in_flight
+            .set_fence(image_index, self.in_flight_fences[self.current_frame]);
 
         let wait_semaphores = [self.image_available_semaphores[self.current_frame]];
 
@@ -183,19 +453,13 @@ impl LveSwapchain {
             .signal_semaphores(&signal_semaphores);
 
         unsafe {
-            device
-                .reset_fences(&[self.in_flight_fences[self.current_frame]])
-                .map_err(|e| log::error!("Unable to reset fences: {}", e))
-                .unwrap();
-
-            device
-                .queue_submit(
-                    *graphics_queue,
-                    std::slice::from_ref(&submit_info),
-                    self.in_flight_fences[self.current_frame],
-                )
-                .map_err(|e| log::error!("Unable to submit draw command buffer: {}", e))
-                .unwrap();
+            device.reset_fences(&[self.in_flight_fences[self.current_frame]])?;
+
+            device.queue_submit(
+                *graphics_queue,
+                std::slice::from_ref(&submit_info),
+                self.in_flight_fences[self.current_frame],
+            )?;
         };
 
         let swapchains = [self.swapchain_khr];
@@ -207,7 +471,7 @@ impl LveSwapchain {
             .swapchains(&swapchains)
             .image_indices(std::slice::from_ref(&image_index));
 
-        self.current_frame = (self.current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
+        self.current_frame = next_frame_index(self.current_frame);
 
         unsafe { self.swapchain.queue_present(*present_queue, &present_info) }
     }
@@ -216,29 +480,46 @@ impl LveSwapchain {
         lve_device: &Rc<LveDevice>,
         window_extent: vk::Extent2D,
         old_swapchain: vk::SwapchainKHR,
+        surface_format_preference: SurfaceFormatPreference,
+        image_count: SwapchainImageCount,
+        extra_image_usage: vk::ImageUsageFlags,
+        preferred_present_modes: &[vk::PresentModeKHR],
     ) -> (
         Swapchain,
         vk::SwapchainKHR,
         Vec<vk::Image>,
-        vk::Format,
+        vk::SurfaceFormatKHR,
+        vk::ImageUsageFlags,
         vk::Extent2D,
+        vk::SurfaceTransformFlagsKHR,
     ) {
         let swapchain_support = lve_device.get_swapchain_support();
 
-        let surface_format = Self::choose_swap_surface_format(&swapchain_support.formats);
+        let surface_format =
+            Self::choose_swap_surface_format(&swapchain_support.formats, surface_format_preference);
 
-        let present_mode = Self::choose_swap_present_mode(&swapchain_support.present_modes);
+        let present_mode = Self::choose_swap_present_mode(
+            &swapchain_support.present_modes,
+            preferred_present_modes,
+        );
 
         let extent = Self::choose_swap_extent(&swapchain_support.capabilities, window_extent);
 
-        let mut image_count = swapchain_support.capabilities.min_image_count + 1;
+        let image_count = Self::choose_image_count(&swapchain_support.capabilities, image_count);
 
-        if swapchain_support.capabilities.max_image_count > 0
-            && image_count > swapchain_support.capabilities.max_image_count
-        {
-            image_count = swapchain_support.capabilities.max_image_count;
+        let image_usage =
+            Self::choose_image_usage(&swapchain_support.capabilities, extra_image_usage);
+
+        // Rendering pre-rotated (see `pre_rotation`) saves the presentation engine a rotation
+        // pass on rotated displays, which is the identity transform on desktops
+        let pre_transform = swapchain_support.capabilities.current_transform;
+
+        if pre_transform != vk::SurfaceTransformFlagsKHR::IDENTITY {
+            log::info!("Swapchain pre-transform: {:?}", pre_transform);
         }
 
+        let composite_alpha = Self::choose_composite_alpha(&swapchain_support.capabilities);
+
         let mut create_info = vk::SwapchainCreateInfoKHR::builder()
             .surface(lve_device.surface_khr)
             .min_image_count(image_count)
@@ -246,12 +527,18 @@ impl LveSwapchain {
             .image_color_space(surface_format.color_space)
             .image_extent(extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT);
+            .image_usage(image_usage);
 
         let indices = lve_device.find_physical_queue_families();
 
         let queue_family_indices = [indices.graphics_family, indices.present_family];
 
+        // With separate families the images are shared concurrently, so they never need a queue
+        // family ownership transfer between rendering and presenting. The render finished
+        // semaphore is all that orders the present after the submit, whether or not the two
+        // queues are the same handle. Exclusive images would be faster on some drivers, but
+        // would need a release barrier on the graphics queue and a matching acquire on the
+        // present queue every frame
         if indices.graphics_family != indices.present_family {
             create_info = create_info
                 .image_sharing_mode(vk::SharingMode::CONCURRENT)
@@ -261,8 +548,8 @@ impl LveSwapchain {
         }
 
         let create_info = create_info
-            .pre_transform(swapchain_support.capabilities.current_transform)
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+            .pre_transform(pre_transform)
+            .composite_alpha(composite_alpha)
             .present_mode(present_mode)
             .clipped(true)
             .old_swapchain(old_swapchain);
@@ -287,7 +574,7 @@ impl LveSwapchain {
                 .unwrap()
         };
 
-        let swapchain_image_format = surface_format.format;
+        log::info!("Swapchain image count: {}", swapchain_images.len());
 
         let swapchain_extent = extent;
 
@@ -295,8 +582,10 @@ impl LveSwapchain {
             swapchain,
             swapchain_khr,
             swapchain_images,
-            swapchain_image_format,
+            surface_format,
+            image_usage,
             swapchain_extent,
+            pre_transform,
         )
     }
 
@@ -333,83 +622,113 @@ impl LveSwapchain {
     fn create_depth_resources(
         lve_device: &Rc<LveDevice>,
         swapchain_images: &Vec<vk::Image>,
+        depth_format: vk::Format,
         swapchain_extent: vk::Extent2D,
-    ) -> (
-        Vec<vk::Image>,
-        Vec<vk::DeviceMemory>,
-        Vec<vk::ImageView>,
-        vk::Format,
-    ) {
-        let depth_format = Self::find_depth_format(lve_device);
+    ) -> Vec<LveImage> {
+        // The framebuffer only gets the stencil aspect if the view includes it
+        let aspect_mask = if Self::has_stencil_component(depth_format) {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        } else {
+            vk::ImageAspectFlags::DEPTH
+        };
 
-        let (images, image_memories): (Vec<vk::Image>, Vec<vk::DeviceMemory>) = swapchain_images
+        swapchain_images
             .iter()
             .map(|_| {
-                let extent = vk::Extent3D {
-                    width: swapchain_extent.width,
-                    height: swapchain_extent.height,
-                    depth: 1,
-                };
-
-                let image_info = vk::ImageCreateInfo::builder()
-                    .image_type(vk::ImageType::TYPE_2D)
-                    .extent(extent)
-                    .mip_levels(1)
-                    .array_layers(1)
-                    .format(depth_format)
-                    .tiling(vk::ImageTiling::OPTIMAL)
-                    .initial_layout(vk::ImageLayout::UNDEFINED)
-                    .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
-                    .samples(vk::SampleCountFlags::TYPE_1)
-                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
-                    .flags(vk::ImageCreateFlags::empty());
-
-                lve_device
-                    .create_image_with_info(&image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL)
+                LveImage::new(
+                    Rc::clone(lve_device),
+                    depth_format,
+                    vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                    aspect_mask,
+                    swapchain_extent,
+                )
             })
-            .unzip();
+            .collect::<Vec<_>>()
+    }
 
-        let image_views = images
-            .iter()
-            .map(|image| {
-                let view_info = vk::ImageViewCreateInfo::builder()
-                    .image(*image)
-                    .view_type(vk::ImageViewType::TYPE_2D)
-                    .format(depth_format)
-                    .subresource_range(vk::ImageSubresourceRange {
-                        aspect_mask: vk::ImageAspectFlags::DEPTH,
-                        base_mip_level: 0,
-                        level_count: 1,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                    });
+    /// Moves freshly created attachments into the layout a `LOAD` render pass expects them
+    /// in. Their contents are undefined, but from then on each frame leaves them in that layout
+    fn prepare_loaded_attachments(
+        lve_device: &Rc<LveDevice>,
+        swapchain_images: &[vk::Image],
+        depth_images: &[LveImage],
+        render_pass_config: RenderPassConfig,
+    ) {
+        let load_color = render_pass_config.color_load_op == vk::AttachmentLoadOp::LOAD;
+        let load_depth = render_pass_config.depth_load_op == vk::AttachmentLoadOp::LOAD;
 
-                unsafe {
-                    lve_device
-                        .device
-                        .create_image_view(&view_info, None)
-                        .map_err(|e| log::error!("Unable to create depth image view: {}", e))
-                        .unwrap()
-                }
-            })
-            .collect::<Vec<_>>();
+        if !load_color && !load_depth {
+            return;
+        }
+
+        let command_buffer = lve_device.begin_single_time_commands();
+
+        // The swapchain images aren't `LveImage`s, so their barriers are written out here
+        if load_color {
+            let barriers: Vec<_> = swapchain_images
+                .iter()
+                .map(|image| {
+                    vk::ImageMemoryBarrier::builder()
+                        .old_layout(vk::ImageLayout::UNDEFINED)
+                        .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+                        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .image(*image)
+                        .subresource_range(vk::ImageSubresourceRange {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            base_mip_level: 0,
+                            level_count: 1,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        })
+                        .build()
+                })
+                .collect();
+
+            unsafe {
+                lve_device.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &barriers,
+                )
+            };
+        }
+
+        if load_depth {
+            for depth_image in depth_images {
+                depth_image.transition_for(command_buffer, ImageUsage::DepthStencilAttachment);
+            }
+        }
 
-        (images, image_memories, image_views, depth_format)
+        lve_device.end_single_time_commands(command_buffer);
     }
 
     fn create_render_pass(
         lve_device: &Rc<LveDevice>,
         swapchain_image_format: vk::Format,
+        render_pass_config: RenderPassConfig,
     ) -> vk::RenderPass {
+        let depth_final_layout = vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL;
+        let (depth_store_op, depth_initial_layout) = RenderPassConfig::attachment_start(
+            render_pass_config.depth_load_op,
+            depth_final_layout,
+        );
+
         let depth_attachment = vk::AttachmentDescription::builder()
             .format(Self::find_depth_format(lve_device))
             .samples(vk::SampleCountFlags::TYPE_1)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .load_op(render_pass_config.depth_load_op)
+            .store_op(depth_store_op)
+            // The stencil is cleared to 0 each frame. It is only used within the render pass,
+            // so there's no need to store it
+            .stencil_load_op(vk::AttachmentLoadOp::CLEAR)
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .initial_layout(depth_initial_layout)
+            .final_layout(depth_final_layout)
             .build();
 
         let depth_attachment_ref = vk::AttachmentReference::builder()
@@ -417,15 +736,21 @@ impl LveSwapchain {
             .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
             .build();
 
+        let color_final_layout = vk::ImageLayout::PRESENT_SRC_KHR;
+        let (color_store_op, color_initial_layout) = RenderPassConfig::attachment_start(
+            render_pass_config.color_load_op,
+            color_final_layout,
+        );
+
         let color_attachment = vk::AttachmentDescription::builder()
             .format(swapchain_image_format)
             .samples(vk::SampleCountFlags::TYPE_1)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .load_op(render_pass_config.color_load_op)
+            .store_op(color_store_op)
             .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .initial_layout(color_initial_layout)
+            .final_layout(color_final_layout)
             .build();
 
         let color_attachment_ref = vk::AttachmentReference::builder()
@@ -440,22 +765,35 @@ impl LveSwapchain {
             .color_attachments(&attachment_refs)
             .depth_stencil_attachment(&depth_attachment_ref);
 
+        let mut src_stage_mask = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS;
+        let mut src_access_mask = vk::AccessFlags::empty();
+        let mut dst_access_mask = vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+
+        // Loading reads the attachments, which mustn't happen until the last frame's writes to
+        // them are done
+        if render_pass_config.color_load_op == vk::AttachmentLoadOp::LOAD {
+            src_access_mask |= vk::AccessFlags::COLOR_ATTACHMENT_WRITE;
+            dst_access_mask |= vk::AccessFlags::COLOR_ATTACHMENT_READ;
+        }
+
+        if render_pass_config.depth_load_op == vk::AttachmentLoadOp::LOAD {
+            src_stage_mask |= vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+            src_access_mask |= vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+            dst_access_mask |= vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ;
+        }
+
         let dependancy = vk::SubpassDependency::builder()
             .src_subpass(vk::SUBPASS_EXTERNAL)
-            .src_access_mask(vk::AccessFlags::empty())
-            .src_stage_mask(
-                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
-                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
-            )
+            .src_access_mask(src_access_mask)
+            .src_stage_mask(src_stage_mask)
             .dst_subpass(0)
             .dst_stage_mask(
                 vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
                     | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
             )
-            .dst_access_mask(
-                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
-                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
-            );
+            .dst_access_mask(dst_access_mask);
 
         let attachments = [color_attachment, depth_attachment];
 
@@ -477,13 +815,13 @@ impl LveSwapchain {
         device: &Device,
         swapchain_extent: vk::Extent2D,
         swapchain_image_views: &Vec<vk::ImageView>,
-        depth_image_views: &Vec<vk::ImageView>,
+        depth_images: &[LveImage],
         render_pass: vk::RenderPass,
     ) -> Vec<vk::Framebuffer> {
         swapchain_image_views
             .iter()
-            .zip(depth_image_views)
-            .map(|view| [*view.0, *view.1])
+            .zip(depth_images)
+            .map(|(view, depth_image)| [*view, depth_image.image_view])
             .map(|attachments| {
                 let frame_buffer_info = vk::FramebufferCreateInfo::builder()
                     .render_pass(render_pass)
@@ -509,7 +847,7 @@ impl LveSwapchain {
         Vec<vk::Semaphore>,
         Vec<vk::Semaphore>,
         Vec<vk::Fence>,
-        Vec<vk::Fence>,
+        ImagesInFlight,
     ) {
         let semaphore_info = vk::SemaphoreCreateInfo::builder();
 
@@ -548,7 +886,7 @@ impl LveSwapchain {
             }
         }
 
-        let images_in_flight = vec![vk::Fence::null(); swapchain_images.len()];
+        let images_in_flight = ImagesInFlight::new(swapchain_images.len());
 
         (
             image_available_semaphores,
@@ -560,7 +898,46 @@ impl LveSwapchain {
 
     fn choose_swap_surface_format(
         available_formats: &Vec<vk::SurfaceFormatKHR>,
+        preference: SurfaceFormatPreference,
     ) -> vk::SurfaceFormatKHR {
+        let hdr_candidates: &[(vk::Format, vk::ColorSpaceKHR)] = match preference {
+            SurfaceFormatPreference::Sdr => &[],
+            SurfaceFormatPreference::Hdr10 => &[
+                (
+                    vk::Format::A2B10G10R10_UNORM_PACK32,
+                    vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+                ),
+                (
+                    vk::Format::A2R10G10B10_UNORM_PACK32,
+                    vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+                ),
+            ],
+            SurfaceFormatPreference::ScRgb => &[(
+                vk::Format::R16G16B16A16_SFLOAT,
+                vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+            )],
+        };
+
+        let hdr_format = hdr_candidates.iter().find_map(|(format, color_space)| {
+            available_formats
+                .iter()
+                .find(|available_format| {
+                    available_format.format == *format
+                        && available_format.color_space == *color_space
+                })
+                .copied()
+        });
+
+        if let Some(format) = hdr_format {
+            log::debug!("Surface format: {:?}", format);
+            return format;
+        } else if preference != SurfaceFormatPreference::Sdr {
+            log::warn!(
+                "Surface does not support the {:?} format, falling back to SDR",
+                preference
+            );
+        }
+
         let format = available_formats
             .iter()
             .map(|f| *f)
@@ -580,25 +957,110 @@ impl LveSwapchain {
         format
     }
 
+    /// The first of `preferred_present_modes` the surface supports. FIFO is always supported,
+    /// so it is the fallback if none of them are
     fn choose_swap_present_mode(
         available_present_modes: &Vec<vk::PresentModeKHR>,
+        preferred_present_modes: &[vk::PresentModeKHR],
     ) -> vk::PresentModeKHR {
-        let present_mode = available_present_modes
+        let present_mode = preferred_present_modes
             .iter()
-            .map(|pm| *pm)
-            // .find(|available_present_mode| *available_present_mode == vk::PresentModeKHR::MAILBOX)
-            // .find(|available_present_mode| *available_present_mode == vk::PresentModeKHR::IMMEDIATE)
-            .find(|available_present_mode| *available_present_mode == vk::PresentModeKHR::FIFO)
+            .copied()
+            .find(|present_mode| available_present_modes.contains(present_mode))
             .unwrap_or_else(|| {
-                log::warn!("Could not find desired present mode, defaulting to FIFO");
+                log::warn!(
+                    "None of the present modes {:?} are supported, defaulting to FIFO",
+                    preferred_present_modes
+                );
                 vk::PresentModeKHR::FIFO
             });
 
-        log::debug!("Present mode: {:?}", present_mode);
+        log::info!("Present mode: {:?}", present_mode);
 
         present_mode
     }
 
+    fn choose_image_count(
+        capabilities: &vk::SurfaceCapabilitiesKHR,
+        image_count: SwapchainImageCount,
+    ) -> u32 {
+        let min_image_count = capabilities.min_image_count;
+
+        let desired_image_count = match image_count {
+            SwapchainImageCount::Minimum => min_image_count,
+            SwapchainImageCount::MinimumPlusOne => min_image_count + 1,
+            SwapchainImageCount::MinimumPlusTwo => min_image_count + 2,
+            SwapchainImageCount::Exact(count) => count,
+        };
+
+        // A max_image_count of 0 means there is no maximum
+        let max_image_count = if capabilities.max_image_count > 0 {
+            capabilities.max_image_count
+        } else {
+            u32::MAX
+        };
+
+        let clamped_image_count = desired_image_count.clamp(min_image_count, max_image_count);
+
+        if clamped_image_count != desired_image_count {
+            log::warn!(
+                "Requested {} swapchain images, but the surface supports {}..={}",
+                desired_image_count,
+                min_image_count,
+                max_image_count
+            );
+        }
+
+        log::debug!("Requesting {} swapchain images", clamped_image_count);
+
+        clamped_image_count
+    }
+
+    /// `COLOR_ATTACHMENT` is always needed to render to the images. Any extra usage (e.g.
+    /// `TRANSFER_SRC` for screenshots or `STORAGE` for compute writing straight to the
+    /// swapchain) the surface doesn't support is dropped with a warning rather than failing
+    fn choose_image_usage(
+        capabilities: &vk::SurfaceCapabilitiesKHR,
+        extra_image_usage: vk::ImageUsageFlags,
+    ) -> vk::ImageUsageFlags {
+        let requested_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | extra_image_usage;
+        let supported_usage = requested_usage & capabilities.supported_usage_flags;
+
+        if supported_usage != requested_usage {
+            log::warn!(
+                "Swapchain image usage {:?} is not supported by the surface, dropping it",
+                requested_usage & !supported_usage
+            );
+        }
+
+        log::debug!("Swapchain image usage: {:?}", supported_usage);
+
+        supported_usage
+    }
+
+    /// Opaque where the surface allows it. Some surfaces (e.g. on Android) only offer inherit,
+    /// leaving it to the window system
+    fn choose_composite_alpha(
+        capabilities: &vk::SurfaceCapabilitiesKHR,
+    ) -> vk::CompositeAlphaFlagsKHR {
+        let preferences = [
+            vk::CompositeAlphaFlagsKHR::OPAQUE,
+            vk::CompositeAlphaFlagsKHR::INHERIT,
+            vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+            vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+        ];
+
+        preferences
+            .iter()
+            .copied()
+            .find(|composite_alpha| {
+                capabilities
+                    .supported_composite_alpha
+                    .contains(*composite_alpha)
+            })
+            .unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE)
+    }
+
     fn choose_swap_extent(
         capabilities: &vk::SurfaceCapabilitiesKHR,
         window_extent: vk::Extent2D,
@@ -631,18 +1093,6 @@ impl Drop for LveSwapchain {
 
             self.swapchain.destroy_swapchain(self.swapchain_khr, None);
 
-            self.depth_image_views
-                .iter()
-                .for_each(|iv| self.lve_device.device.destroy_image_view(*iv, None));
-
-            self.depth_images
-                .iter()
-                .for_each(|i| self.lve_device.device.destroy_image(*i, None));
-
-            self.depth_image_memories
-                .iter()
-                .for_each(|m| self.lve_device.device.free_memory(*m, None));
-
             self.swapchain_framebuffers
                 .iter()
                 .for_each(|f| self.lve_device.device.destroy_framebuffer(*f, None));
@@ -665,3 +1115,70 @@ impl Drop for LveSwapchain {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ash::vk::Handle;
+
+    #[test]
+    fn pre_rotation_turns_clip_space_clockwise() {
+        let right = na::vector![1.0, 0.0, 0.5, 1.0];
+
+        let rotated = |transform| LveSwapchain::pre_rotation_matrix(transform) * right;
+
+        // Clockwise with y down takes right to down, then left, then up
+        let expected = [
+            (vk::SurfaceTransformFlagsKHR::IDENTITY, [1.0, 0.0]),
+            (vk::SurfaceTransformFlagsKHR::ROTATE_90, [0.0, 1.0]),
+            (vk::SurfaceTransformFlagsKHR::ROTATE_180, [-1.0, 0.0]),
+            (vk::SurfaceTransformFlagsKHR::ROTATE_270, [0.0, -1.0]),
+        ];
+
+        for (transform, [x, y]) in expected {
+            let point = rotated(transform);
+            assert!((point - na::vector![x, y, 0.5, 1.0]).norm() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn more_images_than_frames_in_flight_stay_in_bounds() {
+        let frame_fences: Vec<vk::Fence> = (1..=MAX_FRAMES_IN_FLIGHT as u64)
+            .map(vk::Fence::from_raw)
+            .collect();
+
+        for image_count in 3..=5 {
+            let mut images_in_flight = ImagesInFlight::new(image_count);
+            let mut frame_index = 0;
+
+            for frame in 0..image_count * 4 {
+                // Drivers don't have to hand images out in order
+                let image_index = (frame * 7 + 3) % image_count;
+
+                images_in_flight.fence(image_index);
+                images_in_flight.set_fence(image_index, frame_fences[frame_index]);
+
+                assert_eq!(
+                    images_in_flight.fence(image_index),
+                    Some(frame_fences[frame_index])
+                );
+
+                frame_index = next_frame_index(frame_index);
+            }
+        }
+    }
+
+    #[test]
+    fn unused_images_have_no_fence() {
+        let images_in_flight = ImagesInFlight::new(3);
+
+        assert_eq!(images_in_flight.fence(2), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn image_index_past_the_image_count_panics() {
+        ImagesInFlight::new(3).fence(3);
+    }
+}
@@ -0,0 +1,551 @@
+use super::lve_device::*;
+
+use ash::vk;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// How the texture's 8-bit RGBA pixels are interpreted when sampled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFormat {
+    /// Colour data such as albedo, decoded from sRGB to linear by the sampler
+    Srgb,
+    /// Data such as normal or roughness maps, which must be sampled as stored
+    Linear,
+}
+
+impl TextureFormat {
+    pub fn vk_format(self) -> vk::Format {
+        match self {
+            TextureFormat::Srgb => vk::Format::R8G8B8A8_SRGB,
+            TextureFormat::Linear => vk::Format::R8G8B8A8_UNORM,
+        }
+    }
+}
+
+/// How a texture is sampled. The sampler is created from this when the texture is built and
+/// is owned (and destroyed) by the texture
+#[derive(Debug, Clone, Copy)]
+pub struct SamplerConfig {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub address_mode: vk::SamplerAddressMode,
+    /// `None` disables anisotropic filtering, otherwise clamped to the device limit. Ignored
+    /// if the device was created without the `sampler_anisotropy` feature
+    pub max_anisotropy: Option<f32>,
+    /// Blends between the two nearest mip levels (trilinear filtering) rather than using the
+    /// nearest one (bilinear), which is a little cheaper but shows lines where the level changes
+    pub trilinear: bool,
+    /// Added to the mip level the GPU picks, negative for sharper textures and positive for
+    /// blurrier but cheaper ones. Clamped to the device limit
+    pub mip_lod_bias: f32,
+    pub max_lod: f32,
+}
+
+impl Default for SamplerConfig {
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            address_mode: vk::SamplerAddressMode::REPEAT,
+            max_anisotropy: Some(16.0),
+            trilinear: true,
+            mip_lod_bias: 0.0,
+            max_lod: vk::LOD_CLAMP_NONE,
+        }
+    }
+}
+
+impl SamplerConfig {
+    /// Nearest filtering with no mipmapping, for UI and pixel art
+    #[allow(dead_code)]
+    pub fn nearest() -> Self {
+        Self {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            max_anisotropy: None,
+            trilinear: false,
+            mip_lod_bias: 0.0,
+            max_lod: 0.0,
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub struct LveTexture {
+    lve_device: Rc<LveDevice>,
+    pub image: vk::Image,
+    image_memory: vk::DeviceMemory,
+    pub image_view: vk::ImageView,
+    pub sampler: vk::Sampler,
+    pub image_layout: vk::ImageLayout,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+}
+
+impl LveTexture {
+    /// Creates a texture from tightly packed RGBA8 pixel data. The image is left in
+    /// `SHADER_READ_ONLY_OPTIMAL` so it can be bound straight away
+    pub fn from_pixels(
+        lve_device: Rc<LveDevice>,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        sampler_config: SamplerConfig,
+    ) -> Rc<Self> {
+        Self::from_layers(
+            lve_device,
+            pixels,
+            width,
+            height,
+            1,
+            format.vk_format(),
+            sampler_config,
+        )
+    }
+
+    /// Creates a cube map from six square RGBA8 faces, in the order +X, -X, +Y, -Y, +Z, -Z.
+    /// Sample it with a direction vector through a `samplerCube`
+    pub fn cubemap_from_pixels(
+        lve_device: Rc<LveDevice>,
+        faces: [&[u8]; 6],
+        size: u32,
+        format: TextureFormat,
+        sampler_config: SamplerConfig,
+    ) -> Rc<Self> {
+        // The faces are uploaded as consecutive array layers
+        let pixels = faces.concat();
+
+        Self::from_layers(
+            lve_device,
+            &pixels,
+            size,
+            size,
+            6,
+            format.vk_format(),
+            sampler_config,
+        )
+    }
+
+    /// Loads the six faces of a cube map from disk, in the order +X, -X, +Y, -Y, +Z, -Z
+    #[allow(dead_code)]
+    pub fn cubemap_from_files(
+        lve_device: Rc<LveDevice>,
+        file_paths: [&str; 6],
+        format: TextureFormat,
+        sampler_config: SamplerConfig,
+    ) -> Rc<Self> {
+        let faces: Vec<image::RgbaImage> = file_paths
+            .iter()
+            .map(|file_path| {
+                image::open(file_path)
+                    .map_err(|e| log::error!("Unable to load cube map face {}: {}", file_path, e))
+                    .unwrap()
+                    .to_rgba8()
+            })
+            .collect();
+
+        let (size, height) = faces[0].dimensions();
+
+        assert!(
+            size == height && faces.iter().all(|face| face.dimensions() == (size, size)),
+            "Cube map faces must all be square and the same size"
+        );
+
+        log::info!("Loaded cube map {} ({}x{})", file_paths[0], size, size);
+
+        let faces: Vec<&[u8]> = faces.iter().map(|face| face.as_raw().as_slice()).collect();
+
+        Self::cubemap_from_pixels(
+            lve_device,
+            [faces[0], faces[1], faces[2], faces[3], faces[4], faces[5]],
+            size,
+            format,
+            sampler_config,
+        )
+    }
+
+    /// Uploads `layer_count` tightly packed RGBA8 layers. Six layers make a cube map
+    fn from_layers(
+        lve_device: Rc<LveDevice>,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        layer_count: u32,
+        format: vk::Format,
+        sampler_config: SamplerConfig,
+    ) -> Rc<Self> {
+        assert_eq!(
+            pixels.len(),
+            (width * height * layer_count * 4) as usize,
+            "Pixel data must be RGBA8 and match the texture extent"
+        );
+
+        let is_cubemap = layer_count == 6;
+
+        let extent = vk::Extent2D { width, height };
+
+        let (image, image_memory) = Self::create_image(
+            &lve_device,
+            extent,
+            format,
+            layer_count,
+            is_cubemap,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+        );
+
+        // The copy moves the image to TRANSFER_DST_OPTIMAL itself, on the transfer queue
+        lve_device.upload_via_staging(pixels, |staging_buffer| {
+            lve_device.copy_buffer_to_image(staging_buffer, image, width, height, layer_count)
+        });
+        lve_device.transition_image_layout(
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            layer_count,
+        );
+
+        let image_view =
+            Self::create_image_view(&lve_device, image, format, layer_count, is_cubemap);
+
+        let sampler = Self::create_sampler(&lve_device, &sampler_config);
+
+        Rc::new(Self {
+            lve_device,
+            image,
+            image_memory,
+            image_view,
+            sampler,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            format,
+            extent,
+        })
+    }
+
+    /// Loads an image file from disk, converting it to RGBA8
+    pub fn from_file(
+        lve_device: Rc<LveDevice>,
+        file_path: &str,
+        format: TextureFormat,
+        sampler_config: SamplerConfig,
+    ) -> Rc<Self> {
+        let image = image::open(file_path)
+            .map_err(|e| log::error!("Unable to load texture {}: {}", file_path, e))
+            .unwrap()
+            .to_rgba8();
+
+        let (width, height) = image.dimensions();
+
+        log::info!("Loaded texture {} ({}x{})", file_path, width, height);
+
+        Self::from_pixels(
+            lve_device,
+            image.as_raw(),
+            width,
+            height,
+            format,
+            sampler_config,
+        )
+    }
+
+    /// Creates a 1x1 texture filled with a single RGBA8 colour
+    pub fn from_color(
+        lve_device: Rc<LveDevice>,
+        color: [u8; 4],
+        format: TextureFormat,
+    ) -> Rc<Self> {
+        Self::from_pixels(lve_device, &color, 1, 1, format, SamplerConfig::default())
+    }
+
+    /// Creates a texture compute shaders can write to, e.g. a procedural texture, that can
+    /// also be sampled. Its contents start out undefined. `format` must support storage
+    /// images, which the sRGB formats usually don't, e.g. `R8G8B8A8_UNORM` or
+    /// `R16G16B16A16_SFLOAT`.
+    ///
+    /// The texture stays in the `GENERAL` layout, which both storage writes and sampling
+    /// allow, so no layout transitions are needed. Its accesses still have to be ordered by
+    /// the caller with memory barriers:
+    /// - after a dispatch writes it and before a draw samples it, from `COMPUTE_SHADER` /
+    ///   `SHADER_WRITE` to `FRAGMENT_SHADER` / `SHADER_READ`
+    /// - after a draw samples it and before the next dispatch writes it, from
+    ///   `FRAGMENT_SHADER` to `COMPUTE_SHADER`, with no access masks as there is nothing to
+    ///   make visible
+    #[allow(dead_code)]
+    pub fn storage(
+        lve_device: Rc<LveDevice>,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+        sampler_config: SamplerConfig,
+    ) -> Rc<Self> {
+        let format_features = lve_device
+            .get_format_properties(format)
+            .optimal_tiling_features;
+
+        assert!(
+            format_features.contains(vk::FormatFeatureFlags::STORAGE_IMAGE),
+            "{:?} can't be used for storage images",
+            format
+        );
+
+        let extent = vk::Extent2D { width, height };
+
+        let (image, image_memory) = Self::create_image(
+            &lve_device,
+            extent,
+            format,
+            1,
+            false,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        );
+
+        lve_device.transition_image_layout(
+            image,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::GENERAL,
+            1,
+        );
+
+        let image_view = Self::create_image_view(&lve_device, image, format, 1, false);
+
+        let sampler = Self::create_sampler(&lve_device, &sampler_config);
+
+        Rc::new(Self {
+            lve_device,
+            image,
+            image_memory,
+            image_view,
+            sampler,
+            image_layout: vk::ImageLayout::GENERAL,
+            format,
+            extent,
+        })
+    }
+
+    /// For binding a texture made by `storage` to a compute shader's storage image, with
+    /// `LveDescriptorWriter::write_storage_image`
+    #[allow(dead_code)]
+    pub fn storage_descriptor_info(&self) -> vk::DescriptorImageInfo {
+        assert_eq!(
+            self.image_layout,
+            vk::ImageLayout::GENERAL,
+            "Only textures made by LveTexture::storage are storage images"
+        );
+
+        vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: self.image_view,
+            image_layout: vk::ImageLayout::GENERAL,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn descriptor_info(&self) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo {
+            sampler: self.sampler,
+            image_view: self.image_view,
+            image_layout: self.image_layout,
+        }
+    }
+
+    fn create_image(
+        lve_device: &Rc<LveDevice>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        layer_count: u32,
+        is_cubemap: bool,
+        usage: vk::ImageUsageFlags,
+    ) -> (vk::Image, vk::DeviceMemory) {
+        let flags = if is_cubemap {
+            vk::ImageCreateFlags::CUBE_COMPATIBLE
+        } else {
+            vk::ImageCreateFlags::empty()
+        };
+
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(layer_count)
+            .format(format)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(usage)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .flags(flags);
+
+        lve_device.create_image_with_info(&image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL)
+    }
+
+    fn create_image_view(
+        lve_device: &Rc<LveDevice>,
+        image: vk::Image,
+        format: vk::Format,
+        layer_count: u32,
+        is_cubemap: bool,
+    ) -> vk::ImageView {
+        let view_type = if is_cubemap {
+            vk::ImageViewType::CUBE
+        } else {
+            vk::ImageViewType::TYPE_2D
+        };
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(view_type)
+            .format(format)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count,
+            });
+
+        unsafe {
+            lve_device
+                .device
+                .create_image_view(&view_info, None)
+                .map_err(|e| log::error!("Unable to create texture image view: {}", e))
+                .unwrap()
+        }
+    }
+
+    fn create_sampler(lve_device: &Rc<LveDevice>, config: &SamplerConfig) -> vk::Sampler {
+        // Anisotropy can only be used if the device was created with the feature enabled
+        let max_anisotropy = config
+            .max_anisotropy
+            .filter(|_| lve_device.enabled_features.sampler_anisotropy)
+            .map(|anisotropy| {
+                anisotropy.clamp(1.0, lve_device.properties.limits.max_sampler_anisotropy)
+            });
+
+        let max_lod_bias = lve_device.properties.limits.max_sampler_lod_bias;
+        let mip_lod_bias = config.mip_lod_bias.clamp(-max_lod_bias, max_lod_bias);
+
+        let mipmap_mode = if config.trilinear {
+            vk::SamplerMipmapMode::LINEAR
+        } else {
+            vk::SamplerMipmapMode::NEAREST
+        };
+
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(config.mag_filter)
+            .min_filter(config.min_filter)
+            .address_mode_u(config.address_mode)
+            .address_mode_v(config.address_mode)
+            .address_mode_w(config.address_mode)
+            .anisotropy_enable(max_anisotropy.is_some())
+            .max_anisotropy(max_anisotropy.unwrap_or(1.0))
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .mipmap_mode(mipmap_mode)
+            .mip_lod_bias(mip_lod_bias)
+            .min_lod(0.0)
+            .max_lod(config.max_lod);
+
+        unsafe {
+            lve_device
+                .device
+                .create_sampler(&sampler_info, None)
+                .map_err(|e| log::error!("Unable to create texture sampler: {}", e))
+                .unwrap()
+        }
+    }
+}
+
+impl Drop for LveTexture {
+    fn drop(&mut self) {
+        log::debug!("Dropping Texture");
+        unsafe {
+            self.lve_device.device.destroy_sampler(self.sampler, None);
+            self.lve_device
+                .device
+                .destroy_image_view(self.image_view, None);
+            self.lve_device.device.destroy_image(self.image, None);
+            self.lve_device.device.free_memory(self.image_memory, None);
+        }
+    }
+}
+
+/// Lazily created fallback textures, so a material without an explicit texture
+/// still has something valid to bind. These can't live on the `LveDevice` itself
+/// as the textures hold an `Rc` back to the device
+#[allow(dead_code)]
+pub struct LveDefaultTextures {
+    lve_device: Rc<LveDevice>,
+    albedo: RefCell<Option<Rc<LveTexture>>>,
+    normal: RefCell<Option<Rc<LveTexture>>>,
+    environment: RefCell<Option<Rc<LveTexture>>>,
+}
+
+impl LveDefaultTextures {
+    pub fn new(lve_device: Rc<LveDevice>) -> Self {
+        Self {
+            lve_device,
+            albedo: RefCell::new(None),
+            normal: RefCell::new(None),
+            environment: RefCell::new(None),
+        }
+    }
+
+    /// 1x1 white texture
+    #[allow(dead_code)]
+    pub fn default_albedo(&self) -> Rc<LveTexture> {
+        Rc::clone(self.albedo.borrow_mut().get_or_insert_with(|| {
+            log::debug!("Creating default albedo texture");
+            LveTexture::from_color(
+                Rc::clone(&self.lve_device),
+                [255, 255, 255, 255],
+                TextureFormat::Srgb,
+            )
+        }))
+    }
+
+    /// 1x1 flat tangent space normal, pointing straight out of the surface
+    #[allow(dead_code)]
+    pub fn default_normal(&self) -> Rc<LveTexture> {
+        Rc::clone(self.normal.borrow_mut().get_or_insert_with(|| {
+            log::debug!("Creating default normal texture");
+            LveTexture::from_color(
+                Rc::clone(&self.lve_device),
+                [128, 128, 255, 255],
+                TextureFormat::Linear,
+            )
+        }))
+    }
+    /// 1x1 per face cube map of a plain sky over a grey ground, for reflective materials when
+    /// no environment has been loaded. Up is -Y, matching the engine's coordinate system
+    pub fn default_environment(&self) -> Rc<LveTexture> {
+        Rc::clone(self.environment.borrow_mut().get_or_insert_with(|| {
+            log::debug!("Creating default environment cube map");
+            let horizon = [170, 200, 225, 255];
+            LveTexture::cubemap_from_pixels(
+                Rc::clone(&self.lve_device),
+                [
+                    &horizon,
+                    &horizon,
+                    &[90, 90, 90, 255],
+                    &[110, 160, 220, 255],
+                    &horizon,
+                    &horizon,
+                ],
+                1,
+                TextureFormat::Srgb,
+                SamplerConfig {
+                    address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    ..Default::default()
+                },
+            )
+        }))
+    }
+}
@@ -0,0 +1,261 @@
+use super::lve_buffer::DynamicUboBuffer;
+use super::lve_descriptors::*;
+use super::lve_device::*;
+use super::lve_frameinfo::FrameInfo;
+use super::lve_game_object::MaterialComponent;
+use super::lve_pipeline::*;
+use super::lve_swapchain::MAX_FRAMES_IN_FLIGHT;
+use super::render_system::RenderSystem;
+use super::simple_render_system::{SimplePushConstantData, USE_VERTEX_COLOR_CONSTANT_ID};
+
+use ash::{vk, Device};
+
+use std::mem::size_of;
+use std::rc::Rc;
+
+extern crate nalgebra as na;
+
+/// Objects with a material drawn per frame, drawing more panics
+const MAX_MATERIAL_OBJECTS: u32 = 256;
+
+/// Laid out to match the std140 `MaterialUbo` block in material_shader.frag
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct MaterialUbo {
+    albedo: na::Vector4<f32>, // w is ignored
+    roughness: f32,
+    metallic: f32,
+    _padding: [f32; 2],
+}
+
+const _: () = assert!(
+    size_of::<MaterialUbo>() == 32,
+    "MaterialUbo does not match the std140 layout of the shader's MaterialUbo"
+);
+
+impl From<&MaterialComponent> for MaterialUbo {
+    fn from(material: &MaterialComponent) -> Self {
+        Self {
+            albedo: material.albedo.push(1.0),
+            roughness: material.roughness,
+            metallic: material.metallic,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Draws the objects with a `MaterialComponent`, reading each one's material from a dynamic
+/// uniform buffer bound with the object's offset. Objects sharing a mesh can look different
+/// without copying the geometry. The matrices are still push constants, as for the simple
+/// render system
+pub struct MaterialRenderSystem {
+    lve_device: Rc<LveDevice>,
+    lve_pipeline: LvePipeline,
+    pipeline_layout: vk::PipelineLayout,
+    push_constant_layout: PushConstantLayout,
+    /// Kept alive for the descriptor set
+    _material_set_layout: Rc<LveDescriptorSetLayout>,
+    material_buffer: DynamicUboBuffer<MaterialUbo>,
+    /// One set for every frame, the dynamic offset picks the frame's slots too
+    material_descriptor_set: vk::DescriptorSet,
+}
+
+impl MaterialRenderSystem {
+    pub fn new(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        global_set_layout: vk::DescriptorSetLayout,
+        descriptor_pools: &mut LveDescriptorPoolManager,
+        depth_prepass: bool,
+        use_vertex_color: bool,
+    ) -> Self {
+        let material_set_layout = LveDescriptorSetLayoutBuilder::new(Rc::clone(&lve_device))
+            .add_binding(
+                0,
+                vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                vk::ShaderStageFlags::FRAGMENT,
+                1,
+            )
+            .build();
+
+        let material_buffer = DynamicUboBuffer::new(
+            Rc::clone(&lve_device),
+            MAX_FRAMES_IN_FLIGHT,
+            MAX_MATERIAL_OBJECTS,
+        );
+
+        let material_descriptor_set = LveDescriptorWriter::new(
+            Rc::clone(&material_set_layout),
+            descriptor_pools.current_pool(),
+        )
+        .write_buffer(0, &[*material_buffer.descriptor_info()])
+        .build_from(descriptor_pools);
+
+        // The material takes the place of the color in the fragment push constants
+        let push_constant_layout = SimplePushConstantData::vertex_push_constant_layout(&lve_device);
+
+        let pipeline_layout = Self::create_pipeline_layout(
+            &lve_device.device,
+            &[global_set_layout, material_set_layout.descriptor_set_layout],
+            &push_constant_layout,
+        );
+
+        let lve_pipeline = Self::create_pipeline(
+            Rc::clone(&lve_device),
+            render_pass,
+            &pipeline_layout,
+            depth_prepass,
+            use_vertex_color,
+        );
+
+        Self {
+            lve_device,
+            lve_pipeline,
+            pipeline_layout,
+            push_constant_layout,
+            _material_set_layout: material_set_layout,
+            material_buffer,
+            material_descriptor_set,
+        }
+    }
+
+    fn create_pipeline(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        pipeline_layout: &vk::PipelineLayout,
+        depth_prepass: bool,
+        use_vertex_color: bool,
+    ) -> LvePipeline {
+        assert!(
+            pipeline_layout != &vk::PipelineLayout::null(),
+            "Cannot create pipeline before pipeline layout"
+        );
+
+        let mut pipeline_config = LvePipeline::default_pipline_config_info();
+
+        // The same as the simple render system's lit pipeline
+        if depth_prepass {
+            pipeline_config.depth_stencil_info.depth_compare_op = vk::CompareOp::EQUAL;
+            LvePipeline::disable_depth_write(&mut pipeline_config);
+        }
+
+        LvePipeline::add_specialization_constant(
+            &mut pipeline_config,
+            USE_VERTEX_COLOR_CONSTANT_ID,
+            use_vertex_color,
+        );
+
+        LvePipeline::new(
+            lve_device,
+            "shaders/simple_shader.vert.spv",
+            "shaders/material_shader.frag.spv",
+            pipeline_config,
+            render_pass,
+            pipeline_layout,
+        )
+    }
+
+    fn create_pipeline_layout(
+        device: &Device,
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_constant_layout: &PushConstantLayout,
+    ) -> vk::PipelineLayout {
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(set_layouts)
+            .push_constant_ranges(push_constant_layout.ranges())
+            .build();
+
+        unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .map_err(|e| log::error!("Unable to create material pipeline layout: {}", e))
+                .unwrap()
+        }
+    }
+}
+
+impl RenderSystem for MaterialRenderSystem {
+    fn render(&mut self, frame_info: &FrameInfo) {
+        // Skinned objects are posed by the skinned render system, which uses the object color
+        let mut game_objects: Vec<_> = frame_info
+            .game_objects
+            .iter()
+            .filter(|(_, game_obj)| {
+                game_obj.visible && game_obj.material.is_some() && game_obj.skin.is_none()
+            })
+            .collect();
+
+        if game_objects.is_empty() {
+            return;
+        }
+
+        // The objects are stored in a hash map, so sort them to keep the draw order steady
+        game_objects.sort_by_key(|(id, _)| **id);
+
+        let frame_index = frame_info.frame_index as usize;
+
+        unsafe {
+            self.lve_pipeline
+                .bind(&self.lve_device.device, frame_info.command_buffer);
+
+            self.lve_device.device.cmd_bind_descriptor_sets(
+                frame_info.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[frame_info.global_descriptor_set],
+                &[],
+            );
+        }
+        frame_info.record_pipeline_bind();
+
+        for (object_index, (_, game_obj)) in game_objects.into_iter().enumerate() {
+            let object_index = object_index as u32;
+            let material = MaterialUbo::from(game_obj.material.as_ref().unwrap());
+
+            self.material_buffer
+                .write(frame_index, object_index, &material);
+
+            let push = SimplePushConstantData::from_game_object(game_obj);
+
+            unsafe {
+                self.lve_device.device.cmd_bind_descriptor_sets(
+                    frame_info.command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    1,
+                    &[self.material_descriptor_set],
+                    &[self
+                        .material_buffer
+                        .dynamic_offset(frame_index, object_index)],
+                );
+
+                self.push_constant_layout.push(
+                    &self.lve_device.device,
+                    frame_info.command_buffer,
+                    self.pipeline_layout,
+                    push.as_bytes(),
+                );
+
+                let model = game_obj.model_at(frame_info.camera.position());
+                model.bind(&self.lve_device.device, frame_info.command_buffer);
+                model.draw(&self.lve_device.device, frame_info.command_buffer);
+                frame_info.record_draw(model.triangle_count());
+            }
+        }
+
+        self.material_buffer.flush_frame(frame_index);
+    }
+}
+
+impl Drop for MaterialRenderSystem {
+    fn drop(&mut self) {
+        log::debug!("Dropping MaterialRenderSystem");
+
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
@@ -0,0 +1,436 @@
+use super::lve_model::{ModelData, Vertex};
+
+use ordered_float::OrderedFloat;
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+extern crate nalgebra as na;
+
+/// `ModelData::simplify` stops removing triangles once there are this few left
+const MIN_SIMPLIFIED_TRIANGLES: usize = 16;
+
+/// The squared distance to a set of planes, weighted by the area of the triangle each plane
+/// came from (Garland and Heckbert's error quadric)
+type Quadric = na::Matrix4<f64>;
+
+/// A candidate collapse of `from` into `to`, invalid once `from`'s version has moved on
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Collapse {
+    cost: Reverse<OrderedFloat<f64>>,
+    from: u32,
+    to: u32,
+    version: u32,
+}
+
+impl ModelData {
+    /// A copy with about `target_ratio` of the triangles, for generating LOD levels. Edges are
+    /// collapsed cheapest first by quadric error, i.e. how far the surface moves, until the
+    /// target or `MIN_SIMPLIFIED_TRIANGLES` is reached.
+    ///
+    /// Each collapse moves one vertex onto a neighbour, so the vertices that are left keep their
+    /// exact normals, colors and texture coordinates. Vertices on the mesh's open edges and on
+    /// seams (where vertices share a position but not the other attributes) never move, which
+    /// keeps the outline and texture mapping intact but limits how far those meshes go. A
+    /// collapse that would flip a triangle over is skipped. Flat shaded and non-indexed meshes
+    /// have a seam at every vertex, so they come back unchanged
+    pub fn simplify(&self, target_ratio: f32) -> ModelData {
+        let indices = match &self.indices {
+            Some(indices) => indices,
+            None => {
+                log::debug!("Not simplifying a non-indexed mesh");
+                return ModelData {
+                    vertices: self.vertices.clone(),
+                    indices: None,
+                };
+            }
+        };
+
+        let triangle_count = indices.len() / 3;
+        let target_triangles = ((triangle_count as f32 * target_ratio.clamp(0.0, 1.0)) as usize)
+            .max(MIN_SIMPLIFIED_TRIANGLES);
+
+        let mut simplifier = Simplifier::new(&self.vertices, indices);
+        simplifier.collapse_until(target_triangles);
+
+        let simplified = simplifier.into_model_data();
+
+        log::debug!(
+            "Simplified {} triangles to {} (target {})",
+            triangle_count,
+            simplified
+                .indices
+                .as_ref()
+                .map_or(0, |indices| indices.len() / 3),
+            target_triangles
+        );
+
+        simplified
+    }
+}
+
+struct Simplifier<'a> {
+    vertices: &'a [Vertex],
+    triangles: Vec<[u32; 3]>,
+    removed: Vec<bool>,
+    live_triangles: usize,
+    /// The triangles using each vertex, including some that have since been removed
+    vertex_triangles: Vec<Vec<usize>>,
+    /// Vertices that share a position are welded into one for the quadrics
+    position_ids: Vec<usize>,
+    quadrics: Vec<Quadric>,
+    /// Seam and open edge vertices, which are never moved
+    locked: Vec<bool>,
+    versions: Vec<u32>,
+    queue: BinaryHeap<Collapse>,
+}
+
+impl<'a> Simplifier<'a> {
+    fn new(vertices: &'a [Vertex], indices: &[u32]) -> Self {
+        let triangles: Vec<[u32; 3]> = indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect();
+
+        let mut vertex_triangles = vec![Vec::new(); vertices.len()];
+
+        for (triangle_index, triangle) in triangles.iter().enumerate() {
+            for vertex in triangle {
+                vertex_triangles[*vertex as usize].push(triangle_index);
+            }
+        }
+
+        let mut position_lookup = HashMap::new();
+        let mut position_vertex_counts = Vec::new();
+
+        let position_ids: Vec<usize> = vertices
+            .iter()
+            .map(|vertex| {
+                let next_id = position_lookup.len();
+                let id = *position_lookup.entry(vertex.position).or_insert(next_id);
+
+                if id == position_vertex_counts.len() {
+                    position_vertex_counts.push(0);
+                }
+                position_vertex_counts[id] += 1;
+
+                id
+            })
+            .collect();
+
+        let mut quadrics = vec![Quadric::zeros(); position_vertex_counts.len()];
+
+        // An edge only one triangle uses is on an open edge of the mesh. Edges are compared by
+        // position so seams don't look open
+        let mut edge_uses: HashMap<(usize, usize), u32> = HashMap::new();
+
+        for triangle in &triangles {
+            let [a, b, c] = triangle.map(|vertex| position(&vertices[vertex as usize]));
+
+            let normal = (b - a).cross(&(c - a));
+            let double_area = normal.norm();
+
+            if double_area > f64::EPSILON {
+                let normal = normal / double_area;
+                let plane = na::vector![normal[0], normal[1], normal[2], -normal.dot(&a)];
+                let quadric = plane * plane.transpose() * (double_area / 2.0);
+
+                for vertex in triangle {
+                    quadrics[position_ids[*vertex as usize]] += quadric;
+                }
+            }
+
+            for edge in 0..3 {
+                let from = position_ids[triangle[edge] as usize];
+                let to = position_ids[triangle[(edge + 1) % 3] as usize];
+
+                *edge_uses.entry((from.min(to), from.max(to))).or_insert(0) += 1;
+            }
+        }
+
+        let mut locked_positions: Vec<bool> = position_vertex_counts
+            .iter()
+            .map(|count| *count > 1)
+            .collect();
+
+        for ((from, to), uses) in edge_uses {
+            if uses == 1 {
+                locked_positions[from] = true;
+                locked_positions[to] = true;
+            }
+        }
+
+        let locked = position_ids
+            .iter()
+            .map(|id| locked_positions[*id])
+            .collect();
+
+        let mut simplifier = Self {
+            vertices,
+            removed: vec![false; triangles.len()],
+            live_triangles: triangles.len(),
+            triangles,
+            vertex_triangles,
+            position_ids,
+            quadrics,
+            locked,
+            versions: vec![0; vertices.len()],
+            queue: BinaryHeap::new(),
+        };
+
+        for vertex in 0..vertices.len() as u32 {
+            simplifier.queue_best_collapse(vertex);
+        }
+
+        simplifier
+    }
+
+    fn collapse_until(&mut self, target_triangles: usize) {
+        while self.live_triangles > target_triangles {
+            let collapse = match self.queue.pop() {
+                Some(collapse) => collapse,
+                None => break, // Nothing left that can move
+            };
+
+            if collapse.version != self.versions[collapse.from as usize] {
+                continue; // The neighbourhood has changed since it was queued
+            }
+
+            self.collapse(collapse.from, collapse.to);
+        }
+    }
+
+    /// Moves `from` onto `to`, removing the triangles that used both
+    fn collapse(&mut self, from: u32, to: u32) {
+        let from_triangles = std::mem::take(&mut self.vertex_triangles[from as usize]);
+
+        for triangle_index in from_triangles {
+            if self.removed[triangle_index] {
+                continue;
+            }
+
+            let triangle = &mut self.triangles[triangle_index];
+
+            if triangle.contains(&to) {
+                self.removed[triangle_index] = true;
+                self.live_triangles -= 1;
+            } else {
+                for vertex in triangle.iter_mut() {
+                    if *vertex == from {
+                        *vertex = to;
+                    }
+                }
+
+                self.vertex_triangles[to as usize].push(triangle_index);
+            }
+        }
+
+        let from_quadric = self.quadrics[self.position_ids[from as usize]];
+        self.quadrics[self.position_ids[to as usize]] += from_quadric;
+
+        // `from` is gone, and every collapse around `to` now has different triangles to check
+        self.versions[from as usize] += 1;
+
+        let mut neighbours = self.neighbours(to);
+        neighbours.push(to);
+
+        for vertex in neighbours {
+            self.versions[vertex as usize] += 1;
+            self.queue_best_collapse(vertex);
+        }
+    }
+
+    /// Queues the cheapest collapse of `vertex` onto one of its neighbours that doesn't flip a
+    /// triangle, if it can move at all
+    fn queue_best_collapse(&mut self, vertex: u32) {
+        if self.locked[vertex as usize] {
+            return;
+        }
+
+        let best = self
+            .neighbours(vertex)
+            .into_iter()
+            .filter(|to| !self.flips_triangles(vertex, *to))
+            .map(|to| (self.collapse_cost(vertex, to), to))
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+
+        if let Some((cost, to)) = best {
+            self.queue.push(Collapse {
+                cost: Reverse(OrderedFloat(cost)),
+                from: vertex,
+                to,
+                version: self.versions[vertex as usize],
+            });
+        }
+    }
+
+    fn collapse_cost(&self, from: u32, to: u32) -> f64 {
+        let quadric = self.quadrics[self.position_ids[from as usize]]
+            + self.quadrics[self.position_ids[to as usize]];
+        let position = position(&self.vertices[to as usize]).push(1.0);
+
+        (position.transpose() * quadric * position)[0].max(0.0)
+    }
+
+    /// Whether moving `from` onto `to` turns any of `from`'s remaining triangles over or
+    /// squashes one flat
+    fn flips_triangles(&self, from: u32, to: u32) -> bool {
+        let to_position = position(&self.vertices[to as usize]);
+
+        self.live_triangles_of(from)
+            .filter(|triangle| !triangle.contains(&to))
+            .any(|triangle| {
+                let [a, b, c] = triangle.map(|vertex| position(&self.vertices[vertex as usize]));
+                let [new_a, new_b, new_c] = triangle.map(|vertex| match vertex == from {
+                    true => to_position,
+                    false => position(&self.vertices[vertex as usize]),
+                });
+
+                let old_normal = (b - a).cross(&(c - a));
+                let new_normal = (new_b - new_a).cross(&(new_c - new_a));
+
+                new_normal.norm_squared() <= f64::EPSILON || old_normal.dot(&new_normal) <= 0.0
+            })
+    }
+
+    fn live_triangles_of(&self, vertex: u32) -> impl Iterator<Item = [u32; 3]> + '_ {
+        self.vertex_triangles[vertex as usize]
+            .iter()
+            .filter(move |triangle_index| !self.removed[**triangle_index])
+            .map(move |triangle_index| self.triangles[*triangle_index])
+    }
+
+    fn neighbours(&self, vertex: u32) -> Vec<u32> {
+        let mut neighbours: Vec<u32> = self
+            .live_triangles_of(vertex)
+            .flatten()
+            .filter(|neighbour| *neighbour != vertex)
+            .collect();
+
+        neighbours.sort_unstable();
+        neighbours.dedup();
+
+        neighbours
+    }
+
+    /// The triangles that are left, with the vertices nothing uses any more dropped
+    fn into_model_data(self) -> ModelData {
+        let mut remap = vec![u32::MAX; self.vertices.len()];
+        let mut vertices = Vec::new();
+
+        let indices = self
+            .triangles
+            .iter()
+            .zip(&self.removed)
+            .filter(|(_, removed)| !**removed)
+            .flat_map(|(triangle, _)| *triangle)
+            .map(|vertex| {
+                if remap[vertex as usize] == u32::MAX {
+                    remap[vertex as usize] = vertices.len() as u32;
+                    vertices.push(self.vertices[vertex as usize]);
+                }
+
+                remap[vertex as usize]
+            })
+            .collect();
+
+        ModelData {
+            vertices,
+            indices: Some(indices),
+        }
+    }
+}
+
+fn position(vertex: &Vertex) -> na::Vector3<f64> {
+    vertex
+        .position
+        .map(|component| component.into_inner() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A flat `size` by `size` grid of quads facing +z, with the texture stretched over it
+    fn grid(size: u32) -> ModelData {
+        let mut vertices = Vec::new();
+
+        for y in 0..=size {
+            for x in 0..=size {
+                let (u, v) = (x as f32 / size as f32, y as f32 / size as f32);
+
+                vertices.push(Vertex {
+                    position: na::vector![u, v, 0.0].map(OrderedFloat),
+                    color: na::Vector3::repeat(OrderedFloat(1.0)),
+                    normal: na::vector![0.0, 0.0, 1.0].map(OrderedFloat),
+                    uv: na::vector![u, v].map(OrderedFloat),
+                });
+            }
+        }
+
+        let mut indices = Vec::new();
+        let index = |x: u32, y: u32| y * (size + 1) + x;
+
+        for y in 0..size {
+            for x in 0..size {
+                indices.extend([index(x, y), index(x + 1, y), index(x + 1, y + 1)]);
+                indices.extend([index(x, y), index(x + 1, y + 1), index(x, y + 1)]);
+            }
+        }
+
+        ModelData {
+            vertices,
+            indices: Some(indices),
+        }
+    }
+
+    fn triangle_normals(model_data: &ModelData) -> Vec<na::Vector3<f64>> {
+        model_data
+            .indices
+            .as_ref()
+            .unwrap()
+            .chunks_exact(3)
+            .map(|triangle| {
+                let [a, b, c] = [0, 1, 2]
+                    .map(|corner| position(&model_data.vertices[triangle[corner] as usize]));
+                (b - a).cross(&(c - a))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn simplified_grid_keeps_its_outline_and_facing() {
+        let model_data = grid(10);
+
+        let simplified = model_data.simplify(0.25);
+        let triangle_count = simplified.indices.as_ref().unwrap().len() / 3;
+
+        assert!(triangle_count <= 50, "{} triangles", triangle_count);
+        assert!(triangle_count >= MIN_SIMPLIFIED_TRIANGLES);
+        assert_eq!(simplified.aabb(), model_data.aabb());
+
+        for normal in triangle_normals(&simplified) {
+            assert!(normal[2] > 0.0, "{} is flipped or flat", normal);
+        }
+
+        // The vertices that are left are the originals, texture coordinates and all
+        for vertex in &simplified.vertices {
+            assert!(model_data.vertices.contains(vertex));
+        }
+    }
+
+    #[test]
+    fn simplifying_stops_at_the_minimum() {
+        let simplified = grid(10).simplify(0.0);
+        let triangle_count = simplified.indices.as_ref().unwrap().len() / 3;
+
+        assert!(triangle_count >= MIN_SIMPLIFIED_TRIANGLES);
+        assert!(triangle_count < 200);
+
+        // A full ratio leaves the triangles alone, the vertices are only renumbered
+        let unchanged = grid(4).simplify(1.0);
+
+        assert_eq!(unchanged.indices.unwrap().len(), 4 * 4 * 6);
+        assert_eq!(unchanged.vertices.len(), 5 * 5);
+    }
+}
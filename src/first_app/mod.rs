@@ -1,41 +1,91 @@
+mod background_system;
+mod benchmark;
+mod camera_shake;
+mod demo_scene;
+mod depth_prepass_system;
+mod egui_system;
 mod fps_counter;
+mod indirect_render_system;
+mod input_state;
 mod keyboard_movement_controller;
+mod lve_asset_cache;
 mod lve_buffer;
 mod lve_camera;
 mod lve_descriptors;
 mod lve_device;
 mod lve_frameinfo;
 mod lve_game_object;
+mod lve_image;
 mod lve_model;
 mod lve_pipeline;
+mod lve_render_pass;
 mod lve_renderer;
 mod lve_swapchain;
+mod lve_texture;
+mod material_render_system;
+mod mesh_optimization;
+mod mesh_simplification;
+mod outline_render_system;
+mod particle_system;
+mod physics;
+mod picking_system;
+mod reflective_render_system;
+mod render_system;
 mod simple_render_system;
+mod skinned_render_system;
+mod skinning;
+mod text_system;
+mod window_config;
 
 use fps_counter::FPSCounter;
 
+use background_system::BackgroundSystem;
+use benchmark::{Benchmark, StartupTimes};
+use camera_shake::CameraShake;
+
+use depth_prepass_system::*;
+use egui_system::EguiSystem;
+use indirect_render_system::IndirectRenderSystem;
+use input_state::InputState;
 use keyboard_movement_controller::*;
+use lve_asset_cache::*;
 use lve_buffer::*;
 use lve_camera::*;
 use lve_descriptors::*;
 use lve_device::*;
-use lve_frameinfo::FrameInfo;
+use lve_frameinfo::*;
 use lve_game_object::*;
 use lve_model::*;
 use lve_renderer::*;
+use lve_swapchain::{
+    LveSwapchain, RenderPassConfig, SurfaceFormatPreference, SwapchainImageCount,
+};
+use lve_texture::LveDefaultTextures;
+use material_render_system::MaterialRenderSystem;
+use outline_render_system::*;
+use particle_system::{EmitterConfig, ParticleSystem};
+use physics::PhysicsWorld;
+use picking_system::PickingSystem;
+use reflective_render_system::*;
+use render_system::*;
 use simple_render_system::*;
+use skinned_render_system::SkinnedRenderSystem;
+use skinning::{AnimationPlayer, LveSkinnedModel};
+use text_system::*;
+pub use window_config::{FullscreenMode, WindowConfig};
 
 use winit::{
-    dpi::{LogicalSize, PhysicalSize},
-    event::{ElementState, Event, VirtualKeyCode, WindowEvent},
+    dpi::PhysicalSize,
+    event::{Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{EventLoop, ControlFlow},
-    window::{Window, WindowBuilder},
+    platform::run_return::EventLoopExtRunReturn,
+    window::Window,
 };
 
 use std::{
-    collections::HashMap,
-    time::Instant,
-    mem::size_of,
+    cell::RefCell,
+    collections::VecDeque,
+    time::{Duration, Instant},
     rc::Rc,
 };
 
@@ -43,95 +93,330 @@ use ash::vk;
 
 extern crate nalgebra as na;
 
-const WIDTH: u32 = 800;
-const HEIGHT: u32 = 600;
-const NAME: &str = "Hello Vulkan!";
-
-#[derive(Clone, Copy)]
-struct GlobalUBO {
-    _projection_view: na::Matrix4<f32>,
-    // _light_direction: na::Vector3<f32>,
-    _ambient_light_color: na::Vector4<f32>,
-    _light_position: na::Vector4<f32>,
-    _light_color: na::Vector4<f32>, // w is light intensity
-}
+/// Fill the depth buffer before shading so each pixel is only shaded once. Only worth it
+/// in scenes with a lot of overdraw, as every object is drawn twice. The HUD and the benchmark
+/// report the fragment shader invocations, to compare with it on and off
+const DEPTH_PREPASS: bool = false;
+const FOVY_DEGREES: f32 = 50.0;
+const NEAR_PLANE: f32 = 0.1;
+const FAR_PLANE: f32 = 100.0;
+/// How far in front of the camera the orthographic projection frames the same height as the
+/// perspective one, roughly the distance from the starting position to the scene
+const ORTHOGRAPHIC_FOCUS_DISTANCE: f32 = 2.5;
+/// Tried in order, the first the surface supports is used. FIFO (vsync) is always supported,
+/// e.g. `[MAILBOX, IMMEDIATE, FIFO_RELAXED, FIFO]` for the lowest latency available
+const PRESENT_MODES: &[vk::PresentModeKHR] = &[vk::PresentModeKHR::FIFO];
+/// When this environment variable is set (to anything) the device is created with
+/// `DeviceConfig::software_rendering` and presents with FIFO, e.g. for CI on llvmpipe
+const SOFTWARE_RENDERING_VAR: &str = "LVE_SOFTWARE_RENDERING";
+/// The ids of validation messages to leave out of the log, shown in brackets before each
+/// message. For false positives only, the rest of validation stays on
+const SUPPRESSED_VALIDATION_MESSAGES: &[i32] = &[];
+/// Tint objects with their mesh's vertex colors. When false only the object's `color` is used,
+/// for assets that ship with unwanted vertex colors
+const USE_VERTEX_COLOR: bool = true;
+/// What the global descriptor pools are sized for. Going past either count creates another
+/// pool of the same size rather than failing
+const EXPECTED_MATERIAL_COUNT: u32 = 64;
+const TEXTURES_PER_MATERIAL: u32 = 2;
+/// Drop a stack of cubes onto the floor to try out the physics
+const PHYSICS_DEMO: bool = false;
+/// Add a fountain of sparks above the floor to try out the particles
+const PARTICLE_DEMO: bool = false;
+/// Draw the lit objects with an indirect draw per model instead of a draw per object. Enable
+/// `multi_draw_indirect` and `draw_indirect_first_instance` in the required features as well,
+/// or there is still a draw per object
+const INDIRECT_DRAWING: bool = false;
+/// Give the simple render system's objects their matrices through a dynamic uniform buffer
+/// instead of push constants, see `ObjectUbo`. Drawing more than `MAX_UBO_OBJECTS` objects
+/// panics
+const PER_OBJECT_UBO: bool = false;
+const MAX_UBO_OBJECTS: u32 = 1024;
+/// A glTF file with a skinned mesh to add to the scene, playing its first animation on a loop
+const SKINNED_MODEL: Option<&str> = None;
+/// Give the smooth vase LOD levels simplified from its model at load, see `ModelData::simplify`.
+/// Each level keeps its share of the triangles, and every level (full detail first) is drawn
+/// up to its distance from the camera
+const GENERATED_LODS: bool = false;
+const GENERATED_LOD_RATIOS: [f32; 3] = [0.5, 0.25, 0.1];
+const GENERATED_LOD_DISTANCES: [f32; 4] = [3.0, 6.0, 12.0, f32::INFINITY];
+/// Give the flat vase a metallic material, drawn by the `MaterialRenderSystem`
+const MATERIAL_DEMO: bool = false;
+/// Find the object under the cursor with a GPU picking pass and show its id in the HUD
+const OBJECT_PICKING: bool = false;
+/// Jitter the projection by a different sub-pixel offset every frame, the groundwork for
+/// temporal anti-aliasing. There is no resolve pass yet, so on its own this only makes edges
+/// shimmer. The jitter cycles through this many points of the Halton sequence
+const TAA_JITTER: bool = false;
+const TAA_JITTER_PHASES: u32 = 8;
+/// Start with the six degrees of freedom `FreeFlyController` instead of walking in the XZ
+/// plane, N switches between them
+const FREE_FLY_CAMERA: bool = false;
+/// How fast the camera shakes, see `CameraShake`. T adds trauma to try it out
+const CAMERA_SHAKE_FREQUENCY: f32 = 15.0;
+/// The linear colours of the background gradient, drawn behind the scene instead of the clear
+/// colour. `None` leaves the plain clear colour
+const BACKGROUND_GRADIENT: Option<([f32; 3], [f32; 3])> =
+    Some(([0.02, 0.04, 0.1], [0.25, 0.28, 0.32]));
+/// Check the files of the loaded models every `MODEL_RELOAD_INTERVAL` and reload any that have
+/// changed, for editing geometry while the app runs
+const HOT_RELOAD_MODELS: bool = true;
+const MODEL_RELOAD_INTERVAL: Duration = Duration::from_millis(500);
+/// How many frames the debug UI's frame time graph shows
+const FRAME_TIME_HISTORY: usize = 200;
 
+/// Fields are dropped in declaration order once the event loop exits, after `shutdown` has
+/// waited for the GPU and destroyed everything `run` created. Anything using the device must
+/// come before the renderer (and its swapchain), which comes before the device itself. The
+/// window goes last as the device's surface was created from it
 pub struct VulkanApp {
-    window: Window,
-    lve_device: Rc<LveDevice>,
-    lve_renderer: LveRenderer,
-    global_pool: Rc<LveDescriptorPool>,
-    game_objects: HashMap<u64, LveGameObject>,
+    game_objects: GameObjectManager,
+    asset_cache: LveAssetCache,
     viewer_object: LveGameObject,
+    camera: LveCamera,
+    projection_mode: ProjectionMode,
+    /// What F11 switches to when the window isn't fullscreen
+    fullscreen_mode: FullscreenMode,
     camera_controller: KeyboardMovementController,
+    free_fly_controller: FreeFlyController,
+    free_fly: bool,
+    camera_shake: CameraShake,
+    physics_world: PhysicsWorld,
+    benchmark: Option<Benchmark>,
+    /// The object count and seed of the last `generate_demo_scene`, so `recover` can generate
+    /// it again
+    demo_scene: Option<(usize, u64)>,
+    /// When creating the device started and how long loading the scene took, until the first
+    /// frame reports them
+    startup: Option<(Instant, f32)>,
+    /// Starts as `DEPTH_PREPASS`, see `set_depth_prepass`
+    depth_prepass: bool,
+    /// Set when a frame finds the device has been lost, so `run` can say why it returned
+    device_lost: bool,
+    descriptor_pools: LveDescriptorPoolManager,
+    lve_renderer: LveRenderer,
+    lve_device: Rc<LveDevice>,
+    window: Window,
+}
+
+/// Why `VulkanApp::run` returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// The window was closed or the benchmark finished
+    Exited,
+    /// The GPU hung or the driver was reset, see `VulkanApp::recover`
+    DeviceLost,
+}
+
+/// The GPU resources `run` creates with `create_render_resources`. The event loop closure
+/// captures them, and the order captured variables are dropped in isn't something to rely on,
+/// so they are bundled here and destroyed explicitly by `shutdown` (or when the swapchain's
+/// render pass changes). Fields drop in declaration order: the render systems go
+/// before the textures and descriptor sets they bind, and the descriptor sets before the set
+/// layout and UBO buffer they were written with
+struct RenderResources {
+    render_systems: RenderSystemList,
+    /// Also registered in `render_systems`, kept here to queue the HUD text each frame
+    text_system: Rc<RefCell<TextSystem>>,
+    /// Also registered in `render_systems`, kept here to feed it window events and build the
+    /// debug UI each frame
+    egui_system: Rc<RefCell<EguiSystem>>,
+    /// Drawn into the renderer's picking target rather than the swapchain, empty unless
+    /// `OBJECT_PICKING` is set
+    picking_systems: RenderSystemList,
+    _default_textures: LveDefaultTextures,
+    global_descriptor_sets: Vec<vk::DescriptorSet>,
+    _global_set_layout: Rc<LveDescriptorSetLayout>,
+    global_ubo: UboBuffer<GlobalUBO>,
 }
 
 impl VulkanApp {
-    pub fn new() -> (VulkanApp, EventLoop<()>) {
+    pub fn new(window_config: &WindowConfig) -> (VulkanApp, EventLoop<()>) {
         // Create the event loop and application window
-        let (event_loop, window) = Self::new_window(WIDTH, HEIGHT, NAME);
+        let (event_loop, window) = Self::new_window(window_config);
 
-        let lve_device = LveDevice::new(&window);
+        (
+            Self::with_window(window, window_config.toggled_fullscreen_mode()),
+            event_loop,
+        )
+    }
 
-        let lve_renderer = LveRenderer::new(Rc::clone(&lve_device), &window);
+    /// Creates the device and everything made with it for an existing window
+    fn with_window(window: Window, fullscreen_mode: FullscreenMode) -> Self {
+        let startup_start = Instant::now();
 
-        let global_pool = LveDescriptorPoolBuilder::new(Rc::clone(&lve_device))
-            .set_max_sets(lve_swapchain::MAX_FRAMES_IN_FLIGHT as u32)
-            .add_pool_size(
-                ash::vk::DescriptorType::UNIFORM_BUFFER,
-                lve_swapchain::MAX_FRAMES_IN_FLIGHT as u32,
-            )
-            .build();
+        let (device_config, present_modes) = match std::env::var_os(SOFTWARE_RENDERING_VAR) {
+            Some(_) => {
+                log::info!("{} is set, preferring a software device", SOFTWARE_RENDERING_VAR);
+                (
+                    DeviceConfig::software_rendering(),
+                    &[vk::PresentModeKHR::FIFO][..],
+                )
+            }
+            None => (DeviceConfig::default(), PRESENT_MODES),
+        };
+
+        let lve_device = LveDevice::new(
+            Some(&window),
+            &AppInfo::default(),
+            device_config,
+            DebugMessengerConfig::suppressing(SUPPRESSED_VALIDATION_MESSAGES),
+        );
+
+        let lve_renderer = LveRenderer::new(
+            Rc::clone(&lve_device),
+            &window,
+            SurfaceFormatPreference::default(),
+            SwapchainImageCount::default(),
+            // e.g. TRANSFER_SRC to copy frames out for screenshots
+            vk::ImageUsageFlags::empty(),
+            present_modes,
+            // e.g. LOAD the color to draw on top of the last frame
+            RenderPassConfig::default(),
+            CommandBufferAllocation::default(),
+        );
+
+        let descriptor_pools = Self::create_descriptor_pools(&lve_device);
+
+        let mut asset_cache = LveAssetCache::new(Rc::clone(&lve_device));
 
-        let game_objects = Self::load_game_objects(&lve_device);
+        let scene_load_start = Instant::now();
+
+        let mut game_objects = Self::load_game_objects(&lve_device, &mut asset_cache);
+
+        if let Some(file_path) = SKINNED_MODEL {
+            Self::add_skinned_model(&lve_device, &mut game_objects, file_path);
+        }
+
+        let scene_load_time = scene_load_start.elapsed().as_secs_f32();
 
         let viewer_object = LveGameObject::new(
             LveModel::new_null("camera"),
             None,
-            Some(TransformComponent {
-                translation: na::vector![0.0, 0.0, -2.5],
-                scale: na::vector![1.0, 1.0, 1.0],
-                rotation: na::vector![0.0, 0.0, 0.0],
-            }),
+            Some(TransformComponent::new(
+                na::vector![0.0, 0.0, -2.5],
+                na::vector![1.0, 1.0, 1.0],
+                na::vector![0.0, 0.0, 0.0],
+            )),
         );
 
+        let projection_mode = ProjectionMode::default();
+
+        let camera = Self::build_camera(projection_mode, lve_renderer.get_aspect_ratio());
+
         let camera_controller = KeyboardMovementController::new(None, None);
 
+        Self {
+            game_objects,
+            asset_cache,
+            viewer_object,
+            camera,
+            projection_mode,
+            fullscreen_mode,
+            camera_controller,
+            free_fly_controller: FreeFlyController::new(None, None),
+            free_fly: FREE_FLY_CAMERA,
+            camera_shake: CameraShake::new(
+                CAMERA_SHAKE_FREQUENCY,
+                na::vector![0.1, 0.1, 0.1],
+                na::vector![0.05, 0.05, 0.05],
+            ),
+            physics_world: PhysicsWorld::new(),
+            benchmark: None,
+            demo_scene: None,
+            startup: Some((startup_start, scene_load_time)),
+            depth_prepass: DEPTH_PREPASS,
+            device_lost: false,
+            descriptor_pools,
+            lve_renderer,
+            lve_device,
+            window,
+        }
+    }
+
+    /// Rebuilds the app after `run` returned `RunOutcome::DeviceLost`, keeping the window, the
+    /// camera's position and any benchmark in progress. `run` the returned app to carry on.
+    ///
+    /// Nothing made with a lost device can be used again, so the device, renderer, descriptor
+    /// pools and asset cache are all dropped and created again, and `run` recreates the render
+    /// systems and their pipelines. The models are re-uploaded by loading the starting scene
+    /// and generating any demo scene again, so anything else spawned, despawned or moved since
+    /// is lost. Re-uploading the scene as it was is left for later
+    pub fn recover(self) -> Self {
+        log::warn!("Recreating the device after it was lost");
+
+        let depth_prepass = self.depth_prepass;
+
+        // Everything else is dropped before the new device is created, as the window can only
+        // have one swapchain at a time
+        let (window, viewer_object, fullscreen_mode, benchmark, demo_scene) = self.into_window();
+
+        let mut vulkan_app = Self::with_window(window, fullscreen_mode);
+        vulkan_app.viewer_object = viewer_object;
+        vulkan_app.benchmark = benchmark;
+        vulkan_app.depth_prepass = depth_prepass;
+
+        if let Some((count, seed)) = demo_scene {
+            vulkan_app.generate_demo_scene(count, seed);
+        }
+
+        vulkan_app
+    }
+
+    /// Drops everything made with the device, returning what `recover` carries over
+    fn into_window(
+        self,
+    ) -> (
+        Window,
+        LveGameObject,
+        FullscreenMode,
+        Option<Benchmark>,
+        Option<(usize, u64)>,
+    ) {
         (
-            Self {
-                window,
-                lve_device,
-                lve_renderer,
-                global_pool,
-                game_objects,
-                viewer_object,
-                camera_controller,
-            },
-            event_loop,
+            self.window,
+            self.viewer_object,
+            self.fullscreen_mode,
+            self.benchmark,
+            self.demo_scene,
         )
     }
 
-    pub fn run(mut self, event_loop: EventLoop<()>) {
-        let mut ubo_buffers: Vec<Rc<LveBuffer>> = Vec::new();
+    /// Overrides `DEPTH_PREPASS`, e.g. to benchmark the scene with and without it. Must be
+    /// called before `run`
+    pub fn set_depth_prepass(&mut self, depth_prepass: bool) {
+        self.depth_prepass = depth_prepass;
+    }
 
-        for _ in 0..lve_swapchain::MAX_FRAMES_IN_FLIGHT {
-            let mut ubo = lve_buffer::LveBuffer::new(
-                Rc::clone(&self.lve_device),
-                size_of::<GlobalUBO>() as u64,
-                1,
-                ash::vk::BufferUsageFlags::UNIFORM_BUFFER,
-                ash::vk::MemoryPropertyFlags::HOST_VISIBLE,
-                self.lve_device
-                    .properties
-                    .limits
-                    .min_uniform_buffer_offset_alignment,
-                BufferType::Uniform,
-            );
+    /// Renders `frame_count` frames along a fixed camera path instead of taking input, then
+    /// prints the frame time stats as JSON and exits
+    pub fn enable_benchmark(&mut self, frame_count: u32) {
+        log::info!("Benchmarking {} frames", frame_count);
+        self.benchmark = Some(Benchmark::new(frame_count));
+    }
 
-            unsafe { ubo.map(ash::vk::WHOLE_SIZE, 0) };
+    fn create_descriptor_pools(lve_device: &Rc<LveDevice>) -> LveDescriptorPoolManager {
+        LveDescriptorPoolManager::new(
+            Rc::clone(lve_device),
+            DescriptorPoolConfig {
+                uniform_buffer_sets: lve_swapchain::MAX_FRAMES_IN_FLIGHT as u32,
+                dynamic_uniform_buffer_sets: PER_OBJECT_UBO as u32
+                    + SKINNED_MODEL.is_some() as u32
+                    + MATERIAL_DEMO as u32,
+                texture_sets: EXPECTED_MATERIAL_COUNT,
+                textures_per_set: TEXTURES_PER_MATERIAL,
+                storage_image_sets: 0,
+            },
+        )
+    }
 
-            ubo_buffers.push(Rc::new(ubo));
-        }
+    /// The render systems and everything they bind, built against the renderer's current
+    /// render passes. Descriptor sets are allocated from `descriptor_pools`
+    fn create_render_resources(&mut self) -> RenderResources {
+        let global_ubo = UboBuffer::<GlobalUBO>::new(
+            Rc::clone(&self.lve_device),
+            lve_swapchain::MAX_FRAMES_IN_FLIGHT,
+        );
 
         let global_set_layout = LveDescriptorSetLayoutBuilder::new(Rc::clone(&self.lve_device))
             .add_binding(
@@ -145,36 +430,235 @@ impl VulkanApp {
         let mut global_descriptor_sets: Vec<vk::DescriptorSet> = Vec::new();
 
         for i in 0..lve_swapchain::MAX_FRAMES_IN_FLIGHT {
-            let buffer_info = ubo_buffers[i].descriptor_info(vk::WHOLE_SIZE, 0);
+            let buffer_info = global_ubo.descriptor_info(i);
             global_descriptor_sets.push(
                 LveDescriptorWriter::new(
                     Rc::clone(&global_set_layout),
-                    Rc::clone(&self.global_pool),
+                    self.descriptor_pools.current_pool(),
                 )
                 .write_buffer(0, &[*buffer_info])
-                .build()
-                .map_err(|_| log::error!("Unable to create a descriptor set!"))
-                .unwrap(),
+                .build_from(&mut self.descriptor_pools),
             )
         }
 
-        let mut simple_render_system = SimpleRenderSystem::new(
+        let mut render_systems = RenderSystemList::new();
+
+        // With the per object UBO only the color is left in the push constants
+        let (push_constant_layout, object_ubo) = if PER_OBJECT_UBO {
+            (
+                SimplePushConstantData::fragment_push_constant_layout(&self.lve_device),
+                Some(ObjectUbo::new(
+                    Rc::clone(&self.lve_device),
+                    &mut self.descriptor_pools,
+                    MAX_UBO_OBJECTS,
+                )),
+            )
+        } else {
+            (
+                SimplePushConstantData::push_constant_layout(&self.lve_device),
+                None,
+            )
+        };
+
+        let simple_render_system = SimpleRenderSystem::new(
+            Rc::clone(&self.lve_device),
+            &self.lve_renderer.get_swapchain_render_pass(),
+            global_set_layout.descriptor_set_layout,
+            push_constant_layout,
+            self.depth_prepass,
+            USE_VERTEX_COLOR,
+            object_ubo,
+        );
+
+        let default_textures = LveDefaultTextures::new(Rc::clone(&self.lve_device));
+
+        let reflective_render_system = ReflectiveRenderSystem::new(
             Rc::clone(&self.lve_device),
             &self.lve_renderer.get_swapchain_render_pass(),
             global_set_layout.descriptor_set_layout,
+            default_textures.default_environment(),
+            SimplePushConstantData::push_constant_layout(&self.lve_device),
+            self.depth_prepass,
+            USE_VERTEX_COLOR,
         );
 
+        if let Some((top_color, bottom_color)) = BACKGROUND_GRADIENT {
+            render_systems.register(
+                RenderStage::Background,
+                BackgroundSystem::new(
+                    Rc::clone(&self.lve_device),
+                    &self.lve_renderer.get_swapchain_render_pass(),
+                    top_color,
+                    bottom_color,
+                ),
+            );
+        }
+
+        if INDIRECT_DRAWING {
+            render_systems.register(
+                RenderStage::Opaque,
+                IndirectRenderSystem::new(
+                    Rc::clone(&self.lve_device),
+                    &self.lve_renderer.get_swapchain_render_pass(),
+                    global_set_layout.descriptor_set_layout,
+                    self.depth_prepass,
+                    USE_VERTEX_COLOR,
+                ),
+            );
+        } else {
+            render_systems.register(RenderStage::Opaque, simple_render_system);
+        }
+        render_systems.register(RenderStage::Opaque, reflective_render_system);
+
+        if SKINNED_MODEL.is_some() {
+            render_systems.register(
+                RenderStage::Opaque,
+                SkinnedRenderSystem::new(
+                    Rc::clone(&self.lve_device),
+                    &self.lve_renderer.get_swapchain_render_pass(),
+                    global_set_layout.descriptor_set_layout,
+                    &mut self.descriptor_pools,
+                    USE_VERTEX_COLOR,
+                ),
+            );
+        }
+
+        if MATERIAL_DEMO {
+            render_systems.register(
+                RenderStage::Opaque,
+                MaterialRenderSystem::new(
+                    Rc::clone(&self.lve_device),
+                    &self.lve_renderer.get_swapchain_render_pass(),
+                    global_set_layout.descriptor_set_layout,
+                    &mut self.descriptor_pools,
+                    self.depth_prepass,
+                    USE_VERTEX_COLOR,
+                ),
+            );
+        }
+
+        if self.depth_prepass {
+            render_systems.register(
+                RenderStage::DepthPrepass,
+                DepthPrepassSystem::new(
+                    Rc::clone(&self.lve_device),
+                    &self.lve_renderer.get_swapchain_render_pass(),
+                    global_set_layout.descriptor_set_layout,
+                ),
+            );
+        }
+
+        // Outlining relies on the stencil buffer, which not every depth format has
+        if LveSwapchain::has_stencil_component(LveSwapchain::find_depth_format(&self.lve_device)) {
+            render_systems.register(
+                RenderStage::Overlay,
+                OutlineRenderSystem::new(
+                    Rc::clone(&self.lve_device),
+                    &self.lve_renderer.get_swapchain_render_pass(),
+                    global_set_layout.descriptor_set_layout,
+                ),
+            );
+        } else {
+            log::warn!("No depth format with a stencil component, objects won't be outlined");
+        }
+
+        if PARTICLE_DEMO {
+            render_systems.register(
+                RenderStage::Overlay,
+                ParticleSystem::new(
+                    Rc::clone(&self.lve_device),
+                    &self.lve_renderer.get_swapchain_render_pass(),
+                    global_set_layout.descriptor_set_layout,
+                    EmitterConfig {
+                        position: na::vector![0.0, -0.5, 0.0],
+                        ..EmitterConfig::default()
+                    },
+                ),
+            );
+        }
+
+        let text_system = Rc::new(RefCell::new(TextSystem::new(
+            Rc::clone(&self.lve_device),
+            &self.lve_renderer.get_swapchain_render_pass(),
+        )));
+
+        render_systems.register_shared(RenderStage::Ui, Rc::clone(&text_system) as _);
+
+        let egui_system = Rc::new(RefCell::new(EguiSystem::new(
+            Rc::clone(&self.lve_device),
+            &self.lve_renderer.get_swapchain_render_pass(),
+        )));
+
+        render_systems.register_shared(RenderStage::Ui, Rc::clone(&egui_system) as _);
+
+        let mut picking_systems = RenderSystemList::new();
+
+        if OBJECT_PICKING {
+            let picking_render_pass = self.lve_renderer.enable_picking();
+
+            picking_systems.register(
+                RenderStage::Opaque,
+                PickingSystem::new(
+                    Rc::clone(&self.lve_device),
+                    &picking_render_pass,
+                    global_set_layout.descriptor_set_layout,
+                ),
+            );
+        }
+
+        RenderResources {
+            render_systems,
+            text_system,
+            egui_system,
+            picking_systems,
+            _default_textures: default_textures,
+            global_descriptor_sets,
+            _global_set_layout: global_set_layout,
+            global_ubo,
+        }
+    }
+
+    /// Runs the event loop until the window is closed or the device is lost. The event loop can
+    /// be run again afterwards, e.g. with the app `recover` returns
+    pub fn run(&mut self, event_loop: &mut EventLoop<()>) -> RunOutcome {
+        let mut render_resources = Some(self.create_render_resources());
+
         let mut current_time = Instant::now();
 
-        let mut keys_pressed: Vec<VirtualKeyCode> = Vec::new();
+        let mut input_state = InputState::new();
 
         let mut fps_counter = FPSCounter::new(100);
 
+        let mut frame_time_history = VecDeque::with_capacity(FRAME_TIME_HISTORY);
+
+        let mut last_model_reload_check = Instant::now();
+
+        // Counts every frame, unlike the renderer's frame index which cycles through the frames
+        // in flight
+        let mut frame_number: u64 = 0;
+
+        // The unjittered projection view and jitter of the last frame drawn
+        let mut previous_frame: Option<(na::Matrix4<f32>, na::Vector2<f32>)> = None;
+
         // Begin the events loop
-        event_loop.run(move |event, _, control_flow| {
+        event_loop.run_return(|event, _, control_flow| {
             // Set the behavior to poll the window for user events
             *control_flow = ControlFlow::Poll;
 
+            if let Event::WindowEvent { event, .. } = &event {
+                // Input egui captures (e.g. dragging a slider) shouldn't also move the camera
+                let captured = match &render_resources {
+                    Some(resources) => {
+                        resources.egui_system.borrow_mut().handle_window_event(event)
+                    }
+                    None => false,
+                };
+
+                if !captured {
+                    input_state.handle_window_event(event);
+                }
+            }
+
             match event {
                 Event::WindowEvent {
                     event: WindowEvent::CloseRequested,
@@ -192,64 +676,106 @@ impl VulkanApp {
                     self.resize();
                 }
                 Event::WindowEvent {
-                    event: WindowEvent::KeyboardInput { input, .. },
+                    event: WindowEvent::ScaleFactorChanged { scale_factor, .. },
                     ..
                 } => {
-                    match input.virtual_keycode {
-                        Some(VirtualKeyCode::Escape) => {
-                            log::debug!("Closing window");
-                            *control_flow = ControlFlow::Exit;
-                            return;
-                        }
-                        Some(input_key) => {
-                            match input.state {
-                                ElementState::Pressed => {
-                                    if !keys_pressed.contains(&input_key) {
-                                        keys_pressed.push(input_key);
-                                    }
-                                }
-                                ElementState::Released => {
-                                    let index = keys_pressed
-                                        .iter()
-                                        .position(|key| *key == input_key)
-                                        .unwrap();
-                                    keys_pressed.remove(index);
-                                }
-                            };
-                        }
-                        None => {}
-                    };
+                    // The logical size stays the same but the physical size (and so the
+                    // swapchain extent) changes with the scale factor
+                    log::info!("New scale factor: {}", scale_factor);
+                    self.resize();
+                }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    virtual_keycode: Some(VirtualKeyCode::Escape),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    log::debug!("Closing window");
+                    *control_flow = ControlFlow::Exit;
                 }
                 Event::MainEventsCleared => {
                     self.window.request_redraw();
                 }
+                Event::LoopDestroyed => {
+                    self.shutdown(render_resources.take());
+                }
                 Event::RedrawRequested(_window_id) => {
+                    let mut resources = match render_resources.as_mut() {
+                        Some(resources) => resources,
+                        None => return, // Already shut down
+                    };
+
                     let time_since_last_frame = current_time.elapsed().as_secs_f32();
                     current_time = Instant::now();
+                    let frame_start_time = current_time;
 
                     // Code to run each frame goes here
 
-                    self.camera_controller.move_in_plane_xz(
-                        keys_pressed.as_slice(),
-                        time_since_last_frame,
-                        &mut self.viewer_object,
-                    );
+                    if input_state.was_key_pressed(VirtualKeyCode::P) {
+                        self.toggle_projection();
+                    }
 
-                    let aspect = self.lve_renderer.get_aspect_ratio();
-                    // self.camera = LveCamera::set_orthographic_projection(-aspect, aspect, -1.0, 1.0, -1.0, 1.0);
-                    let camera = LveCameraBuilder::new()
-                        .set_view_xyz(
-                            self.viewer_object.transform.translation,
-                            self.viewer_object.transform.rotation,
-                        )
-                        .set_perspective_projection(50_f32.to_radians(), aspect, 0.1, 100.0)
-                        // .set_view_direction(na::Vector3::zeros(), na::vector![0.5, 0.0, 1.0], None)
-                        // .set_view_target(
-                        //     na::vector![-1.0, -2.0, 2.0],
-                        //     na::vector![0.0, 0.0, 2.5],
-                        //     None,
-                        // )
-                        .build();
+                    if input_state.was_key_pressed(VirtualKeyCode::F11) {
+                        self.toggle_fullscreen();
+                    }
+
+                    if input_state.was_key_pressed(VirtualKeyCode::T) {
+                        self.camera_shake.add_trauma(0.5);
+                    }
+
+                    if input_state.was_key_pressed(VirtualKeyCode::F) {
+                        self.frame_scene();
+                    }
+
+                    if input_state.was_key_pressed(VirtualKeyCode::N) {
+                        self.toggle_free_fly();
+                    }
+
+                    if self.free_fly {
+                        self.free_fly_controller.fly(
+                            &input_state,
+                            time_since_last_frame,
+                            &mut self.viewer_object,
+                        );
+                    } else {
+                        self.camera_controller.move_in_plane_xz(
+                            &input_state,
+                            time_since_last_frame,
+                            &mut self.viewer_object,
+                        );
+                    }
+
+                    input_state.end_frame();
+
+                    self.physics_world
+                        .update(&mut self.game_objects, time_since_last_frame);
+
+                    for (_, game_object) in self.game_objects.iter_mut() {
+                        if let Some(skin) = &mut game_object.skin {
+                            skin.player.advance(time_since_last_frame, &skin.model.clips);
+                        }
+                    }
+
+                    if let Some(benchmark) = &self.benchmark {
+                        let (translation, rotation) = benchmark.camera_transform();
+                        self.viewer_object.transform.set_translation(translation);
+                        self.viewer_object.transform.set_rotation(rotation);
+                    }
+
+                    // The shake only moves the camera, the viewer object stays where it is
+                    self.camera_shake.update(time_since_last_frame);
+                    let (shake_translation, shake_rotation) = self.camera_shake.offset();
+
+                    self.camera.set_view_xyz(
+                        self.viewer_object.transform.translation() + shake_translation,
+                        self.viewer_object.transform.rotation() + shake_rotation,
+                    );
 
                     let extent = LveRenderer::get_window_extent(&self.window);
 
@@ -257,121 +783,662 @@ impl VulkanApp {
                         return; // Don't do anything if the window is minimised
                     }
 
+                    if HOT_RELOAD_MODELS
+                        && last_model_reload_check.elapsed() >= MODEL_RELOAD_INTERVAL
+                    {
+                        last_model_reload_check = Instant::now();
+
+                        for geometry in self.asset_cache.reload_changed_models() {
+                            self.lve_renderer.defer_deletion(geometry);
+                        }
+                    }
+
+                    let fps = fps_counter.tick(time_since_last_frame);
+
+                    if frame_time_history.len() == FRAME_TIME_HISTORY {
+                        frame_time_history.pop_front();
+                    }
+                    frame_time_history.push_back(time_since_last_frame);
+
                     match self.lve_renderer.begin_frame(&self.window) {
-                        Some(command_buffer) => {
+                        Ok(Some(command_buffer)) => {
+                            // begin_frame may have recreated the swapchain, so make sure the
+                            // projection matches the extent we are about to render at
+                            self.camera.set_aspect(self.lve_renderer.get_aspect_ratio());
+
+                            // A new swapchain format means a new render pass, which the old
+                            // pipelines can't draw in. The device is idle, so everything is
+                            // dropped and built again, with fresh pools for the descriptor sets
+                            if self.lve_renderer.take_render_pass_changed() {
+                                log::info!("Rebuilding the render systems");
+                                drop(render_resources.take());
+                                self.descriptor_pools =
+                                    Self::create_descriptor_pools(&self.lve_device);
+                                resources =
+                                    render_resources.insert(self.create_render_resources());
+                            }
+
+                            if TAA_JITTER {
+                                self.camera.set_jitter(halton_jitter(
+                                    frame_number,
+                                    TAA_JITTER_PHASES,
+                                    self.lve_renderer.get_swapchain_extent(),
+                                ));
+                            }
+                            frame_number += 1;
+
                             let frame_index = self.lve_renderer.get_frame_index() as u64;
 
-                            let mut frame_info = FrameInfo {
+                            // A few frames behind the cursor, see `LveRenderer::pick_at`
+                            let hovered_object = match input_state.mouse_position() {
+                                Some(position) if OBJECT_PICKING => self
+                                    .lve_renderer
+                                    .pick_at(position[0] as u32, position[1] as u32),
+                                _ => None,
+                            };
+
+                            // Build the UI before the frame info borrows the objects it edits
+                            {
+                                let mut egui_system = resources.egui_system.borrow_mut();
+                                let ctx = egui_system.begin_frame(
+                                    self.lve_renderer.get_viewport_rect(),
+                                    self.window.scale_factor() as f32,
+                                );
+                                Self::draw_debug_ui(
+                                    &ctx,
+                                    &mut self.game_objects,
+                                    fps,
+                                    &frame_time_history,
+                                );
+                                egui_system.end_frame();
+                            }
+
+                            // Gather the point lights before the frame info borrows the objects
+                            let mut ubo = GlobalUBO::new(
+                                self.lve_renderer.get_pre_rotation()
+                                    * self.camera.projection_matrix
+                                    * self.camera.view_matrix,
+                                self.camera.inverse_view_matrix,
+                            );
+
+                            let unjittered_projection_view = self.lve_renderer.get_pre_rotation()
+                                * self.camera.unjittered_projection_matrix()
+                                * self.camera.view_matrix;
+                            let jitter = self.camera.jitter();
+
+                            let (previous_projection_view, previous_jitter) = previous_frame
+                                .replace((unjittered_projection_view, jitter))
+                                .unwrap_or((unjittered_projection_view, jitter));
+
+                            ubo.previous_projection_view = previous_projection_view;
+                            ubo.jitter = na::vector![
+                                jitter[0],
+                                jitter[1],
+                                previous_jitter[0],
+                                previous_jitter[1]
+                            ];
+
+                            if let (Some(near), Some(far)) = (self.camera.near(), self.camera.far())
+                            {
+                                ubo.near_plane = near;
+                                ubo.far_plane = far;
+                            }
+
+                            for (_, game_obj) in self.game_objects.iter() {
+                                if let Some(point_light) = &game_obj.point_light {
+                                    assert!(
+                                        (ubo.num_lights as usize) < MAX_LIGHTS,
+                                        "Point lights exceed maximum specified"
+                                    );
+
+                                    let translation = game_obj.transform.translation();
+                                    let color = game_obj.color;
+
+                                    ubo.point_lights[ubo.num_lights as usize] = PointLight {
+                                        position: na::vector![
+                                            translation[0],
+                                            translation[1],
+                                            translation[2],
+                                            1.0
+                                        ],
+                                        color: na::vector![
+                                            color[0],
+                                            color[1],
+                                            color[2],
+                                            point_light.light_intensity
+                                        ],
+                                    };
+                                    ubo.num_lights += 1;
+                                }
+                            }
+
+                            let frame_stats = self.lve_renderer.frame_stats();
+
+                            let frame_info = FrameInfo {
                                 frame_index,
                                 frame_time: time_since_last_frame,
                                 command_buffer,
-                                camera: &camera,
-                                global_descriptor_set: global_descriptor_sets[frame_index as usize],
+                                camera: &self.camera,
+                                global_descriptor_set: resources.global_descriptor_sets
+                                    [frame_index as usize],
                                 game_objects: &mut self.game_objects,
+                                viewport: self.lve_renderer.get_viewport_rect(),
+                                stats: &frame_stats,
                             };
 
                             // Update
-                            let ubo = GlobalUBO {
-                                _projection_view: camera.projection_matrix * camera.view_matrix,
-                                _ambient_light_color: na::vector![1.0, 1.0, 1.0, 0.015],
-                                _light_position: na::vector![-1.0, -1.0, -1.0, 0.0],
-                                _light_color: na::vector![1.0, 1.0, 1.0, 1.0],
-                            };
+                            resources.global_ubo.update(frame_index as usize, &ubo);
 
-                            unsafe {
-                                ubo_buffers[frame_index as usize].write_to_buffer(
-                                    &[ubo],
-                                    ash::vk::WHOLE_SIZE,
-                                    0,
+                            // Queue the HUD before rendering, the text system draws it last
+                            {
+                                let mut text_system = resources.text_system.borrow_mut();
+                                text_system.draw_text(
+                                    10.0,
+                                    10.0,
+                                    &format!("FPS: {}", fps),
+                                    [1.0, 1.0, 1.0, 1.0],
+                                );
+                                text_system.draw_text(
+                                    10.0,
+                                    34.0,
+                                    &format!(
+                                        "Meshes: {}, VRAM: {:.2} MB",
+                                        LveModel::live_model_count(),
+                                        LveModel::total_gpu_bytes() as f64 / (1024.0 * 1024.0)
+                                    ),
+                                    [1.0, 1.0, 1.0, 1.0],
                                 );
-                                ubo_buffers[frame_index as usize]
-                                    .flush(ash::vk::WHOLE_SIZE, 0)
-                                    .map_err(|e| log::error!("Unable to flush memory: {}", e))
-                                    .unwrap();
+                                text_system.draw_text(
+                                    10.0,
+                                    58.0,
+                                    &format!(
+                                        "Objects: {}/{} visible",
+                                        frame_info.game_objects.visible_count(),
+                                        frame_info.game_objects.len()
+                                    ),
+                                    [1.0, 1.0, 1.0, 1.0],
+                                );
+
+                                let stats = self.lve_renderer.last_frame_stats();
+                                text_system.draw_text(
+                                    10.0,
+                                    82.0,
+                                    &format!(
+                                        "Draws: {}, Triangles: {}, Pipeline binds: {}, Culled: {}",
+                                        stats.draw_calls,
+                                        stats.triangles,
+                                        stats.pipeline_binds,
+                                        stats.culled_objects
+                                    ),
+                                    [1.0, 1.0, 1.0, 1.0],
+                                );
+
+                                if let Some(fragment_invocations) = stats.fragment_invocations {
+                                    text_system.draw_text(
+                                        10.0,
+                                        106.0,
+                                        &format!(
+                                            "Fragment invocations: {} (depth pre-pass {})",
+                                            fragment_invocations,
+                                            if self.depth_prepass { "on" } else { "off" }
+                                        ),
+                                        [1.0, 1.0, 1.0, 1.0],
+                                    );
+                                }
+
+                                if OBJECT_PICKING {
+                                    text_system.draw_text(
+                                        10.0,
+                                        130.0,
+                                        &format!("Under cursor: {:?}", hovered_object),
+                                        [1.0, 1.0, 1.0, 1.0],
+                                    );
+                                }
                             }
 
                             // Render
                             self.lve_renderer
-                                .begin_swapchain_render_pass(command_buffer);
-                            simple_render_system
-                                .render_game_objects(&mut frame_info);
-                            self.lve_renderer.end_swapchain_render_pass(command_buffer);
+                                .render_picking_pass(&frame_info, &resources.picking_systems);
+                            self.lve_renderer
+                                .render_swapchain_pass(&frame_info, &resources.render_systems);
                         }
-                        None => {}
+                        Ok(None) => {}
+                        Err(FrameError::DeviceLost) => {
+                            self.stop_for_device_lost(control_flow);
+                            return;
+                        }
+                    }
+
+                    if let Err(FrameError::DeviceLost) = self.lve_renderer.end_frame() {
+                        self.stop_for_device_lost(control_flow);
+                        return;
                     }
 
-                    self.lve_renderer.end_frame();
+                    if let Some((startup_start, scene_load_time)) = self.startup.take() {
+                        let startup = StartupTimes {
+                            scene_load: scene_load_time,
+                            first_frame: startup_start.elapsed().as_secs_f32(),
+                        };
 
-                    let window_title = format!(
-                        "HELLO VULAKN | fps: {}",
-                        fps_counter.tick(time_since_last_frame)
-                    );
+                        log::info!(
+                            "First frame submitted {:.1} ms after startup, {:.1} ms of it \
+                             loading the scene",
+                            startup.first_frame * 1000.0,
+                            startup.scene_load * 1000.0
+                        );
+
+                        if let Some(benchmark) = &mut self.benchmark {
+                            benchmark.record_startup(startup);
+                        }
+                    }
+
+                    if let Some(benchmark) = &mut self.benchmark {
+                        benchmark.record_frame(
+                            time_since_last_frame,
+                            frame_start_time.elapsed().as_secs_f32(),
+                            self.lve_renderer.last_frame_stats().fragment_invocations,
+                        );
+
+                        if benchmark.is_finished() {
+                            println!("{}", benchmark.report_json(self.depth_prepass));
+                            *control_flow = ControlFlow::Exit;
+                        }
+                    }
+
+                    let window_title = format!("HELLO VULAKN | fps: {}", fps);
                     self.window.set_title(&window_title);
                 }
                 _ => (),
             };
         });
+
+        if self.device_lost {
+            RunOutcome::DeviceLost
+        } else {
+            RunOutcome::Exited
+        }
+    }
+
+    /// Ends the event loop so `run` can return `RunOutcome::DeviceLost`. The render resources
+    /// are destroyed by the shutdown as usual
+    fn stop_for_device_lost(&mut self, control_flow: &mut ControlFlow) {
+        log::error!("The device was lost, stopping to recover");
+
+        self.device_lost = true;
+        *control_flow = ControlFlow::Exit;
+    }
+
+    /// Waits for the GPU to finish the frames still in flight, then destroys the resources
+    /// `run` created. Everything else is destroyed when the app itself drops
+    fn shutdown(&mut self, render_resources: Option<RenderResources>) {
+        log::debug!("Shutting down");
+
+        self.lve_device.wait_idle();
+
+        drop(render_resources);
+    }
+
+    /// Adds an object to the scene, loading its model unless it is already in the asset cache.
+    /// Returns the id to `despawn` it with
+    pub fn spawn(&mut self, model_path: &str, transform: TransformComponent) -> u64 {
+        let model = self.asset_cache.create_model_cached(model_path);
+
+        self.game_objects
+            .create_object(LveGameObject::new(model, None, Some(transform)))
+    }
+
+    /// Adds `count` objects at pseudo-random transforms, using only a few models. The same
+    /// seed always adds the same objects, so runs stress testing culling or benchmarking can
+    /// be compared. Returns their ids
+    pub fn generate_demo_scene(&mut self, count: usize, seed: u64) -> Vec<u64> {
+        log::info!("Generating a demo scene of {} objects from seed {}", count, seed);
+
+        self.demo_scene = Some((count, seed));
+
+        self.asset_cache
+            .preload_models(&demo_scene::DEMO_SCENE_MODELS);
+
+        demo_scene::generate(count, seed)
+            .into_iter()
+            .map(|object| self.spawn(object.model_path, object.transform))
+            .collect()
+    }
+
+    /// Removes an object from the scene. Frames still in flight may be drawing its model, so the
+    /// object is only dropped once they have finished
+    #[allow(dead_code)]
+    pub fn despawn(&mut self, id: u64) {
+        match self.game_objects.remove(id) {
+            Some(game_object) => self.lve_renderer.defer_deletion(game_object),
+            None => log::warn!("Cannot despawn object {}, it doesn't exist", id),
+        }
+    }
+
+    /// An egui window with the frame time graph and every object's transform
+    fn draw_debug_ui(
+        ctx: &egui::CtxRef,
+        game_objects: &mut GameObjectManager,
+        fps: u32,
+        frame_time_history: &VecDeque<f32>,
+    ) {
+        egui::Window::new("Debug").show(ctx, |ui| {
+            ui.label(format!("FPS: {}", fps));
+
+            let frame_times = frame_time_history
+                .iter()
+                .enumerate()
+                .map(|(frame, time)| egui::plot::Value::new(frame as f64, *time * 1000.0));
+
+            ui.add(
+                egui::plot::Plot::new("frame_times")
+                    .line(egui::plot::Line::new(egui::plot::Values::from_values_iter(
+                        frame_times,
+                    )))
+                    .include_y(0.0)
+                    .height(100.0)
+                    .allow_drag(false)
+                    .allow_zoom(false),
+            );
+            ui.label("Frame time (ms)");
+
+            // Sorted so the list doesn't shuffle around every frame
+            let mut objects: Vec<_> = game_objects.iter_mut().collect();
+            objects.sort_by_key(|(id, _)| **id);
+
+            for (id, game_object) in objects {
+                egui::CollapsingHeader::new(format!("Object {}", id)).show(ui, |ui| {
+                    ui.checkbox(&mut game_object.visible, "Visible");
+
+                    let transform = &mut game_object.transform;
+
+                    let mut translation = transform.translation();
+                    if Self::vector_drag_values(ui, "Translation", &mut translation, 0.01) {
+                        transform.set_translation(translation);
+                    }
+
+                    let mut rotation = transform.rotation();
+                    if Self::vector_drag_values(ui, "Rotation", &mut rotation, 0.01) {
+                        transform.set_rotation(rotation);
+                    }
+
+                    let mut scale = transform.scale();
+                    if Self::vector_drag_values(ui, "Scale", &mut scale, 0.01) {
+                        transform.set_scale(scale);
+                    }
+                });
+            }
+        });
+    }
+
+    /// Returns true if any component was changed
+    fn vector_drag_values(
+        ui: &mut egui::Ui,
+        label: &str,
+        vector: &mut na::Vector3<f32>,
+        speed: f32,
+    ) -> bool {
+        ui.horizontal(|ui| {
+            ui.label(label);
+
+            let mut changed = false;
+            for (component, prefix) in vector.iter_mut().zip(["x: ", "y: ", "z: "]) {
+                changed |= ui
+                    .add(egui::DragValue::new(component).speed(speed).prefix(prefix))
+                    .changed();
+            }
+
+            changed
+        })
+        .inner
+    }
+
+    fn build_camera(projection_mode: ProjectionMode, aspect: f32) -> LveCamera {
+        let fovy = FOVY_DEGREES.to_radians();
+
+        match projection_mode {
+            ProjectionMode::Perspective => LveCameraBuilder::new()
+                .set_perspective_projection(fovy, aspect, NEAR_PLANE, FAR_PLANE)
+                .build(),
+            ProjectionMode::Orthographic => {
+                // The height the perspective projection sees at the focus distance, so the
+                // switch keeps the scene roughly the same size
+                let half_height = ORTHOGRAPHIC_FOCUS_DISTANCE * (fovy / 2.0).tan();
+                let half_width = half_height * aspect;
+
+                LveCameraBuilder::new()
+                    .set_orthographic_projection(
+                        -half_width,
+                        half_width,
+                        -half_height,
+                        half_height,
+                        NEAR_PLANE,
+                        FAR_PLANE,
+                    )
+                    .build()
+            }
+        }
+    }
+
+    fn toggle_projection(&mut self) {
+        self.projection_mode = self.projection_mode.toggled();
+        log::info!("Switching to {:?} projection", self.projection_mode);
+
+        self.camera =
+            Self::build_camera(self.projection_mode, self.lve_renderer.get_aspect_ratio());
+
+        // The view is only updated each frame, so carry it over to the new camera
+        self.camera.set_view_xyz(
+            self.viewer_object.transform.translation(),
+            self.viewer_object.transform.rotation(),
+        );
+    }
+
+    /// Walking has no roll, so leaving free fly levels the viewer out, keeping where it faces
+    fn toggle_free_fly(&mut self) {
+        self.free_fly = !self.free_fly;
+        log::info!(
+            "Switching to {} camera",
+            if self.free_fly { "free fly" } else { "walking" }
+        );
+
+        if !self.free_fly {
+            let mut rotation = self.viewer_object.transform.rotation();
+            rotation[2] = 0.0;
+            self.viewer_object.transform.set_rotation(rotation);
+        }
+    }
+
+    /// Moves the viewer so every visible object is in view
+    fn frame_scene(&mut self) {
+        let bounds = match SceneBounds::from_objects(self.game_objects.iter()) {
+            Some(bounds) => bounds,
+            None => {
+                log::info!("Nothing visible to frame");
+                return;
+            }
+        };
+
+        if let Some((translation, rotation)) = self.camera.framing_view(&bounds) {
+            self.viewer_object.transform.set_translation(translation);
+            self.viewer_object.transform.set_rotation(rotation);
+        }
+    }
+
+    /// Going in or out of fullscreen resizes the window, and the resulting `Resized` event
+    /// recreates the swapchain (and its depth images) at the new size
+    fn toggle_fullscreen(&mut self) {
+        if self.window.fullscreen().is_some() {
+            log::info!("Leaving fullscreen");
+            self.window.set_fullscreen(None);
+            return;
+        }
+
+        log::info!("Entering {:?} fullscreen", self.fullscreen_mode);
+
+        match self.fullscreen_mode.to_winit(self.window.current_monitor()) {
+            Some(fullscreen) => self.window.set_fullscreen(Some(fullscreen)),
+            None => log::warn!("No monitor to go fullscreen on"),
+        }
     }
 
     pub fn resize(&mut self) {
-        self.lve_renderer.recreate_swapchain(&self.window)
+        self.lve_renderer.recreate_swapchain(&self.window);
+        self.camera.set_aspect(self.lve_renderer.get_aspect_ratio());
     }
 
-    fn new_window(w: u32, h: u32, name: &str) -> (EventLoop<()>, Window) {
+    fn new_window(window_config: &WindowConfig) -> (EventLoop<()>, Window) {
         log::debug!("Starting event loop");
         let event_loop = EventLoop::new();
 
         log::debug!("Creating window");
-        let winit_window = WindowBuilder::new()
-            .with_title(name)
-            .with_inner_size(LogicalSize::new(w, h))
-            .with_resizable(true)
+        let winit_window = window_config
+            .window_builder(&event_loop)
             .build(&event_loop)
+            .map_err(|e| log::error!("Unable to create window: {}", e))
             .unwrap();
 
         (event_loop, winit_window)
     }
 
-    fn load_game_objects(lve_device: &Rc<LveDevice>) -> HashMap<u64, LveGameObject> {
-        let mut game_objects: HashMap<u64, LveGameObject> = HashMap::new();
+    fn load_game_objects(
+        lve_device: &Rc<LveDevice>,
+        asset_cache: &mut LveAssetCache,
+    ) -> GameObjectManager {
+        let mut game_objects = GameObjectManager::new();
 
-        let mut object_id: u64 = 0;
+        let mut model_paths = vec![
+            "models/smooth_vase.obj",
+            "models/flat_vase.obj",
+            "models/quad.obj",
+        ];
 
-        let smooth_vase =
-            LveModel::create_model_from_file(Rc::clone(lve_device), "models/smooth_vase.obj");
+        if PHYSICS_DEMO {
+            model_paths.push("models/cube.obj");
+        }
 
-        let transform = Some(TransformComponent {
-            translation: na::vector![-0.5, 0.5, 0.0],
-            scale: na::vector![3.0, 1.5, 3.0],
-            rotation: na::vector![0.0, 0.0, 0.0],
-        });
+        // Parsed in parallel, the loads below are then cache hits
+        asset_cache.preload_models(&model_paths);
 
-        game_objects.insert(object_id, LveGameObject::new(smooth_vase, None, transform));
-        object_id += 1;
+        let smooth_vase = asset_cache.create_model_cached("models/smooth_vase.obj");
 
-        let flat_vase =
-            LveModel::create_model_from_file(Rc::clone(lve_device), "models/flat_vase.obj");
+        let transform = Some(TransformComponent::new(
+            na::vector![-0.5, 0.5, 0.0],
+            na::vector![3.0, 1.5, 3.0],
+            na::vector![0.0, 0.0, 0.0],
+        ));
 
-        let transform = Some(TransformComponent {
-            translation: na::vector![0.5, 0.5, 0.0],
-            scale: na::vector![3.0, 3.0, 3.0],
-            rotation: na::vector![0.0, 0.0, 0.0],
-        });
+        let mut smooth_vase = LveGameObject::new(smooth_vase, None, transform);
+        smooth_vase.outlined = true;
 
-        game_objects.insert(object_id, LveGameObject::new(flat_vase, None, transform));
-        object_id += 1;
+        if GENERATED_LODS {
+            let levels = LveModel::create_lods_from_file(
+                lve_device,
+                "models/smooth_vase.obj",
+                false,
+                &GENERATED_LOD_RATIOS,
+            );
 
-        let floor = LveModel::create_model_from_file(Rc::clone(lve_device), "models/quad.obj");
+            smooth_vase.lod = Some(LodComponent::new(
+                levels
+                    .into_iter()
+                    .zip(GENERATED_LOD_DISTANCES)
+                    .map(|(model, max_distance)| LodLevel {
+                        model,
+                        max_distance,
+                    })
+                    .collect(),
+            ));
+        }
 
-        let transform = Some(TransformComponent {
-            translation: na::vector![0.0, 0.5, 0.0],
-            scale: na::vector![3.0, 1.0, 3.0],
-            rotation: na::vector![0.0, 0.0, 0.0],
-        });
+        game_objects.create_object(smooth_vase);
+
+        let flat_vase = asset_cache.create_model_cached("models/flat_vase.obj");
+
+        let transform = Some(TransformComponent::new(
+            na::vector![0.5, 0.5, 0.0],
+            na::vector![3.0, 3.0, 3.0],
+            na::vector![0.0, 0.0, 0.0],
+        ));
+
+        let mut flat_vase = LveGameObject::new(flat_vase, None, transform);
+
+        if MATERIAL_DEMO {
+            flat_vase.material = Some(MaterialComponent {
+                albedo: na::vector![0.95, 0.64, 0.54],
+                roughness: 0.3,
+                metallic: 1.0,
+            });
+        }
+
+        game_objects.create_object(flat_vase);
+
+        let floor = asset_cache.create_model_cached("models/quad.obj");
 
-        game_objects.insert(object_id, LveGameObject::new(floor, None, transform));
-        // object_id += 1;
+        let transform = Some(TransformComponent::new(
+            na::vector![0.0, 0.5, 0.0],
+            na::vector![3.0, 1.0, 3.0],
+            na::vector![0.0, 0.0, 0.0],
+        ));
+
+        let mut floor = LveGameObject::new(floor, None, transform);
+        floor.physics = Some(PhysicsComponent::fixed());
+
+        game_objects.create_object(floor);
+
+        if PHYSICS_DEMO {
+            let cube = asset_cache.create_model_cached("models/cube.obj");
+
+            for level in 0..4 {
+                // Slightly offset so the stack topples a little as it lands
+                let transform = TransformComponent::new(
+                    na::vector![0.02 * level as f32, -0.5 - 0.3 * level as f32, -0.8],
+                    na::vector![0.1, 0.1, 0.1],
+                    na::vector![0.0, 0.0, 0.0],
+                );
+
+                let mut cube = LveGameObject::new(Rc::clone(&cube), None, Some(transform));
+                cube.physics = Some(PhysicsComponent::new(1.0, 0.3));
+
+                game_objects.create_object(cube);
+            }
+        }
+
+        let point_light = LveGameObject::make_point_light(
+            1.0,
+            na::vector![1.0, 1.0, 1.0],
+            na::vector![-1.0, -1.0, -1.0],
+        );
+
+        game_objects.create_object(point_light);
 
         game_objects
     }
+
+    /// Stands the skinned model in `file_path` on the floor, playing its first animation
+    fn add_skinned_model(
+        lve_device: &Rc<LveDevice>,
+        game_objects: &mut GameObjectManager,
+        file_path: &str,
+    ) {
+        let skinned_model = LveSkinnedModel::load(Rc::clone(lve_device), file_path);
+
+        let transform = Some(TransformComponent::new(
+            na::vector![0.0, 0.5, -0.5],
+            na::vector![0.5, 0.5, 0.5],
+            // glTF is y up, this engine is y down
+            na::vector![std::f32::consts::PI, 0.0, 0.0],
+        ));
+
+        let mut game_object =
+            LveGameObject::new(Rc::clone(&skinned_model.model), None, transform);
+
+        let first_clip = (!skinned_model.clips.is_empty()).then_some(0);
+
+        game_object.skin = Some(SkinComponent {
+            model: skinned_model,
+            player: AnimationPlayer::new(first_clip),
+        });
+
+        game_objects.create_object(game_object);
+    }
 }
@@ -0,0 +1,257 @@
+use super::lve_device::*;
+use super::lve_frameinfo::FrameInfo;
+use super::lve_game_object::LveGameObject;
+use super::lve_pipeline::*;
+use super::render_system::RenderSystem;
+use super::simple_render_system::Align16;
+
+use ash::{vk, Device};
+
+use std::rc::Rc;
+
+extern crate nalgebra as na;
+
+/// How much bigger than the object the outline is drawn
+const OUTLINE_SCALE: f32 = 1.05;
+/// The stencil value written under outlined objects
+const OUTLINE_STENCIL_REFERENCE: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct OutlinePushConstantData {
+    _model_matrix: Align16<na::Matrix4<f32>>,
+    _color: na::Vector4<f32>,
+}
+
+impl OutlinePushConstantData {
+    fn new(model_matrix: na::Matrix4<f32>, color: na::Vector3<f32>) -> Self {
+        Self {
+            _model_matrix: Align16(model_matrix),
+            _color: na::vector![color[0], color[1], color[2], 1.0],
+        }
+    }
+
+    /// The model matrix is visible to the vertex stage and the color to the fragment stage
+    pub fn push_constant_layout(lve_device: &LveDevice) -> PushConstantLayout {
+        let matrix_size = std::mem::size_of::<na::Matrix4<f32>>() as u32;
+
+        PushConstantLayout::new(
+            lve_device,
+            vec![
+                vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::VERTEX)
+                    .offset(0)
+                    .size(matrix_size)
+                    .build(),
+                vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .offset(matrix_size)
+                    .size(std::mem::size_of::<na::Vector4<f32>>() as u32)
+                    .build(),
+            ],
+        )
+    }
+
+    pub unsafe fn as_bytes(&self) -> &[u8] {
+        let size_in_bytes = std::mem::size_of::<Self>();
+        let start_ptr = self as *const Self as *const u8;
+        std::slice::from_raw_parts(start_ptr, size_in_bytes)
+    }
+}
+
+/// Draws a solid outline around every object with `outlined` set, in two passes:
+///
+/// 1. The object is drawn without colour, writing `OUTLINE_STENCIL_REFERENCE` to the stencil
+///    buffer wherever it covers
+/// 2. A slightly scaled up copy is drawn in the outline colour, but only where the stencil is
+///    not the reference, leaving just the rim around the object
+///
+/// Depth testing is off for both passes so the outline shows through anything in front of the
+/// object. Needs a depth format with a stencil component, see
+/// `LveSwapchain::has_stencil_component`
+pub struct OutlineRenderSystem {
+    lve_device: Rc<LveDevice>,
+    stencil_pipeline: LvePipeline,
+    outline_pipeline: LvePipeline,
+    pipeline_layout: vk::PipelineLayout,
+    push_constant_layout: PushConstantLayout,
+}
+
+impl OutlineRenderSystem {
+    pub fn new(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        global_set_layout: vk::DescriptorSetLayout,
+    ) -> Self {
+        let push_constant_layout = OutlinePushConstantData::push_constant_layout(&lve_device);
+
+        let pipeline_layout = Self::create_pipeline_layout(
+            &lve_device.device,
+            global_set_layout,
+            &push_constant_layout,
+        );
+
+        let stencil_pipeline =
+            Self::create_stencil_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout);
+
+        let outline_pipeline =
+            Self::create_outline_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout);
+
+        Self {
+            lve_device,
+            stencil_pipeline,
+            outline_pipeline,
+            pipeline_layout,
+            push_constant_layout,
+        }
+    }
+
+    fn create_stencil_pipeline(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        pipeline_layout: &vk::PipelineLayout,
+    ) -> LvePipeline {
+        assert!(
+            pipeline_layout != &vk::PipelineLayout::null(),
+            "Cannot create pipeline before pipeline layout"
+        );
+
+        let mut pipeline_config = LvePipeline::default_pipline_config_info();
+
+        LvePipeline::disable_color_writes(&mut pipeline_config);
+        LvePipeline::enable_stencil_write(&mut pipeline_config, OUTLINE_STENCIL_REFERENCE);
+        LvePipeline::disable_depth_test(&mut pipeline_config);
+
+        LvePipeline::new_vertex_only(
+            lve_device,
+            "shaders/outline_shader.vert.spv",
+            pipeline_config,
+            render_pass,
+            pipeline_layout,
+        )
+    }
+
+    fn create_outline_pipeline(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        pipeline_layout: &vk::PipelineLayout,
+    ) -> LvePipeline {
+        assert!(
+            pipeline_layout != &vk::PipelineLayout::null(),
+            "Cannot create pipeline before pipeline layout"
+        );
+
+        let mut pipeline_config = LvePipeline::default_pipline_config_info();
+
+        LvePipeline::enable_stencil_not_equal_test(&mut pipeline_config, OUTLINE_STENCIL_REFERENCE);
+        LvePipeline::disable_depth_test(&mut pipeline_config);
+
+        LvePipeline::new(
+            lve_device,
+            "shaders/outline_shader.vert.spv",
+            "shaders/outline_shader.frag.spv",
+            pipeline_config,
+            render_pass,
+            pipeline_layout,
+        )
+    }
+
+    fn create_pipeline_layout(
+        device: &Device,
+        global_set_layout: vk::DescriptorSetLayout,
+        push_constant_layout: &PushConstantLayout,
+    ) -> vk::PipelineLayout {
+        let descriptor_set_layouts = vec![global_set_layout];
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(descriptor_set_layouts.as_slice())
+            .push_constant_ranges(push_constant_layout.ranges())
+            .build();
+
+        unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .map_err(|e| log::error!("Unable to create outline pipeline layout: {}", e))
+                .unwrap()
+        }
+    }
+
+    fn draw(
+        &self,
+        frame_info: &FrameInfo,
+        game_obj: &LveGameObject,
+        model_matrix: na::Matrix4<f32>,
+    ) {
+        let push = OutlinePushConstantData::new(model_matrix, game_obj.outline_color);
+
+        unsafe {
+            self.push_constant_layout.push(
+                &self.lve_device.device,
+                frame_info.command_buffer,
+                self.pipeline_layout,
+                push.as_bytes(),
+            );
+
+            let model = game_obj.model_at(frame_info.camera.position());
+            model.bind(&self.lve_device.device, frame_info.command_buffer);
+            model.draw(&self.lve_device.device, frame_info.command_buffer);
+            frame_info.record_draw(model.triangle_count());
+        }
+    }
+}
+
+impl RenderSystem for OutlineRenderSystem {
+    fn render(&mut self, frame_info: &FrameInfo) {
+        unsafe {
+            self.lve_device.device.cmd_bind_descriptor_sets(
+                frame_info.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[frame_info.global_descriptor_set],
+                &[],
+            );
+        };
+
+        // Every object has to be in the stencil before any outline is drawn, otherwise an
+        // outline could be drawn over another outlined object
+        unsafe {
+            self.stencil_pipeline
+                .bind(&self.lve_device.device, frame_info.command_buffer);
+        }
+        frame_info.record_pipeline_bind();
+
+        for (_, game_obj) in frame_info.game_objects.iter() {
+            if game_obj.visible && game_obj.outlined {
+                let model_matrix = game_obj.transform.cached_mat4();
+                self.draw(frame_info, game_obj, model_matrix);
+            }
+        }
+
+        unsafe {
+            self.outline_pipeline
+                .bind(&self.lve_device.device, frame_info.command_buffer);
+        }
+        frame_info.record_pipeline_bind();
+
+        for (_, game_obj) in frame_info.game_objects.iter() {
+            if game_obj.visible && game_obj.outlined {
+                let model_matrix =
+                    game_obj.transform.cached_mat4() * na::Matrix4::new_scaling(OUTLINE_SCALE);
+                self.draw(frame_info, game_obj, model_matrix);
+            }
+        }
+    }
+}
+
+impl Drop for OutlineRenderSystem {
+    fn drop(&mut self) {
+        log::debug!("Dropping OutlineRenderSystem");
+
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
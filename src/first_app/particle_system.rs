@@ -0,0 +1,417 @@
+use super::lve_buffer::*;
+use super::lve_device::*;
+use super::lve_frameinfo::FrameInfo;
+use super::lve_pipeline::*;
+use super::lve_swapchain::MAX_FRAMES_IN_FLIGHT;
+use super::render_system::RenderSystem;
+
+use ash::{vk, Device};
+
+use std::mem::size_of;
+use std::rc::Rc;
+
+extern crate nalgebra as na;
+
+/// Particles alive at once per emitter, spawns past this wait for a particle to die
+const MAX_PARTICLES: usize = 4096;
+/// Acceleration due to gravity, +y points down in this engine
+const GRAVITY: f32 = 9.81;
+/// Frames can be long (e.g. dragging the window), spawning a whole second of particles at
+/// once makes a visible clump
+const MAX_SPAWN_TIME: f32 = 0.1;
+
+/// What an emitter spawns and how its particles change over their life
+#[derive(Debug, Clone, Copy)]
+pub struct EmitterConfig {
+    /// Where particles spawn, in world space
+    pub position: na::Vector3<f32>,
+    /// Particles spawned per second
+    pub rate: f32,
+    /// How long each particle lives, in seconds
+    pub lifetime: f32,
+    pub initial_velocity: na::Vector3<f32>,
+    /// Up to this much is added to `initial_velocity` in a random direction
+    pub velocity_spread: f32,
+    /// 0 for particles that float, 1 for particles that fall like everything else
+    pub gravity_scale: f32,
+    /// Half the width of each billboard, in world units
+    pub size: f32,
+    /// Linear colours the particles fade between over their life. Blended additively, so the
+    /// alpha only scales the colour and fading to 0 fades the particle out
+    pub start_color: na::Vector4<f32>,
+    pub end_color: na::Vector4<f32>,
+}
+
+impl Default for EmitterConfig {
+    /// A small fountain of orange sparks
+    fn default() -> Self {
+        Self {
+            position: na::Vector3::zeros(),
+            rate: 200.0,
+            lifetime: 1.5,
+            initial_velocity: na::vector![0.0, -3.0, 0.0],
+            velocity_spread: 1.0,
+            gravity_scale: 1.0,
+            size: 0.02,
+            start_color: na::vector![1.0, 0.6, 0.2, 1.0],
+            end_color: na::vector![1.0, 0.1, 0.0, 0.0],
+        }
+    }
+}
+
+/// One particle's state. Laid out to match std430, so the same data can live in a storage
+/// buffer once particles are updated by a compute shader
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Particle {
+    position: na::Vector3<f32>,
+    /// Seconds since the particle spawned
+    age: f32,
+    velocity: na::Vector3<f32>,
+    lifetime: f32,
+}
+
+/// Per instance data for a single particle billboard
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ParticleInstance {
+    position_size: [f32; 4], // xyz is the world position, w is the size
+    color: [f32; 4],
+}
+
+impl ParticleInstance {
+    fn get_binding_descriptions() -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<ParticleInstance>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build()]
+    }
+
+    fn get_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+        (0..2)
+            .map(|location| vk::VertexInputAttributeDescription {
+                binding: 0,
+                location,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: location * size_of::<[f32; 4]>() as u32,
+            })
+            .collect()
+    }
+}
+
+/// Spawns, moves and kills the particles of one emitter on the CPU
+struct ParticleEmitter {
+    config: EmitterConfig,
+    particles: Vec<Particle>,
+    /// Fractions of a particle left over from previous updates
+    spawn_accumulator: f32,
+    /// xorshift state for the velocity spread, never 0
+    rng_state: u32,
+}
+
+impl ParticleEmitter {
+    fn new(config: EmitterConfig) -> Self {
+        Self {
+            config,
+            particles: Vec::with_capacity(MAX_PARTICLES),
+            spawn_accumulator: 0.0,
+            rng_state: 0x9e37_79b9,
+        }
+    }
+
+    /// Ages and moves every particle, removes the ones past their lifetime, then spawns
+    /// `rate * delta_time` new ones
+    fn update(&mut self, delta_time: f32) {
+        let gravity = na::vector![0.0, GRAVITY * self.config.gravity_scale, 0.0];
+
+        for particle in self.particles.iter_mut() {
+            particle.age += delta_time;
+            particle.velocity += gravity * delta_time;
+            particle.position += particle.velocity * delta_time;
+        }
+
+        self.particles
+            .retain(|particle| particle.age < particle.lifetime);
+
+        self.spawn_accumulator += self.config.rate * delta_time.min(MAX_SPAWN_TIME);
+
+        while self.spawn_accumulator >= 1.0 {
+            self.spawn_accumulator -= 1.0;
+
+            if self.particles.len() == MAX_PARTICLES {
+                continue;
+            }
+
+            let velocity = self.config.initial_velocity
+                + self.random_direction() * self.config.velocity_spread;
+
+            self.particles.push(Particle {
+                position: self.config.position,
+                age: 0.0,
+                velocity,
+                lifetime: self.config.lifetime,
+            });
+        }
+    }
+
+    /// The billboard for every live particle
+    fn instances(&self) -> impl Iterator<Item = ParticleInstance> + '_ {
+        self.particles.iter().map(move |particle| {
+            let position = particle.position;
+            let life = (particle.age / particle.lifetime).clamp(0.0, 1.0);
+            let color = self.config.start_color.lerp(&self.config.end_color, life);
+
+            ParticleInstance {
+                position_size: [position[0], position[1], position[2], self.config.size],
+                color: [color[0], color[1], color[2], color[3]],
+            }
+        })
+    }
+
+    /// A random point in the unit sphere, for spreading out the initial velocities
+    fn random_direction(&mut self) -> na::Vector3<f32> {
+        loop {
+            let point = na::vector![self.random(), self.random(), self.random()] * 2.0
+                - na::Vector3::repeat(1.0);
+
+            if point.norm_squared() <= 1.0 {
+                return point;
+            }
+        }
+    }
+
+    /// In `0.0..1.0`
+    fn random(&mut self) -> f32 {
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 17;
+        self.rng_state ^= self.rng_state << 5;
+
+        (self.rng_state >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+/// Draws the particles of one emitter as camera facing billboards, blended additively so they
+/// glow and never need sorting. They are depth tested against the scene but don't write depth.
+///
+/// Particles are updated on the CPU each frame and uploaded to a per frame instance buffer.
+/// `Particle` already matches the std430 layout a compute shader would need, so the update
+/// can move to the GPU without changing how particles are drawn
+pub struct ParticleSystem {
+    lve_device: Rc<LveDevice>,
+    lve_pipeline: LvePipeline,
+    pipeline_layout: vk::PipelineLayout,
+    instance_buffers: Vec<LveBuffer>,
+    emitter: ParticleEmitter,
+}
+
+impl ParticleSystem {
+    pub fn new(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        global_set_layout: vk::DescriptorSetLayout,
+        config: EmitterConfig,
+    ) -> Self {
+        let pipeline_layout = Self::create_pipeline_layout(&lve_device.device, global_set_layout);
+
+        let lve_pipeline =
+            Self::create_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout);
+
+        let instance_buffers = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let mut buffer = LveBuffer::new(
+                    Rc::clone(&lve_device),
+                    size_of::<ParticleInstance>() as u64,
+                    MAX_PARTICLES as u32,
+                    vk::BufferUsageFlags::VERTEX_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    1,
+                    BufferType::Instance,
+                );
+
+                unsafe { buffer.map(vk::WHOLE_SIZE, 0) };
+
+                buffer
+            })
+            .collect();
+
+        Self {
+            lve_device,
+            lve_pipeline,
+            pipeline_layout,
+            instance_buffers,
+            emitter: ParticleEmitter::new(config),
+        }
+    }
+
+    /// Changes take effect for particles spawned from now on
+    #[allow(dead_code)]
+    pub fn emitter_config_mut(&mut self) -> &mut EmitterConfig {
+        &mut self.emitter.config
+    }
+
+    fn create_pipeline(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        pipeline_layout: &vk::PipelineLayout,
+    ) -> LvePipeline {
+        assert!(
+            pipeline_layout != &vk::PipelineLayout::null(),
+            "Cannot create pipeline before pipeline layout"
+        );
+
+        let mut pipeline_config = LvePipeline::default_pipline_config_info();
+
+        LvePipeline::enable_additive_blending(&mut pipeline_config);
+
+        // Hidden behind the scene, but particles don't hide each other
+        LvePipeline::disable_depth_write(&mut pipeline_config);
+
+        pipeline_config.binding_descriptions = ParticleInstance::get_binding_descriptions();
+        pipeline_config.attribute_descriptions = ParticleInstance::get_attribute_descriptions();
+
+        LvePipeline::new(
+            lve_device,
+            "shaders/particle_shader.vert.spv",
+            "shaders/particle_shader.frag.spv",
+            pipeline_config,
+            render_pass,
+            pipeline_layout,
+        )
+    }
+
+    fn create_pipeline_layout(
+        device: &Device,
+        global_set_layout: vk::DescriptorSetLayout,
+    ) -> vk::PipelineLayout {
+        let descriptor_set_layouts = vec![global_set_layout];
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(descriptor_set_layouts.as_slice())
+            .build();
+
+        unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .map_err(|e| log::error!("Unable to create particle pipeline layout: {}", e))
+                .unwrap()
+        }
+    }
+}
+
+impl RenderSystem for ParticleSystem {
+    /// Steps the particles by the frame time, then draws them. Register it after the opaque
+    /// stage so there is depth to test against
+    fn render(&mut self, frame_info: &FrameInfo) {
+        self.emitter.update(frame_info.frame_time);
+
+        let instances = self.emitter.instances().collect::<Vec<_>>();
+
+        if instances.is_empty() {
+            return;
+        }
+
+        let instance_buffer = &self.instance_buffers[frame_info.frame_index as usize];
+
+        unsafe {
+            instance_buffer.write_to_buffer(
+                instances.as_slice(),
+                (instances.len() * size_of::<ParticleInstance>()) as u64,
+                0,
+            );
+
+            self.lve_pipeline
+                .bind(&self.lve_device.device, frame_info.command_buffer);
+            frame_info.record_pipeline_bind();
+
+            self.lve_device.device.cmd_bind_descriptor_sets(
+                frame_info.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[frame_info.global_descriptor_set],
+                &[],
+            );
+
+            self.lve_device.device.cmd_bind_vertex_buffers(
+                frame_info.command_buffer,
+                0,
+                &[instance_buffer.buffer],
+                &[0],
+            );
+
+            self.lve_device.device.cmd_draw(
+                frame_info.command_buffer,
+                6,
+                instances.len() as u32,
+                0,
+                0,
+            );
+            frame_info.record_draw(2 * instances.len() as u64);
+        }
+    }
+}
+
+impl Drop for ParticleSystem {
+    fn drop(&mut self) {
+        log::debug!("Dropping ParticleSystem");
+
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn particles_spawn_at_the_rate_and_die_after_their_lifetime() {
+        // Powers of two so the times add up exactly
+        let mut emitter = ParticleEmitter::new(EmitterConfig {
+            rate: 16.0,
+            lifetime: 1.0,
+            ..EmitterConfig::default()
+        });
+
+        for _ in 0..8 {
+            emitter.update(1.0 / 16.0);
+        }
+
+        assert_eq!(emitter.particles.len(), 8);
+
+        // Once the first particles have died, the live count settles at rate * lifetime
+        for _ in 0..32 {
+            emitter.update(1.0 / 16.0);
+        }
+
+        assert_eq!(emitter.particles.len(), 16);
+        assert!(emitter
+            .particles
+            .iter()
+            .all(|particle| particle.age < particle.lifetime));
+    }
+
+    #[test]
+    fn color_fades_from_start_to_end_over_life() {
+        let mut emitter = ParticleEmitter::new(EmitterConfig {
+            start_color: na::vector![1.0, 1.0, 1.0, 1.0],
+            end_color: na::vector![0.0, 0.5, 0.0, 0.0],
+            ..EmitterConfig::default()
+        });
+
+        emitter.particles.push(Particle {
+            position: na::Vector3::zeros(),
+            age: 1.0,
+            velocity: na::Vector3::zeros(),
+            lifetime: 4.0,
+        });
+
+        let instances = emitter.instances().collect::<Vec<_>>();
+
+        assert_eq!(instances[0].color, [0.75, 0.875, 0.75, 0.75]);
+    }
+}
@@ -0,0 +1,304 @@
+use super::lve_game_object::GameObjectManager;
+
+extern crate nalgebra as na;
+
+/// The simulation always advances in steps of this many seconds, however long the frames are,
+/// so it behaves the same at any frame rate
+const FIXED_TIMESTEP: f32 = 1.0 / 120.0;
+/// After a long stall (e.g. dragging the window) the simulation slows down for a moment rather
+/// than running hundreds of steps to catch up
+const MAX_STEPS_PER_UPDATE: u32 = 8;
+/// Acceleration due to gravity, +y points down in this engine
+const GRAVITY: f32 = 9.81;
+/// Collisions slower than this along the contact normal don't bounce, so resting objects
+/// settle instead of jittering off the floor every step
+const BOUNCE_SPEED: f32 = 0.2;
+/// Times the collisions are resolved each step. Pushing one object out of another can push
+/// it into a third, so stacks need a few passes to settle
+const SOLVER_ITERATIONS: u32 = 4;
+
+/// Steps every object with a `PhysicsComponent` at a fixed timestep. Objects collide using
+/// their world space bounding boxes, and are all treated as having the same mass
+#[derive(Debug, Default)]
+pub struct PhysicsWorld {
+    /// Frame time not yet simulated, always less than one step after an update
+    accumulator: f32,
+}
+
+impl PhysicsWorld {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs as many fixed steps as fit in `frame_time` plus what was left over last time
+    pub fn update(&mut self, game_objects: &mut GameObjectManager, frame_time: f32) {
+        self.accumulator += frame_time;
+
+        let mut steps = 0;
+
+        while self.accumulator >= FIXED_TIMESTEP {
+            if steps == MAX_STEPS_PER_UPDATE {
+                self.accumulator = 0.0;
+                break;
+            }
+
+            Self::step(game_objects, FIXED_TIMESTEP);
+
+            self.accumulator -= FIXED_TIMESTEP;
+            steps += 1;
+        }
+    }
+
+    fn step(game_objects: &mut GameObjectManager, delta_time: f32) {
+        let mut bodies: Vec<(u64, Body)> = game_objects
+            .iter()
+            .filter_map(|(id, game_object)| {
+                let physics = game_object.physics.as_ref()?;
+                let (local_min, local_max) = game_object.model.aabb();
+                let (min, max) = game_object.transform.transform_aabb(local_min, local_max);
+
+                Some((
+                    *id,
+                    Body {
+                        min,
+                        max,
+                        velocity: physics.velocity,
+                        gravity_scale: physics.gravity_scale,
+                        restitution: physics.restitution,
+                        fixed: physics.fixed,
+                        displacement: na::Vector3::zeros(),
+                    },
+                ))
+            })
+            .collect();
+
+        // The objects are stored in a hash map, so sort them to resolve in the same order
+        // every step
+        bodies.sort_by_key(|(id, _)| *id);
+
+        let mut step_bodies: Vec<Body> = bodies.iter().map(|(_, body)| *body).collect();
+
+        integrate(&mut step_bodies, delta_time);
+
+        for _ in 0..SOLVER_ITERATIONS {
+            resolve_collisions(&mut step_bodies);
+        }
+
+        for ((id, _), body) in bodies.iter().zip(step_bodies) {
+            if body.fixed {
+                continue;
+            }
+
+            if let Some(game_object) = game_objects.get_mut(*id) {
+                let translation = game_object.transform.translation() + body.displacement;
+                game_object.transform.set_translation(translation);
+
+                if let Some(physics) = &mut game_object.physics {
+                    physics.velocity = body.velocity;
+                }
+            }
+        }
+    }
+}
+
+/// A physics object's state during a step, in world space
+#[derive(Debug, Clone, Copy)]
+struct Body {
+    min: na::Vector3<f32>,
+    max: na::Vector3<f32>,
+    velocity: na::Vector3<f32>,
+    gravity_scale: f32,
+    restitution: f32,
+    fixed: bool,
+    /// How far the body has moved this step
+    displacement: na::Vector3<f32>,
+}
+
+impl Body {
+    fn translate(&mut self, offset: na::Vector3<f32>) {
+        self.min += offset;
+        self.max += offset;
+        self.displacement += offset;
+    }
+}
+
+/// Applies gravity, then moves each body by its velocity
+fn integrate(bodies: &mut [Body], delta_time: f32) {
+    for body in bodies.iter_mut().filter(|body| !body.fixed) {
+        body.velocity[1] += GRAVITY * body.gravity_scale * delta_time;
+
+        let offset = body.velocity * delta_time;
+        body.translate(offset);
+    }
+}
+
+/// Pushes every overlapping pair apart along the axis they overlap least on, and removes (or
+/// with restitution, reflects) the part of their velocities heading into each other
+fn resolve_collisions(bodies: &mut [Body]) {
+    for i in 0..bodies.len() {
+        for j in (i + 1)..bodies.len() {
+            let (head, tail) = bodies.split_at_mut(j);
+            let (a, b) = (&mut head[i], &mut tail[0]);
+
+            if a.fixed && b.fixed {
+                continue;
+            }
+
+            let push = match penetration((a.min, a.max), (b.min, b.max)) {
+                Some(push) => push,
+                None => continue,
+            };
+
+            // How much of the correction each body takes, a fixed body takes none of it
+            let (a_share, b_share) = match (a.fixed, b.fixed) {
+                (true, _) => (0.0, 1.0),
+                (_, true) => (1.0, 0.0),
+                _ => (0.5, 0.5),
+            };
+
+            a.translate(push * a_share);
+            b.translate(-push * b_share);
+
+            let normal = push.normalize();
+            let approach_speed = (a.velocity - b.velocity).dot(&normal);
+
+            // Already separating
+            if approach_speed >= 0.0 {
+                continue;
+            }
+
+            let restitution = if -approach_speed < BOUNCE_SPEED {
+                0.0
+            } else {
+                a.restitution.min(b.restitution)
+            };
+
+            let impulse = normal * (-(1.0 + restitution) * approach_speed);
+
+            a.velocity += impulse * a_share;
+            b.velocity -= impulse * b_share;
+        }
+    }
+}
+
+/// The smallest move that takes box `a` out of box `b`, or `None` if they don't overlap.
+/// Boxes that only touch don't overlap, but flat boxes (e.g. a floor quad) do
+fn penetration(
+    (a_min, a_max): (na::Vector3<f32>, na::Vector3<f32>),
+    (b_min, b_max): (na::Vector3<f32>, na::Vector3<f32>),
+) -> Option<na::Vector3<f32>> {
+    // How far `a` has to move down or up each axis to clear `b`
+    let to_clear_below = a_max - b_min;
+    let to_clear_above = b_max - a_min;
+
+    let mut push = None;
+
+    for axis in 0..3 {
+        let (below, above) = (to_clear_below[axis], to_clear_above[axis]);
+
+        if below <= 0.0 || above <= 0.0 {
+            return None;
+        }
+
+        let axis_push = if below < above { -below } else { above };
+
+        match push {
+            Some((_, shortest)) if f32::abs(shortest) <= f32::abs(axis_push) => {}
+            _ => push = Some((axis, axis_push)),
+        }
+    }
+
+    push.map(|(axis, axis_push)| {
+        let mut push = na::Vector3::zeros();
+        push[axis] = axis_push;
+        push
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(min: na::Vector3<f32>, max: na::Vector3<f32>, fixed: bool) -> Body {
+        Body {
+            min,
+            max,
+            velocity: na::Vector3::zeros(),
+            gravity_scale: 1.0,
+            restitution: 0.5,
+            fixed,
+            displacement: na::Vector3::zeros(),
+        }
+    }
+
+    #[test]
+    fn penetration_is_along_the_shallowest_axis() {
+        let a = (na::vector![0.0, 0.0, 0.0], na::vector![1.0, 1.0, 1.0]);
+        let b = (na::vector![0.5, 0.9, 0.0], na::vector![1.5, 2.0, 1.0]);
+
+        let push = penetration(a, b).unwrap();
+
+        assert!((push - na::vector![0.0, -0.1, 0.0]).norm() < 1e-5);
+    }
+
+    #[test]
+    fn separate_or_touching_boxes_dont_overlap() {
+        let a = (na::vector![0.0, 0.0, 0.0], na::vector![1.0, 1.0, 1.0]);
+
+        assert_eq!(
+            penetration(a, (na::vector![2.0, 0.0, 0.0], na::vector![3.0, 1.0, 1.0])),
+            None
+        );
+        assert_eq!(
+            penetration(a, (na::vector![1.0, 0.0, 0.0], na::vector![2.0, 1.0, 1.0])),
+            None
+        );
+    }
+
+    #[test]
+    fn falling_box_comes_to_rest_on_a_fixed_floor() {
+        let mut bodies = [
+            body(
+                na::vector![-0.1, -1.0, -0.1],
+                na::vector![0.1, -0.8, 0.1],
+                false,
+            ),
+            // A flat floor, like the quad model
+            body(
+                na::vector![-1.0, 0.0, -1.0],
+                na::vector![1.0, 0.0, 1.0],
+                true,
+            ),
+        ];
+
+        for _ in 0..600 {
+            integrate(&mut bodies, FIXED_TIMESTEP);
+
+            for _ in 0..SOLVER_ITERATIONS {
+                resolve_collisions(&mut bodies);
+            }
+        }
+
+        // Resting on top of the floor, at most one step of gravity into it
+        assert!((bodies[0].max[1] - 0.0).abs() < 1e-3);
+        assert!(bodies[0].velocity.norm() < BOUNCE_SPEED);
+        assert_eq!(bodies[1].displacement, na::Vector3::zeros());
+    }
+
+    #[test]
+    fn fast_collisions_bounce_with_restitution() {
+        let mut falling = body(
+            na::vector![0.0, -0.95, 0.0],
+            na::vector![1.0, 0.05, 1.0],
+            false,
+        );
+        falling.velocity = na::vector![0.0, 2.0, 0.0];
+        let floor = body(na::vector![0.0, 0.0, 0.0], na::vector![1.0, 1.0, 1.0], true);
+
+        let mut bodies = [falling, floor];
+        resolve_collisions(&mut bodies);
+
+        assert!((bodies[0].velocity - na::vector![0.0, -1.0, 0.0]).norm() < 1e-5);
+        assert!((bodies[0].max[1] - 0.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,525 @@
+use super::lve_buffer::*;
+use super::lve_device::*;
+use super::lve_frameinfo::FrameInfo;
+use super::lve_image::LveImage;
+use super::lve_pipeline::*;
+use super::lve_swapchain::{LveSwapchain, MAX_FRAMES_IN_FLIGHT};
+use super::render_system::RenderSystem;
+use super::simple_render_system::Align16;
+
+use ash::{vk, Device};
+
+use std::mem::size_of;
+use std::rc::Rc;
+
+extern crate nalgebra as na;
+
+/// The format of the object id attachment. 0 is cleared to where there is no object, so ids
+/// are written plus one
+const PICKING_FORMAT: vk::Format = vk::Format::R32_UINT;
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct PickingPushConstantData {
+    _model_matrix: Align16<na::Matrix4<f32>>,
+    _object_id: u32,
+}
+
+impl PickingPushConstantData {
+    /// Ids past `u32::MAX - 1` don't fit in the attachment and wrap around
+    fn new(model_matrix: na::Matrix4<f32>, object_id: u64) -> Self {
+        Self {
+            _model_matrix: Align16(model_matrix),
+            _object_id: object_id.wrapping_add(1) as u32,
+        }
+    }
+
+    /// The model matrix is visible to the vertex stage and the id to the fragment stage
+    pub fn push_constant_layout(lve_device: &LveDevice) -> PushConstantLayout {
+        let matrix_size = size_of::<na::Matrix4<f32>>() as u32;
+
+        PushConstantLayout::new(
+            lve_device,
+            vec![
+                vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::VERTEX)
+                    .offset(0)
+                    .size(matrix_size)
+                    .build(),
+                vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .offset(matrix_size)
+                    .size(size_of::<u32>() as u32)
+                    .build(),
+            ],
+        )
+    }
+
+    pub unsafe fn as_bytes(&self) -> &[u8] {
+        let size_in_bytes = size_of::<Self>();
+        let start_ptr = self as *const Self as *const u8;
+        std::slice::from_raw_parts(start_ptr, size_in_bytes)
+    }
+}
+
+/// The offscreen render pass the picking system draws into, and the buffers the picked pixel
+/// is copied back to. Owned by the renderer, see `LveRenderer::pick_at`.
+///
+/// The copy can only be read once the frame that recorded it has finished on the GPU, so each
+/// frame in flight has its own readback buffer. A pick is read back the next time its frame
+/// index starts, `MAX_FRAMES_IN_FLIGHT` frames after it was requested
+pub struct PickingTarget {
+    lve_device: Rc<LveDevice>,
+    render_pass: vk::RenderPass,
+    depth_format: vk::Format,
+    id_image: LveImage,
+    depth_image: LveImage,
+    framebuffer: vk::Framebuffer,
+    readback_buffers: Vec<LveBuffer>,
+    /// The pixel each frame in flight copied into its readback buffer, if any
+    in_flight: Vec<Option<(u32, u32)>>,
+    /// The pixel to copy in the next picking pass
+    requested: Option<(u32, u32)>,
+    latest: Option<u64>,
+}
+
+impl PickingTarget {
+    pub fn new(lve_device: Rc<LveDevice>, extent: vk::Extent2D) -> Self {
+        let depth_format = LveSwapchain::find_depth_format(&lve_device);
+        let render_pass = Self::create_render_pass(&lve_device.device, depth_format);
+
+        let (id_image, depth_image, framebuffer) =
+            Self::create_framebuffer(&lve_device, render_pass, depth_format, extent);
+
+        let readback_buffers = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let mut buffer = LveBuffer::new(
+                    Rc::clone(&lve_device),
+                    size_of::<u32>() as u64,
+                    1,
+                    vk::BufferUsageFlags::TRANSFER_DST,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    1,
+                    BufferType::Instance,
+                );
+
+                unsafe { buffer.map(vk::WHOLE_SIZE, 0) };
+
+                buffer
+            })
+            .collect();
+
+        Self {
+            lve_device,
+            render_pass,
+            depth_format,
+            id_image,
+            depth_image,
+            framebuffer,
+            readback_buffers,
+            in_flight: vec![None; MAX_FRAMES_IN_FLIGHT],
+            requested: None,
+            latest: None,
+        }
+    }
+
+    /// For creating the picking system's pipeline
+    pub fn render_pass_info(&self) -> RenderPassInfo {
+        RenderPassInfo {
+            render_pass: self.render_pass,
+            color_formats: vec![PICKING_FORMAT],
+            subpass: 0,
+        }
+    }
+
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    pub fn framebuffer(&self) -> vk::Framebuffer {
+        self.framebuffer
+    }
+
+    /// Recreates the attachments at the new size, the device must be idle
+    pub fn resize(&mut self, extent: vk::Extent2D) {
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_framebuffer(self.framebuffer, None);
+        }
+
+        let (id_image, depth_image, framebuffer) = Self::create_framebuffer(
+            &self.lve_device,
+            self.render_pass,
+            self.depth_format,
+            extent,
+        );
+
+        self.id_image = id_image;
+        self.depth_image = depth_image;
+        self.framebuffer = framebuffer;
+    }
+
+    /// Asks for the object at `(x, y)` in the next picking pass, and returns the last pick
+    /// that has finished
+    pub fn request(&mut self, x: u32, y: u32) -> Option<u64> {
+        self.requested = Some((x, y));
+        self.latest
+    }
+
+    /// The pixel to copy this frame, if a pick was requested since the last picking pass
+    pub fn take_request(&mut self) -> Option<(u32, u32)> {
+        self.requested.take()
+    }
+
+    /// Copies `pixel` of the id attachment to this frame's readback buffer. Must be recorded
+    /// after the picking render pass, which leaves the attachment ready to transfer from
+    pub fn cmd_copy_pixel(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        pixel: (u32, u32),
+    ) {
+        let region = vk::BufferImageCopy::builder()
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_offset(vk::Offset3D {
+                x: pixel.0 as i32,
+                y: pixel.1 as i32,
+                z: 0,
+            })
+            .image_extent(vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            })
+            .build();
+
+        // Waiting on the frame's fence doesn't make the copy visible to the host by itself
+        let host_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .build();
+
+        unsafe {
+            self.lve_device.device.cmd_copy_image_to_buffer(
+                command_buffer,
+                self.id_image.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.readback_buffers[frame_index].buffer,
+                &[region],
+            );
+
+            self.lve_device.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[host_barrier],
+                &[],
+                &[],
+            );
+        }
+
+        self.in_flight[frame_index] = Some(pixel);
+    }
+
+    /// Reads back the pick `frame_index` copied last time it was recorded. Only call once that
+    /// frame's fence has been waited on
+    pub fn read_back(&mut self, frame_index: usize) {
+        if self.in_flight[frame_index].take().is_none() {
+            return;
+        }
+
+        let id = unsafe { *(self.readback_buffers[frame_index].mapped as *const u32) };
+
+        self.latest = match id {
+            0 => None,
+            id => Some(id as u64 - 1),
+        };
+    }
+
+    fn create_framebuffer(
+        lve_device: &Rc<LveDevice>,
+        render_pass: vk::RenderPass,
+        depth_format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> (LveImage, LveImage, vk::Framebuffer) {
+        let id_image = LveImage::new(
+            Rc::clone(lve_device),
+            PICKING_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::ImageAspectFlags::COLOR,
+            extent,
+        );
+
+        let depth_image = LveImage::new(
+            Rc::clone(lve_device),
+            depth_format,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::ImageAspectFlags::DEPTH,
+            extent,
+        );
+
+        let attachments = [id_image.image_view, depth_image.image_view];
+
+        let framebuffer_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+
+        let framebuffer = unsafe {
+            lve_device
+                .device
+                .create_framebuffer(&framebuffer_info, None)
+                .map_err(|e| log::error!("Unable to create picking framebuffer: {}", e))
+                .unwrap()
+        };
+
+        (id_image, depth_image, framebuffer)
+    }
+
+    fn create_render_pass(device: &Device, depth_format: vk::Format) -> vk::RenderPass {
+        let id_attachment = vk::AttachmentDescription::builder()
+            .format(PICKING_FORMAT)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .build();
+
+        let depth_attachment = vk::AttachmentDescription::builder()
+            .format(depth_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+
+        let id_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build();
+
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+
+        let attachment_refs = [id_attachment_ref];
+
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&attachment_refs)
+            .depth_stencil_attachment(&depth_attachment_ref);
+
+        // One set of attachments is shared by every frame in flight, so each picking pass
+        // waits for the previous one's writes and copy
+        let dependencies = [
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                        | vk::PipelineStageFlags::TRANSFER,
+                )
+                .src_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .dst_subpass(0)
+                .dst_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                )
+                .dst_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .build(),
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .build(),
+        ];
+
+        let attachments = [id_attachment, depth_attachment];
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(std::slice::from_ref(&subpass))
+            .dependencies(&dependencies);
+
+        unsafe {
+            device
+                .create_render_pass(&render_pass_info, None)
+                .map_err(|e| log::error!("Unable to create picking render pass: {}", e))
+                .unwrap()
+        }
+    }
+}
+
+impl Drop for PickingTarget {
+    fn drop(&mut self) {
+        log::debug!("Dropping PickingTarget");
+
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_framebuffer(self.framebuffer, None);
+            self.lve_device
+                .device
+                .destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
+
+/// Draws every visible object's id into the picking target, see `LveRenderer::pick_at`.
+/// Register it in its own `RenderSystemList`, as it must be created with the picking render
+/// pass rather than the swapchain's
+pub struct PickingSystem {
+    lve_device: Rc<LveDevice>,
+    lve_pipeline: LvePipeline,
+    pipeline_layout: vk::PipelineLayout,
+    push_constant_layout: PushConstantLayout,
+}
+
+impl PickingSystem {
+    pub fn new(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        global_set_layout: vk::DescriptorSetLayout,
+    ) -> Self {
+        let push_constant_layout = PickingPushConstantData::push_constant_layout(&lve_device);
+
+        let pipeline_layout = Self::create_pipeline_layout(
+            &lve_device.device,
+            global_set_layout,
+            &push_constant_layout,
+        );
+
+        let lve_pipeline =
+            Self::create_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout);
+
+        Self {
+            lve_device,
+            lve_pipeline,
+            pipeline_layout,
+            push_constant_layout,
+        }
+    }
+
+    fn create_pipeline(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        pipeline_layout: &vk::PipelineLayout,
+    ) -> LvePipeline {
+        assert!(
+            pipeline_layout != &vk::PipelineLayout::null(),
+            "Cannot create pipeline before pipeline layout"
+        );
+
+        // Integer attachments can't be blended, which the default config already leaves off
+        let pipeline_config = LvePipeline::default_pipline_config_info();
+
+        LvePipeline::new(
+            lve_device,
+            "shaders/picking_shader.vert.spv",
+            "shaders/picking_shader.frag.spv",
+            pipeline_config,
+            render_pass,
+            pipeline_layout,
+        )
+    }
+
+    fn create_pipeline_layout(
+        device: &Device,
+        global_set_layout: vk::DescriptorSetLayout,
+        push_constant_layout: &PushConstantLayout,
+    ) -> vk::PipelineLayout {
+        let descriptor_set_layouts = vec![global_set_layout];
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(descriptor_set_layouts.as_slice())
+            .push_constant_ranges(push_constant_layout.ranges())
+            .build();
+
+        unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .map_err(|e| log::error!("Unable to create picking pipeline layout: {}", e))
+                .unwrap()
+        }
+    }
+}
+
+impl RenderSystem for PickingSystem {
+    fn render(&mut self, frame_info: &FrameInfo) {
+        let device = &self.lve_device.device;
+        let command_buffer = frame_info.command_buffer;
+
+        unsafe {
+            self.lve_pipeline.bind(device, command_buffer);
+            frame_info.record_pipeline_bind();
+
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[frame_info.global_descriptor_set],
+                &[],
+            );
+        }
+
+        for (id, game_obj) in frame_info.game_objects.iter() {
+            if !game_obj.visible {
+                continue;
+            }
+
+            let push = PickingPushConstantData::new(game_obj.transform.cached_mat4(), *id);
+
+            unsafe {
+                self.push_constant_layout.push(
+                    device,
+                    command_buffer,
+                    self.pipeline_layout,
+                    push.as_bytes(),
+                );
+
+                let model = game_obj.model_at(frame_info.camera.position());
+                model.bind(device, command_buffer);
+                model.draw(device, command_buffer);
+                frame_info.record_draw(model.triangle_count());
+            }
+        }
+    }
+}
+
+impl Drop for PickingSystem {
+    fn drop(&mut self) {
+        log::debug!("Dropping PickingSystem");
+
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
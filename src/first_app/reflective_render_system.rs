@@ -0,0 +1,215 @@
+use super::lve_descriptors::*;
+use super::lve_device::*;
+use super::lve_frameinfo::FrameInfo;
+use super::lve_pipeline::*;
+use super::lve_texture::LveTexture;
+use super::render_system::RenderSystem;
+use super::simple_render_system::{SimplePushConstantData, USE_VERTEX_COLOR_CONSTANT_ID};
+
+use ash::{vk, Device};
+
+use std::rc::Rc;
+
+/// Draws every object with a `ReflectionComponent`, blending its lit color with the
+/// environment cube map sampled along the reflected view vector.
+///
+/// The cube map lives in its own descriptor set (set 1, a single `samplerCube` at binding 0)
+/// rather than the global set, so it can be swapped without touching the per frame UBOs. A
+/// skybox pass should draw the same environment by binding `environment_descriptor_set` with
+/// a pipeline layout that uses `environment_set_layout` for the same set index, so both passes
+/// share one descriptor set and the reflections always match the visible sky
+pub struct ReflectiveRenderSystem {
+    lve_device: Rc<LveDevice>,
+    lve_pipeline: LvePipeline,
+    pipeline_layout: vk::PipelineLayout,
+    push_constant_layout: PushConstantLayout,
+    environment_set_layout: Rc<LveDescriptorSetLayout>,
+    _descriptor_pool: Rc<LveDescriptorPool>,
+    environment_descriptor_set: vk::DescriptorSet,
+    _environment: Rc<LveTexture>,
+}
+
+impl ReflectiveRenderSystem {
+    pub fn new(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        global_set_layout: vk::DescriptorSetLayout,
+        environment: Rc<LveTexture>,
+        push_constant_layout: PushConstantLayout,
+        depth_prepass: bool,
+        use_vertex_color: bool,
+    ) -> Self {
+        let environment_set_layout = LveDescriptorSetLayoutBuilder::new(Rc::clone(&lve_device))
+            .add_binding(
+                0,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::ShaderStageFlags::FRAGMENT,
+                1,
+            )
+            .build();
+
+        let descriptor_pool = LveDescriptorPoolBuilder::new(Rc::clone(&lve_device))
+            .set_max_sets(1)
+            .add_pool_size(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1)
+            .build();
+
+        let environment_descriptor_set = LveDescriptorWriter::new(
+            Rc::clone(&environment_set_layout),
+            Rc::clone(&descriptor_pool),
+        )
+        .write_image(0, &[environment.descriptor_info()])
+        .build()
+        .map_err(|_| log::error!("Unable to create the environment descriptor set!"))
+        .unwrap();
+
+        let pipeline_layout = Self::create_pipeline_layout(
+            &lve_device.device,
+            global_set_layout,
+            environment_set_layout.descriptor_set_layout,
+            &push_constant_layout,
+        );
+
+        let lve_pipeline = Self::create_pipeline(
+            Rc::clone(&lve_device),
+            render_pass,
+            &pipeline_layout,
+            depth_prepass,
+            use_vertex_color,
+        );
+
+        Self {
+            lve_device,
+            lve_pipeline,
+            pipeline_layout,
+            push_constant_layout,
+            environment_set_layout,
+            _descriptor_pool: descriptor_pool,
+            environment_descriptor_set,
+            _environment: environment,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn environment_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.environment_set_layout.descriptor_set_layout
+    }
+
+    #[allow(dead_code)]
+    pub fn environment_descriptor_set(&self) -> vk::DescriptorSet {
+        self.environment_descriptor_set
+    }
+
+    fn create_pipeline(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        pipeline_layout: &vk::PipelineLayout,
+        depth_prepass: bool,
+        use_vertex_color: bool,
+    ) -> LvePipeline {
+        assert!(
+            pipeline_layout != &vk::PipelineLayout::null(),
+            "Cannot create pipeline before pipeline layout"
+        );
+
+        let mut pipeline_config = LvePipeline::default_pipline_config_info();
+
+        // Same as the SimpleRenderSystem, reflective objects are drawn in the pre-pass too
+        if depth_prepass {
+            pipeline_config.depth_stencil_info.depth_compare_op = vk::CompareOp::EQUAL;
+            pipeline_config.depth_stencil_info.depth_write_enable = vk::FALSE;
+        }
+
+        LvePipeline::add_specialization_constant(
+            &mut pipeline_config,
+            USE_VERTEX_COLOR_CONSTANT_ID,
+            use_vertex_color,
+        );
+
+        // The vertex stage is identical to the simple shader, only the shading differs
+        LvePipeline::new(
+            lve_device,
+            "shaders/simple_shader.vert.spv",
+            "shaders/reflective_shader.frag.spv",
+            pipeline_config,
+            render_pass,
+            pipeline_layout,
+        )
+    }
+
+    fn create_pipeline_layout(
+        device: &Device,
+        global_set_layout: vk::DescriptorSetLayout,
+        environment_set_layout: vk::DescriptorSetLayout,
+        push_constant_layout: &PushConstantLayout,
+    ) -> vk::PipelineLayout {
+        let descriptor_set_layouts = vec![global_set_layout, environment_set_layout];
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(descriptor_set_layouts.as_slice())
+            .push_constant_ranges(push_constant_layout.ranges())
+            .build();
+
+        unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .map_err(|e| log::error!("Unable to create reflective pipeline layout: {}", e))
+                .unwrap()
+        }
+    }
+}
+
+impl RenderSystem for ReflectiveRenderSystem {
+    fn render(&mut self, frame_info: &FrameInfo) {
+        unsafe {
+            self.lve_pipeline
+                .bind(&self.lve_device.device, frame_info.command_buffer);
+            frame_info.record_pipeline_bind();
+
+            self.lve_device.device.cmd_bind_descriptor_sets(
+                frame_info.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[
+                    frame_info.global_descriptor_set,
+                    self.environment_descriptor_set,
+                ],
+                &[],
+            );
+        };
+
+        for (_, game_obj) in frame_info.game_objects.iter() {
+            if !game_obj.visible || game_obj.reflection.is_none() {
+                continue;
+            }
+
+            let push = SimplePushConstantData::from_game_object(game_obj);
+
+            unsafe {
+                self.push_constant_layout.push(
+                    &self.lve_device.device,
+                    frame_info.command_buffer,
+                    self.pipeline_layout,
+                    push.as_bytes(),
+                );
+
+                let model = game_obj.model_at(frame_info.camera.position());
+                model.bind(&self.lve_device.device, frame_info.command_buffer);
+                model.draw(&self.lve_device.device, frame_info.command_buffer);
+                frame_info.record_draw(model.triangle_count());
+            }
+        }
+    }
+}
+
+impl Drop for ReflectiveRenderSystem {
+    fn drop(&mut self) {
+        log::debug!("Dropping ReflectiveRenderSystem");
+
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use super::lve_frameinfo::FrameInfo;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Anything that records draw calls into the swapchain render pass
+pub trait RenderSystem {
+    /// Called once per frame inside the swapchain render pass, after the frame's global UBO has
+    /// been written. Systems bind their own pipeline and descriptor sets
+    fn render(&mut self, frame_info: &FrameInfo);
+}
+
+/// Where in the swapchain render pass a system runs. Stages run in declaration order, so later
+/// stages draw over (and depth test against) the earlier ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RenderStage {
+    /// Fills the background, drawn before anything else without writing depth
+    Background,
+    /// Depth only passes the lit geometry is tested against
+    DepthPrepass,
+    /// Lit scene geometry
+    Opaque,
+    /// Effects drawn on top of the scene, e.g. outlines
+    Overlay,
+    /// Screen space UI, always drawn last
+    Ui,
+}
+
+/// The render systems drawn into the swapchain render pass, kept sorted by stage. Systems in
+/// the same stage run in the order they were registered
+#[derive(Default)]
+pub struct RenderSystemList {
+    systems: Vec<(RenderStage, Rc<RefCell<dyn RenderSystem>>)>,
+}
+
+impl RenderSystemList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, stage: RenderStage, system: impl RenderSystem + 'static) {
+        self.register_shared(stage, Rc::new(RefCell::new(system)));
+    }
+
+    /// For systems the app also needs to reach between frames, e.g. to queue text
+    pub fn register_shared(&mut self, stage: RenderStage, system: Rc<RefCell<dyn RenderSystem>>) {
+        let index = self
+            .systems
+            .iter()
+            .position(|(registered_stage, _)| *registered_stage > stage)
+            .unwrap_or(self.systems.len());
+
+        self.systems.insert(index, (stage, system));
+    }
+
+    /// Runs every system in order, must be called inside the swapchain render pass
+    pub fn render(&self, frame_info: &FrameInfo) {
+        for (_, system) in self.systems.iter() {
+            system.borrow_mut().render(frame_info);
+        }
+    }
+
+    /// The stage of each system, in the order they run
+    #[allow(dead_code)]
+    pub fn stages(&self) -> impl Iterator<Item = RenderStage> + '_ {
+        self.systems.iter().map(|(stage, _)| *stage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NullSystem;
+
+    impl RenderSystem for NullSystem {
+        fn render(&mut self, _frame_info: &FrameInfo) {}
+    }
+
+    #[test]
+    fn systems_are_ordered_by_stage_then_registration() {
+        let mut render_systems = RenderSystemList::new();
+
+        render_systems.register(RenderStage::Ui, NullSystem);
+        render_systems.register(RenderStage::Opaque, NullSystem);
+        render_systems.register(RenderStage::DepthPrepass, NullSystem);
+        render_systems.register(RenderStage::Background, NullSystem);
+        render_systems.register(RenderStage::Overlay, NullSystem);
+        render_systems.register(RenderStage::Opaque, NullSystem);
+
+        assert_eq!(
+            render_systems.stages().collect::<Vec<_>>(),
+            vec![
+                RenderStage::Background,
+                RenderStage::DepthPrepass,
+                RenderStage::Opaque,
+                RenderStage::Opaque,
+                RenderStage::Overlay,
+                RenderStage::Ui,
+            ]
+        );
+    }
+}
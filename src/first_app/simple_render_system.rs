@@ -1,6 +1,11 @@
+use super::lve_buffer::DynamicUboBuffer;
+use super::lve_descriptors::*;
 use super::lve_device::*;
 use super::lve_frameinfo::FrameInfo;
+use super::lve_game_object::LveGameObject;
 use super::lve_pipeline::*;
+use super::lve_swapchain::MAX_FRAMES_IN_FLIGHT;
+use super::render_system::RenderSystem;
 
 use ash::{vk, Device};
 
@@ -14,13 +19,90 @@ pub struct Align16<T>(pub T);
 
 type Mat4 = Align16<na::Matrix4<f32>>;
 
-#[derive(Debug)]
+/// `constant_id` of the `USE_VERTEX_COLOR` specialization constant in the fragment shaders
+pub const USE_VERTEX_COLOR_CONSTANT_ID: u32 = 0;
+
+/// Where the color is in the push constants, column 3 of the normal matrix. The shaders only
+/// use the normal matrix's upper 3x3, so the color fits in the rest of it
+const COLOR_OFFSET: usize = 112;
+
+/// Exactly the 128 bytes the spec guarantees for `maxPushConstantsSize`. Also what the per
+/// object UBO holds, std140 lays the two matrices out the same way
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
 pub struct SimplePushConstantData {
     _model_matrix: Mat4,
-    _normal_matrix: Mat4,
+    _normal_matrix: Mat4, // column 3 is the color, rgb is the object color, w the reflectivity
 }
 
+const _: () = assert!(
+    std::mem::size_of::<SimplePushConstantData>() == 128,
+    "The push constants must fit in the 128 bytes every device supports"
+);
+
 impl SimplePushConstantData {
+    pub fn from_game_object(game_obj: &LveGameObject) -> Self {
+        let reflectivity = game_obj
+            .reflection
+            .as_ref()
+            .map_or(0.0, |reflection| reflection.reflectivity);
+
+        let mut normal_matrix = game_obj.transform.cached_normal_matrix();
+        normal_matrix.set_column(
+            3,
+            &na::vector![
+                game_obj.color[0],
+                game_obj.color[1],
+                game_obj.color[2],
+                reflectivity
+            ],
+        );
+
+        Self {
+            _model_matrix: Align16(game_obj.transform.cached_mat4()),
+            _normal_matrix: Align16(normal_matrix),
+        }
+    }
+
+    /// One range for both stages, as the color the fragment stage reads is inside the normal
+    /// matrix the vertex stage reads
+    pub fn push_constant_layout(lve_device: &LveDevice) -> PushConstantLayout {
+        let range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(std::mem::size_of::<Self>() as u32)
+            .build();
+
+        PushConstantLayout::new(lve_device, vec![range])
+    }
+
+    /// Only the fragment region, for when the matrices are in the per object UBO
+    pub fn fragment_push_constant_layout(lve_device: &LveDevice) -> PushConstantLayout {
+        PushConstantLayout::new(lve_device, vec![Self::fragment_range()])
+    }
+
+    /// Only the vertex region, for passes without a fragment shader
+    pub fn vertex_push_constant_layout(lve_device: &LveDevice) -> PushConstantLayout {
+        PushConstantLayout::new(lve_device, vec![Self::vertex_range()])
+    }
+
+    fn vertex_range() -> vk::PushConstantRange {
+        vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .offset(0)
+            .size(std::mem::size_of::<Self>() as u32)
+            .build()
+    }
+
+    /// Only the color, for systems that don't push the matrices
+    pub fn fragment_range() -> vk::PushConstantRange {
+        vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(COLOR_OFFSET as u32)
+            .size(std::mem::size_of::<na::Vector4<f32>>() as u32)
+            .build()
+    }
+
     pub unsafe fn as_bytes(&self) -> &[u8] {
         let size_in_bytes = std::mem::size_of::<Self>();
         let size_in_u8 = size_in_bytes / std::mem::size_of::<u8>();
@@ -39,45 +121,161 @@ impl SimplePushConstantData {
     }
 }
 
+/// Each object's matrices in a dynamic uniform buffer rather than the push constants, bound at
+/// set 1 with the object's offset for each draw. Leaves the push constants for the color, and
+/// room for per object data that would never fit in them (e.g. bone matrices)
+pub struct ObjectUbo {
+    set_layout: Rc<LveDescriptorSetLayout>,
+    buffer: DynamicUboBuffer<SimplePushConstantData>,
+    /// One set for every frame, the dynamic offset picks the frame's slots too
+    descriptor_set: vk::DescriptorSet,
+}
+
+impl ObjectUbo {
+    /// Room for `max_objects` objects a frame, drawing more panics
+    pub fn new(
+        lve_device: Rc<LveDevice>,
+        descriptor_pools: &mut LveDescriptorPoolManager,
+        max_objects: u32,
+    ) -> Self {
+        let set_layout = LveDescriptorSetLayoutBuilder::new(Rc::clone(&lve_device))
+            .add_binding(
+                0,
+                vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                vk::ShaderStageFlags::VERTEX,
+                1,
+            )
+            .build();
+
+        let buffer = DynamicUboBuffer::new(lve_device, MAX_FRAMES_IN_FLIGHT, max_objects);
+
+        let descriptor_set =
+            LveDescriptorWriter::new(Rc::clone(&set_layout), descriptor_pools.current_pool())
+                .write_buffer(0, &[*buffer.descriptor_info()])
+                .build_from(descriptor_pools);
+
+        Self {
+            set_layout,
+            buffer,
+            descriptor_set,
+        }
+    }
+}
+
 pub struct SimpleRenderSystem {
     lve_device: Rc<LveDevice>,
-    lve_pipeline: LvePipeline,
+    pipelines: PipelineRegistry,
     pipeline_layout: vk::PipelineLayout, // I think this should be a part of the pipeline module
+    push_constant_layout: PushConstantLayout,
+    object_ubo: Option<ObjectUbo>,
 }
 
 impl SimpleRenderSystem {
     pub fn new(
         lve_device: Rc<LveDevice>,
-        render_pass: &vk::RenderPass,
-        global_set_layout: vk::DescriptorSetLayout
+        render_pass: &RenderPassInfo,
+        global_set_layout: vk::DescriptorSetLayout,
+        push_constant_layout: PushConstantLayout,
+        depth_prepass: bool,
+        use_vertex_color: bool,
+        object_ubo: Option<ObjectUbo>,
     ) -> Self {
-        let pipeline_layout = Self::create_pipeline_layout(&lve_device.device, global_set_layout);
+        // Set 1 is only there with the per object UBO
+        let mut set_layouts = vec![global_set_layout];
+        set_layouts.extend(
+            object_ubo
+                .iter()
+                .map(|object_ubo| object_ubo.set_layout.descriptor_set_layout),
+        );
+
+        let pipeline_layout =
+            Self::create_pipeline_layout(&lve_device.device, &set_layouts, &push_constant_layout);
+
+        let vert_shader = match object_ubo {
+            Some(_) => "shaders/simple_shader_object_ubo.vert.spv",
+            None => "shaders/simple_shader.vert.spv",
+        };
+
+        let mut pipelines = PipelineRegistry::new(PipelineTag::Lit);
 
-        let lve_pipeline =
-            Self::create_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout);
+        pipelines.register(
+            PipelineTag::Lit,
+            Self::create_pipeline(
+                Rc::clone(&lve_device),
+                render_pass,
+                &pipeline_layout,
+                PipelineTag::Lit,
+                depth_prepass,
+                use_vertex_color,
+                vert_shader,
+            ),
+        );
+
+        // Without the feature wireframe objects fall back to the lit pipeline
+        if lve_device.enabled_features.fill_mode_non_solid {
+            pipelines.register(
+                PipelineTag::Wireframe,
+                Self::create_pipeline(
+                    Rc::clone(&lve_device),
+                    render_pass,
+                    &pipeline_layout,
+                    PipelineTag::Wireframe,
+                    depth_prepass,
+                    use_vertex_color,
+                    vert_shader,
+                ),
+            );
+        } else {
+            log::warn!("fillModeNonSolid is not enabled, wireframe objects will be drawn filled");
+        }
 
         Self {
             lve_device,
-            lve_pipeline,
+            pipelines,
             pipeline_layout,
+            push_constant_layout,
+            object_ubo,
         }
     }
 
     fn create_pipeline(
         lve_device: Rc<LveDevice>,
-        render_pass: &vk::RenderPass,
+        render_pass: &RenderPassInfo,
         pipeline_layout: &vk::PipelineLayout,
+        tag: PipelineTag,
+        depth_prepass: bool,
+        use_vertex_color: bool,
+        vert_shader: &str,
     ) -> LvePipeline {
         assert!(
             pipeline_layout != &vk::PipelineLayout::null(),
             "Cannot create pipeline before pipeline layout"
         );
 
-        let pipeline_config = LvePipeline::default_pipline_config_info();
+        let mut pipeline_config = LvePipeline::default_pipline_config_info();
+
+        if tag == PipelineTag::Wireframe {
+            LvePipeline::enable_wireframe(&mut pipeline_config);
+        }
+
+        // The depth buffer has already been filled by the pre-pass, so only shade the
+        // fragments that survived it. Wireframes aren't in the pre-pass, so they depth test
+        // as normal against it
+        if depth_prepass && tag != PipelineTag::Wireframe {
+            pipeline_config.depth_stencil_info.depth_compare_op = vk::CompareOp::EQUAL;
+            pipeline_config.depth_stencil_info.depth_write_enable = vk::FALSE;
+        }
+
+        // Baked into the shader so there's no per fragment branch
+        LvePipeline::add_specialization_constant(
+            &mut pipeline_config,
+            USE_VERTEX_COLOR_CONSTANT_ID,
+            use_vertex_color,
+        );
 
         LvePipeline::new(
             lve_device,
-            "shaders/simple_shader.vert.spv",
+            vert_shader,
             "shaders/simple_shader.frag.spv",
             pipeline_config,
             render_pass,
@@ -87,19 +285,12 @@ impl SimpleRenderSystem {
 
     fn create_pipeline_layout(
         device: &Device,
-        global_set_layout: vk::DescriptorSetLayout
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_constant_layout: &PushConstantLayout,
     ) -> vk::PipelineLayout {
-        let push_constant_range = vk::PushConstantRange::builder()
-            .stage_flags(vk::ShaderStageFlags::VERTEX)
-            .offset(0)
-            .size(std::mem::size_of::<SimplePushConstantData>() as u32)
-            .build();
-
-        let descriptor_set_layouts = vec![global_set_layout];
-
         let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
-            .set_layouts(descriptor_set_layouts.as_slice())
-            .push_constant_ranges(&[push_constant_range])
+            .set_layouts(set_layouts)
+            .push_constant_ranges(push_constant_layout.ranges())
             .build();
 
         unsafe {
@@ -109,15 +300,36 @@ impl SimpleRenderSystem {
                 .unwrap()
         }
     }
+}
 
-    pub fn render_game_objects(
-        &mut self,
-        frame_info: &mut FrameInfo,
-    ) {
-        unsafe {
-            self.lve_pipeline
-                .bind(&self.lve_device.device, frame_info.command_buffer);
+impl RenderSystem for SimpleRenderSystem {
+    fn render(&mut self, frame_info: &FrameInfo) {
+        // This system covers the whole scene, so it is the one that counts the hidden objects
+        let hidden_count = frame_info.game_objects.len() - frame_info.game_objects.visible_count();
+        frame_info.record_culled(hidden_count as u32);
 
+        // Point lights only feed the global UBO, they have no geometry to draw. Reflective
+        // objects are drawn by the ReflectiveRenderSystem, skinned ones by the
+        // SkinnedRenderSystem and ones with a material by the MaterialRenderSystem
+        let mut game_objects: Vec<_> = frame_info
+            .game_objects
+            .iter()
+            .filter(|(_, game_obj)| {
+                game_obj.visible
+                    && game_obj.point_light.is_none()
+                    && game_obj.reflection.is_none()
+                    && game_obj.skin.is_none()
+                    && game_obj.material.is_none()
+            })
+            .collect();
+
+        // Grouped by pipeline so each is only bound once. The ids break ties so the draw order
+        // doesn't change from frame to frame
+        game_objects.sort_by_key(|(id, game_obj)| {
+            (self.pipelines.resolve(game_obj.pipeline_tag), **id)
+        });
+
+        unsafe {
             self.lve_device.device.cmd_bind_descriptor_sets(
                 frame_info.command_buffer,
                 vk::PipelineBindPoint::GRAPHICS,
@@ -128,31 +340,62 @@ impl SimpleRenderSystem {
             );
         };
 
-        for (_, game_obj) in frame_info.game_objects.iter_mut() {
-            let push = SimplePushConstantData {
-                _model_matrix: Align16(game_obj.transform.mat4()),
-                _normal_matrix: Align16(game_obj.transform.normal_matrix()),
-            };
+        let mut bound_tag = None;
 
-            unsafe {
-                let push_ptr = push.as_bytes();
+        for (object_index, (_, game_obj)) in game_objects.into_iter().enumerate() {
+            let tag = self.pipelines.resolve(game_obj.pipeline_tag);
 
-                self.lve_device.device.cmd_push_constants(
+            if bound_tag != Some(tag) {
+                unsafe {
+                    self.pipelines
+                        .get(tag)
+                        .bind(&self.lve_device.device, frame_info.command_buffer);
+                }
+                frame_info.record_pipeline_bind();
+                bound_tag = Some(tag);
+            }
+
+            let push = SimplePushConstantData::from_game_object(game_obj);
+
+            if let Some(object_ubo) = &self.object_ubo {
+                let frame_index = frame_info.frame_index as usize;
+                let object_index = object_index as u32;
+
+                object_ubo
+                    .buffer
+                    .write(frame_index, object_index, &push);
+
+                unsafe {
+                    self.lve_device.device.cmd_bind_descriptor_sets(
+                        frame_info.command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        self.pipeline_layout,
+                        1,
+                        &[object_ubo.descriptor_set],
+                        &[object_ubo.buffer.dynamic_offset(frame_index, object_index)],
+                    );
+                }
+            }
+
+            // Only the color when the matrices are in the per object UBO
+            unsafe {
+                self.push_constant_layout.push(
+                    &self.lve_device.device,
                     frame_info.command_buffer,
                     self.pipeline_layout,
-                    vk::ShaderStageFlags::VERTEX,
-                    0,
-                    push_ptr,
+                    push.as_bytes(),
                 );
 
-                game_obj
-                    .model
-                    .bind(&self.lve_device.device, frame_info.command_buffer);
-                game_obj
-                    .model
-                    .draw(&self.lve_device.device, frame_info.command_buffer);
+                let model = game_obj.model_at(frame_info.camera.position());
+                model.bind(&self.lve_device.device, frame_info.command_buffer);
+                model.draw(&self.lve_device.device, frame_info.command_buffer);
+                frame_info.record_draw(model.triangle_count());
             }
         }
+
+        if let Some(object_ubo) = &self.object_ubo {
+            object_ubo.buffer.flush_frame(frame_info.frame_index as usize);
+        }
     }
 }
 
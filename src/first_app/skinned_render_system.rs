@@ -0,0 +1,248 @@
+use super::lve_buffer::DynamicUboBuffer;
+use super::lve_descriptors::*;
+use super::lve_device::*;
+use super::lve_frameinfo::FrameInfo;
+use super::lve_pipeline::*;
+use super::lve_swapchain::MAX_FRAMES_IN_FLIGHT;
+use super::render_system::RenderSystem;
+use super::simple_render_system::{SimplePushConstantData, USE_VERTEX_COLOR_CONSTANT_ID};
+use super::skinning::{SkinVertex, MAX_BONES};
+
+use ash::{vk, Device};
+
+use std::rc::Rc;
+
+extern crate nalgebra as na;
+
+/// Skinned objects drawn per frame, drawing more panics
+const MAX_SKINNED_OBJECTS: u32 = 16;
+
+/// What skinned_shader.vert reads at set 1. std140 lays a mat4 array out with the same 64 byte
+/// stride as Rust
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct BoneMatrices {
+    bones: [na::Matrix4<f32>; MAX_BONES],
+}
+
+/// Draws the objects with a `SkinComponent`, posing each one on the GPU with linear blend
+/// skinning. The bone matrices go in a dynamic uniform buffer bound with the object's offset,
+/// the rest is the same as the simple render system's lit pipeline. Skinned objects aren't in
+/// the depth pre-pass, so they depth test as normal
+pub struct SkinnedRenderSystem {
+    lve_device: Rc<LveDevice>,
+    lve_pipeline: LvePipeline,
+    pipeline_layout: vk::PipelineLayout,
+    push_constant_layout: PushConstantLayout,
+    /// Kept alive for the descriptor set
+    _bone_set_layout: Rc<LveDescriptorSetLayout>,
+    bone_buffer: DynamicUboBuffer<BoneMatrices>,
+    /// One set for every frame, the dynamic offset picks the frame's slots too
+    bone_descriptor_set: vk::DescriptorSet,
+}
+
+impl SkinnedRenderSystem {
+    pub fn new(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        global_set_layout: vk::DescriptorSetLayout,
+        descriptor_pools: &mut LveDescriptorPoolManager,
+        use_vertex_color: bool,
+    ) -> Self {
+        let bone_set_layout = LveDescriptorSetLayoutBuilder::new(Rc::clone(&lve_device))
+            .add_binding(
+                0,
+                vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                vk::ShaderStageFlags::VERTEX,
+                1,
+            )
+            .build();
+
+        let bone_buffer = DynamicUboBuffer::new(
+            Rc::clone(&lve_device),
+            MAX_FRAMES_IN_FLIGHT,
+            MAX_SKINNED_OBJECTS,
+        );
+
+        let bone_descriptor_set =
+            LveDescriptorWriter::new(Rc::clone(&bone_set_layout), descriptor_pools.current_pool())
+                .write_buffer(0, &[*bone_buffer.descriptor_info()])
+                .build_from(descriptor_pools);
+
+        let push_constant_layout = SimplePushConstantData::push_constant_layout(&lve_device);
+
+        let pipeline_layout = Self::create_pipeline_layout(
+            &lve_device.device,
+            &[global_set_layout, bone_set_layout.descriptor_set_layout],
+            &push_constant_layout,
+        );
+
+        let lve_pipeline = Self::create_pipeline(
+            Rc::clone(&lve_device),
+            render_pass,
+            &pipeline_layout,
+            use_vertex_color,
+        );
+
+        Self {
+            lve_device,
+            lve_pipeline,
+            pipeline_layout,
+            push_constant_layout,
+            _bone_set_layout: bone_set_layout,
+            bone_buffer,
+            bone_descriptor_set,
+        }
+    }
+
+    fn create_pipeline(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        pipeline_layout: &vk::PipelineLayout,
+        use_vertex_color: bool,
+    ) -> LvePipeline {
+        assert!(
+            pipeline_layout != &vk::PipelineLayout::null(),
+            "Cannot create pipeline before pipeline layout"
+        );
+
+        let mut pipeline_config = LvePipeline::default_pipline_config_info();
+
+        LvePipeline::add_specialization_constant(
+            &mut pipeline_config,
+            USE_VERTEX_COLOR_CONSTANT_ID,
+            use_vertex_color,
+        );
+
+        // The model's vertices at binding 0, then the joints and weights at binding 1
+        pipeline_config
+            .binding_descriptions
+            .extend(SkinVertex::get_binding_descriptions());
+        pipeline_config
+            .attribute_descriptions
+            .extend(SkinVertex::get_attribute_descriptions());
+
+        LvePipeline::new(
+            lve_device,
+            "shaders/skinned_shader.vert.spv",
+            "shaders/simple_shader.frag.spv",
+            pipeline_config,
+            render_pass,
+            pipeline_layout,
+        )
+    }
+
+    fn create_pipeline_layout(
+        device: &Device,
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_constant_layout: &PushConstantLayout,
+    ) -> vk::PipelineLayout {
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(set_layouts)
+            .push_constant_ranges(push_constant_layout.ranges())
+            .build();
+
+        unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .map_err(|e| log::error!("Unable to create skinned pipeline layout: {}", e))
+                .unwrap()
+        }
+    }
+}
+
+impl RenderSystem for SkinnedRenderSystem {
+    fn render(&mut self, frame_info: &FrameInfo) {
+        let mut game_objects: Vec<_> = frame_info
+            .game_objects
+            .iter()
+            .filter(|(_, game_obj)| game_obj.visible && game_obj.skin.is_some())
+            .collect();
+
+        if game_objects.is_empty() {
+            return;
+        }
+
+        // The objects are stored in a hash map, so sort them to keep the draw order steady
+        game_objects.sort_by_key(|(id, _)| **id);
+
+        let frame_index = frame_info.frame_index as usize;
+
+        unsafe {
+            self.lve_pipeline
+                .bind(&self.lve_device.device, frame_info.command_buffer);
+
+            self.lve_device.device.cmd_bind_descriptor_sets(
+                frame_info.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[frame_info.global_descriptor_set],
+                &[],
+            );
+        }
+        frame_info.record_pipeline_bind();
+
+        for (object_index, (_, game_obj)) in game_objects.into_iter().enumerate() {
+            let skin = game_obj.skin.as_ref().unwrap();
+            let object_index = object_index as u32;
+
+            // Joints the skin doesn't have are never referenced by its vertices
+            let mut bone_matrices = BoneMatrices {
+                bones: [na::Matrix4::identity(); MAX_BONES],
+            };
+
+            for (bone, matrix) in bone_matrices
+                .bones
+                .iter_mut()
+                .zip(skin.model.pose(&skin.player))
+            {
+                *bone = matrix;
+            }
+
+            self.bone_buffer
+                .write(frame_index, object_index, &bone_matrices);
+
+            let push = SimplePushConstantData::from_game_object(game_obj);
+
+            unsafe {
+                self.lve_device.device.cmd_bind_descriptor_sets(
+                    frame_info.command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    1,
+                    &[self.bone_descriptor_set],
+                    &[self.bone_buffer.dynamic_offset(frame_index, object_index)],
+                );
+
+                self.push_constant_layout.push(
+                    &self.lve_device.device,
+                    frame_info.command_buffer,
+                    self.pipeline_layout,
+                    push.as_bytes(),
+                );
+
+                skin.model
+                    .bind(&self.lve_device.device, frame_info.command_buffer);
+                skin.model
+                    .model
+                    .draw(&self.lve_device.device, frame_info.command_buffer);
+                frame_info.record_draw(skin.model.model.triangle_count());
+            }
+        }
+
+        self.bone_buffer.flush_frame(frame_index);
+    }
+}
+
+impl Drop for SkinnedRenderSystem {
+    fn drop(&mut self) {
+        log::debug!("Dropping SkinnedRenderSystem");
+
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
@@ -0,0 +1,790 @@
+use super::lve_buffer::*;
+use super::lve_device::*;
+use super::lve_model::*;
+
+use ash::{vk, Device};
+
+use std::mem::size_of;
+use std::rc::Rc;
+
+use ordered_float::OrderedFloat;
+
+extern crate nalgebra as na;
+
+/// The most joints a skin can have. Must match MAX_BONES in skinned_shader.vert
+pub const MAX_BONES: usize = 64;
+
+/// The joints that move a vertex and how much each one pulls on it. Kept in its own vertex
+/// buffer (binding 1) next to the model's `Vertex`es, so models without a skin don't carry the
+/// extra 24 bytes a vertex
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkinVertex {
+    /// Indices into the skin's joints, and so into the bone matrices
+    pub joint_indices: [u16; 4],
+    /// Sum to 1, unused joints have a weight of 0
+    pub joint_weights: [f32; 4],
+}
+
+impl SkinVertex {
+    pub fn get_binding_descriptions() -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription::builder()
+            .binding(1)
+            .stride(size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()]
+    }
+
+    /// Follows on from the locations used by `Vertex`
+    pub fn get_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![
+            vk::VertexInputAttributeDescription {
+                location: 4,
+                binding: 1,
+                format: vk::Format::R16G16B16A16_UINT,
+                offset: 0,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 5,
+                binding: 1,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: size_of::<[u16; 4]>() as u32,
+            },
+        ]
+    }
+}
+
+/// A node's transform relative to its parent, split up the way glTF animates it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeTransform {
+    pub translation: na::Vector3<f32>,
+    pub rotation: na::UnitQuaternion<f32>,
+    pub scale: na::Vector3<f32>,
+}
+
+impl NodeTransform {
+    #[allow(dead_code)]
+    pub fn identity() -> Self {
+        Self {
+            translation: na::Vector3::zeros(),
+            rotation: na::UnitQuaternion::identity(),
+            scale: na::vector![1.0, 1.0, 1.0],
+        }
+    }
+
+    /// Translate * rotate * scale
+    pub fn matrix(&self) -> na::Matrix4<f32> {
+        na::Matrix4::new_translation(&self.translation)
+            * self.rotation.to_homogeneous()
+            * na::Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+
+    fn from_gltf(transform: gltf::scene::Transform) -> Self {
+        let (translation, [x, y, z, w], scale) = transform.decomposed();
+
+        Self {
+            translation: translation.into(),
+            rotation: na::UnitQuaternion::from_quaternion(na::Quaternion::new(w, x, y, z)),
+            scale: scale.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SkeletonNode {
+    parent: Option<usize>,
+    rest: NodeTransform,
+}
+
+/// The node hierarchy a skin's joints sit in. Every node of the file is kept, not just the
+/// joints, as a joint's parents may not be joints themselves
+#[derive(Debug, Clone)]
+pub struct Skeleton {
+    /// Ordered so every node comes after its parent
+    nodes: Vec<SkeletonNode>,
+    /// The node of each joint
+    joints: Vec<usize>,
+    /// Takes a vertex from model space into the space of each joint in the rest pose
+    inverse_bind_matrices: Vec<na::Matrix4<f32>>,
+}
+
+impl Skeleton {
+    pub fn joint_count(&self) -> usize {
+        self.joints.len()
+    }
+
+    /// The bone matrix of every joint `time` seconds into `clip`, or in the rest pose without
+    /// one. Each takes a vertex from the bind pose to where the joint has moved it
+    pub fn pose(&self, clip: Option<&AnimationClip>, time: f32) -> Vec<na::Matrix4<f32>> {
+        let mut locals: Vec<NodeTransform> = self.nodes.iter().map(|node| node.rest).collect();
+
+        if let Some(clip) = clip {
+            clip.apply(time, &mut locals);
+        }
+
+        // Parents come first, so their global matrix is always ready for their children
+        let mut globals: Vec<na::Matrix4<f32>> = Vec::with_capacity(self.nodes.len());
+
+        for (node, local) in self.nodes.iter().zip(locals) {
+            let global = match node.parent {
+                Some(parent) => globals[parent] * local.matrix(),
+                None => local.matrix(),
+            };
+
+            globals.push(global);
+        }
+
+        self.joints
+            .iter()
+            .zip(&self.inverse_bind_matrices)
+            .map(|(joint, inverse_bind_matrix)| globals[*joint] * inverse_bind_matrix)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Interpolation {
+    Linear,
+    Step,
+}
+
+#[derive(Debug, Clone)]
+enum ChannelValues {
+    Translation(Vec<na::Vector3<f32>>),
+    Rotation(Vec<na::UnitQuaternion<f32>>),
+    Scale(Vec<na::Vector3<f32>>),
+}
+
+/// The keyframes of one property of one node
+#[derive(Debug, Clone)]
+struct Channel {
+    node: usize,
+    interpolation: Interpolation,
+    /// Seconds, in increasing order, one for each value
+    times: Vec<f32>,
+    values: ChannelValues,
+}
+
+impl Channel {
+    fn apply(&self, time: f32, transform: &mut NodeTransform) {
+        let (previous, next, factor) = match keyframe(&self.times, time, self.interpolation) {
+            Some(keyframe) => keyframe,
+            None => return,
+        };
+
+        match &self.values {
+            ChannelValues::Translation(values) => {
+                transform.translation = values[previous].lerp(&values[next], factor);
+            }
+            ChannelValues::Rotation(values) => {
+                transform.rotation = interpolate_rotation(values[previous], values[next], factor);
+            }
+            ChannelValues::Scale(values) => {
+                transform.scale = values[previous].lerp(&values[next], factor);
+            }
+        }
+    }
+}
+
+/// The keyframes either side of `time` and how far it is between them. Before the first or
+/// after the last keyframe it holds that keyframe's value. `None` if there are no keyframes
+fn keyframe(times: &[f32], time: f32, interpolation: Interpolation) -> Option<(usize, usize, f32)> {
+    let last = times.len().checked_sub(1)?;
+    let next = times.partition_point(|keyframe_time| *keyframe_time <= time);
+
+    if next == 0 {
+        return Some((0, 0, 0.0));
+    } else if next > last {
+        return Some((last, last, 0.0));
+    }
+
+    let previous = next - 1;
+
+    if interpolation == Interpolation::Step {
+        return Some((previous, previous, 0.0));
+    }
+
+    let span = times[next] - times[previous];
+    let factor = if span > 0.0 {
+        (time - times[previous]) / span
+    } else {
+        0.0
+    };
+
+    Some((previous, next, factor))
+}
+
+/// Spherical interpolation the short way round
+fn interpolate_rotation(
+    from: na::UnitQuaternion<f32>,
+    to: na::UnitQuaternion<f32>,
+    factor: f32,
+) -> na::UnitQuaternion<f32> {
+    // q and -q are the same rotation, but slerping to the one on the far side of the
+    // hypersphere spins the long way round
+    let to = if from.coords.dot(&to.coords) < 0.0 {
+        na::UnitQuaternion::new_unchecked(-to.into_inner())
+    } else {
+        to
+    };
+
+    from.try_slerp(&to, factor, f32::EPSILON)
+        .unwrap_or_else(|| from.nlerp(&to, factor))
+}
+
+/// One of a file's animations, sampled by an `AnimationPlayer`
+#[derive(Debug, Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    /// Seconds, the time of the last keyframe of any channel
+    pub duration: f32,
+    channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    /// Overwrites the animated properties of `locals` with their values at `time`
+    fn apply(&self, time: f32, locals: &mut [NodeTransform]) {
+        for channel in &self.channels {
+            if let Some(transform) = locals.get_mut(channel.node) {
+                channel.apply(time, transform);
+            }
+        }
+    }
+}
+
+/// Plays one of a skinned model's clips. Only holds the playback state, the clips themselves
+/// are shared by every object using the model
+#[derive(Debug, Clone, Copy)]
+pub struct AnimationPlayer {
+    /// Index into the model's clips, `None` holds the rest pose
+    pub clip: Option<usize>,
+    /// Seconds into the clip
+    pub time: f32,
+    /// 1 plays at normal speed, negative values play backwards
+    pub speed: f32,
+    /// Wrap around at the end of the clip rather than holding the last frame
+    pub looping: bool,
+}
+
+impl AnimationPlayer {
+    /// Loops `clip` from the start at normal speed
+    pub fn new(clip: Option<usize>) -> Self {
+        Self {
+            clip,
+            time: 0.0,
+            speed: 1.0,
+            looping: true,
+        }
+    }
+
+    pub fn advance(&mut self, delta_time: f32, clips: &[AnimationClip]) {
+        let duration = match self.clip.and_then(|clip| clips.get(clip)) {
+            Some(clip) => clip.duration,
+            None => return,
+        };
+
+        if duration <= 0.0 {
+            self.time = 0.0;
+            return;
+        }
+
+        self.time += delta_time * self.speed;
+
+        self.time = if self.looping {
+            self.time.rem_euclid(duration)
+        } else {
+            self.time.clamp(0.0, duration)
+        };
+    }
+}
+
+#[derive(Debug)]
+pub enum SkinLoadError {
+    Gltf(gltf::Error),
+    /// The file has no node with both a mesh and a skin
+    NoSkinnedMesh,
+    /// The skin has more joints than `MAX_BONES`
+    TooManyJoints(usize),
+    /// A vertex uses a joint the skin doesn't have
+    JointOutOfRange(u16),
+}
+
+impl std::fmt::Display for SkinLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Gltf(error) => write!(f, "{}", error),
+            Self::NoSkinnedMesh => write!(f, "no node has both a mesh and a skin"),
+            Self::TooManyJoints(count) => {
+                write!(
+                    f,
+                    "the skin has {} joints, the most is {}",
+                    count, MAX_BONES
+                )
+            }
+            Self::JointOutOfRange(joint) => write!(f, "a vertex uses missing joint {}", joint),
+        }
+    }
+}
+
+impl From<gltf::Error> for SkinLoadError {
+    fn from(error: gltf::Error) -> Self {
+        Self::Gltf(error)
+    }
+}
+
+/// The CPU side of a skinned model loaded from a glTF file
+pub struct SkinnedModelData {
+    pub name: String,
+    pub model_data: ModelData,
+    /// One for each of `model_data`'s vertices
+    pub skin_vertices: Vec<SkinVertex>,
+    pub skeleton: Skeleton,
+    pub clips: Vec<AnimationClip>,
+}
+
+impl SkinnedModelData {
+    /// Loads the first node in the file with both a mesh and a skin, and every animation in
+    /// the file. Only triangle primitives are loaded, and the vertices aren't merged, so the
+    /// skin vertices line up with the model's. Cubic spline keyframes are interpolated
+    /// linearly between their values
+    pub fn load_gltf(file_path: &str) -> Result<Self, SkinLoadError> {
+        let (document, buffers, _images) = gltf::import(file_path)?;
+        let get_buffer = |buffer: gltf::Buffer| buffers.get(buffer.index()).map(|data| &data[..]);
+
+        let (mesh, skin) = document
+            .nodes()
+            .find_map(|node| Some((node.mesh()?, node.skin()?)))
+            .ok_or(SkinLoadError::NoSkinnedMesh)?;
+
+        let joint_count = skin.joints().count();
+
+        if joint_count > MAX_BONES {
+            return Err(SkinLoadError::TooManyJoints(joint_count));
+        }
+
+        let mut vertices = Vec::new();
+        let mut skin_vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        let triangles = mesh
+            .primitives()
+            .filter(|primitive| primitive.mode() == gltf::mesh::Mode::Triangles);
+
+        for primitive in triangles {
+            let reader = primitive.reader(get_buffer);
+
+            let positions: Vec<[f32; 3]> = match reader.read_positions() {
+                Some(positions) => positions.collect(),
+                None => continue,
+            };
+
+            let count = positions.len();
+            let first_vertex = vertices.len() as u32;
+
+            // Zero normals are rebuilt from the faces by `validate_and_repair`
+            let normals: Vec<[f32; 3]> = reader
+                .read_normals()
+                .map_or_else(|| vec![[0.0; 3]; count], |normals| normals.collect());
+            let colors: Vec<[f32; 3]> = reader.read_colors(0).map_or_else(
+                || vec![[1.0; 3]; count],
+                |colors| colors.into_rgb_f32().collect(),
+            );
+            let uvs: Vec<[f32; 2]> = reader
+                .read_tex_coords(0)
+                .map_or_else(|| vec![[0.0; 2]; count], |uvs| uvs.into_f32().collect());
+            let joints: Vec<[u16; 4]> = reader
+                .read_joints(0)
+                .map_or_else(|| vec![[0; 4]; count], |joints| joints.into_u16().collect());
+            let weights: Vec<[f32; 4]> = reader.read_weights(0).map_or_else(
+                || vec![[1.0, 0.0, 0.0, 0.0]; count],
+                |weights| weights.into_f32().collect(),
+            );
+
+            for i in 0..count {
+                vertices.push(Vertex {
+                    position: na::Vector3::from(positions[i]).map(OrderedFloat),
+                    color: na::Vector3::from(colors[i]).map(OrderedFloat),
+                    normal: na::Vector3::from(normals[i]).map(OrderedFloat),
+                    uv: na::Vector2::from(uvs[i]).map(OrderedFloat),
+                });
+
+                if let Some(joint) = joints[i]
+                    .iter()
+                    .find(|joint| **joint as usize >= joint_count)
+                {
+                    return Err(SkinLoadError::JointOutOfRange(*joint));
+                }
+
+                skin_vertices.push(SkinVertex {
+                    joint_indices: joints[i],
+                    joint_weights: normalize_weights(weights[i]),
+                });
+            }
+
+            match reader.read_indices() {
+                Some(primitive_indices) => indices.extend(
+                    primitive_indices
+                        .into_u32()
+                        .map(|index| first_vertex + index),
+                ),
+                None => indices.extend(first_vertex..first_vertex + count as u32),
+            }
+        }
+
+        let mut model_data = ModelData {
+            vertices,
+            indices: Some(indices),
+        };
+
+        // Only fixes values in place, so the skin vertices still line up
+        model_data.validate_and_repair();
+
+        let (skeleton, node_map) = Self::load_skeleton(&document, &skin, get_buffer);
+
+        let clips = document
+            .animations()
+            .enumerate()
+            .map(|(i, animation)| Self::load_clip(i, &animation, &node_map, get_buffer))
+            .collect();
+
+        Ok(Self {
+            name: mesh.name().unwrap_or(file_path).to_string(),
+            model_data,
+            skin_vertices,
+            skeleton,
+            clips,
+        })
+    }
+
+    /// Also returns where each of the file's nodes ended up in the skeleton
+    fn load_skeleton<'a, 's>(
+        document: &gltf::Document,
+        skin: &'a gltf::Skin<'a>,
+        get_buffer: impl Clone + Fn(gltf::Buffer<'a>) -> Option<&'s [u8]>,
+    ) -> (Skeleton, Vec<usize>) {
+        let node_count = document.nodes().count();
+
+        let mut parents = vec![None; node_count];
+
+        for node in document.nodes() {
+            for child in node.children() {
+                parents[child.index()] = Some(node.index());
+            }
+        }
+
+        // Depth first from the roots, so parents are always visited before their children
+        let mut order = Vec::with_capacity(node_count);
+        let mut stack: Vec<gltf::Node> = document
+            .nodes()
+            .filter(|node| parents[node.index()].is_none())
+            .collect();
+
+        while let Some(node) = stack.pop() {
+            stack.extend(node.children());
+            order.push(node);
+        }
+
+        let mut node_map = vec![0; node_count];
+
+        for (skeleton_index, node) in order.iter().enumerate() {
+            node_map[node.index()] = skeleton_index;
+        }
+
+        let nodes = order
+            .iter()
+            .map(|node| SkeletonNode {
+                parent: parents[node.index()].map(|parent| node_map[parent]),
+                rest: NodeTransform::from_gltf(node.transform()),
+            })
+            .collect();
+
+        let joints: Vec<usize> = skin.joints().map(|joint| node_map[joint.index()]).collect();
+
+        let inverse_bind_matrices = skin
+            .reader(get_buffer)
+            .read_inverse_bind_matrices()
+            .map_or_else(
+                || vec![na::Matrix4::identity(); joints.len()],
+                // Both glTF and nalgebra store the matrices column by column
+                |matrices| matrices.map(na::Matrix4::from).collect(),
+            );
+
+        let skeleton = Skeleton {
+            nodes,
+            joints,
+            inverse_bind_matrices,
+        };
+
+        (skeleton, node_map)
+    }
+
+    fn load_clip<'a, 's>(
+        index: usize,
+        animation: &gltf::Animation<'a>,
+        node_map: &[usize],
+        get_buffer: impl Clone + Fn(gltf::Buffer<'a>) -> Option<&'s [u8]>,
+    ) -> AnimationClip {
+        use gltf::animation::util::ReadOutputs;
+
+        let channels: Vec<Channel> = animation
+            .channels()
+            .filter_map(|channel| {
+                let reader = channel.reader(get_buffer.clone());
+                let times: Vec<f32> = reader.read_inputs()?.collect();
+
+                let interpolation = channel.sampler().interpolation();
+                let cubic_spline = interpolation == gltf::animation::Interpolation::CubicSpline;
+
+                let values = match reader.read_outputs()? {
+                    ReadOutputs::Translations(translations) => ChannelValues::Translation(
+                        spline_values(translations.map(na::Vector3::from).collect(), cubic_spline),
+                    ),
+                    ReadOutputs::Rotations(rotations) => {
+                        let rotations = rotations
+                            .into_f32()
+                            .map(|[x, y, z, w]| {
+                                na::UnitQuaternion::from_quaternion(na::Quaternion::new(w, x, y, z))
+                            })
+                            .collect();
+
+                        ChannelValues::Rotation(spline_values(rotations, cubic_spline))
+                    }
+                    ReadOutputs::Scales(scales) => ChannelValues::Scale(spline_values(
+                        scales.map(na::Vector3::from).collect(),
+                        cubic_spline,
+                    )),
+                    ReadOutputs::MorphTargetWeights(_) => return None,
+                };
+
+                let interpolation = match interpolation {
+                    gltf::animation::Interpolation::Step => Interpolation::Step,
+                    _ => Interpolation::Linear,
+                };
+
+                Some(Channel {
+                    node: node_map[channel.target().node().index()],
+                    interpolation,
+                    times,
+                    values,
+                })
+            })
+            .collect();
+
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last())
+            .fold(0.0, |duration: f32, time| duration.max(*time));
+
+        AnimationClip {
+            name: animation
+                .name()
+                .map_or_else(|| format!("animation {}", index), str::to_string),
+            duration,
+            channels,
+        }
+    }
+}
+
+/// Cubic spline outputs are (in tangent, value, out tangent) for each keyframe, this keeps only
+/// the values
+fn spline_values<T>(values: Vec<T>, cubic_spline: bool) -> Vec<T> {
+    if !cubic_spline {
+        return values;
+    }
+
+    values.into_iter().skip(1).step_by(3).collect()
+}
+
+/// Scales the weights to sum to 1, exporters don't always manage it exactly. All zero weights
+/// give the whole vertex to the first joint
+fn normalize_weights(weights: [f32; 4]) -> [f32; 4] {
+    let total: f32 = weights.iter().sum();
+
+    if total <= 0.0 {
+        return [1.0, 0.0, 0.0, 0.0];
+    }
+
+    weights.map(|weight| weight / total)
+}
+
+/// A model with a skin, drawn by the `SkinnedRenderSystem`. `model` is a normal `LveModel`, so
+/// the rest of the engine (bounds, physics, picking) treats the object as its rest pose
+pub struct LveSkinnedModel {
+    pub model: Rc<LveModel>,
+    skin_buffer: LveBuffer,
+    pub skeleton: Skeleton,
+    pub clips: Vec<AnimationClip>,
+}
+
+impl LveSkinnedModel {
+    pub fn load(lve_device: Rc<LveDevice>, file_path: &str) -> Rc<Self> {
+        let data = SkinnedModelData::load_gltf(file_path)
+            .map_err(|e| log::error!("Unable to load skinned model {}: {}", file_path, e))
+            .unwrap();
+
+        log::info!(
+            "Skinned model {} has {} joints and animations {:?}",
+            data.name,
+            data.skeleton.joint_count(),
+            data.clips.iter().map(|clip| &clip.name).collect::<Vec<_>>()
+        );
+
+        let model = LveModel::upload(Rc::clone(&lve_device), &data.model_data, &data.name, false);
+        let skin_buffer = Self::create_skin_buffer(&lve_device, &data.skin_vertices);
+
+        Rc::new(Self {
+            model,
+            skin_buffer,
+            skeleton: data.skeleton,
+            clips: data.clips,
+        })
+    }
+
+    /// The bone matrices for where `player` is in its clip
+    pub fn pose(&self, player: &AnimationPlayer) -> Vec<na::Matrix4<f32>> {
+        let clip = player.clip.and_then(|clip| self.clips.get(clip));
+        self.skeleton.pose(clip, player.time)
+    }
+
+    /// Binds the model's buffers and the skin vertices at binding 1
+    pub unsafe fn bind(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        self.model.bind(device, command_buffer);
+        device.cmd_bind_vertex_buffers(command_buffer, 1, &[self.skin_buffer.buffer], &[0]);
+    }
+
+    fn create_skin_buffer(lve_device: &Rc<LveDevice>, skin_vertices: &[SkinVertex]) -> LveBuffer {
+        let vertex_size = size_of::<SkinVertex>() as vk::DeviceSize;
+        let buffer_size = vertex_size * skin_vertices.len() as vk::DeviceSize;
+
+        let skin_buffer = LveBuffer::new(
+            Rc::clone(lve_device),
+            vertex_size,
+            skin_vertices.len() as u32,
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            1,
+            BufferType::Vertex,
+        );
+
+        // Copy the data through a staging buffer to the device local memory
+        lve_device.upload_via_staging(skin_vertices, |staging_buffer| {
+            lve_device.copy_buffer(staging_buffer, skin_buffer.buffer, buffer_size)
+        });
+
+        skin_buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation_channel(interpolation: Interpolation) -> Channel {
+        Channel {
+            node: 0,
+            interpolation,
+            times: vec![1.0, 2.0],
+            values: ChannelValues::Translation(vec![
+                na::vector![0.0, 0.0, 0.0],
+                na::vector![2.0, 4.0, 0.0],
+            ]),
+        }
+    }
+
+    fn sample(channel: &Channel, time: f32) -> na::Vector3<f32> {
+        let mut transform = NodeTransform::identity();
+        channel.apply(time, &mut transform);
+        transform.translation
+    }
+
+    #[test]
+    fn linear_channels_interpolate_and_hold_their_ends() {
+        let channel = translation_channel(Interpolation::Linear);
+
+        assert_eq!(sample(&channel, 1.5), na::vector![1.0, 2.0, 0.0]);
+        assert_eq!(sample(&channel, 0.0), na::vector![0.0, 0.0, 0.0]);
+        assert_eq!(sample(&channel, 5.0), na::vector![2.0, 4.0, 0.0]);
+    }
+
+    #[test]
+    fn step_channels_hold_the_previous_keyframe() {
+        let channel = translation_channel(Interpolation::Step);
+
+        assert_eq!(sample(&channel, 1.9), na::vector![0.0, 0.0, 0.0]);
+        assert_eq!(sample(&channel, 2.0), na::vector![2.0, 4.0, 0.0]);
+    }
+
+    #[test]
+    fn rotations_take_the_short_way_round() {
+        let from = na::UnitQuaternion::from_euler_angles(0.0, 0.1, 0.0);
+        // The same rotation as 0.3, on the other side of the hypersphere
+        let to = na::UnitQuaternion::new_unchecked(
+            -na::UnitQuaternion::from_euler_angles(0.0, 0.3, 0.0).into_inner(),
+        );
+
+        let halfway = interpolate_rotation(from, to, 0.5);
+
+        assert!((halfway.angle() - 0.2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn rest_pose_bones_are_the_identity() {
+        let shoulder = NodeTransform {
+            translation: na::vector![1.0, 0.0, 0.0],
+            ..NodeTransform::identity()
+        };
+        let elbow = NodeTransform {
+            translation: na::vector![0.0, 2.0, 0.0],
+            rotation: na::UnitQuaternion::from_euler_angles(0.0, 0.0, 0.5),
+            scale: na::vector![1.0, 1.0, 1.0],
+        };
+
+        let nodes = vec![
+            SkeletonNode {
+                parent: None,
+                rest: shoulder,
+            },
+            SkeletonNode {
+                parent: Some(0),
+                rest: elbow,
+            },
+        ];
+
+        // The inverse of each joint's global rest transform
+        let inverse_bind_matrices = vec![
+            shoulder.matrix().try_inverse().unwrap(),
+            (shoulder.matrix() * elbow.matrix()).try_inverse().unwrap(),
+        ];
+
+        let skeleton = Skeleton {
+            nodes,
+            joints: vec![0, 1],
+            inverse_bind_matrices,
+        };
+
+        for bone in skeleton.pose(None, 0.0) {
+            assert!((bone - na::Matrix4::identity()).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn players_loop_or_hold_at_the_end() {
+        let clips = [AnimationClip {
+            name: String::from("walk"),
+            duration: 2.0,
+            channels: Vec::new(),
+        }];
+
+        let mut player = AnimationPlayer::new(Some(0));
+        player.advance(2.5, &clips);
+        assert!((player.time - 0.5).abs() < 1e-5);
+
+        player.looping = false;
+        player.advance(2.5, &clips);
+        assert_eq!(player.time, 2.0);
+
+        player.speed = -1.0;
+        player.advance(3.0, &clips);
+        assert_eq!(player.time, 0.0);
+    }
+}
@@ -0,0 +1,435 @@
+use super::lve_buffer::*;
+use super::lve_descriptors::*;
+use super::lve_device::*;
+use super::lve_frameinfo::FrameInfo;
+use super::lve_pipeline::*;
+use super::lve_swapchain::MAX_FRAMES_IN_FLIGHT;
+use super::lve_texture::*;
+use super::render_system::RenderSystem;
+
+use ash::{vk, Device};
+
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::rc::Rc;
+
+const FONT_PATH: &str = "fonts/DejaVuSansMono.ttf";
+const FONT_SIZE: f32 = 20.0;
+const ATLAS_WIDTH: u32 = 256;
+/// Space left between glyphs in the atlas so linear filtering can't bleed into a neighbour
+const ATLAS_PADDING: u32 = 1;
+const MAX_GLYPHS: usize = 4096;
+
+/// Per instance data for a single glyph quad
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct GlyphInstance {
+    rect: [f32; 4],    // x, y, width, height in pixels
+    uv_rect: [f32; 4], // u0, v0, u1, v1
+    color: [f32; 4],
+}
+
+impl GlyphInstance {
+    fn get_binding_descriptions() -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<GlyphInstance>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build()]
+    }
+
+    fn get_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+        (0..3)
+            .map(|location| vk::VertexInputAttributeDescription {
+                binding: 0,
+                location,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: location * size_of::<[f32; 4]>() as u32,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct TextPushConstantData {
+    _screen_size: [f32; 2],
+}
+
+impl TextPushConstantData {
+    pub unsafe fn as_bytes(&self) -> &[u8] {
+        let size_in_bytes = size_of::<Self>();
+        let start_ptr = self as *const Self as *const u8;
+        std::slice::from_raw_parts(start_ptr, size_in_bytes)
+    }
+}
+
+/// Where a glyph lives in the atlas and how to place it relative to the pen position
+#[derive(Debug, Clone, Copy)]
+struct Glyph {
+    uv_rect: [f32; 4],
+    width: f32,
+    height: f32,
+    x_offset: f32,
+    /// Distance from the baseline up to the top of the glyph bitmap
+    y_offset: f32,
+    advance: f32,
+}
+
+/// Draws screen space text on top of the scene. Text is queued with `draw_text` during the
+/// frame and everything queued is drawn with a single instanced draw call in `render`
+pub struct TextSystem {
+    lve_device: Rc<LveDevice>,
+    lve_pipeline: LvePipeline,
+    pipeline_layout: vk::PipelineLayout,
+    _set_layout: Rc<LveDescriptorSetLayout>,
+    _descriptor_pool: Rc<LveDescriptorPool>,
+    descriptor_set: vk::DescriptorSet,
+    _font_atlas: Rc<LveTexture>,
+    glyphs: HashMap<char, Glyph>,
+    ascent: f32,
+    line_height: f32,
+    instance_buffers: Vec<LveBuffer>,
+    queued_glyphs: Vec<GlyphInstance>,
+}
+
+impl TextSystem {
+    pub fn new(lve_device: Rc<LveDevice>, render_pass: &RenderPassInfo) -> Self {
+        let font_data = std::fs::read(FONT_PATH)
+            .map_err(|e| log::error!("Unable to read font {}: {}", FONT_PATH, e))
+            .unwrap();
+        let font = fontdue::Font::from_bytes(font_data, fontdue::FontSettings::default())
+            .map_err(|e| log::error!("Unable to parse font {}: {}", FONT_PATH, e))
+            .unwrap();
+
+        let line_metrics = font
+            .horizontal_line_metrics(FONT_SIZE)
+            .expect("Font has no horizontal line metrics");
+
+        let (font_atlas, glyphs) = Self::create_font_atlas(Rc::clone(&lve_device), &font);
+
+        let set_layout = LveDescriptorSetLayoutBuilder::new(Rc::clone(&lve_device))
+            .add_binding(
+                0,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::ShaderStageFlags::FRAGMENT,
+                1,
+            )
+            .build();
+
+        let descriptor_pool = LveDescriptorPoolBuilder::new(Rc::clone(&lve_device))
+            .set_max_sets(1)
+            .add_pool_size(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1)
+            .build();
+
+        let descriptor_set =
+            LveDescriptorWriter::new(Rc::clone(&set_layout), Rc::clone(&descriptor_pool))
+                .write_image(0, &[font_atlas.descriptor_info()])
+                .build()
+                .map_err(|_| log::error!("Unable to create the font atlas descriptor set!"))
+                .unwrap();
+
+        let pipeline_layout =
+            Self::create_pipeline_layout(&lve_device.device, set_layout.descriptor_set_layout);
+
+        let lve_pipeline =
+            Self::create_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout);
+
+        let instance_buffers = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let mut buffer = LveBuffer::new(
+                    Rc::clone(&lve_device),
+                    size_of::<GlyphInstance>() as u64,
+                    MAX_GLYPHS as u32,
+                    vk::BufferUsageFlags::VERTEX_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    1,
+                    BufferType::Instance,
+                );
+
+                unsafe { buffer.map(vk::WHOLE_SIZE, 0) };
+
+                buffer
+            })
+            .collect();
+
+        Self {
+            lve_device,
+            lve_pipeline,
+            pipeline_layout,
+            _set_layout: set_layout,
+            _descriptor_pool: descriptor_pool,
+            descriptor_set,
+            _font_atlas: font_atlas,
+            glyphs,
+            ascent: line_metrics.ascent,
+            line_height: line_metrics.new_line_size,
+            instance_buffers,
+            queued_glyphs: Vec::new(),
+        }
+    }
+
+    /// Queues a string to be drawn this frame. `x` and `y` are the top left corner of the text
+    /// in pixels, with the origin in the top left of the window. Newlines start a new line
+    pub fn draw_text(&mut self, x: f32, y: f32, text: &str, color: [f32; 4]) {
+        let mut pen_x = x;
+        let mut baseline = y + self.ascent;
+
+        for character in text.chars() {
+            if character == '\n' {
+                pen_x = x;
+                baseline += self.line_height;
+                continue;
+            }
+
+            let glyph = match self
+                .glyphs
+                .get(&character)
+                .or_else(|| self.glyphs.get(&'?'))
+            {
+                Some(glyph) => *glyph,
+                None => continue,
+            };
+
+            if glyph.width > 0.0 && glyph.height > 0.0 {
+                if self.queued_glyphs.len() == MAX_GLYPHS {
+                    log::warn!("Text glyph limit of {} reached, dropping text", MAX_GLYPHS);
+                    return;
+                }
+
+                self.queued_glyphs.push(GlyphInstance {
+                    rect: [
+                        (pen_x + glyph.x_offset).round(),
+                        (baseline - glyph.y_offset).round(),
+                        glyph.width,
+                        glyph.height,
+                    ],
+                    uv_rect: glyph.uv_rect,
+                    color,
+                });
+            }
+
+            pen_x += glyph.advance;
+        }
+    }
+
+    /// Rasterizes the printable ASCII range into a single row packed atlas. The coverage goes
+    /// in the alpha channel so the shader can tint the text any colour
+    fn create_font_atlas(
+        lve_device: Rc<LveDevice>,
+        font: &fontdue::Font,
+    ) -> (Rc<LveTexture>, HashMap<char, Glyph>) {
+        let rasterized: Vec<(char, fontdue::Metrics, Vec<u8>)> = (' '..='~')
+            .map(|character| {
+                let (metrics, bitmap) = font.rasterize(character, FONT_SIZE);
+                (character, metrics, bitmap)
+            })
+            .collect();
+
+        // Work out where each glyph goes before we know how tall the atlas needs to be
+        let mut positions = Vec::with_capacity(rasterized.len());
+        let (mut cursor_x, mut cursor_y, mut row_height) = (ATLAS_PADDING, ATLAS_PADDING, 0);
+
+        for (_, metrics, _) in rasterized.iter() {
+            let (width, height) = (metrics.width as u32, metrics.height as u32);
+
+            if cursor_x + width + ATLAS_PADDING > ATLAS_WIDTH {
+                cursor_x = ATLAS_PADDING;
+                cursor_y += row_height + ATLAS_PADDING;
+                row_height = 0;
+            }
+
+            positions.push((cursor_x, cursor_y));
+
+            cursor_x += width + ATLAS_PADDING;
+            row_height = row_height.max(height);
+        }
+
+        let atlas_height = (cursor_y + row_height + ATLAS_PADDING).next_power_of_two();
+
+        let mut pixels = vec![0_u8; (ATLAS_WIDTH * atlas_height * 4) as usize];
+        let mut glyphs = HashMap::new();
+
+        for ((character, metrics, bitmap), (atlas_x, atlas_y)) in rasterized.iter().zip(positions) {
+            for row in 0..metrics.height {
+                for column in 0..metrics.width {
+                    let coverage = bitmap[row * metrics.width + column];
+                    let index = (((atlas_y as usize + row) * ATLAS_WIDTH as usize)
+                        + atlas_x as usize
+                        + column)
+                        * 4;
+                    pixels[index..index + 4].copy_from_slice(&[255, 255, 255, coverage]);
+                }
+            }
+
+            let (width, height) = (metrics.width as f32, metrics.height as f32);
+
+            glyphs.insert(
+                *character,
+                Glyph {
+                    uv_rect: [
+                        atlas_x as f32 / ATLAS_WIDTH as f32,
+                        atlas_y as f32 / atlas_height as f32,
+                        (atlas_x as f32 + width) / ATLAS_WIDTH as f32,
+                        (atlas_y as f32 + height) / atlas_height as f32,
+                    ],
+                    width,
+                    height,
+                    x_offset: metrics.xmin as f32,
+                    y_offset: metrics.ymin as f32 + height,
+                    advance: metrics.advance_width,
+                },
+            );
+        }
+
+        log::debug!(
+            "Created {}x{} font atlas with {} glyphs",
+            ATLAS_WIDTH,
+            atlas_height,
+            glyphs.len()
+        );
+
+        let font_atlas = LveTexture::from_pixels(
+            lve_device,
+            &pixels,
+            ATLAS_WIDTH,
+            atlas_height,
+            TextureFormat::Linear,
+            SamplerConfig::nearest(),
+        );
+
+        (font_atlas, glyphs)
+    }
+
+    fn create_pipeline(
+        lve_device: Rc<LveDevice>,
+        render_pass: &RenderPassInfo,
+        pipeline_layout: &vk::PipelineLayout,
+    ) -> LvePipeline {
+        assert!(
+            pipeline_layout != &vk::PipelineLayout::null(),
+            "Cannot create pipeline before pipeline layout"
+        );
+
+        let mut pipeline_config = LvePipeline::default_pipline_config_info();
+
+        LvePipeline::enable_alpha_blending(&mut pipeline_config);
+
+        // The overlay is always drawn on top of the scene
+        LvePipeline::disable_depth_test(&mut pipeline_config);
+
+        pipeline_config.binding_descriptions = GlyphInstance::get_binding_descriptions();
+        pipeline_config.attribute_descriptions = GlyphInstance::get_attribute_descriptions();
+
+        LvePipeline::new(
+            lve_device,
+            "shaders/text_shader.vert.spv",
+            "shaders/text_shader.frag.spv",
+            pipeline_config,
+            render_pass,
+            pipeline_layout,
+        )
+    }
+
+    fn create_pipeline_layout(
+        device: &Device,
+        set_layout: vk::DescriptorSetLayout,
+    ) -> vk::PipelineLayout {
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .offset(0)
+            .size(size_of::<TextPushConstantData>() as u32)
+            .build();
+
+        let descriptor_set_layouts = vec![set_layout];
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(descriptor_set_layouts.as_slice())
+            .push_constant_ranges(&[push_constant_range])
+            .build();
+
+        unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .map_err(|e| log::error!("Unable to create text pipeline layout: {}", e))
+                .unwrap()
+        }
+    }
+}
+
+impl RenderSystem for TextSystem {
+    /// Draws everything queued since the last call, then clears the queue. Register it in the
+    /// UI stage so it draws after the scene
+    fn render(&mut self, frame_info: &FrameInfo) {
+        if self.queued_glyphs.is_empty() {
+            return;
+        }
+
+        let instance_buffer = &self.instance_buffers[frame_info.frame_index as usize];
+
+        let push = TextPushConstantData {
+            _screen_size: [
+                frame_info.viewport.extent.width as f32,
+                frame_info.viewport.extent.height as f32,
+            ],
+        };
+
+        unsafe {
+            instance_buffer.write_to_buffer(
+                self.queued_glyphs.as_slice(),
+                (self.queued_glyphs.len() * size_of::<GlyphInstance>()) as u64,
+                0,
+            );
+
+            self.lve_pipeline
+                .bind(&self.lve_device.device, frame_info.command_buffer);
+            frame_info.record_pipeline_bind();
+
+            self.lve_device.device.cmd_bind_descriptor_sets(
+                frame_info.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+
+            self.lve_device.device.cmd_push_constants(
+                frame_info.command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                push.as_bytes(),
+            );
+
+            self.lve_device.device.cmd_bind_vertex_buffers(
+                frame_info.command_buffer,
+                0,
+                &[instance_buffer.buffer],
+                &[0],
+            );
+
+            self.lve_device.device.cmd_draw(
+                frame_info.command_buffer,
+                6,
+                self.queued_glyphs.len() as u32,
+                0,
+                0,
+            );
+            frame_info.record_draw(2 * self.queued_glyphs.len() as u64);
+        }
+
+        self.queued_glyphs.clear();
+    }
+}
+
+impl Drop for TextSystem {
+    fn drop(&mut self) {
+        log::debug!("Dropping TextSystem");
+
+        unsafe {
+            self.lve_device
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use winit::{
+    dpi::LogicalSize,
+    event_loop::EventLoop,
+    monitor::MonitorHandle,
+    window::{Fullscreen, Icon, WindowBuilder},
+};
+
+/// How the window goes fullscreen. winit's `Fullscreen` needs a monitor (and for exclusive
+/// fullscreen a video mode), which only exist once the event loop does, so they're picked
+/// when the window goes fullscreen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum FullscreenMode {
+    /// A borderless window covering the monitor, without changing its video mode
+    Borderless,
+    /// Takes over the monitor at its highest resolution video mode
+    Exclusive,
+}
+
+impl FullscreenMode {
+    /// `None` if there's no monitor to go fullscreen on. Borderless fullscreen falls back to
+    /// whichever monitor the window is on when `monitor` is `None`
+    pub fn to_winit(self, monitor: Option<MonitorHandle>) -> Option<Fullscreen> {
+        match self {
+            FullscreenMode::Borderless => Some(Fullscreen::Borderless(monitor)),
+            FullscreenMode::Exclusive => {
+                let monitor = monitor?;
+
+                // Video modes aren't in any particular order
+                let video_mode = monitor.video_modes().max_by_key(|video_mode| {
+                    let size = video_mode.size();
+                    (
+                        size.width * size.height,
+                        video_mode.refresh_rate(),
+                        video_mode.bit_depth(),
+                    )
+                })?;
+
+                Some(Fullscreen::Exclusive(video_mode))
+            }
+        }
+    }
+}
+
+/// Everything about the window that is fixed when it's created
+#[derive(Debug, Clone)]
+pub struct WindowConfig {
+    pub title: String,
+    /// The inner size in logical pixels, when not fullscreen or maximized
+    pub size: (u32, u32),
+    /// Whether the window starts fullscreen
+    pub fullscreen: Option<FullscreenMode>,
+    /// The mode F11 switches to. Ignored (the starting mode is used) when starting fullscreen
+    pub toggle_fullscreen_mode: FullscreenMode,
+    pub decorations: bool,
+    pub maximized: bool,
+    /// An image file for the window icon. If it can't be loaded the window has no icon
+    pub icon_path: Option<String>,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: String::from("Hello Vulkan!"),
+            size: (800, 600),
+            fullscreen: None,
+            toggle_fullscreen_mode: FullscreenMode::Borderless,
+            decorations: true,
+            maximized: false,
+            icon_path: None,
+        }
+    }
+}
+
+impl WindowConfig {
+    /// The fullscreen mode F11 toggles in and out of
+    pub fn toggled_fullscreen_mode(&self) -> FullscreenMode {
+        self.fullscreen.unwrap_or(self.toggle_fullscreen_mode)
+    }
+
+    pub fn window_builder<T>(&self, event_loop: &EventLoop<T>) -> WindowBuilder {
+        let fullscreen = self.fullscreen.and_then(|fullscreen| {
+            let winit_fullscreen = fullscreen.to_winit(event_loop.primary_monitor());
+
+            if winit_fullscreen.is_none() {
+                log::warn!("No monitor for {:?} fullscreen, using a window", fullscreen);
+            }
+
+            winit_fullscreen
+        });
+
+        let (width, height) = self.size;
+
+        WindowBuilder::new()
+            .with_title(&self.title)
+            .with_inner_size(LogicalSize::new(width, height))
+            .with_resizable(true)
+            .with_fullscreen(fullscreen)
+            .with_decorations(self.decorations)
+            .with_maximized(self.maximized)
+            .with_window_icon(self.icon_path.as_deref().and_then(load_icon))
+    }
+}
+
+fn load_icon(icon_path: &str) -> Option<Icon> {
+    let image = match image::open(icon_path) {
+        Ok(image) => image.to_rgba8(),
+        Err(e) => {
+            log::warn!("Unable to load window icon {}: {}", icon_path, e);
+            return None;
+        }
+    };
+
+    let (width, height) = image.dimensions();
+
+    Icon::from_rgba(image.into_raw(), width, height)
+        .map_err(|e| log::warn!("Invalid window icon {}: {}", icon_path, e))
+        .ok()
+}
@@ -2,14 +2,71 @@ mod first_app;
 
 use first_app::*;
 
+/// Frames rendered by `--bench` when no count is given
+const DEFAULT_BENCHMARK_FRAMES: u32 = 1000;
+/// Objects added by `--demo-scene` when no count is given
+const DEFAULT_DEMO_SCENE_OBJECTS: usize = 500;
+/// Fixed so every `--demo-scene` run gets the same scene
+const DEMO_SCENE_SEED: u64 = 1;
+
 fn main() {
     // Begin the rust logging functionality
     env_logger::init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // `--fullscreen` starts in borderless fullscreen
+    let window_config = WindowConfig {
+        fullscreen: args
+            .iter()
+            .any(|arg| arg == "--fullscreen")
+            .then_some(FullscreenMode::Borderless),
+        ..Default::default()
+    };
+
     // Create the application and events loop
-    let (vulkan_app, event_loop) = VulkanApp::new();
+    let (mut vulkan_app, mut event_loop) = VulkanApp::new(&window_config);
+
+    // `--demo-scene [count]` adds that many objects, the same ones every run
+    let mut demo_scene_args = args.iter();
+    if demo_scene_args.any(|arg| arg == "--demo-scene") {
+        let count = match demo_scene_args.next().filter(|arg| !arg.starts_with("--")) {
+            Some(count) => count
+                .parse()
+                .map_err(|e| log::error!("Invalid demo scene object count {}: {}", count, e))
+                .unwrap(),
+            None => DEFAULT_DEMO_SCENE_OBJECTS,
+        };
+
+        vulkan_app.generate_demo_scene(count, DEMO_SCENE_SEED);
+    }
+
+    // `--depth-prepass` and `--no-depth-prepass` override `DEPTH_PREPASS`, to compare the
+    // fragment invocations the benchmark reports with and without it
+    if args.iter().any(|arg| arg == "--depth-prepass") {
+        vulkan_app.set_depth_prepass(true);
+    } else if args.iter().any(|arg| arg == "--no-depth-prepass") {
+        vulkan_app.set_depth_prepass(false);
+    }
+
+    // `--bench [frames]` renders a fixed camera path and prints the frame times as JSON
+    let mut args = args.iter();
+    if args.any(|arg| arg == "--bench") {
+        let frame_count = match args.next().filter(|arg| !arg.starts_with("--")) {
+            Some(count) => count
+                .parse()
+                .map_err(|e| log::error!("Invalid benchmark frame count {}: {}", count, e))
+                .unwrap(),
+            None => DEFAULT_BENCHMARK_FRAMES,
+        };
+
+        vulkan_app.enable_benchmark(frame_count);
+    }
 
     log::debug!("Running Application");
 
-    vulkan_app.run(event_loop);
+    // A lost device (e.g. after a GPU hang) is rebuilt and the app carries on
+    while vulkan_app.run(&mut event_loop) == RunOutcome::DeviceLost {
+        vulkan_app = vulkan_app.recover();
+    }
 }